@@ -1,9 +1,9 @@
-use std::{env, fs, process};
+use std::{env, fs, path::PathBuf};
 
-fn main() {
+fn rerun_if_ld_scripts_changed() {
     let ld_script_path = match env::var("LD_SCRIPT_PATH") {
         Ok(var) => var,
-        _ => process::exit(0),
+        _ => return,
     };
 
     let files = fs::read_dir(ld_script_path).unwrap();
@@ -18,3 +18,30 @@ fn main() {
         })
         .for_each(|f| println!("cargo:rerun-if-changed={}", f.path().display()));
 }
+
+/// Pass the path of the initramfs archive to the kernel, which embeds it.
+///
+/// Builds outside of the Makefile, e.g. by an IDE, get an empty archive.
+fn set_initramfs_path() {
+    println!("cargo:rerun-if-env-changed=INITRAMFS_PATH");
+
+    let initramfs_path = match env::var("INITRAMFS_PATH") {
+        Ok(var) => PathBuf::from(var),
+        _ => {
+            let path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("initramfs_empty.cpio");
+            fs::write(&path, []).unwrap();
+            path
+        }
+    };
+
+    println!("cargo:rerun-if-changed={}", initramfs_path.display());
+    println!(
+        "cargo:rustc-env=INITRAMFS_PATH={}",
+        initramfs_path.display()
+    );
+}
+
+fn main() {
+    rerun_if_ld_scripts_changed();
+    set_initramfs_path();
+}
@@ -11,7 +11,8 @@
 //!
 //! crate::cpu::arch_cpu
 
-use aarch64_cpu::asm;
+use aarch64_cpu::{asm, registers::*};
+use tock_registers::interfaces::Readable;
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
@@ -19,6 +20,23 @@ use aarch64_cpu::asm;
 
 pub use asm::nop;
 
+/// Wait until all preceding memory accesses have completed.
+///
+/// Needed before handing a buffer in normal memory to a device through a device register.
+#[inline(always)]
+pub fn memory_barrier() {
+    asm::barrier::dsb(asm::barrier::SY)
+}
+
+/// Return the primary part number of the executing core, e.g. `0xD03` for a Cortex-A53.
+#[inline(always)]
+pub fn part_number() -> u16 {
+    const PART_NUM_SHIFT: u64 = 4;
+    const PART_NUM_MASK: u64 = 0xFFF;
+
+    ((MIDR_EL1.get() >> PART_NUM_SHIFT) & PART_NUM_MASK) as u16
+}
+
 /// Pause execution on the core.
 #[inline(always)]
 pub fn wait_forever() -> ! {
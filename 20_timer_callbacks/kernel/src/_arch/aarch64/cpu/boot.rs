@@ -11,7 +11,10 @@
 //!
 //! crate::cpu::boot::arch_boot
 
-use crate::{memory, memory::Address};
+use crate::{
+    memory,
+    memory::{Address, Physical},
+};
 use aarch64_cpu::{asm, registers::*};
 use core::{
     arch::global_asm,
@@ -26,6 +29,15 @@ global_asm!(
     CONST_CORE_ID_MASK = const 0b11
 );
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Boot assembly code overwrites this value with the physical address of the device tree blob,
+/// which the firmware passes in `x0`. The firmware passes 0 if there is none.
+#[no_mangle]
+static PHYS_DEVICE_TREE_ADDR: u64 = 0;
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -86,6 +98,21 @@ unsafe fn prepare_backtrace_reset() {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// The physical address of the device tree blob that the firmware passed, if any.
+pub fn phys_device_tree_addr() -> Option<Address<Physical>> {
+    // Read volatile is needed here to prevent the compiler from optimizing PHYS_DEVICE_TREE_ADDR
+    // away.
+    //
+    // This is safe, because all the safety requirements as stated in read_volatile()'s
+    // documentation are fulfilled.
+    let addr = unsafe { core::ptr::read_volatile(&PHYS_DEVICE_TREE_ADDR) };
+
+    match addr {
+        0 => None,
+        addr => Some(Address::new(addr as usize)),
+    }
+}
+
 /// The Rust entry of the `kernel` binary.
 ///
 /// The function is called from the assembly `_start` function.
@@ -39,6 +39,10 @@
 // fn _start()
 //------------------------------------------------------------------------------
 _start:
+	// The firmware passes the physical address of the device tree blob in x0. Keep it until DRAM
+	// is initialized.
+	mov	x9, x0
+
 	// Only proceed if the core executes in EL2. Park it otherwise.
 	mrs	x0, CurrentEL
 	cmp	x0, {CONST_CURRENTEL_EL2}
@@ -65,6 +69,10 @@ _start:
 
 	// Prepare the jump to Rust code.
 .L_prepare_rust:
+	// Store the address of the device tree blob in PHYS_DEVICE_TREE_ADDR.
+	ADR_REL	x4, PHYS_DEVICE_TREE_ADDR // provided by aarch64/cpu/boot.rs
+	str	x9, [x4]
+
 	// Load the base address of the kernel's translation tables.
 	ldr	x0, PHYS_KERNEL_TABLES_BASE_ADDR // provided by bsp/__board_name__/memory/mmu.rs
 
@@ -109,7 +109,18 @@ extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
 #[no_mangle]
 extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
     let token = unsafe { &exception::asynchronous::IRQContext::new() };
+
+    exception::asynchronous::deferred_work::irq_enter(token);
     exception::asynchronous::irq_manager().handle_pending_irqs(token);
+    exception::asynchronous::deferred_work::irq_exit(token);
+}
+
+/// FIQs take a fast path that does not save the full `ExceptionContext`.
+#[no_mangle]
+extern "C" fn current_elx_fiq() {
+    let token = unsafe { &exception::asynchronous::IRQContext::new() };
+
+    exception::asynchronous::irq_manager().handle_pending_fiq(token);
 }
 
 #[no_mangle]
@@ -89,6 +89,48 @@ __vector_\handler:
 .type	__vector_\handler, function
 .endm
 
+/// Call the function provided by parameter `\handler` after saving only the caller-saved registers.
+///
+/// This is the fast path for FIQs. The handler is a normal function following the procedure call
+/// standard, so callee-saved registers are preserved by the handler itself. ELR_EL1 and SPSR_EL1
+/// are not saved, because FIQ handlers run with all asynchronous exceptions masked and are not
+/// expected to cause synchronous exceptions.
+.macro CALL_FIQ handler
+__vector_\handler:
+	sub	sp,  sp,  #16 * 10
+
+	stp	x0,  x1,  [sp, #16 * 0]
+	stp	x2,  x3,  [sp, #16 * 1]
+	stp	x4,  x5,  [sp, #16 * 2]
+	stp	x6,  x7,  [sp, #16 * 3]
+	stp	x8,  x9,  [sp, #16 * 4]
+	stp	x10, x11, [sp, #16 * 5]
+	stp	x12, x13, [sp, #16 * 6]
+	stp	x14, x15, [sp, #16 * 7]
+	stp	x16, x17, [sp, #16 * 8]
+	stp	x18, lr,  [sp, #16 * 9]
+
+	bl	\handler
+
+	ldp	x0,  x1,  [sp, #16 * 0]
+	ldp	x2,  x3,  [sp, #16 * 1]
+	ldp	x4,  x5,  [sp, #16 * 2]
+	ldp	x6,  x7,  [sp, #16 * 3]
+	ldp	x8,  x9,  [sp, #16 * 4]
+	ldp	x10, x11, [sp, #16 * 5]
+	ldp	x12, x13, [sp, #16 * 6]
+	ldp	x14, x15, [sp, #16 * 7]
+	ldp	x16, x17, [sp, #16 * 8]
+	ldp	x18, lr,  [sp, #16 * 9]
+
+	add	sp,  sp,  #16 * 10
+
+	eret
+
+.size	__vector_\handler, . - __vector_\handler
+.type	__vector_\handler, function
+.endm
+
 .macro FIQ_SUSPEND
 1:	wfe
 	b	1b
@@ -115,7 +157,7 @@ __exception_vector_start:
 //
 // # Safety
 //
-// - It must be ensured that `CALL_WITH_CONTEXT` <= 0x80 bytes.
+// - It must be ensured that `CALL_WITH_CONTEXT` and `CALL_FIQ` <= 0x80 bytes.
 .org 0x000
 	CALL_WITH_CONTEXT current_el0_synchronous, 0, 1
 .org 0x080
@@ -131,7 +173,7 @@ __exception_vector_start:
 .org 0x280
 	CALL_WITH_CONTEXT current_elx_irq, 0, 0
 .org 0x300
-	FIQ_SUSPEND
+	CALL_FIQ current_elx_fiq
 .org 0x380
 	CALL_WITH_CONTEXT current_elx_serror, 0, 0
 
@@ -21,6 +21,7 @@ use tock_registers::interfaces::{Readable, Writeable};
 
 mod daif_bits {
     pub const IRQ: u8 = 0b0010;
+    pub const FIQ: u8 = 0b0001;
 }
 
 trait DaifField {
@@ -115,6 +116,15 @@ pub fn local_irq_mask_save() -> u64 {
     saved
 }
 
+/// Unmask IRQs on the executing core and return the previously saved interrupt mask bits (DAIF).
+#[inline(always)]
+pub fn local_irq_unmask_save() -> u64 {
+    let saved = DAIF.get();
+    local_irq_unmask();
+
+    saved
+}
+
 /// Restore the interrupt mask bits (DAIF) using the callee's argument.
 ///
 /// # Invariant
@@ -125,6 +135,30 @@ pub fn local_irq_restore(saved: u64) {
     DAIF.set(saved);
 }
 
+/// Unmask FIQs on the executing core.
+#[inline(always)]
+pub fn local_fiq_unmask() {
+    unsafe {
+        asm!(
+            "msr DAIFClr, {arg}",
+            arg = const daif_bits::FIQ,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Mask FIQs on the executing core.
+#[inline(always)]
+pub fn local_fiq_mask() {
+    unsafe {
+        asm!(
+            "msr DAIFSet, {arg}",
+            arg = const daif_bits::FIQ,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
 /// Print the AArch64 exceptions status.
 #[rustfmt::skip]
 pub fn print_state() {
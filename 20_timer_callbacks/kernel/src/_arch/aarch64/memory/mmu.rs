@@ -40,6 +40,7 @@ pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
 pub mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -70,7 +71,11 @@ impl MemoryManagementUnit {
     fn set_up_mair(&self) {
         // Define the memory types being mapped.
         MAIR_EL1.write(
-            // Attribute 1 - Cacheable normal DRAM.
+            // Attribute 2 - Non-cacheable normal DRAM, for memory shared with devices.
+            MAIR_EL1::Attr2_Normal_Outer::NonCacheable +
+        MAIR_EL1::Attr2_Normal_Inner::NonCacheable +
+
+        // Attribute 1 - Cacheable normal DRAM.
             MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc +
         MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
 
@@ -155,4 +160,15 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
     fn is_enabled(&self) -> bool {
         SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
     }
+
+    fn invalidate_tlb_local(&self) {
+        // Make sure that translation table updates are visible before the TLB is invalidated.
+        barrier::dsb(barrier::ISHST);
+
+        unsafe { core::arch::asm!("tlbi vmalle1", options(nostack)) };
+
+        // Wait for completion, and make the following instructions use the new translations.
+        barrier::dsb(barrier::NSH);
+        barrier::isb(barrier::SY);
+    }
 }
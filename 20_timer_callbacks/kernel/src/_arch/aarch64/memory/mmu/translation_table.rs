@@ -191,6 +191,11 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::NORMAL)
             }
+            MemAttributes::NonCacheableDRAM => {
+                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx
+                        .val(memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE)
+            }
             MemAttributes::Device => {
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(memory::mmu::arch_mmu::mair::DEVICE)
@@ -226,6 +231,7 @@ impl convert::TryFrom<InMemoryRegister<u64, STAGE1_PAGE_DESCRIPTOR::Register>> f
     ) -> Result<AttributeFields, Self::Error> {
         let mem_attributes = match desc.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx) {
             memory::mmu::arch_mmu::mair::NORMAL => MemAttributes::CacheableDRAM,
+            memory::mmu::arch_mmu::mair::NORMAL_NON_CACHEABLE => MemAttributes::NonCacheableDRAM,
             memory::mmu::arch_mmu::mair::DEVICE => MemAttributes::Device,
             _ => return Err("Unexpected memory attribute"),
         };
@@ -165,8 +165,10 @@ pub fn spin_for(duration: Duration) {
 }
 
 /// The associated IRQ number.
-pub const fn timeout_irq() -> exception::asynchronous::IRQNumber {
-    bsp::exception::asynchronous::irq_map::ARM_NS_PHYSICAL_TIMER
+///
+/// Read from the device tree, so it must be initialized.
+pub fn timeout_irq() -> Result<exception::asynchronous::IRQNumber, &'static str> {
+    bsp::exception::asynchronous::irq_map::arm_ns_physical_timer()
 }
 
 /// Program a timer IRQ to be fired after `delay` has passed.
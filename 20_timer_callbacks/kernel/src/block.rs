@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Block devices.
+//!
+//! Storage is accessed in blocks of a fixed size. Drivers implement [`interface::BlockDevice`] and
+//! register their devices under a name, e.g. `sd0`, with [`register_block_device()`].
+
+pub mod partition;
+
+use crate::{
+    common, info,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+use alloc::vec::Vec;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct BlockDeviceDescriptor {
+    name: &'static str,
+    device: &'static (dyn interface::BlockDevice + Sync),
+}
+
+/// A list of block devices with unique names.
+struct BlockDeviceRegistry {
+    devices: InitStateLock<Vec<BlockDeviceDescriptor>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Block device interfaces.
+pub mod interface {
+    /// Block device functions.
+    pub trait BlockDevice {
+        /// Return the size of a block in bytes.
+        fn block_size(&self) -> usize;
+
+        /// Return the number of blocks.
+        fn num_blocks(&self) -> u64;
+
+        /// Read consecutive blocks into `buffer`, starting at `start_block`.
+        ///
+        /// The length of `buffer` must be a multiple of the block size.
+        fn read_blocks(&self, start_block: u64, buffer: &mut [u8]) -> Result<(), &'static str>;
+
+        /// Write consecutive blocks from `buffer`, starting at `start_block`.
+        ///
+        /// The length of `buffer` must be a multiple of the block size.
+        fn write_blocks(&self, start_block: u64, buffer: &[u8]) -> Result<(), &'static str>;
+
+        /// Block until all written blocks are stored persistently.
+        fn flush(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static BLOCK_DEVICES: BlockDeviceRegistry = BlockDeviceRegistry::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl BlockDeviceRegistry {
+    const fn new() -> Self {
+        Self {
+            devices: InitStateLock::new(Vec::new()),
+        }
+    }
+
+    /// Add a device under `name`, unless the name is taken.
+    fn register(
+        &self,
+        name: &'static str,
+        device: &'static (dyn interface::BlockDevice + Sync),
+    ) -> Result<(), &'static str> {
+        self.devices.write(|devices| {
+            if devices.iter().any(|desc| desc.name == name) {
+                return Err("Block device already registered");
+            }
+
+            devices.push(BlockDeviceDescriptor { name, device });
+
+            Ok(())
+        })
+    }
+
+    fn find(&self, name: &str) -> Option<&'static (dyn interface::BlockDevice + Sync)> {
+        self.devices.read(|devices| {
+            devices
+                .iter()
+                .find(|desc| desc.name == name)
+                .map(|desc| desc.device)
+        })
+    }
+
+    fn print(&self) {
+        self.devices.read(|devices| {
+            for desc in devices {
+                let device = desc.device;
+                let size = device.num_blocks() as usize * device.block_size();
+                let (size, unit) = common::size_human_readable_ceil(size);
+
+                info!(
+                    "      {:<8} {:>10} blocks of {} Byte | {} {}",
+                    desc.name,
+                    device.num_blocks(),
+                    device.block_size(),
+                    size,
+                    unit
+                );
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Check a request of `len` bytes starting at `start_block` against the size of `device`.
+///
+/// Returns the number of blocks of the request.
+pub fn check_request(
+    device: &dyn interface::BlockDevice,
+    start_block: u64,
+    len: usize,
+) -> Result<u64, &'static str> {
+    if len % device.block_size() != 0 {
+        return Err("Buffer length is not a multiple of the block size");
+    }
+
+    let num_blocks = (len / device.block_size()) as u64;
+    match start_block.checked_add(num_blocks) {
+        Some(end) if end <= device.num_blocks() => Ok(num_blocks),
+        _ => Err("Request exceeds the device"),
+    }
+}
+
+/// Register a block device under `name`.
+///
+/// Fails if a device of the same name exists already.
+pub fn register_block_device(
+    name: &'static str,
+    device: &'static (dyn interface::BlockDevice + Sync),
+) -> Result<(), &'static str> {
+    BLOCK_DEVICES.register(name, device)
+}
+
+/// Return the block device registered under `name`.
+pub fn block_device(name: &str) -> Option<&'static (dyn interface::BlockDevice + Sync)> {
+    BLOCK_DEVICES.find(name)
+}
+
+/// Print all registered block devices.
+pub fn print_block_devices() {
+    BLOCK_DEVICES.print();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    struct TestDevice;
+
+    impl interface::BlockDevice for TestDevice {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn num_blocks(&self) -> u64 {
+            8
+        }
+
+        fn read_blocks(&self, start_block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            check_request(self, start_block, buffer.len())?;
+            buffer.fill(start_block as u8);
+
+            Ok(())
+        }
+
+        fn write_blocks(&self, start_block: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            check_request(self, start_block, buffer.len()).map(|_| ())
+        }
+    }
+
+    static TEST_DEVICE: TestDevice = TestDevice;
+
+    /// Requests must consist of whole blocks within the device.
+    #[kernel_test]
+    fn requests_are_checked() {
+        assert_eq!(check_request(&TEST_DEVICE, 0, 0), Ok(0));
+        assert_eq!(check_request(&TEST_DEVICE, 2, 3 * 512), Ok(3));
+        assert_eq!(check_request(&TEST_DEVICE, 0, 8 * 512), Ok(8));
+
+        assert!(check_request(&TEST_DEVICE, 0, 100).is_err());
+        assert!(check_request(&TEST_DEVICE, 7, 2 * 512).is_err());
+        assert!(check_request(&TEST_DEVICE, u64::MAX, 512).is_err());
+    }
+
+    /// Registered devices can be looked up by name, and names are unique.
+    #[kernel_test]
+    fn devices_are_found_by_name() {
+        let registry = BlockDeviceRegistry::new();
+
+        assert!(registry.register("test0", &TEST_DEVICE).is_ok());
+        assert!(registry.register("test0", &TEST_DEVICE).is_err());
+
+        let device = registry.find("test0").unwrap();
+        let mut buffer = [0; 512];
+        device.read_blocks(5, &mut buffer).unwrap();
+        assert!(buffer.iter().all(|&x| x == 5));
+
+        assert!(registry.find("test1").is_none());
+        assert!(block_device("test0").is_none());
+    }
+}
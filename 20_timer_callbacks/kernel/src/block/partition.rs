@@ -0,0 +1,785 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Partition tables.
+//!
+//! MBR partition tables, including logical partitions in extended partitions, and GPT partition
+//! tables are parsed from the first blocks of a block device. Each partition is exposed as a block
+//! device of its own, which translates block numbers by the partition's offset.
+
+use super::interface::BlockDevice;
+use crate::{
+    block, common, info,
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+    warn,
+};
+use alloc::{boxed::Box, format, string::String, vec, vec::Vec};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_NUM_ENTRIES: usize = 4;
+const MBR_SIGNATURE_OFFSET: usize = 510;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// MBR type of the protective partition that covers a GPT disk.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+/// MBR types of extended partitions, which contain a chain of logical partitions.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+
+/// Upper limit of logical partitions. Protects against loops in the chain.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+const GPT_HEADER_MIN_SIZE: usize = 92;
+const GPT_ENTRY_MIN_SIZE: usize = 128;
+
+/// Upper limit of GPT entries. Protects against corrupted headers.
+const GPT_MAX_ENTRIES: usize = 1024;
+
+/// Block of the primary GPT header.
+const GPT_PRIMARY_HEADER_BLOCK: u64 = 1;
+
+/// An entry of an MBR or EBR.
+struct MbrEntry {
+    partition_type: u8,
+    start_block: u64,
+    num_blocks: u64,
+}
+
+/// The fields of a GPT header that locate the entries.
+struct GptHeader {
+    entries_block: u64,
+    num_entries: usize,
+    entry_size: usize,
+    entries_crc: u32,
+    alternate_header_block: u64,
+}
+
+struct PartitionDescriptor {
+    name: &'static str,
+    partition: &'static Partition,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A GUID, in the mixed-endian byte order used on disk.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Guid([u8; 16]);
+
+/// The type of a partition.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PartitionType {
+    /// Type byte of an MBR entry.
+    Mbr(u8),
+
+    /// Type GUID of a GPT entry.
+    Gpt(Guid),
+}
+
+/// A partition, accessible as a block device.
+pub struct Partition {
+    device: &'static (dyn BlockDevice + Sync),
+    start_block: u64,
+    num_blocks: u64,
+    partition_type: PartitionType,
+
+    /// The name of GPT partitions. Empty for MBR partitions.
+    label: String,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static PARTITIONS: InitStateLock<Vec<PartitionDescriptor>> = InitStateLock::new(Vec::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// CRC-32 as used by GPT (IEEE 802.3, reflected).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Read at least `len` bytes starting at `start_block`.
+fn read_bytes(
+    device: &dyn BlockDevice,
+    start_block: u64,
+    len: usize,
+) -> Result<Vec<u8>, &'static str> {
+    let mut buffer = vec![0; len.next_multiple_of(device.block_size())];
+    device.read_blocks(start_block, &mut buffer)?;
+
+    Ok(buffer)
+}
+
+/// Return the used entries of an MBR or EBR, or an error if the signature is missing.
+fn mbr_entries(block: &[u8]) -> Result<Vec<MbrEntry>, &'static str> {
+    if block[MBR_SIGNATURE_OFFSET..MBR_SIGNATURE_OFFSET + 2] != MBR_SIGNATURE {
+        return Err("No MBR signature");
+    }
+
+    Ok((0..MBR_NUM_ENTRIES)
+        .map(|i| &block[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE])
+        .map(|entry| MbrEntry {
+            partition_type: entry[4],
+            start_block: le_u32(entry, 8) as u64,
+            num_blocks: le_u32(entry, 12) as u64,
+        })
+        .filter(|entry| entry.partition_type != 0 && entry.num_blocks != 0)
+        .collect())
+}
+
+/// Add the partition of an MBR or EBR entry that starts at `start_block`.
+///
+/// An entry that does not fit the device is skipped with a warning, so that it does not hide the
+/// valid partitions.
+fn push_mbr_partition(
+    device: &'static (dyn BlockDevice + Sync),
+    start_block: u64,
+    entry: &MbrEntry,
+    partitions: &mut Vec<Partition>,
+) {
+    match Partition::new(
+        device,
+        start_block,
+        entry.num_blocks,
+        PartitionType::Mbr(entry.partition_type),
+        String::new(),
+    ) {
+        Ok(partition) => partitions.push(partition),
+        Err(x) => warn!("MBR partition at block {} skipped: {}", start_block, x),
+    }
+}
+
+/// Follow the chain of EBRs of the extended partition at `extended_start_block`.
+fn scan_logical_partitions(
+    device: &'static (dyn BlockDevice + Sync),
+    extended_start_block: u64,
+    partitions: &mut Vec<Partition>,
+) -> Result<(), &'static str> {
+    let mut ebr_block = extended_start_block;
+
+    for _ in 0..MAX_LOGICAL_PARTITIONS {
+        let ebr = read_bytes(device, ebr_block, 512)?;
+        let mut entries = mbr_entries(&ebr)?.into_iter();
+
+        // The first entry is relative to the EBR, the link to the next EBR is relative to the
+        // extended partition.
+        match entries.next() {
+            None => return Ok(()),
+            Some(entry) => {
+                push_mbr_partition(device, ebr_block + entry.start_block, &entry, partitions)
+            }
+        }
+
+        match entries.next() {
+            Some(link) if MBR_TYPES_EXTENDED.contains(&link.partition_type) => {
+                ebr_block = extended_start_block + link.start_block
+            }
+            _ => return Ok(()),
+        }
+    }
+
+    Err("Too many logical partitions")
+}
+
+fn scan_mbr(
+    device: &'static (dyn BlockDevice + Sync),
+    entries: Vec<MbrEntry>,
+) -> Result<Vec<Partition>, &'static str> {
+    let mut partitions = Vec::new();
+
+    for entry in entries {
+        if !MBR_TYPES_EXTENDED.contains(&entry.partition_type) {
+            push_mbr_partition(device, entry.start_block, &entry, &mut partitions);
+            continue;
+        }
+
+        // Keep the logical partitions that were found before a damaged EBR.
+        if let Err(x) = scan_logical_partitions(device, entry.start_block, &mut partitions) {
+            warn!("Extended partition at block {}: {}", entry.start_block, x);
+        }
+    }
+
+    Ok(partitions)
+}
+
+/// Parse and validate the GPT header at `block`.
+fn gpt_header(device: &dyn BlockDevice, block: u64) -> Result<GptHeader, &'static str> {
+    let mut header = read_bytes(device, block, GPT_HEADER_MIN_SIZE)?;
+
+    if !header.starts_with(GPT_SIGNATURE) {
+        return Err("No GPT signature");
+    }
+
+    let header_size = le_u32(&header, 12) as usize;
+    if !(GPT_HEADER_MIN_SIZE..=header.len()).contains(&header_size) {
+        return Err("Invalid GPT header size");
+    }
+
+    // The CRC is calculated with the CRC field zeroed.
+    let header_crc = le_u32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_size]) != header_crc {
+        return Err("GPT header CRC mismatch");
+    }
+
+    if le_u64(&header, 24) != block {
+        return Err("GPT header at unexpected block");
+    }
+
+    // Entries are not larger than a block in practice. Bounding them keeps the read small.
+    let num_entries = le_u32(&header, 80) as usize;
+    let entry_size = le_u32(&header, 84) as usize;
+    if num_entries > GPT_MAX_ENTRIES
+        || !(GPT_ENTRY_MIN_SIZE..=device.block_size()).contains(&entry_size)
+        || entry_size % 8 != 0
+    {
+        return Err("Invalid GPT entry layout");
+    }
+
+    Ok(GptHeader {
+        entries_block: le_u64(&header, 72),
+        num_entries,
+        entry_size,
+        entries_crc: le_u32(&header, 88),
+        alternate_header_block: le_u64(&header, 32),
+    })
+}
+
+/// Parse the GPT entries described by `header`.
+fn scan_gpt_at(
+    device: &'static (dyn BlockDevice + Sync),
+    header: &GptHeader,
+) -> Result<Vec<Partition>, &'static str> {
+    let len = header
+        .num_entries
+        .checked_mul(header.entry_size)
+        .ok_or("Invalid GPT entry layout")?;
+    let entries = read_bytes(device, header.entries_block, len)?;
+    let entries = &entries[..len];
+
+    if crc32(entries) != header.entries_crc {
+        return Err("GPT entries CRC mismatch");
+    }
+
+    let mut partitions = Vec::new();
+    for entry in entries.chunks_exact(header.entry_size) {
+        let type_guid = Guid(entry[0..16].try_into().unwrap());
+        if type_guid == Guid::UNUSED {
+            continue;
+        }
+
+        let first_block = le_u64(entry, 32);
+        let last_block = le_u64(entry, 40);
+        if last_block < first_block {
+            return Err("Invalid GPT entry");
+        }
+
+        // The name is UTF-16LE, padded with zeros.
+        let name = entry[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0);
+        let label = char::decode_utf16(name)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        partitions.push(Partition::new(
+            device,
+            first_block,
+            last_block - first_block + 1,
+            PartitionType::Gpt(type_guid),
+            label,
+        )?);
+    }
+
+    Ok(partitions)
+}
+
+/// Parse the GPT, falling back to the backup at the end of the device if the primary one is
+/// damaged.
+fn scan_gpt(device: &'static (dyn BlockDevice + Sync)) -> Result<Vec<Partition>, &'static str> {
+    let (primary_err, backup_block) = match gpt_header(device, GPT_PRIMARY_HEADER_BLOCK) {
+        Ok(header) => match scan_gpt_at(device, &header) {
+            Ok(partitions) => return Ok(partitions),
+            Err(x) => (x, header.alternate_header_block),
+        },
+        Err(x) => (x, device.num_blocks() - 1),
+    };
+    warn!("Primary GPT unusable: {}. Trying the backup", primary_err);
+
+    scan_gpt_at(device, &gpt_header(device, backup_block)?)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Guid {
+    /// Type of unused GPT entries.
+    pub const UNUSED: Self = Self([0; 16]);
+
+    /// EFI System Partition.
+    pub const EFI_SYSTEM: Self = Self::new(
+        0xC12A_7328,
+        0xF81F,
+        0x11D2,
+        [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+    );
+
+    /// Microsoft basic data, used for FAT file systems.
+    pub const BASIC_DATA: Self = Self::new(
+        0xEBD0_A0A2,
+        0xB9E5,
+        0x4433,
+        [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7],
+    );
+
+    /// Linux file system.
+    pub const LINUX_FILESYSTEM: Self = Self::new(
+        0x0FC6_3DAF,
+        0x8483,
+        0x4772,
+        [0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4],
+    );
+
+    /// Create an instance from the fields of the textual representation.
+    pub const fn new(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Self {
+        let d1 = data1.to_le_bytes();
+        let d2 = data2.to_le_bytes();
+        let d3 = data3.to_le_bytes();
+        let d4 = data4;
+
+        Self([
+            d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1], d4[0], d4[1], d4[2], d4[3],
+            d4[4], d4[5], d4[6], d4[7],
+        ])
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            le_u32(b, 0),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9],
+            b[10],
+            b[11],
+            b[12],
+            b[13],
+            b[14],
+            b[15]
+        )
+    }
+}
+
+impl PartitionType {
+    /// Return whether the partition might contain a FAT file system.
+    pub fn is_fat(&self) -> bool {
+        match self {
+            Self::Mbr(t) => matches!(t, 0x01 | 0x04 | 0x06 | 0x0B | 0x0C | 0x0E),
+            Self::Gpt(guid) => *guid == Guid::EFI_SYSTEM || *guid == Guid::BASIC_DATA,
+        }
+    }
+}
+
+impl fmt::Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let known = match self {
+            Self::Mbr(0x01) => "FAT12",
+            Self::Mbr(0x04 | 0x06 | 0x0E) => "FAT16",
+            Self::Mbr(0x0B) => "FAT32",
+            Self::Mbr(0x0C) => "FAT32 (LBA)",
+            Self::Mbr(0x07) => "NTFS/exFAT",
+            Self::Mbr(0x82) => "Linux swap",
+            Self::Mbr(0x83) => "Linux",
+            Self::Gpt(guid) if *guid == Guid::EFI_SYSTEM => "EFI System",
+            Self::Gpt(guid) if *guid == Guid::BASIC_DATA => "Basic data",
+            Self::Gpt(guid) if *guid == Guid::LINUX_FILESYSTEM => "Linux file system",
+            Self::Mbr(t) => return write!(f, "MBR type {:#04x}", t),
+            Self::Gpt(guid) => return write!(f, "{}", guid),
+        };
+
+        f.write_str(known)
+    }
+}
+
+impl Partition {
+    fn new(
+        device: &'static (dyn BlockDevice + Sync),
+        start_block: u64,
+        num_blocks: u64,
+        partition_type: PartitionType,
+        label: String,
+    ) -> Result<Self, &'static str> {
+        match start_block.checked_add(num_blocks) {
+            Some(end) if end <= device.num_blocks() => (),
+            _ => return Err("Partition exceeds the device"),
+        }
+
+        Ok(Self {
+            device,
+            start_block,
+            num_blocks,
+            partition_type,
+            label,
+        })
+    }
+
+    /// Return the first block of the partition on the underlying device.
+    pub fn start_block(&self) -> u64 {
+        self.start_block
+    }
+
+    /// Return the type of the partition.
+    pub fn partition_type(&self) -> PartitionType {
+        self.partition_type
+    }
+
+    /// Return the name of GPT partitions. Empty for MBR partitions.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read_blocks(&self, start_block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        block::check_request(self, start_block, buffer.len())?;
+
+        self.device
+            .read_blocks(self.start_block + start_block, buffer)
+    }
+
+    fn write_blocks(&self, start_block: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        block::check_request(self, start_block, buffer.len())?;
+
+        self.device
+            .write_blocks(self.start_block + start_block, buffer)
+    }
+
+    fn flush(&self) -> Result<(), &'static str> {
+        self.device.flush()
+    }
+}
+
+/// Parse the partition table of `device`.
+pub fn scan(device: &'static (dyn BlockDevice + Sync)) -> Result<Vec<Partition>, &'static str> {
+    if device.block_size() < 512 {
+        return Err("Block size too small for a partition table");
+    }
+
+    // The backup GPT is searched in the last block.
+    if device.num_blocks() == 0 {
+        return Err("Empty device");
+    }
+
+    let entries = mbr_entries(&read_bytes(device, 0, 512)?)?;
+
+    // A GPT disk has a protective MBR with a single partition covering the disk.
+    if entries
+        .iter()
+        .any(|entry| entry.partition_type == MBR_TYPE_GPT_PROTECTIVE)
+    {
+        scan_gpt(device)
+    } else {
+        scan_mbr(device, entries)
+    }
+}
+
+/// Parse the partition table of `device` and register each partition as a block device.
+///
+/// The partitions are named after the device with a suffix, e.g. `sd0p1` for the first partition
+/// of `sd0`. Returns the number of partitions.
+pub fn register_partitions(
+    device_name: &str,
+    device: &'static (dyn BlockDevice + Sync),
+) -> Result<usize, &'static str> {
+    let partitions = scan(device)?;
+    let num_partitions = partitions.len();
+
+    for (i, partition) in partitions.into_iter().enumerate() {
+        // Partitions are never removed, so they live as long as the kernel.
+        let name: &'static str = Box::leak(format!("{}p{}", device_name, i + 1).into_boxed_str());
+        let partition: &'static Partition = Box::leak(Box::new(partition));
+
+        block::register_block_device(name, partition)?;
+        PARTITIONS.write(|partitions| partitions.push(PartitionDescriptor { name, partition }));
+    }
+
+    Ok(num_partitions)
+}
+
+/// Human-readable print of all registered partitions.
+pub fn print_partitions() {
+    info!("      -------------------------------------------------------------------------------------------------");
+    info!(
+        "      {:<8}   {:^30}   {:^8}   {:^20}   {:<20}",
+        "Name", "Blocks", "Size", "Type", "Label"
+    );
+    info!("      -------------------------------------------------------------------------------------------------");
+
+    PARTITIONS.read(|partitions| {
+        for desc in partitions {
+            let p = desc.partition;
+            let size = p.num_blocks as usize * p.block_size();
+            let (size, unit) = common::size_human_readable_ceil(size);
+
+            info!(
+                "      {:<8} | {:>13}..{:<13} | {:>4} {:<3} | {:<20} | {}",
+                desc.name,
+                p.start_block,
+                p.start_block + p.num_blocks - 1,
+                size,
+                unit,
+                format!("{}", p.partition_type),
+                p.label
+            );
+        }
+    });
+
+    info!("      -------------------------------------------------------------------------------------------------");
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+    use test_macros::kernel_test;
+
+    const NUM_BLOCKS: usize = 64;
+
+    struct RamDisk {
+        data: IRQSafeNullLock<[u8; NUM_BLOCKS * 512]>,
+    }
+
+    impl RamDisk {
+        const fn new() -> Self {
+            Self {
+                data: IRQSafeNullLock::new([0; NUM_BLOCKS * 512]),
+            }
+        }
+
+        fn write(&self, offset: usize, bytes: &[u8]) {
+            self.data
+                .lock(|data| data[offset..offset + bytes.len()].copy_from_slice(bytes));
+        }
+
+        fn write_mbr_entry(
+            &self,
+            sector: usize,
+            i: usize,
+            partition_type: u8,
+            start: u32,
+            len: u32,
+        ) {
+            let offset = sector * 512 + MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE;
+            self.write(offset + 4, &[partition_type]);
+            self.write(offset + 8, &start.to_le_bytes());
+            self.write(offset + 12, &len.to_le_bytes());
+            self.write(sector * 512 + MBR_SIGNATURE_OFFSET, &MBR_SIGNATURE);
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> usize {
+            512
+        }
+
+        fn num_blocks(&self) -> u64 {
+            NUM_BLOCKS as u64
+        }
+
+        fn read_blocks(&self, start_block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            block::check_request(self, start_block, buffer.len())?;
+
+            let offset = start_block as usize * 512;
+            self.data
+                .lock(|data| buffer.copy_from_slice(&data[offset..offset + buffer.len()]));
+
+            Ok(())
+        }
+
+        fn write_blocks(&self, start_block: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            block::check_request(self, start_block, buffer.len())?;
+            self.write(start_block as usize * 512, buffer);
+
+            Ok(())
+        }
+    }
+
+    /// Write a GPT with one FAT partition, whose header is in block 1 and entries in block 2.
+    fn write_gpt(disk: &RamDisk) {
+        disk.write_mbr_entry(0, 0, MBR_TYPE_GPT_PROTECTIVE, 1, NUM_BLOCKS as u32 - 1);
+
+        let mut entry = [0; 128];
+        entry[0..16].copy_from_slice(&Guid::BASIC_DATA.0);
+        entry[32..40].copy_from_slice(&8_u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&39_u64.to_le_bytes());
+        for (i, c) in "boot".encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        let mut entries = [0; 512];
+        entries[..128].copy_from_slice(&entry);
+        disk.write(2 * 512, &entries);
+
+        let mut header = [0; GPT_HEADER_MIN_SIZE];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[12..16].copy_from_slice(&(GPT_HEADER_MIN_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&1_u64.to_le_bytes());
+        header[32..40].copy_from_slice(&(NUM_BLOCKS as u64 - 1).to_le_bytes());
+        header[72..80].copy_from_slice(&2_u64.to_le_bytes());
+        header[80..84].copy_from_slice(&4_u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128_u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+        let header_crc = crc32(&header);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        disk.write(512, &header);
+    }
+
+    /// The CRC matches the standard check value.
+    #[kernel_test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    /// Primary and logical MBR partitions are found, and their blocks are translated.
+    #[kernel_test]
+    fn mbr_partitions_are_found() {
+        static DISK: RamDisk = RamDisk::new();
+
+        DISK.write_mbr_entry(0, 0, 0x0C, 8, 16);
+        DISK.write_mbr_entry(0, 1, 0x05, 32, 32);
+        DISK.write_mbr_entry(32, 0, 0x83, 2, 8);
+        DISK.write_mbr_entry(32, 1, 0x05, 16, 16);
+        DISK.write_mbr_entry(48, 0, 0x82, 2, 4);
+        DISK.write(9 * 512, b"marker");
+
+        let partitions = scan(&DISK).unwrap();
+        let layout: Vec<_> = partitions
+            .iter()
+            .map(|p| (p.partition_type(), p.start_block(), p.num_blocks()))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (PartitionType::Mbr(0x0C), 8, 16),
+                (PartitionType::Mbr(0x83), 34, 8),
+                (PartitionType::Mbr(0x82), 50, 4),
+            ]
+        );
+        assert!(partitions[0].partition_type().is_fat());
+
+        let mut buffer = [0; 512];
+        partitions[0].read_blocks(1, &mut buffer).unwrap();
+        assert!(buffer.starts_with(b"marker"));
+        assert!(partitions[0].read_blocks(16, &mut buffer).is_err());
+    }
+
+    /// GPT partitions are found, and damaged tables are rejected.
+    #[kernel_test]
+    fn gpt_partitions_are_validated() {
+        static DISK: RamDisk = RamDisk::new();
+
+        write_gpt(&DISK);
+        let partitions = scan(&DISK).unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert!(partitions[0].partition_type() == PartitionType::Gpt(Guid::BASIC_DATA));
+        assert_eq!(partitions[0].label(), "boot");
+        assert_eq!(
+            (partitions[0].start_block(), partitions[0].num_blocks()),
+            (8, 32)
+        );
+
+        // There is no backup GPT to fall back to.
+        DISK.write(2 * 512 + 60, b"x");
+        assert!(scan(&DISK).is_err());
+    }
+
+    /// GPT entries larger than a block are rejected before they are read.
+    #[kernel_test]
+    fn gpt_entry_size_is_bounded() {
+        static DISK: RamDisk = RamDisk::new();
+
+        write_gpt(&DISK);
+        let mut header = [0; 512];
+        DISK.read_blocks(1, &mut header).unwrap();
+        header[84..88].copy_from_slice(&1024_u32.to_le_bytes());
+        header[16..20].fill(0);
+        let header_crc = crc32(&header[..GPT_HEADER_MIN_SIZE]);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        DISK.write(512, &header);
+
+        assert_eq!(gpt_header(&DISK, 1).err(), Some("Invalid GPT entry layout"));
+    }
+
+    /// MBR entries that exceed the device are skipped, the others are kept.
+    #[kernel_test]
+    fn invalid_mbr_entries_are_skipped() {
+        static DISK: RamDisk = RamDisk::new();
+
+        DISK.write_mbr_entry(0, 0, 0x83, 60, 16);
+        DISK.write_mbr_entry(0, 1, 0x0C, 8, 16);
+        DISK.write_mbr_entry(0, 2, 0x05, 32, 32);
+        DISK.write_mbr_entry(32, 0, 0x83, 30, 8);
+        DISK.write_mbr_entry(32, 1, 0x05, 16, 16);
+        DISK.write_mbr_entry(48, 0, 0x82, 2, 4);
+
+        let layout: Vec<_> = scan(&DISK)
+            .unwrap()
+            .iter()
+            .map(|p| (p.start_block(), p.num_blocks()))
+            .collect();
+        assert_eq!(layout, [(8, 16), (50, 4)]);
+    }
+}
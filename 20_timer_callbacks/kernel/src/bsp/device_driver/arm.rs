@@ -6,4 +6,4 @@
 
 pub mod gicv2;
 
-pub use gicv2::*;
+pub use gicv2::{GICv2, IRQNumber as GICv2IRQNumber};
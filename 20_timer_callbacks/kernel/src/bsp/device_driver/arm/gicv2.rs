@@ -81,18 +81,23 @@ mod gicd;
 
 use crate::{
     bsp::{self, device_driver::common::BoundedUsize},
-    cpu, driver, exception,
+    cpu, driver,
+    exception::{
+        self,
+        asynchronous::{interface::IRQHandler, IPIKind, IRQHandlerTable, IRQPriority},
+    },
     memory::{Address, Virtual},
     synchronization,
     synchronization::InitStateLock,
 };
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
-type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<IRQNumber>>>;
+type StatisticsTable = Vec<exception::asynchronous::IRQStatisticsRecorder>;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -109,8 +114,17 @@ pub struct GICv2 {
     /// The CPU Interface.
     gicc: gicc::GICC,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers.
+    handler_table: IRQHandlerTable<IRQNumber>,
+
+    /// Per-IRQ statistics. Allocated during kernel init, updated lock-free afterwards.
+    statistics: InitStateLock<StatisticsTable>,
+
+    /// The number of spurious IRQs.
+    num_spurious: AtomicU64,
+
+    /// The IRQ that is delivered as FIQ, and its handler. Writable only during kernel init.
+    fiq: InitStateLock<Option<(IRQNumber, &'static (dyn IRQHandler + Sync))>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -119,6 +133,7 @@ pub struct GICv2 {
 
 impl GICv2 {
     const MAX_IRQ_NUMBER: usize = 1019;
+    const MAX_SGI_NUMBER: usize = 15;
 
     pub const COMPATIBLE: &'static str = "GICv2 (ARM Generic Interrupt Controller v2)";
 
@@ -134,9 +149,36 @@ impl GICv2 {
         Self {
             gicd: gicd::GICD::new(gicd_mmio_start_addr),
             gicc: gicc::GICC::new(gicc_mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            handler_table: IRQHandlerTable::new(),
+            statistics: InitStateLock::new(Vec::new()),
+            num_spurious: AtomicU64::new(0),
+            fiq: InitStateLock::new(None),
         }
     }
+
+    /// Translate a device tree interrupt specifier of the form `<type number flags>`.
+    ///
+    /// Type 0 is an SPI, which starts at IRQ 32, and type 1 is a PPI, which starts at IRQ 16.
+    pub fn irq_number_from_device_tree(
+        interrupt: &fdt::Interrupt,
+    ) -> Result<IRQNumber, &'static str> {
+        let number = match (interrupt.cell(0), interrupt.cell(1)) {
+            (Some(0), Some(spi)) => spi as usize + 32,
+            (Some(1), Some(ppi)) if ppi < 16 => ppi as usize + 16,
+            _ => return Err("Invalid GIC interrupt specifier"),
+        };
+
+        if number > Self::MAX_IRQ_NUMBER {
+            return Err("GIC interrupt number out of range");
+        }
+
+        Ok(IRQNumber::new(number))
+    }
+
+    /// Return whether an FIQ was set up, which moved all other interrupts to Group 1.
+    fn fiq_enabled(&self) -> bool {
+        self.fiq.read(|fiq| fiq.is_some())
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -152,14 +194,22 @@ impl driver::interface::DeviceDriver for GICv2 {
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
-        self.handler_table
-            .write(|table| table.resize(IRQNumber::MAX_INCLUSIVE + 1, None));
+        self.handler_table.init(IRQNumber::MAX_INCLUSIVE + 1);
+        self.statistics
+            .write(|table| table.resize_with(IRQNumber::MAX_INCLUSIVE + 1, Default::default));
 
         if bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id() {
             self.gicd.boot_core_init();
         }
 
+        // IPIs are handled with IRQs masked, so give them the matching priority.
+        for kind in IPIKind::ALL {
+            self.gicd
+                .set_priority(&IRQNumber::new(kind.index()), IRQPriority::HIGHEST);
+        }
+
         self.gicc.priority_accept_all();
+        self.gicc.enable_preemption();
         self.gicc.enable();
 
         Ok(())
@@ -173,49 +223,152 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         &self,
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+        let irq_number = irq_handler_descriptor.number();
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+        self.handler_table
+            .register(irq_number.get(), irq_handler_descriptor)?;
 
-            table[irq_number] = Some(irq_handler_descriptor);
+        // A chained handler might have raised the priority of the line.
+        if let Some(priority) = self.handler_table.priority(irq_number.get()) {
+            self.gicd.set_priority(&irq_number, priority);
+        }
 
-            Ok(())
-        })
+        Ok(())
+    }
+
+    fn unregister_handler(
+        &self,
+        irq_number: &Self::IRQNumberType,
+        handler: &'static (dyn exception::asynchronous::interface::IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        let chain_empty = self.handler_table.unregister(irq_number.get(), handler)?;
+
+        if chain_empty {
+            self.disable(irq_number);
+        } else if let Some(priority) = self.handler_table.priority(irq_number.get()) {
+            self.gicd.set_priority(irq_number, priority);
+        }
+
+        Ok(())
     }
 
     fn enable(&self, irq_number: &Self::IRQNumberType) {
         self.gicd.enable(irq_number);
     }
 
+    fn disable(&self, irq_number: &Self::IRQNumberType) {
+        self.gicd.disable(irq_number);
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
+        // Once an FIQ is set up, all IRQs are in Group 1 and acknowledged through the aliased
+        // registers.
+        let group1 = self.fiq_enabled();
+
         // Extract the highest priority pending IRQ number from the Interrupt Acknowledge Register
         // (IAR).
-        let irq_number = self.gicc.pending_irq_number(ic);
+        let (irq_number, source_cpu) = self.gicc.pending_irq_number(group1, ic);
 
         // Guard against spurious interrupts.
         if irq_number > GICv2::MAX_IRQ_NUMBER {
+            self.num_spurious.fetch_add(1, Ordering::Relaxed);
             return;
         }
 
-        // Call the IRQ handler. Panic if there is none.
-        self.handler_table.read(|table| {
-            match table[irq_number] {
-                None => panic!("No handler registered for IRQ {}", irq_number),
-                Some(descriptor) => {
-                    // Call the IRQ handler. Panics on failure.
-                    descriptor.handler().handle().expect("Error handling IRQ");
-                }
+        // SGIs are used for IPIs.
+        if irq_number <= GICv2::MAX_SGI_NUMBER {
+            match IPIKind::from_index(irq_number) {
+                None => panic!("Unexpected SGI {}", irq_number),
+                Some(kind) => cpu::smp::handle_ipi(kind, ic),
             }
+
+            self.gicc
+                .mark_comleted(irq_number as u32, source_cpu, group1, ic);
+            return;
+        }
+
+        // Call the IRQ handler. Panic if there is none.
+        let chain = match self.handler_table.get(irq_number) {
+            None => panic!("No handler registered for IRQ {}", irq_number),
+            Some(chain) => chain,
+        };
+
+        // Acknowledging the IRQ raised the running priority of the CPU interface to the priority of
+        // this IRQ. Until completion is signaled, the GIC only signals IRQs of higher priority, so
+        // it is safe to let them preempt the handler.
+        self.statistics.read(|statistics| {
+            statistics[irq_number].record(|| unsafe {
+                exception::asynchronous::exec_with_irq_unmasked(|| {
+                    exception::asynchronous::handle_chain(&chain)
+                })
+            })
         });
 
         // Signal completion of handling.
-        self.gicc.mark_comleted(irq_number as u32, ic);
+        self.gicc
+            .mark_comleted(irq_number as u32, source_cpu, group1, ic);
+    }
+
+    fn send_ipi(&self, target_core: usize, kind: IPIKind) -> Result<(), &'static str> {
+        if target_core >= bsp::cpu::NUM_CORES {
+            return Err("Invalid target core");
+        }
+
+        // Once an FIQ is set up, all interrupts except the FIQ source are in Group 1.
+        let group1 = self.fiq_enabled();
+
+        // On the RPi4, the CPU interface number equals the core id.
+        self.gicd
+            .send_sgi(&IRQNumber::new(kind.index()), 1 << target_core, group1);
+
+        Ok(())
+    }
+
+    fn set_fiq(
+        &self,
+        irq_number: &Self::IRQNumberType,
+        handler: &'static (dyn IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        self.fiq.write(|fiq| {
+            if fiq.is_some() {
+                return Err("FIQ already assigned");
+            }
+
+            self.gicd.make_sole_group0(irq_number)?;
+            self.gicd.set_priority(irq_number, IRQPriority::FIQ);
+            self.gicc.enable_fiq();
+
+            *fiq = Some((*irq_number, handler));
+
+            Ok(())
+        })
+    }
+
+    fn handle_pending_fiq<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        // The FIQ source is the only Group 0 interrupt.
+        let (irq_number, source_cpu) = self.gicc.pending_irq_number(false, ic);
+
+        // Guard against spurious interrupts.
+        if irq_number > GICv2::MAX_IRQ_NUMBER {
+            self.num_spurious.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.fiq.read(|fiq| match fiq {
+            Some((fiq_number, handler)) if fiq_number.get() == irq_number => {
+                handler.handle().expect("Error handling FIQ");
+            }
+            _ => panic!("No FIQ handler for IRQ {}", irq_number),
+        });
+
+        self.gicc
+            .mark_comleted(irq_number as u32, source_cpu, false, ic);
     }
 
     fn print_handler(&self) {
@@ -223,12 +376,45 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
 
         info!("      Peripheral handler:");
 
-        self.handler_table.read(|table| {
-            for (i, opt) in table.iter().skip(32).enumerate() {
-                if let Some(handler) = opt {
-                    info!("            {: >3}. {}", i + 32, handler.name());
-                }
+        for (i, chain) in self.handler_table.registered() {
+            if i < 32 {
+                continue;
+            }
+
+            for handler in chain.iter() {
+                info!(
+                    "            {: >3}. {} (priority {})",
+                    i,
+                    handler.name(),
+                    handler.priority()
+                );
+            }
+        }
+    }
+
+    fn irq_statistics(
+        &self,
+        irq_number: &Self::IRQNumberType,
+    ) -> exception::asynchronous::IRQStatistics {
+        self.statistics
+            .read(|statistics| statistics[irq_number.get()].get())
+    }
+
+    fn num_spurious_irqs(&self) -> u64 {
+        self.num_spurious.load(Ordering::Relaxed)
+    }
+
+    fn print_statistics(&self) {
+        use exception::asynchronous::IRQStatistics;
+
+        IRQStatistics::print_header();
+
+        self.statistics.read(|statistics| {
+            for (i, chain) in self.handler_table.registered() {
+                statistics[i].get().print_row(&i, &chain);
             }
         });
+
+        IRQStatistics::print_spurious_row(self.num_spurious_irqs());
     }
 }
@@ -10,7 +10,7 @@ use crate::{
     memory::{Address, Virtual},
 };
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
     registers::ReadWrite,
 };
@@ -24,6 +24,9 @@ register_bitfields! {
 
     /// CPU Interface Control Register
     CTLR [
+        FIQEn OFFSET(3) NUMBITS(1) [],
+        AckCtl OFFSET(2) NUMBITS(1) [],
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
         Enable OFFSET(0) NUMBITS(1) []
     ],
 
@@ -32,13 +35,20 @@ register_bitfields! {
         Priority OFFSET(0) NUMBITS(8) []
     ],
 
+    /// Binary Point Register
+    BPR [
+        BinaryPoint OFFSET(0) NUMBITS(3) []
+    ],
+
     /// Interrupt Acknowledge Register
     IAR [
+        CPUID OFFSET(10) NUMBITS(3) [],
         InterruptID OFFSET(0) NUMBITS(10) []
     ],
 
     /// End of Interrupt Register
     EOIR [
+        CPUID OFFSET(10) NUMBITS(3) [],
         EOIINTID OFFSET(0) NUMBITS(10) []
     ]
 }
@@ -48,10 +58,13 @@ register_structs! {
     pub RegisterBlock {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => PMR: ReadWrite<u32, PMR::Register>),
-        (0x008 => _reserved1),
+        (0x008 => BPR: ReadWrite<u32, BPR::Register>),
         (0x00C => IAR: ReadWrite<u32, IAR::Register>),
         (0x010 => EOIR: ReadWrite<u32, EOIR::Register>),
-        (0x014  => @END),
+        (0x014 => _reserved1),
+        (0x020 => AIAR: ReadWrite<u32, IAR::Register>),
+        (0x024 => AEOIR: ReadWrite<u32, EOIR::Register>),
+        (0x028  => @END),
     }
 }
 
@@ -98,6 +111,27 @@ impl GICC {
         self.registers.PMR.write(PMR::Priority.val(255)); // Comment in arch spec.
     }
 
+    /// Configure the preemption behavior.
+    ///
+    /// The binary point splits the 8 bit priority field into a group priority and a subpriority.
+    /// Only the group priority is considered for preemption. Quoting the GICv2 Architecture
+    /// Specification:
+    ///
+    ///   "The GIC uses the group priority field to determine whether a pending interrupt has
+    ///    sufficient priority to preempt an active interrupt."
+    ///
+    /// A value of 3 makes bits [7:4] part of the group priority regardless of the interrupt group,
+    /// which is where the distributor stores the priority levels. Hence, every priority level is
+    /// able to preempt all levels below it.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn enable_preemption(&self) {
+        self.registers.BPR.write(BPR::BinaryPoint.val(3));
+    }
+
     /// Enable the interface - start accepting IRQs.
     ///
     /// # Safety
@@ -108,8 +142,30 @@ impl GICC {
         self.registers.CTLR.write(CTLR::Enable::SET);
     }
 
+    /// Signal Group 0 interrupts as FIQ and Group 1 interrupts as IRQ.
+    ///
+    /// `AckCtl` stays clear, so that the IAR only acknowledges Group 0 interrupts in the Secure
+    /// state. Otherwise, the IRQ path could acknowledge the FIQ source. Group 1 interrupts must be
+    /// acknowledged through the aliased registers instead.
+    ///
+    /// # Safety
+    ///
+    /// - GICC MMIO registers are banked per CPU core. It is therefore safe to have `&self` instead
+    ///   of `&mut self`.
+    pub fn enable_fiq(&self) {
+        self.registers
+            .CTLR
+            .modify(CTLR::EnableGrp1::SET + CTLR::AckCtl::CLEAR + CTLR::FIQEn::SET);
+    }
+
     /// Extract the number of the highest-priority pending IRQ.
     ///
+    /// Also returns the id of the CPU interface that requested the IRQ, which is only valid for
+    /// SGIs and must be passed to `mark_comleted()`.
+    ///
+    /// If `group1` is set, the Group 1 interrupt is acknowledged through the aliased IAR. This is
+    /// needed in the Secure state once FIQs are enabled, see `enable_fiq()`.
+    ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
     ///
     /// # Safety
@@ -119,16 +175,23 @@ impl GICC {
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub fn pending_irq_number<'irq_context>(
         &self,
+        group1: bool,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
-    ) -> usize {
-        self.registers.IAR.read(IAR::InterruptID) as usize
+    ) -> (usize, u32) {
+        let iar = if group1 {
+            self.registers.AIAR.extract()
+        } else {
+            self.registers.IAR.extract()
+        };
+
+        (iar.read(IAR::InterruptID) as usize, iar.read(IAR::CPUID))
     }
 
     /// Complete handling of the currently active IRQ.
     ///
     /// Can only be called from IRQ context, which is ensured by taking an `IRQContext` token.
     ///
-    /// To be called after `pending_irq_number()`.
+    /// To be called after `pending_irq_number()`, with the same `group1`.
     ///
     /// # Safety
     ///
@@ -138,8 +201,16 @@ impl GICC {
     pub fn mark_comleted<'irq_context>(
         &self,
         irq_number: u32,
+        source_cpu: u32,
+        group1: bool,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        self.registers.EOIR.write(EOIR::EOIINTID.val(irq_number));
+        let eoir = EOIR::CPUID.val(source_cpu) + EOIR::EOIINTID.val(irq_number);
+
+        if group1 {
+            self.registers.AEOIR.write(eoir);
+        } else {
+            self.registers.EOIR.write(eoir);
+        }
     }
 }
@@ -9,14 +9,15 @@
 
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
+    exception::asynchronous::IRQPriority,
     memory::{Address, Virtual},
     state, synchronization,
     synchronization::IRQSafeNullLock,
 };
 use tock_registers::{
-    interfaces::{Readable, Writeable},
+    interfaces::{ReadWriteable, Readable, Writeable},
     register_bitfields, register_structs,
-    registers::{ReadOnly, ReadWrite},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -28,6 +29,7 @@ register_bitfields! {
 
     /// Distributor Control Register
     CTLR [
+        EnableGrp1 OFFSET(1) NUMBITS(1) [],
         Enable OFFSET(0) NUMBITS(1) []
     ],
 
@@ -42,6 +44,18 @@ register_bitfields! {
         Offset2 OFFSET(16) NUMBITS(8) [],
         Offset1 OFFSET(8)  NUMBITS(8) [],
         Offset0 OFFSET(0)  NUMBITS(8) []
+    ],
+
+    /// Software Generated Interrupt Register
+    SGIR [
+        TargetListFilter OFFSET(24) NUMBITS(2) [
+            TargetList = 0b00,
+            AllOthers = 0b01,
+            Myself = 0b10
+        ],
+        CPUTargetList OFFSET(16) NUMBITS(8) [],
+        NSATT OFFSET(15) NUMBITS(1) [],
+        SGIINTID OFFSET(0) NUMBITS(4) []
     ]
 }
 
@@ -51,10 +65,18 @@ register_structs! {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
         (0x008 => _reserved1),
+        (0x084 => IGROUPR: [ReadWrite<u32>; 31]),
+        (0x100 => _reserved2),
         (0x104 => ISENABLER: [ReadWrite<u32>; 31]),
-        (0x180 => _reserved2),
+        (0x180 => _reserved3),
+        (0x184 => ICENABLER: [ReadWrite<u32>; 31]),
+        (0x200 => _reserved4),
+        (0x420 => IPRIORITYR: [ReadWrite<u32>; 247]),
+        (0x7FC => _reserved5),
         (0x820 => ITARGETSR: [ReadWrite<u32, ITARGETSR::Register>; 248]),
-        (0xC00 => @END),
+        (0xC00 => _reserved6),
+        (0xF00 => SGIR: WriteOnly<u32, SGIR::Register>),
+        (0xF04 => @END),
     }
 }
 
@@ -62,8 +84,14 @@ register_structs! {
     #[allow(non_snake_case)]
     BankedRegisterBlock {
         (0x000 => _reserved1),
+        (0x080 => IGROUPR: ReadWrite<u32>),
+        (0x084 => _reserved2),
         (0x100 => ISENABLER: ReadWrite<u32>),
-        (0x104 => _reserved2),
+        (0x104 => _reserved3),
+        (0x180 => ICENABLER: ReadWrite<u32>),
+        (0x184 => _reserved4),
+        (0x400 => IPRIORITYR: [ReadWrite<u32>; 8]),
+        (0x420 => _reserved5),
         (0x800 => ITARGETSR: [ReadOnly<u32, ITARGETSR::Register>; 8]),
         (0x820 => @END),
     }
@@ -117,6 +145,16 @@ impl SharedRegisters {
         // Rust automatically inserts slice range sanity check, i.e. max >= min.
         &self.ITARGETSR[0..spi_itargetsr_max_index]
     }
+
+    /// Return a slice of the implemented IGROUPR.
+    #[inline(always)]
+    fn implemented_igroupr_slice(&mut self) -> &[ReadWrite<u32>] {
+        // Each IGROUPR covers 32 IRQs. The first register is banked, so not included in
+        // `shared_registers`.
+        let spi_igroupr_len = (self.num_irqs() >> 5) - 1;
+
+        &self.IGROUPR[0..spi_igroupr_len]
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -171,6 +209,75 @@ impl GICD {
         });
     }
 
+    /// Assign an interrupt to Group 0 and all other interrupts to Group 1.
+    ///
+    /// With FIQs enabled in the CPU interface, Group 0 interrupts are signaled as FIQ. Interrupt
+    /// groups can only be configured from the Secure state, though. Non-secure accesses to the
+    /// group registers are RAZ/WI, which is detected by reading back the written value.
+    pub fn make_sole_group0(&self, irq_num: &super::IRQNumber) -> Result<(), &'static str> {
+        let irq_num = irq_num.get();
+        let group_reg_index = irq_num >> 5;
+        let group0_bit: u32 = 1u32 << (irq_num % 32);
+
+        let group_val = |reg_index: usize| {
+            if reg_index == group_reg_index {
+                !group0_bit
+            } else {
+                u32::MAX
+            }
+        };
+
+        // Private.
+        let banked = &self.banked_registers.IGROUPR;
+        banked.set(group_val(0));
+        if banked.get() != group_val(0) {
+            return Err("Interrupt groups not accessible. Not running in Secure state?");
+        }
+
+        // Shared.
+        self.shared_registers.lock(|regs| {
+            for (i, reg) in regs.implemented_igroupr_slice().iter().enumerate() {
+                reg.set(group_val(i + 1));
+            }
+
+            regs.CTLR.modify(CTLR::EnableGrp1::SET);
+        });
+
+        Ok(())
+    }
+
+    /// Set the priority of an interrupt.
+    ///
+    /// Each IPRIORITYR holds four 8 bit priority fields. The priority level is stored in the upper
+    /// four bits, which the GIC-400 implements at the very least.
+    pub fn set_priority(&self, irq_num: &super::IRQNumber, priority: IRQPriority) {
+        let irq_num = irq_num.get();
+
+        // Shift right by 2 (division by 4) and arrive at the index for the respective
+        // IPRIORITYR[i].
+        let priority_reg_index = irq_num >> 2;
+        let priority_shift = (irq_num % 4) * 8;
+        let priority_mask: u32 = 0xff << priority_shift;
+        let priority_val: u32 = ((priority.level() as u32) << 4) << priority_shift;
+
+        let update = |reg: &ReadWrite<u32>| {
+            reg.set((reg.get() & !priority_mask) | priority_val);
+        };
+
+        // Check if we are handling a private or shared IRQ.
+        match irq_num {
+            // Private.
+            0..=31 => update(&self.banked_registers.IPRIORITYR[priority_reg_index]),
+            // Shared.
+            _ => {
+                let priority_reg_index_shared = priority_reg_index - 8;
+
+                self.shared_registers
+                    .lock(|regs| update(&regs.IPRIORITYR[priority_reg_index_shared]));
+            }
+        }
+    }
+
     /// Enable an interrupt.
     pub fn enable(&self, irq_num: &super::IRQNumber) {
         let irq_num = irq_num.get();
@@ -198,4 +305,41 @@ impl GICD {
             }
         }
     }
+
+    /// Disable an interrupt.
+    pub fn disable(&self, irq_num: &super::IRQNumber) {
+        let irq_num = irq_num.get();
+
+        // Same layout as the enable registers.
+        let disable_reg_index = irq_num >> 5;
+        let disable_bit: u32 = 1u32 << (irq_num % 32);
+
+        // Writing a 1 to a bit disables the corresponding IRQ. All other bits are unaffected.
+        match irq_num {
+            // Private.
+            0..=31 => self.banked_registers.ICENABLER.set(disable_bit),
+            // Shared.
+            _ => {
+                let disable_reg_index_shared = disable_reg_index - 1;
+
+                self.shared_registers
+                    .lock(|regs| regs.ICENABLER[disable_reg_index_shared].set(disable_bit));
+            }
+        }
+    }
+
+    /// Send a software generated interrupt to the CPU interfaces in `target_list`.
+    ///
+    /// `group1` must be set if SGIs were assigned to Group 1. It is ignored in the Non-secure
+    /// state.
+    pub fn send_sgi(&self, sgi_num: &super::IRQNumber, target_list: u32, group1: bool) {
+        self.shared_registers.lock(|regs| {
+            regs.SGIR.write(
+                SGIR::TargetListFilter::TargetList
+                    + SGIR::CPUTargetList.val(target_list)
+                    + SGIR::NSATT.val(group1 as u32)
+                    + SGIR::SGIINTID.val(sgi_num.get() as u32),
+            )
+        });
+    }
 }
@@ -4,12 +4,18 @@
 
 //! BCM driver top level.
 
+mod bcm2xxx_emmc;
+mod bcm2xxx_framebuffer;
 mod bcm2xxx_gpio;
-#[cfg(feature = "bsp_rpi3")]
 mod bcm2xxx_interrupt_controller;
+mod bcm2xxx_mailbox;
+mod bcm2xxx_mini_uart;
 mod bcm2xxx_pl011_uart;
 
+pub use bcm2xxx_emmc::*;
+pub use bcm2xxx_framebuffer::*;
 pub use bcm2xxx_gpio::*;
-#[cfg(feature = "bsp_rpi3")]
-pub use bcm2xxx_interrupt_controller::*;
+pub use bcm2xxx_interrupt_controller::{IRQNumber as BcmIRQNumber, InterruptController};
+pub use bcm2xxx_mailbox::*;
+pub use bcm2xxx_mini_uart::*;
 pub use bcm2xxx_pl011_uart::*;
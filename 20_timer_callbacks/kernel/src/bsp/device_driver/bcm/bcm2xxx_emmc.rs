@@ -0,0 +1,841 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! EMMC driver for SD cards.
+//!
+//! The EMMC controller of the BCM2837 (Arasan) and the EMMC2 controller of the BCM2711 both follow
+//! the SD Host Controller specification. Cards are identified and switched to 4-bit, high speed
+//! operation if they support it. Data is transferred by the CPU through the data port.
+//!
+//! Transfers are polled and can span many blocks, so IRQs stay unmasked while they are running.
+//!
+//! # Resources
+//!
+//! - BCM2835 ARM Peripherals, chapter 5
+//! - SD Specifications Part 1, Physical Layer Simplified Specification
+//! - SD Specifications Part A2, SD Host Controller Simplified Specification
+
+use crate::{
+    block,
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, driver,
+    exception::asynchronous::IRQNumber,
+    memory::{Address, Virtual},
+    synchronization,
+    synchronization::NullLock,
+    time,
+};
+use core::time::Duration;
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Block Size and Count.
+    BLKSIZECNT [
+        /// Number of blocks to transfer.
+        BLKCNT OFFSET(16) NUMBITS(16) [],
+
+        /// Block size in bytes.
+        BLKSIZE OFFSET(0) NUMBITS(10) []
+    ],
+
+    /// Command and Transfer Mode.
+    CMDTM [
+        /// Index of the command to issue.
+        CMD_INDEX OFFSET(24) NUMBITS(6) [],
+
+        /// The command transfers data.
+        CMD_ISDATA OFFSET(21) NUMBITS(1) [],
+
+        /// Check that the response has the same index as the command.
+        CMD_IXCHK_EN OFFSET(20) NUMBITS(1) [],
+
+        /// Check the CRC of the response.
+        CMD_CRCCHK_EN OFFSET(19) NUMBITS(1) [],
+
+        /// Type of the expected response.
+        CMD_RSPNS_TYPE OFFSET(16) NUMBITS(2) [
+            None = 0b00,
+            Bits136 = 0b01,
+            Bits48 = 0b10,
+            Bits48Busy = 0b11
+        ],
+
+        /// Transfer more than one block.
+        TM_MULTI_BLOCK OFFSET(5) NUMBITS(1) [],
+
+        /// Direction of the data transfer.
+        TM_DAT_DIR OFFSET(4) NUMBITS(1) [
+            HostToCard = 0,
+            CardToHost = 1
+        ],
+
+        /// Command to send after completion of the data transfer.
+        TM_AUTO_CMD_EN OFFSET(2) NUMBITS(2) [
+            None = 0b00,
+            Cmd12 = 0b01
+        ],
+
+        /// Count the blocks with BLKSIZECNT.BLKCNT.
+        TM_BLKCNT_EN OFFSET(1) NUMBITS(1) []
+    ],
+
+    /// Status.
+    STATUS [
+        /// The data lines are in use.
+        DAT_INHIBIT OFFSET(1) NUMBITS(1) [],
+
+        /// The command line is in use.
+        CMD_INHIBIT OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Host Configuration bits.
+    CONTROL0 [
+        /// Voltage of the SD bus.
+        SD_BUS_VOLTAGE OFFSET(9) NUMBITS(3) [
+            V3_3 = 0b111
+        ],
+
+        /// Power the SD bus.
+        SD_BUS_POWER OFFSET(8) NUMBITS(1) [],
+
+        /// Use high speed timing.
+        HCTL_HS_EN OFFSET(2) NUMBITS(1) [],
+
+        /// Use 4 data lines.
+        HCTL_DWIDTH OFFSET(1) NUMBITS(1) []
+    ],
+
+    /// Host Configuration bits.
+    CONTROL1 [
+        /// Reset the data handling circuit.
+        SRST_DATA OFFSET(26) NUMBITS(1) [],
+
+        /// Reset the command handling circuit.
+        SRST_CMD OFFSET(25) NUMBITS(1) [],
+
+        /// Reset the complete host circuit.
+        SRST_HC OFFSET(24) NUMBITS(1) [],
+
+        /// Data timeout exponent.
+        DATA_TOUNIT OFFSET(16) NUMBITS(4) [
+            Max = 0b1110
+        ],
+
+        /// Lower 8 bits of the SD clock divisor.
+        CLK_FREQ8 OFFSET(8) NUMBITS(8) [],
+
+        /// Upper 2 bits of the SD clock divisor.
+        CLK_FREQ_MS2 OFFSET(6) NUMBITS(2) [],
+
+        /// Enable the SD clock.
+        CLK_EN OFFSET(2) NUMBITS(1) [],
+
+        /// The internal clock is stable.
+        CLK_STABLE OFFSET(1) NUMBITS(1) [],
+
+        /// Enable the internal clock.
+        CLK_INTLEN OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Flags. Writing 1 clears a flag.
+    INTERRUPT [
+        /// Data end bit error.
+        DEND_ERR OFFSET(22) NUMBITS(1) [],
+
+        /// Data CRC error.
+        DCRC_ERR OFFSET(21) NUMBITS(1) [],
+
+        /// Data timeout.
+        DTO_ERR OFFSET(20) NUMBITS(1) [],
+
+        /// Command index error.
+        CBAD_ERR OFFSET(19) NUMBITS(1) [],
+
+        /// Command end bit error.
+        CEND_ERR OFFSET(18) NUMBITS(1) [],
+
+        /// Command CRC error.
+        CCRC_ERR OFFSET(17) NUMBITS(1) [],
+
+        /// Command timeout.
+        CTO_ERR OFFSET(16) NUMBITS(1) [],
+
+        /// Any error.
+        ERR OFFSET(15) NUMBITS(1) [],
+
+        /// The data port can be read.
+        READ_RDY OFFSET(5) NUMBITS(1) [],
+
+        /// The data port can be written.
+        WRITE_RDY OFFSET(4) NUMBITS(1) [],
+
+        /// The data transfer completed.
+        DATA_DONE OFFSET(1) NUMBITS(1) [],
+
+        /// The command completed.
+        CMD_DONE OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => BLKSIZECNT: ReadWrite<u32, BLKSIZECNT::Register>),
+        (0x08 => ARG1: ReadWrite<u32>),
+        (0x0C => CMDTM: ReadWrite<u32, CMDTM::Register>),
+        (0x10 => RESP: [ReadOnly<u32>; 4]),
+        (0x20 => DATA: ReadWrite<u32>),
+        (0x24 => STATUS: ReadOnly<u32, STATUS::Register>),
+        (0x28 => CONTROL0: ReadWrite<u32, CONTROL0::Register>),
+        (0x2C => CONTROL1: ReadWrite<u32, CONTROL1::Register>),
+        (0x30 => INTERRUPT: ReadWrite<u32, INTERRUPT::Register>),
+        (0x34 => IRPT_MASK: ReadWrite<u32>),
+        (0x38 => IRPT_EN: ReadWrite<u32>),
+        (0x3C => CONTROL2: ReadWrite<u32>),
+        (0x40 => _reserved2),
+        (0x100 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// Size of a block in bytes. Fixed for high capacity cards, and configured for all others.
+const BLOCK_SIZE: usize = 512;
+
+/// Maximum number of blocks of a single transfer.
+const MAX_BLOCKS_PER_TRANSFER: usize = u16::MAX as usize;
+
+/// SD clock during card identification.
+const IDENTIFICATION_CLOCK_HZ: u32 = 400_000;
+
+/// SD clock in default speed mode.
+const DEFAULT_SPEED_CLOCK_HZ: u32 = 25_000_000;
+
+/// SD clock in high speed mode.
+const HIGH_SPEED_CLOCK_HZ: u32 = 50_000_000;
+
+/// How long to wait for the controller and for commands.
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long the card may take to power up.
+const POWER_UP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Returned by commands that the card did not respond to.
+const ERR_COMMAND_TIMEOUT: &str = "SD command timeout";
+
+/// Check pattern of SEND_IF_COND, with the 2.7-3.6 V range.
+const IF_COND_CHECK: u32 = 0x1AA;
+
+/// OCR bits of SD_SEND_OP_COND.
+const OCR_BUSY: u32 = 1 << 31;
+const OCR_HCS: u32 = 1 << 30;
+const OCR_VOLTAGE_WINDOW: u32 = 0x00FF_8000;
+
+/// Arguments of SWITCH_FUNC that check for, or switch to, high speed in function group 1.
+const SWITCH_FUNC_CHECK_HIGH_SPEED: u32 = 0x00FF_FFF1;
+const SWITCH_FUNC_SET_HIGH_SPEED: u32 = 0x80FF_FFF1;
+
+/// Argument of SET_BUS_WIDTH for 4 data lines.
+const BUS_WIDTH_4: u32 = 0b10;
+
+/// Response types of commands.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Response {
+    None,
+    R1,
+    R1b,
+    R2,
+    R3,
+    R6,
+    R7,
+}
+
+/// An SD command.
+#[derive(Copy, Clone)]
+struct Command {
+    index: u32,
+    response: Response,
+
+    /// An application specific command, which must be preceded by APP_CMD.
+    app: bool,
+}
+
+/// Direction of a data transfer.
+enum Transfer<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+/// The identified card.
+#[derive(Copy, Clone)]
+struct Card {
+    /// Relative card address.
+    rca: u32,
+
+    /// SDHC/SDXC cards are addressed in blocks, SDSC cards in bytes.
+    high_capacity: bool,
+
+    num_blocks: u64,
+}
+
+struct EmmcInner {
+    registers: Registers,
+    base_clock_hz: u32,
+    card: Option<Card>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the EMMC controller.
+pub struct Emmc {
+    inner: NullLock<EmmcInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Command {
+    const fn new(index: u32, response: Response) -> Self {
+        Self {
+            index,
+            response,
+            app: false,
+        }
+    }
+
+    const fn app(index: u32, response: Response) -> Self {
+        Self {
+            index,
+            response,
+            app: true,
+        }
+    }
+
+    const GO_IDLE_STATE: Self = Self::new(0, Response::None);
+    const ALL_SEND_CID: Self = Self::new(2, Response::R2);
+    const SEND_RELATIVE_ADDR: Self = Self::new(3, Response::R6);
+    const SWITCH_FUNC: Self = Self::new(6, Response::R1);
+    const SELECT_CARD: Self = Self::new(7, Response::R1b);
+    const SEND_IF_COND: Self = Self::new(8, Response::R7);
+    const SEND_CSD: Self = Self::new(9, Response::R2);
+    const SET_BLOCKLEN: Self = Self::new(16, Response::R1);
+    const READ_SINGLE_BLOCK: Self = Self::new(17, Response::R1);
+    const READ_MULTIPLE_BLOCK: Self = Self::new(18, Response::R1);
+    const WRITE_BLOCK: Self = Self::new(24, Response::R1);
+    const WRITE_MULTIPLE_BLOCK: Self = Self::new(25, Response::R1);
+    const APP_CMD: Self = Self::new(55, Response::R1);
+
+    const SET_BUS_WIDTH: Self = Self::app(6, Response::R1);
+    const SD_SEND_OP_COND: Self = Self::app(41, Response::R3);
+    const SEND_SCR: Self = Self::app(51, Response::R1);
+
+    fn cmdtm(&self) -> tock_registers::fields::FieldValue<u32, CMDTM::Register> {
+        let response = match self.response {
+            Response::None => CMDTM::CMD_RSPNS_TYPE::None,
+            Response::R2 => CMDTM::CMD_RSPNS_TYPE::Bits136 + CMDTM::CMD_CRCCHK_EN::SET,
+            // R3 has neither index nor CRC.
+            Response::R3 => CMDTM::CMD_RSPNS_TYPE::Bits48,
+            Response::R1 | Response::R6 | Response::R7 => {
+                CMDTM::CMD_RSPNS_TYPE::Bits48 + CMDTM::CMD_CRCCHK_EN::SET + CMDTM::CMD_IXCHK_EN::SET
+            }
+            Response::R1b => {
+                CMDTM::CMD_RSPNS_TYPE::Bits48Busy
+                    + CMDTM::CMD_CRCCHK_EN::SET
+                    + CMDTM::CMD_IXCHK_EN::SET
+            }
+        };
+
+        CMDTM::CMD_INDEX.val(self.index) + response
+    }
+}
+
+/// Return bits `high..=low` of a CSD that was received as an R2 response.
+fn csd_bits(response: &[u32; 4], high: usize, low: usize) -> u64 {
+    let csd = response
+        .iter()
+        .rev()
+        .fold(0_u128, |csd, word| (csd << 32) | *word as u128);
+
+    // The controller strips the CRC, so the CSD is shifted by 8 bits.
+    ((csd >> (low - 8)) & ((1 << (high - low + 1)) - 1)) as u64
+}
+
+/// Return the number of 512 byte blocks of a card, decoded from its CSD.
+fn csd_num_blocks(csd: &[u32; 4]) -> Result<u64, &'static str> {
+    match csd_bits(csd, 127, 126) {
+        // Standard capacity.
+        0 => {
+            let c_size = csd_bits(csd, 73, 62);
+            let c_size_mult = csd_bits(csd, 49, 47);
+            let read_bl_len = csd_bits(csd, 83, 80);
+
+            Ok((c_size + 1) << (c_size_mult + 2 + read_bl_len - 9))
+        }
+        // High and extended capacity, in units of 512 KiB.
+        1 => Ok((csd_bits(csd, 69, 48) + 1) << 10),
+        _ => Err("Unsupported CSD structure"),
+    }
+}
+
+impl EmmcInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, base_clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            base_clock_hz,
+            card: None,
+        }
+    }
+
+    /// Spin until `condition` is met, or fail after `timeout`.
+    fn wait_for(
+        &self,
+        timeout: Duration,
+        condition: impl Fn(&Self) -> bool,
+    ) -> Result<(), &'static str> {
+        let deadline = time::time_manager().uptime() + timeout;
+
+        while !condition(self) {
+            if time::time_manager().uptime() > deadline {
+                return Err("EMMC timeout");
+            }
+            cpu::nop();
+        }
+
+        Ok(())
+    }
+
+    /// Wait for any of the interrupt `flags` and clear it, or fail on errors.
+    fn wait_for_interrupt(
+        &self,
+        flags: tock_registers::fields::FieldValue<u32, INTERRUPT::Register>,
+    ) -> Result<(), &'static str> {
+        let mask = flags.mask() | INTERRUPT::ERR::SET.mask();
+        self.wait_for(TIMEOUT, |s| s.registers.INTERRUPT.get() & mask != 0)?;
+
+        let interrupt = self.registers.INTERRUPT.extract();
+        if !interrupt.is_set(INTERRUPT::ERR) {
+            self.registers.INTERRUPT.write(flags);
+            return Ok(());
+        }
+
+        // Clear all flags and bring the command and data circuits back to a defined state.
+        self.registers.INTERRUPT.set(u32::MAX);
+        self.registers
+            .CONTROL1
+            .modify(CONTROL1::SRST_CMD::SET + CONTROL1::SRST_DATA::SET);
+        self.wait_for(TIMEOUT, |s| {
+            !s.registers
+                .CONTROL1
+                .matches_any(CONTROL1::SRST_CMD::SET + CONTROL1::SRST_DATA::SET)
+        })?;
+
+        Err(if interrupt.is_set(INTERRUPT::CTO_ERR) {
+            ERR_COMMAND_TIMEOUT
+        } else if interrupt.is_set(INTERRUPT::DTO_ERR) {
+            "SD data timeout"
+        } else if interrupt.matches_any(INTERRUPT::CCRC_ERR::SET + INTERRUPT::DCRC_ERR::SET) {
+            "SD CRC error"
+        } else if interrupt.matches_any(
+            INTERRUPT::CBAD_ERR::SET + INTERRUPT::CEND_ERR::SET + INTERRUPT::DEND_ERR::SET,
+        ) {
+            "SD transmission error"
+        } else {
+            "EMMC error"
+        })
+    }
+
+    /// Issue `command` without waiting for its completion.
+    fn start_command(
+        &self,
+        command: Command,
+        arg: u32,
+        mode: tock_registers::fields::FieldValue<u32, CMDTM::Register>,
+    ) -> Result<(), &'static str> {
+        self.wait_for(TIMEOUT, |s| {
+            !s.registers.STATUS.matches_any(STATUS::CMD_INHIBIT::SET)
+        })?;
+
+        self.registers.ARG1.set(arg);
+        self.registers.CMDTM.write(command.cmdtm() + mode);
+
+        self.wait_for_interrupt(INTERRUPT::CMD_DONE::SET)
+    }
+
+    /// Issue `command` and return its response.
+    fn command(&self, command: Command, arg: u32) -> Result<[u32; 4], &'static str> {
+        if command.app {
+            let rca = self.card.map_or(0, |card| card.rca);
+            self.command(Command::APP_CMD, rca << 16)?;
+        }
+
+        self.start_command(command, arg, CMDTM::CMD_ISDATA::CLEAR)?;
+
+        // Wait until the card is not busy anymore.
+        if command.response == Response::R1b {
+            self.wait_for_interrupt(INTERRUPT::DATA_DONE::SET)?;
+        }
+
+        Ok(core::array::from_fn(|i| self.registers.RESP[i].get()))
+    }
+
+    /// Issue `command`, which transfers `transfer.len() / block_size` blocks of data.
+    fn data_command(
+        &self,
+        command: Command,
+        arg: u32,
+        block_size: usize,
+        transfer: Transfer,
+    ) -> Result<(), &'static str> {
+        if command.app {
+            let rca = self.card.map_or(0, |card| card.rca);
+            self.command(Command::APP_CMD, rca << 16)?;
+        }
+
+        let (len, direction, ready) = match &transfer {
+            Transfer::Read(buffer) => (
+                buffer.len(),
+                CMDTM::TM_DAT_DIR::CardToHost,
+                INTERRUPT::READ_RDY::SET,
+            ),
+            Transfer::Write(buffer) => (
+                buffer.len(),
+                CMDTM::TM_DAT_DIR::HostToCard,
+                INTERRUPT::WRITE_RDY::SET,
+            ),
+        };
+        let num_blocks = len / block_size;
+
+        let mut mode = CMDTM::CMD_ISDATA::SET + direction;
+        if num_blocks > 1 {
+            mode += CMDTM::TM_MULTI_BLOCK::SET
+                + CMDTM::TM_BLKCNT_EN::SET
+                + CMDTM::TM_AUTO_CMD_EN::Cmd12;
+        }
+
+        self.wait_for(TIMEOUT, |s| {
+            !s.registers.STATUS.matches_any(STATUS::DAT_INHIBIT::SET)
+        })?;
+        self.registers.BLKSIZECNT.write(
+            BLKSIZECNT::BLKSIZE.val(block_size as u32) + BLKSIZECNT::BLKCNT.val(num_blocks as u32),
+        );
+        self.start_command(command, arg, mode)?;
+
+        // The data port is 32 bits wide, and signals readiness per block.
+        match transfer {
+            Transfer::Read(buffer) => {
+                for block in buffer.chunks_exact_mut(block_size) {
+                    self.wait_for_interrupt(ready)?;
+
+                    for word in block.chunks_exact_mut(4) {
+                        word.copy_from_slice(&self.registers.DATA.get().to_le_bytes());
+                    }
+                }
+            }
+            Transfer::Write(buffer) => {
+                for block in buffer.chunks_exact(block_size) {
+                    self.wait_for_interrupt(ready)?;
+
+                    for word in block.chunks_exact(4) {
+                        self.registers
+                            .DATA
+                            .set(u32::from_le_bytes(word.try_into().unwrap()));
+                    }
+                }
+            }
+        }
+
+        self.wait_for_interrupt(INTERRUPT::DATA_DONE::SET)
+    }
+
+    /// Set the SD clock to at most `clock_hz`.
+    fn set_clock(&self, clock_hz: u32) -> Result<(), &'static str> {
+        // The SD clock is the base clock divided by twice the 10-bit divisor, or undivided for 0.
+        let divisor = if clock_hz >= self.base_clock_hz {
+            0
+        } else {
+            self.base_clock_hz.div_ceil(2 * clock_hz).min(0x3FF)
+        };
+
+        self.wait_for(TIMEOUT, |s| {
+            !s.registers
+                .STATUS
+                .matches_any(STATUS::CMD_INHIBIT::SET + STATUS::DAT_INHIBIT::SET)
+        })?;
+
+        self.registers.CONTROL1.modify(CONTROL1::CLK_EN::CLEAR);
+        self.registers.CONTROL1.modify(
+            CONTROL1::CLK_FREQ8.val(divisor & 0xFF)
+                + CONTROL1::CLK_FREQ_MS2.val(divisor >> 8)
+                + CONTROL1::DATA_TOUNIT::Max
+                + CONTROL1::CLK_INTLEN::SET,
+        );
+        self.wait_for(TIMEOUT, |s| {
+            s.registers.CONTROL1.is_set(CONTROL1::CLK_STABLE)
+        })?;
+        self.registers.CONTROL1.modify(CONTROL1::CLK_EN::SET);
+
+        Ok(())
+    }
+
+    /// Reset the controller and power the SD bus.
+    fn reset(&mut self) -> Result<(), &'static str> {
+        self.card = None;
+
+        self.registers.CONTROL1.write(CONTROL1::SRST_HC::SET);
+        self.wait_for(TIMEOUT, |s| !s.registers.CONTROL1.is_set(CONTROL1::SRST_HC))?;
+
+        self.registers
+            .CONTROL0
+            .write(CONTROL0::SD_BUS_VOLTAGE::V3_3 + CONTROL0::SD_BUS_POWER::SET);
+        self.registers.CONTROL2.set(0);
+
+        // Flags are polled, so enable all of them, but do not signal any as an IRQ.
+        self.registers.IRPT_EN.set(0);
+        self.registers.IRPT_MASK.set(u32::MAX);
+        self.registers.INTERRUPT.set(u32::MAX);
+
+        self.set_clock(IDENTIFICATION_CLOCK_HZ)
+    }
+
+    /// Wait until the card finished powering up. Returns whether it is a high capacity card.
+    fn power_up_card(&self, supports_v2: bool) -> Result<bool, &'static str> {
+        let arg = OCR_VOLTAGE_WINDOW | if supports_v2 { OCR_HCS } else { 0 };
+        let deadline = time::time_manager().uptime() + POWER_UP_TIMEOUT;
+
+        loop {
+            let ocr = self.command(Command::SD_SEND_OP_COND, arg)?[0];
+            if ocr & OCR_BUSY != 0 {
+                return Ok(ocr & OCR_HCS != 0);
+            }
+
+            if time::time_manager().uptime() > deadline {
+                return Err("SD card did not power up");
+            }
+            time::time_manager().spin_for(Duration::from_millis(10));
+        }
+    }
+
+    /// Switch to high speed timing if the card supports it. Returns whether it switched.
+    fn switch_to_high_speed(&self) -> Result<bool, &'static str> {
+        // The 512 bit switch function status is sent most significant byte first.
+        const FUNCTION_GROUP_1_SUPPORT: usize = 13;
+        const FUNCTION_GROUP_1_SELECTION: usize = 16;
+        const HIGH_SPEED: u8 = 1;
+
+        let mut status = [0; 64];
+        self.data_command(
+            Command::SWITCH_FUNC,
+            SWITCH_FUNC_CHECK_HIGH_SPEED,
+            status.len(),
+            Transfer::Read(&mut status),
+        )?;
+        if status[FUNCTION_GROUP_1_SUPPORT] & (1 << HIGH_SPEED) == 0 {
+            return Ok(false);
+        }
+
+        self.data_command(
+            Command::SWITCH_FUNC,
+            SWITCH_FUNC_SET_HIGH_SPEED,
+            status.len(),
+            Transfer::Read(&mut status),
+        )?;
+
+        Ok(status[FUNCTION_GROUP_1_SELECTION] & 0xF == HIGH_SPEED)
+    }
+
+    /// Identify the card and prepare it for data transfers.
+    fn init_card(&mut self) -> Result<(), &'static str> {
+        self.reset()?;
+
+        self.command(Command::GO_IDLE_STATE, 0)?;
+
+        // Cards before version 2.00 do not know SEND_IF_COND.
+        let supports_v2 = match self.command(Command::SEND_IF_COND, IF_COND_CHECK) {
+            Ok(response) if response[0] & 0xFFF == IF_COND_CHECK => true,
+            Ok(_) => return Err("SD card does not support 3.3 V"),
+            Err(ERR_COMMAND_TIMEOUT) => false,
+            Err(x) => return Err(x),
+        };
+
+        let high_capacity = self.power_up_card(supports_v2)?;
+
+        self.command(Command::ALL_SEND_CID, 0)?;
+        let rca = self.command(Command::SEND_RELATIVE_ADDR, 0)?[0] >> 16;
+        let num_blocks = csd_num_blocks(&self.command(Command::SEND_CSD, rca << 16)?)?;
+        self.command(Command::SELECT_CARD, rca << 16)?;
+
+        self.card = Some(Card {
+            rca,
+            high_capacity,
+            num_blocks,
+        });
+
+        if !high_capacity {
+            self.command(Command::SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        // The SD configuration register is sent most significant byte first.
+        let mut scr = [0; 8];
+        self.data_command(Command::SEND_SCR, 0, scr.len(), Transfer::Read(&mut scr))?;
+        let sd_spec = scr[0] & 0xF;
+        let supports_4_bit = scr[1] & 0b0100 != 0;
+
+        if supports_4_bit {
+            self.command(Command::SET_BUS_WIDTH, BUS_WIDTH_4)?;
+            self.registers.CONTROL0.modify(CONTROL0::HCTL_DWIDTH::SET);
+        }
+
+        // SWITCH_FUNC was introduced with version 1.10.
+        if sd_spec >= 1 && self.switch_to_high_speed()? {
+            self.registers.CONTROL0.modify(CONTROL0::HCTL_HS_EN::SET);
+            self.set_clock(HIGH_SPEED_CLOCK_HZ)
+        } else {
+            self.set_clock(DEFAULT_SPEED_CLOCK_HZ)
+        }
+    }
+
+    /// Transfer whole blocks, starting at `start_block`.
+    fn transfer_blocks(&self, start_block: u64, transfer: Transfer) -> Result<(), &'static str> {
+        let card = self.card.ok_or("No SD card")?;
+
+        let len = match &transfer {
+            Transfer::Read(buffer) => buffer.len(),
+            Transfer::Write(buffer) => buffer.len(),
+        };
+        if len == 0 {
+            return Ok(());
+        }
+
+        let arg = if card.high_capacity {
+            start_block
+        } else {
+            start_block * BLOCK_SIZE as u64
+        };
+        let arg = u32::try_from(arg).map_err(|_| "Block address out of range")?;
+
+        let command = match (&transfer, len / BLOCK_SIZE) {
+            (Transfer::Read(_), 1) => Command::READ_SINGLE_BLOCK,
+            (Transfer::Read(_), _) => Command::READ_MULTIPLE_BLOCK,
+            (Transfer::Write(_), 1) => Command::WRITE_BLOCK,
+            (Transfer::Write(_), _) => Command::WRITE_MULTIPLE_BLOCK,
+        };
+
+        self.data_command(command, arg, BLOCK_SIZE, transfer)
+    }
+
+    fn read_blocks(&self, start_block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        for (i, chunk) in buffer
+            .chunks_mut(MAX_BLOCKS_PER_TRANSFER * BLOCK_SIZE)
+            .enumerate()
+        {
+            let block = start_block + (i * MAX_BLOCKS_PER_TRANSFER) as u64;
+            self.transfer_blocks(block, Transfer::Read(chunk))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_blocks(&self, start_block: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        for (i, chunk) in buffer
+            .chunks(MAX_BLOCKS_PER_TRANSFER * BLOCK_SIZE)
+            .enumerate()
+        {
+            let block = start_block + (i * MAX_BLOCKS_PER_TRANSFER) as u64;
+            self.transfer_blocks(block, Transfer::Write(chunk))?;
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Emmc {
+    pub const COMPATIBLE: &'static str = "BCM EMMC";
+
+    /// Create an instance.
+    ///
+    /// `base_clock_hz` is the frequency of the controller's base clock, from which the SD clock is
+    /// derived.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, base_clock_hz: u32) -> Self {
+        Self {
+            inner: NullLock::new(EmmcInner::new(mmio_start_addr, base_clock_hz)),
+        }
+    }
+
+    /// Identify the card in the slot and prepare it for data transfers.
+    ///
+    /// Fails if there is no card.
+    pub fn init_card(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init_card())
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for Emmc {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.reset())
+    }
+}
+
+impl block::interface::BlockDevice for Emmc {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.inner
+            .lock(|inner| inner.card.map_or(0, |card| card.num_blocks))
+    }
+
+    fn read_blocks(&self, start_block: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        block::check_request(self, start_block, buffer.len())?;
+
+        self.inner
+            .lock(|inner| inner.read_blocks(start_block, buffer))
+    }
+
+    fn write_blocks(&self, start_block: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        block::check_request(self, start_block, buffer.len())?;
+
+        self.inner
+            .lock(|inner| inner.write_blocks(start_block, buffer))
+    }
+}
@@ -0,0 +1,671 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Text console on a framebuffer allocated by the VideoCore firmware.
+//!
+//! Characters are drawn with an embedded bitmap font. The subset of ANSI escape sequences that the
+//! kernel emits is understood: Colors and attributes (SGR), cursor positioning, erasing, saving and
+//! restoring the cursor, and scrolling regions.
+//!
+//! The framebuffer is mapped non-cacheable, so reading it back is slow. Therefore, the characters
+//! on screen are also kept in a grid of cells in normal memory. Scrolling moves the cells and only
+//! redraws the ones that changed, instead of copying the framebuffer.
+
+mod font;
+
+use super::{FramebufferInfo, PixelOrder};
+use crate::{
+    console, driver,
+    exception::asynchronous::IRQNumber,
+    fs,
+    memory::{Address, Virtual},
+    synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of numeric parameters of a control sequence.
+const MAX_CSI_PARAMS: usize = 4;
+
+/// The 16 ANSI colors as (red, green, blue). The second half are the bright variants.
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xAA, 0x00, 0x00),
+    (0x00, 0xAA, 0x00),
+    (0xAA, 0x55, 0x00),
+    (0x00, 0x00, 0xAA),
+    (0xAA, 0x00, 0xAA),
+    (0x00, 0xAA, 0xAA),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0xFF, 0x55, 0x55),
+    (0x55, 0xFF, 0x55),
+    (0xFF, 0xFF, 0x55),
+    (0x55, 0x55, 0xFF),
+    (0xFF, 0x55, 0xFF),
+    (0x55, 0xFF, 0xFF),
+    (0xFF, 0xFF, 0xFF),
+];
+
+/// Palette index of the default foreground color.
+const DEFAULT_FG: usize = 7;
+
+/// Palette index of the default background color.
+const DEFAULT_BG: usize = 0;
+
+/// Progress of parsing an escape sequence.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum EscapeState {
+    /// Not in an escape sequence.
+    None,
+
+    /// After ESC.
+    Escape,
+
+    /// After ESC [, collecting numeric parameters.
+    Csi,
+}
+
+/// Character attributes set with SGR.
+#[derive(Copy, Clone)]
+struct Attributes {
+    fg: usize,
+    bg: usize,
+    bold: bool,
+    dim: bool,
+    reverse: bool,
+}
+
+/// A character on screen, with the palette indices it is drawn with.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Cell {
+    c: char,
+    fg: u8,
+    bg: u8,
+}
+
+struct FramebufferConsoleInner {
+    /// The framebuffer, mapped non-cacheable.
+    buffer: *mut u8,
+    pitch: usize,
+    pixel_order: PixelOrder,
+
+    /// Size of the console in characters.
+    columns: usize,
+    rows: usize,
+
+    /// What is on screen, row by row. Allocated in `init()`.
+    cells: Vec<Cell>,
+
+    /// Cursor position in characters.
+    column: usize,
+    row: usize,
+    saved_cursor: (usize, usize),
+
+    /// First and last row that are scrolled.
+    scroll_top: usize,
+    scroll_bottom: usize,
+
+    attributes: Attributes,
+    escape_state: EscapeState,
+    csi_params: [usize; MAX_CSI_PARAMS],
+    num_csi_params: usize,
+
+    chars_written: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the framebuffer console.
+pub struct FramebufferConsole {
+    inner: IRQSafeNullLock<FramebufferConsoleInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// The raw pointer is only dereferenced while the lock is held.
+unsafe impl Send for FramebufferConsoleInner {}
+
+impl Attributes {
+    const DEFAULT: Self = Self {
+        fg: DEFAULT_FG,
+        bg: DEFAULT_BG,
+        bold: false,
+        dim: false,
+        reverse: false,
+    };
+
+    /// Return the foreground and background palette index, with all attributes applied.
+    fn colors(&self) -> (usize, usize) {
+        let mut fg = self.fg;
+        if self.bold && fg < 8 {
+            fg += 8;
+        } else if self.dim && fg == DEFAULT_FG {
+            fg = 8;
+        }
+
+        if self.reverse {
+            (self.bg, fg)
+        } else {
+            (fg, self.bg)
+        }
+    }
+}
+
+impl FramebufferConsoleInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure that `buffer` is the start of a mapping of the framebuffer described
+    ///   by `info`.
+    pub const unsafe fn new(buffer: Address<Virtual>, info: FramebufferInfo) -> Self {
+        let columns = info.width as usize / font::WIDTH;
+        let rows = info.height as usize / font::HEIGHT;
+
+        Self {
+            buffer: buffer.as_usize() as *mut u8,
+            pitch: info.pitch as usize,
+            pixel_order: info.pixel_order,
+            columns,
+            rows,
+            cells: Vec::new(),
+            column: 0,
+            row: 0,
+            saved_cursor: (0, 0),
+            scroll_top: 0,
+            scroll_bottom: rows.saturating_sub(1),
+            attributes: Attributes::DEFAULT,
+            escape_state: EscapeState::None,
+            csi_params: [0; MAX_CSI_PARAMS],
+            num_csi_params: 0,
+            chars_written: 0,
+        }
+    }
+
+    /// Convert a palette index to the framebuffer's pixel format.
+    fn pixel(&self, color: usize) -> u32 {
+        let (r, g, b) = PALETTE[color];
+
+        match self.pixel_order {
+            PixelOrder::Rgb => u32::from_le_bytes([r, g, b, 0]),
+            PixelOrder::Bgr => u32::from_le_bytes([b, g, r, 0]),
+        }
+    }
+
+    /// Return a pointer to the pixel in line `y` and column `x`.
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        unsafe { self.buffer.add(y * self.pitch + x * 4) as *mut u32 }
+    }
+
+    /// Draw `cell` at the given character position.
+    fn draw_cell(&self, cell: Cell, column: usize, row: usize) {
+        let (fg, bg) = (self.pixel(cell.fg as usize), self.pixel(cell.bg as usize));
+        let glyph = font::glyph(cell.c);
+
+        for (y, line) in glyph.iter().enumerate() {
+            for x in 0..font::WIDTH {
+                let pixel = if line & (0x80 >> x) != 0 { fg } else { bg };
+                let ptr = self.pixel_ptr(column * font::WIDTH + x, row * font::HEIGHT + y);
+
+                unsafe { core::ptr::write_volatile(ptr, pixel) };
+            }
+        }
+    }
+
+    /// Put `cell` at the given character position, and draw it if it differs from what is on
+    /// screen. Positions outside of the console are ignored.
+    fn set_cell(&mut self, cell: Cell, column: usize, row: usize) {
+        if column >= self.columns || row >= self.rows {
+            return;
+        }
+
+        let index = row * self.columns + column;
+        if self.cells[index] != cell {
+            self.cells[index] = cell;
+            self.draw_cell(cell, column, row);
+        }
+    }
+
+    /// Draw `c` at the given character position with the current attributes.
+    fn draw_char(&mut self, c: char, column: usize, row: usize) {
+        let (fg, bg) = self.attributes.colors();
+
+        self.set_cell(
+            Cell {
+                c,
+                fg: fg as u8,
+                bg: bg as u8,
+            },
+            column,
+            row,
+        );
+    }
+
+    /// Fill the character cells `columns` of `row` with the background color.
+    fn clear_cells(&mut self, row: usize, columns: core::ops::Range<usize>) {
+        for column in columns {
+            self.draw_char(' ', column, row);
+        }
+    }
+
+    /// Move the rows of the scrolling region up by one and clear the last one.
+    fn scroll_up(&mut self) {
+        for row in self.scroll_top..self.scroll_bottom {
+            for column in 0..self.columns {
+                let cell = self.cells[(row + 1) * self.columns + column];
+                self.set_cell(cell, column, row);
+            }
+        }
+
+        self.clear_cells(self.scroll_bottom, 0..self.columns);
+    }
+
+    /// Move the cursor to the next line, scrolling if it is at the bottom of the scrolling region.
+    fn new_line(&mut self) {
+        self.column = 0;
+
+        if self.row == self.scroll_bottom {
+            self.scroll_up();
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    /// Return CSI parameter `i`, or `default` if it was omitted or zero.
+    fn csi_param(&self, i: usize, default: usize) -> usize {
+        match self.csi_params[i] {
+            0 => default,
+            x => x,
+        }
+    }
+
+    /// Apply a Select Graphic Rendition sequence.
+    fn select_graphic_rendition(&mut self) {
+        // No parameter means reset.
+        let num_params = self.num_csi_params.max(1);
+
+        for &param in &self.csi_params[..num_params] {
+            let attributes = &mut self.attributes;
+            match param {
+                0 => *attributes = Attributes::DEFAULT,
+                1 => attributes.bold = true,
+                2 => attributes.dim = true,
+                7 => attributes.reverse = true,
+                22 => (attributes.bold, attributes.dim) = (false, false),
+                27 => attributes.reverse = false,
+                30..=37 => attributes.fg = param - 30,
+                39 => attributes.fg = DEFAULT_FG,
+                40..=47 => attributes.bg = param - 40,
+                49 => attributes.bg = DEFAULT_BG,
+                90..=97 => attributes.fg = param - 90 + 8,
+                100..=107 => attributes.bg = param - 100 + 8,
+                _ => (),
+            }
+        }
+    }
+
+    /// Execute the control sequence that is terminated by `c`.
+    fn execute_csi(&mut self, c: char) {
+        match c {
+            // Cursor position, 1-based.
+            'H' | 'f' => {
+                self.row = (self.csi_param(0, 1) - 1).min(self.rows.saturating_sub(1));
+                self.column = (self.csi_param(1, 1) - 1).min(self.columns.saturating_sub(1));
+            }
+            // Erase in display.
+            'J' => {
+                let rows = match self.csi_params[0] {
+                    0 => {
+                        self.clear_cells(self.row, self.column..self.columns);
+                        self.row + 1..self.rows
+                    }
+                    1 => {
+                        self.clear_cells(self.row, 0..self.columns.min(self.column + 1));
+                        0..self.row
+                    }
+                    _ => 0..self.rows,
+                };
+
+                for row in rows {
+                    self.clear_cells(row, 0..self.columns);
+                }
+            }
+            // Erase in line.
+            'K' => {
+                let columns = match self.csi_params[0] {
+                    0 => self.column..self.columns,
+                    1 => 0..self.columns.min(self.column + 1),
+                    _ => 0..self.columns,
+                };
+
+                self.clear_cells(self.row, columns);
+            }
+            'm' => self.select_graphic_rendition(),
+            // Set scrolling region, 1-based and inclusive.
+            'r' => {
+                let top = self.csi_param(0, 1) - 1;
+                let bottom = self
+                    .csi_param(1, self.rows)
+                    .min(self.rows)
+                    .saturating_sub(1);
+
+                if top < bottom {
+                    (self.scroll_top, self.scroll_bottom) = (top, bottom);
+                    (self.row, self.column) = (0, 0);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Feed a character of an escape sequence. Return whether it was consumed.
+    fn parse_escape(&mut self, c: char) -> bool {
+        match self.escape_state {
+            EscapeState::None => {
+                if c != '\x1b' {
+                    return false;
+                }
+
+                self.escape_state = EscapeState::Escape;
+            }
+            EscapeState::Escape => {
+                self.escape_state = EscapeState::None;
+
+                match c {
+                    '[' => {
+                        self.escape_state = EscapeState::Csi;
+                        self.csi_params = [0; MAX_CSI_PARAMS];
+                        self.num_csi_params = 0;
+                    }
+                    '7' => self.saved_cursor = (self.row, self.column),
+                    '8' => (self.row, self.column) = self.saved_cursor,
+                    _ => (),
+                }
+            }
+            EscapeState::Csi => match c {
+                '0'..='9' => {
+                    if self.num_csi_params == 0 {
+                        self.num_csi_params = 1;
+                    }
+
+                    if let Some(param) = self.csi_params.get_mut(self.num_csi_params - 1) {
+                        let digit = c as usize - '0' as usize;
+                        *param = param.saturating_mul(10).saturating_add(digit);
+                    }
+                }
+                ';' => self.num_csi_params = self.num_csi_params.max(1) + 1,
+                _ => {
+                    self.num_csi_params = self.num_csi_params.min(MAX_CSI_PARAMS);
+                    self.escape_state = EscapeState::None;
+
+                    self.execute_csi(c);
+                }
+            },
+        }
+
+        true
+    }
+
+    /// Allocate the cells and clear the screen.
+    fn init(&mut self) {
+        let (fg, bg) = Attributes::DEFAULT.colors();
+        let blank = Cell {
+            c: ' ',
+            fg: fg as u8,
+            bg: bg as u8,
+        };
+        self.cells = vec![blank; self.columns * self.rows];
+
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                self.draw_cell(blank, column, row);
+            }
+        }
+    }
+
+    /// Write a character, interpreting control characters and escape sequences.
+    fn write_char(&mut self, c: char) {
+        self.chars_written += 1;
+
+        if self.parse_escape(c) {
+            return;
+        }
+
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => {
+                let next_stop = ((self.column / 8 + 1) * 8).min(self.columns);
+
+                self.clear_cells(self.row, self.column..next_stop);
+                self.column = next_stop;
+            }
+            c if c.is_control() => (),
+            c => {
+                if self.column == self.columns {
+                    self.new_line();
+                }
+
+                self.draw_char(c, self.column, self.row);
+                self.column += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Write for FramebufferConsoleInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FramebufferConsole {
+    pub const COMPATIBLE: &'static str = "BCM Framebuffer Console";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure that `buffer` is the start of a mapping of the framebuffer described
+    ///   by `info`, and that the framebuffer has 32 bits per pixel.
+    pub const unsafe fn new(buffer: Address<Virtual>, info: FramebufferInfo) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(FramebufferConsoleInner::new(buffer, info)),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for FramebufferConsole {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    fn device_file(&'static self) -> Option<&'static (dyn fs::interface::DeviceFile + Sync)> {
+        Some(self)
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init());
+
+        Ok(())
+    }
+}
+
+impl console::interface::Write for FramebufferConsole {
+    fn write_char(&self, c: char) {
+        self.inner.lock(|inner| inner.write_char(c));
+    }
+
+    fn write_array(&self, a: &[char]) {
+        self.inner.lock(|inner| {
+            for c in a {
+                inner.write_char(*c);
+            }
+        });
+    }
+
+    fn write_fmt(&self, args: core::fmt::Arguments) -> fmt::Result {
+        self.inner.lock(|inner| fmt::Write::write_fmt(inner, args))
+    }
+
+    fn flush(&self) {}
+}
+
+impl console::interface::Read for FramebufferConsole {
+    fn clear_rx(&self) {}
+}
+
+impl console::interface::Statistics for FramebufferConsole {
+    fn chars_written(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_written)
+    }
+}
+
+impl console::interface::All for FramebufferConsole {}
+
+impl fs::interface::DeviceFile for FramebufferConsole {
+    fn file_name(&self) -> &'static str {
+        "fb0"
+    }
+
+    fn write(&self, _offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        fs::devfs::write_console(self, data)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use test_macros::kernel_test;
+
+    /// Return a console of `columns` x `rows` characters that draws into `pixels`.
+    fn console(pixels: &mut Vec<u32>, columns: usize, rows: usize) -> FramebufferConsoleInner {
+        let (width, height) = (columns * font::WIDTH, rows * font::HEIGHT);
+        *pixels = vec![0; width * height];
+
+        let info = FramebufferInfo {
+            bus_addr: 0,
+            size: (width * height * 4) as u32,
+            width: width as u32,
+            height: height as u32,
+            pitch: (width * 4) as u32,
+            depth: 32,
+            pixel_order: PixelOrder::Rgb,
+        };
+        let buffer = Address::new(pixels.as_mut_ptr() as usize);
+        let mut inner = unsafe { FramebufferConsoleInner::new(buffer, info) };
+        inner.init();
+
+        inner
+    }
+
+    fn write(inner: &mut FramebufferConsoleInner, s: &str) {
+        fmt::Write::write_str(inner, s).unwrap();
+    }
+
+    fn text(inner: &FramebufferConsoleInner, row: usize) -> String {
+        let cells = &inner.cells[row * inner.columns..][..inner.columns];
+
+        cells.iter().map(|cell| cell.c).collect()
+    }
+
+    /// Cursor positioning and colors are parsed from control sequences.
+    #[kernel_test]
+    fn control_sequences_are_parsed() {
+        let mut pixels = Vec::new();
+        let mut inner = console(&mut pixels, 4, 3);
+
+        write(&mut inner, "\x1b[2;3Hab");
+        assert_eq!(text(&inner, 1), "  ab");
+
+        // Bold turns red into bright red. The line wraps, as the cursor is past the last column.
+        write(&mut inner, "\x1b[31;1mX\x1b[mY");
+        assert_eq!(text(&inner, 2), "XY  ");
+        assert_eq!(
+            (inner.cells[8].fg, inner.cells[8].bg),
+            (9, DEFAULT_BG as u8)
+        );
+        assert_eq!(inner.cells[9].fg, DEFAULT_FG as u8);
+
+        // Surplus parameters are ignored.
+        write(&mut inner, "\x1b[1;2;3;4;5;6HZ");
+        assert_eq!(text(&inner, 0), " Z  ");
+        assert!(inner.escape_state == EscapeState::None);
+    }
+
+    /// The cursor and erasing are clipped at the edges of the console.
+    #[kernel_test]
+    fn edges_are_clipped() {
+        let mut pixels = Vec::new();
+        let mut inner = console(&mut pixels, 4, 3);
+
+        write(&mut inner, "\x1b[99;99H");
+        assert_eq!((inner.row, inner.column), (2, 3));
+
+        // The cursor is past the last column after writing a full line.
+        write(&mut inner, "\x1b[Habcd\x1b[1K");
+        assert_eq!(text(&inner, 0), "    ");
+        write(&mut inner, "\x1b[2Habcd\x1b[1J");
+        assert_eq!(text(&inner, 1), "    ");
+
+        // Without a single row, all output is dropped.
+        let mut inner = console(&mut pixels, 4, 0);
+        write(&mut inner, "ab\n\x1b[H\x1b[1K\x1b[1J\x1b[r\tc");
+        assert!(inner.cells.is_empty());
+    }
+
+    /// Scrolling moves the cells, and the framebuffer shows the same as if they had been drawn
+    /// directly.
+    #[kernel_test]
+    fn scrolling_redraws_cells() {
+        let mut pixels = Vec::new();
+        let mut inner = console(&mut pixels, 4, 3);
+        write(&mut inner, "a\nbb\nc\nd");
+
+        let mut expected_pixels = Vec::new();
+        let mut expected = console(&mut expected_pixels, 4, 3);
+        write(&mut expected, "bb\nc\nd");
+
+        assert_eq!(text(&inner, 0), "bb  ");
+        assert_eq!(text(&inner, 2), "d   ");
+        assert!(pixels == expected_pixels);
+
+        // Only the scrolling region moves.
+        write(&mut inner, "\x1b[2;3r\x1b[3He\nf");
+        assert_eq!(text(&inner, 0), "bb  ");
+        assert_eq!(text(&inner, 1), "e   ");
+        assert_eq!(text(&inner, 2), "f   ");
+    }
+}
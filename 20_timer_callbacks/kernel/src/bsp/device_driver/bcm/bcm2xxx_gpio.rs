@@ -7,15 +7,20 @@
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
     driver,
-    exception::asynchronous::IRQNumber,
+    exception::{
+        self,
+        asynchronous::{IRQNumber, IRQReturn},
+    },
+    fs, info,
     memory::{Address, Virtual},
     synchronization,
     synchronization::IRQSafeNullLock,
+    warn,
 };
 use tock_registers::{
-    interfaces::{ReadWriteable, Writeable},
+    interfaces::{Readable, Writeable},
     register_bitfields, register_structs,
-    registers::ReadWrite,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -30,24 +35,6 @@ use tock_registers::{
 register_bitfields! {
     u32,
 
-    /// GPIO Function Select 1
-    GPFSEL1 [
-        /// Pin 15
-        FSEL15 OFFSET(15) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100  // PL011 UART RX
-
-        ],
-
-        /// Pin 14
-        FSEL14 OFFSET(12) NUMBITS(3) [
-            Input = 0b000,
-            Output = 0b001,
-            AltFunc0 = 0b100  // PL011 UART TX
-        ]
-    ],
-
     /// GPIO Pull-up/down Register
     ///
     /// BCM2837 only.
@@ -58,135 +45,449 @@ register_bitfields! {
             PullDown = 0b01,
             PullUp = 0b10
         ]
-    ],
-
-    /// GPIO Pull-up/down Clock Register 0
-    ///
-    /// BCM2837 only.
-    GPPUDCLK0 [
-        /// Pin 15
-        PUDCLK15 OFFSET(15) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1
-        ],
-
-        /// Pin 14
-        PUDCLK14 OFFSET(14) NUMBITS(1) [
-            NoEffect = 0,
-            AssertClock = 1
-        ]
-    ],
-
-    /// GPIO Pull-up / Pull-down Register 0
-    ///
-    /// BCM2711 only.
-    GPIO_PUP_PDN_CNTRL_REG0 [
-        /// Pin 15
-        GPIO_PUP_PDN_CNTRL15 OFFSET(30) NUMBITS(2) [
-            NoResistor = 0b00,
-            PullUp = 0b01
-        ],
-
-        /// Pin 14
-        GPIO_PUP_PDN_CNTRL14 OFFSET(28) NUMBITS(2) [
-            NoResistor = 0b00,
-            PullUp = 0b01
-        ]
     ]
 }
 
 register_structs! {
     #[allow(non_snake_case)]
     RegisterBlock {
-        (0x00 => _reserved1),
-        (0x04 => GPFSEL1: ReadWrite<u32, GPFSEL1::Register>),
-        (0x08 => _reserved2),
+        /// GPIO Function Select 0-5, with 3 bits per pin.
+        (0x00 => GPFSEL: [ReadWrite<u32>; 6]),
+        (0x18 => _reserved1),
+        /// GPIO Pin Output Set 0-1.
+        (0x1C => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved2),
+        /// GPIO Pin Output Clear 0-1.
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved3),
+        /// GPIO Pin Level 0-1.
+        (0x34 => GPLEV: [ReadOnly<u32>; 2]),
+        (0x3C => _reserved4),
+        /// GPIO Pin Event Detect Status 0-1. Write 1 to clear.
+        (0x40 => GPEDS: [ReadWrite<u32>; 2]),
+        (0x48 => _reserved5),
+        /// GPIO Pin Rising Edge Detect Enable 0-1.
+        (0x4C => GPREN: [ReadWrite<u32>; 2]),
+        (0x54 => _reserved6),
+        /// GPIO Pin Falling Edge Detect Enable 0-1.
+        (0x58 => GPFEN: [ReadWrite<u32>; 2]),
+        (0x60 => _reserved7),
+        /// GPIO Pin High Detect Enable 0-1.
+        (0x64 => GPHEN: [ReadWrite<u32>; 2]),
+        (0x6C => _reserved8),
+        /// GPIO Pin Low Detect Enable 0-1.
+        (0x70 => GPLEN: [ReadWrite<u32>; 2]),
+        (0x78 => _reserved9),
+        /// GPIO Pin Async. Rising Edge Detect 0-1.
+        (0x7C => GPAREN: [ReadWrite<u32>; 2]),
+        (0x84 => _reserved10),
+        /// GPIO Pin Async. Falling Edge Detect 0-1.
+        (0x88 => GPAFEN: [ReadWrite<u32>; 2]),
+        (0x90 => _reserved11),
         (0x94 => GPPUD: ReadWrite<u32, GPPUD::Register>),
-        (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
-        (0x9C => _reserved3),
-        (0xE4 => GPIO_PUP_PDN_CNTRL_REG0: ReadWrite<u32, GPIO_PUP_PDN_CNTRL_REG0::Register>),
-        (0xE8 => @END),
+        /// GPIO Pull-up/down Clock 0-1, with 1 bit per pin. BCM2837 only.
+        (0x98 => GPPUDCLK: [ReadWrite<u32>; 2]),
+        (0xA0 => _reserved12),
+        /// GPIO Pull-up / Pull-down 0-3, with 2 bits per pin. BCM2711 only.
+        (0xE4 => GPIO_PUP_PDN_CNTRL_REG: [ReadWrite<u32>; 4]),
+        (0xF4 => @END),
     }
 }
 
+/// Number of GPIO pins.
+const NUM_PINS: usize = 54;
+
+/// Events are only delivered for the pins of bank 0, which covers the 40-pin header. The other
+/// banks have IRQs of their own, which are not wired up.
+const NUM_EVENT_PINS: usize = 28;
+
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
 struct GPIOInner {
     registers: Registers,
+    variant: GpioVariant,
+
+    /// The driver or subsystem that claimed each pin.
+    owners: [Option<&'static str>; NUM_PINS],
+
+    /// The handler of each pin's events.
+    event_handlers: [Option<PinEventHandler>; NUM_EVENT_PINS],
+
+    /// Pins whose high or low level detection is masked until the event is acknowledged.
+    masked_high: u32,
+    masked_low: u32,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// The generations of the GPIO block, which differ in how pulls are set.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpioVariant {
+    /// BCM2835 to BCM2837, as on the RPi 3.
+    Bcm2835,
+
+    /// BCM2711, as on the RPi 4.
+    Bcm2711,
+}
+
+/// Pin functions, as encoded in the GPFSEL registers.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PinFunction {
+    Input = 0b000,
+    Output = 0b001,
+    Alt0 = 0b100,
+    Alt1 = 0b101,
+    Alt2 = 0b110,
+    Alt3 = 0b111,
+    Alt4 = 0b011,
+    Alt5 = 0b010,
+}
+
+/// Internal pull resistor of a pin.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PinPull {
+    None,
+    Up,
+    Down,
+}
+
+/// Pin events that can trigger an IRQ.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PinEvent {
+    /// A rising edge, sampled with the system clock. Filters glitches.
+    RisingEdge,
+
+    /// A falling edge, sampled with the system clock. Filters glitches.
+    FallingEdge,
+
+    /// A high level. Masked after each detection until acknowledged with
+    /// [`Pin::acknowledge_events()`], as the level would keep triggering otherwise.
+    High,
+
+    /// A low level. Masked after each detection until acknowledged with
+    /// [`Pin::acknowledge_events()`], as the level would keep triggering otherwise.
+    Low,
+
+    /// A rising edge, not sampled. Detects very short pulses.
+    AsyncRisingEdge,
+
+    /// A falling edge, not sampled. Detects very short pulses.
+    AsyncFallingEdge,
+}
+
+/// Called in IRQ context with the number of the pin whose event was detected.
+///
+/// Level events stay masked afterwards until they are acknowledged, see
+/// [`Pin::acknowledge_events()`].
+pub type PinEventHandler = fn(pin: usize);
+
+/// Pin pairs that the TX and RX signals of a UART can be routed to.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UartPins {
+    /// Pins 8 and 10 of the 40-pin header.
+    Gpio14_15,
+
+    /// Wired to the Bluetooth controller on boards that have one.
+    Gpio32_33,
+}
+
 /// Representation of the GPIO HW.
 pub struct GPIO {
     inner: IRQSafeNullLock<GPIOInner>,
 }
 
+/// A claimed GPIO pin.
+///
+/// The pin is released when the handle is dropped. Its function, level and pull stay as they are,
+/// but events are disabled.
+pub struct Pin {
+    gpio: &'static GPIO,
+    number: usize,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// Return the register index and the bit mask of `pin` in registers with one bit per pin.
+const fn bank_bit(pin: usize) -> (usize, u32) {
+    (pin / 32, 1 << (pin % 32))
+}
+
 impl GPIOInner {
     /// Create an instance.
     ///
     /// # Safety
     ///
     /// - The user must ensure to provide a correct MMIO start address.
-    pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, variant: GpioVariant) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            variant,
+            owners: [None; NUM_PINS],
+            event_handlers: [None; NUM_EVENT_PINS],
+            masked_high: 0,
+            masked_low: 0,
+        }
+    }
+
+    /// Record `owner` as the owner of `pin`.
+    fn claim(&mut self, pin: usize, owner: &'static str) -> Result<(), &'static str> {
+        if pin >= NUM_PINS {
+            return Err("GPIO pin number out of range");
+        }
+
+        if let Some(current) = self.owners[pin] {
+            warn!(
+                "GPIO pin {}: Claimed by {}, but already owned by {}",
+                pin, owner, current
+            );
+            return Err("GPIO pin already claimed");
+        }
+
+        self.owners[pin] = Some(owner);
+
+        Ok(())
+    }
+
+    fn release(&mut self, pin: usize) {
+        self.disable_events(pin);
+        self.owners[pin] = None;
+    }
+
+    /// Select the function of `pin`.
+    fn set_function(&mut self, pin: usize, function: PinFunction) {
+        let reg = &self.registers.GPFSEL[pin / 10];
+        let shift = (pin % 10) * 3;
+
+        reg.set((reg.get() & !(0b111 << shift)) | ((function as u32) << shift));
+    }
+
+    /// Set the pull of `pins`.
+    fn set_pull(&mut self, pins: &[usize], pull: PinPull) {
+        match self.variant {
+            GpioVariant::Bcm2835 => self.set_pull_bcm2835(pins, pull),
+            GpioVariant::Bcm2711 => self.set_pull_bcm2711(pins, pull),
         }
     }
 
-    /// Disable pull-up/down on pins 14 and 15.
-    #[cfg(feature = "bsp_rpi3")]
-    fn disable_pud_14_15_bcm2837(&mut self) {
+    /// Set the pull of `pins` with the clocked control signal of the BCM2835.
+    fn set_pull_bcm2835(&mut self, pins: &[usize], pull: PinPull) {
         use crate::time;
         use core::time::Duration;
 
         // The Linux 2837 GPIO driver waits 1 µs between the steps.
         const DELAY: Duration = Duration::from_micros(1);
 
-        self.registers.GPPUD.write(GPPUD::PUD::Off);
+        let mut clock = [0_u32; 2];
+        for pin in pins {
+            let (reg, bit) = bank_bit(*pin);
+            clock[reg] |= bit;
+        }
+
+        let pud = match pull {
+            PinPull::None => GPPUD::PUD::Off,
+            PinPull::Up => GPPUD::PUD::PullUp,
+            PinPull::Down => GPPUD::PUD::PullDown,
+        };
+
+        // The control signal is latched into the pins that are clocked.
+        self.registers.GPPUD.write(pud);
         time::time_manager().spin_for(DELAY);
 
-        self.registers
-            .GPPUDCLK0
-            .write(GPPUDCLK0::PUDCLK15::AssertClock + GPPUDCLK0::PUDCLK14::AssertClock);
+        for (reg, clock) in self.registers.GPPUDCLK.iter().zip(clock) {
+            reg.set(clock);
+        }
         time::time_manager().spin_for(DELAY);
 
         self.registers.GPPUD.write(GPPUD::PUD::Off);
-        self.registers.GPPUDCLK0.set(0);
+        for reg in &self.registers.GPPUDCLK {
+            reg.set(0);
+        }
+    }
+
+    /// Set the pull of `pins` with the pull control registers of the BCM2711.
+    fn set_pull_bcm2711(&mut self, pins: &[usize], pull: PinPull) {
+        let bits = match pull {
+            PinPull::None => 0b00,
+            PinPull::Up => 0b01,
+            PinPull::Down => 0b10,
+        };
+
+        for pin in pins {
+            let reg = &self.registers.GPIO_PUP_PDN_CNTRL_REG[pin / 16];
+            let shift = (pin % 16) * 2;
+
+            reg.set((reg.get() & !(0b11 << shift)) | (bits << shift));
+        }
+    }
+
+    fn write(&mut self, pin: usize, high: bool) {
+        let (reg, bit) = bank_bit(pin);
+
+        match high {
+            true => self.registers.GPSET[reg].set(bit),
+            false => self.registers.GPCLR[reg].set(bit),
+        }
+    }
+
+    fn read(&self, pin: usize) -> bool {
+        let (reg, bit) = bank_bit(pin);
+
+        self.registers.GPLEV[reg].get() & bit != 0
+    }
+
+    /// Enable exactly `events` for `pin`.
+    fn set_events(&mut self, pin: usize, events: &[PinEvent]) {
+        let (reg, bit) = bank_bit(pin);
+        let r = &self.registers;
+
+        for (event, enable_reg) in [
+            (PinEvent::RisingEdge, &r.GPREN[reg]),
+            (PinEvent::FallingEdge, &r.GPFEN[reg]),
+            (PinEvent::High, &r.GPHEN[reg]),
+            (PinEvent::Low, &r.GPLEN[reg]),
+            (PinEvent::AsyncRisingEdge, &r.GPAREN[reg]),
+            (PinEvent::AsyncFallingEdge, &r.GPAFEN[reg]),
+        ] {
+            match events.contains(&event) {
+                true => enable_reg.set(enable_reg.get() | bit),
+                false => enable_reg.set(enable_reg.get() & !bit),
+            }
+        }
+
+        // Drop events that were detected before.
+        r.GPEDS[reg].set(bit);
+
+        if reg == 0 {
+            self.masked_high &= !bit;
+            self.masked_low &= !bit;
+        }
     }
 
-    /// Disable pull-up/down on pins 14 and 15.
-    #[cfg(feature = "bsp_rpi4")]
-    fn disable_pud_14_15_bcm2711(&mut self) {
-        self.registers.GPIO_PUP_PDN_CNTRL_REG0.write(
-            GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL15::PullUp
-                + GPIO_PUP_PDN_CNTRL_REG0::GPIO_PUP_PDN_CNTRL14::PullUp,
-        );
+    fn disable_events(&mut self, pin: usize) {
+        self.set_events(pin, &[]);
+
+        if let Some(handler) = self.event_handlers.get_mut(pin) {
+            *handler = None;
+        }
     }
 
-    /// Map PL011 UART as standard output.
+    /// Clear and return the detected events of the pins that support events.
     ///
-    /// TX to pin 14
-    /// RX to pin 15
-    pub fn map_pl011_uart(&mut self) {
-        // Select the UART on pins 14 and 15.
-        self.registers
-            .GPFSEL1
-            .modify(GPFSEL1::FSEL15::AltFunc0 + GPFSEL1::FSEL14::AltFunc0);
+    /// Level detection stays masked for the pending pins until `acknowledge_events()`. Otherwise,
+    /// the event would be detected again right after clearing it, as long as the level persists.
+    fn take_events(&mut self) -> u32 {
+        let r = &self.registers;
+        let pending = r.GPEDS[0].get() & ((1 << NUM_EVENT_PINS) - 1);
 
-        // Disable pull-up/down on pins 14 and 15.
-        #[cfg(feature = "bsp_rpi3")]
-        self.disable_pud_14_15_bcm2837();
+        let (high, low) = (r.GPHEN[0].get(), r.GPLEN[0].get());
+        r.GPHEN[0].set(high & !pending);
+        r.GPLEN[0].set(low & !pending);
+        self.masked_high |= high & pending;
+        self.masked_low |= low & pending;
 
-        #[cfg(feature = "bsp_rpi4")]
-        self.disable_pud_14_15_bcm2711();
+        r.GPEDS[0].set(pending);
+
+        pending
+    }
+
+    /// Unmask the level detection of `pin` that was masked by `take_events()`.
+    fn acknowledge_events(&mut self, pin: usize) {
+        let (_, bit) = bank_bit(pin);
+        let r = &self.registers;
+
+        if self.masked_high & bit != 0 {
+            r.GPHEN[0].set(r.GPHEN[0].get() | bit);
+        }
+        if self.masked_low & bit != 0 {
+            r.GPLEN[0].set(r.GPLEN[0].get() | bit);
+        }
+
+        self.masked_high &= !bit;
+        self.masked_low &= !bit;
+    }
+
+    /// Pull of the UART pins. On the RPi 4, pulling RX up keeps it idle while nothing is connected.
+    fn uart_pull(&self) -> PinPull {
+        match self.variant {
+            GpioVariant::Bcm2835 => PinPull::None,
+            GpioVariant::Bcm2711 => PinPull::Up,
+        }
+    }
+
+    /// Route a UART's TX and RX to `pins` with `function`.
+    fn map_uart(
+        &mut self,
+        (tx, rx): (usize, usize),
+        function: PinFunction,
+        owner: &'static str,
+    ) -> Result<(), &'static str> {
+        self.claim(tx, owner)?;
+        if let Err(x) = self.claim(rx, owner) {
+            self.release(tx);
+            return Err(x);
+        }
+
+        self.set_function(tx, function);
+        self.set_function(rx, function);
+        self.set_pull(&[tx, rx], self.uart_pull());
+
+        Ok(())
+    }
+
+    /// Map the PL011 UART's TX and RX to `pins`.
+    pub fn map_pl011_uart(&mut self, pins: UartPins) -> Result<(), &'static str> {
+        const OWNER: &str = "PL011 UART";
+
+        match pins {
+            UartPins::Gpio14_15 => self.map_uart((14, 15), PinFunction::Alt0, OWNER),
+            UartPins::Gpio32_33 => self.map_uart((32, 33), PinFunction::Alt3, OWNER),
+        }
+    }
+
+    /// Map the mini UART's TX and RX to `pins`.
+    pub fn map_mini_uart(&mut self, pins: UartPins) -> Result<(), &'static str> {
+        const OWNER: &str = "Mini UART";
+
+        match pins {
+            UartPins::Gpio14_15 => self.map_uart((14, 15), PinFunction::Alt5, OWNER),
+            UartPins::Gpio32_33 => self.map_uart((32, 33), PinFunction::Alt5, OWNER),
+        }
+    }
+
+    /// Route the SD card slot to the EMMC controller. The firmware routes it to the SD host
+    /// controller instead.
+    ///
+    /// On the RPi 4, the SD card slot is wired to EMMC2 directly.
+    pub fn map_emmc(&mut self) -> Result<(), &'static str> {
+        const OWNER: &str = "EMMC";
+
+        // CLK, CMD and DAT0-3.
+        const PINS: [usize; 6] = [48, 49, 50, 51, 52, 53];
+
+        for (i, pin) in PINS.iter().enumerate() {
+            if let Err(x) = self.claim(*pin, OWNER) {
+                PINS[..i].iter().for_each(|pin| self.release(*pin));
+                return Err(x);
+            }
+        }
+
+        for pin in PINS {
+            self.set_function(pin, PinFunction::Alt3);
+        }
+
+        // The SD specification requires pull-ups on the command and data lines.
+        self.set_pull(&PINS[..1], PinPull::None);
+        self.set_pull(&PINS[1..], PinPull::Up);
+
+        Ok(())
     }
 }
 
@@ -202,15 +503,136 @@ impl GPIO {
     /// # Safety
     ///
     /// - The user must ensure to provide a correct MMIO start address.
-    pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, variant: GpioVariant) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(GPIOInner::new(mmio_start_addr)),
+            inner: IRQSafeNullLock::new(GPIOInner::new(mmio_start_addr, variant)),
         }
     }
 
     /// Concurrency safe version of `GPIOInner.map_pl011_uart()`
-    pub fn map_pl011_uart(&self) {
-        self.inner.lock(|inner| inner.map_pl011_uart())
+    pub fn map_pl011_uart(&self, pins: UartPins) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.map_pl011_uart(pins))
+    }
+
+    /// Concurrency safe version of `GPIOInner.map_mini_uart()`
+    pub fn map_mini_uart(&self, pins: UartPins) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.map_mini_uart(pins))
+    }
+
+    /// Concurrency safe version of `GPIOInner.map_emmc()`
+    pub fn map_emmc(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.map_emmc())
+    }
+
+    /// Claim pin `number` for `owner`.
+    ///
+    /// Fails if the pin is claimed already, so that two drivers can not drive the same pin.
+    pub fn claim(&'static self, number: usize, owner: &'static str) -> Result<Pin, &'static str> {
+        self.inner.lock(|inner| inner.claim(number, owner))?;
+
+        Ok(Pin { gpio: self, number })
+    }
+
+    /// Detect level events on pin `number` again, see [`Pin::acknowledge_events()`].
+    ///
+    /// For owners that do not keep the [`Pin`] handle around.
+    pub fn acknowledge_events(&self, number: usize) {
+        if number >= NUM_EVENT_PINS {
+            return;
+        }
+
+        self.inner.lock(|inner| inner.acknowledge_events(number))
+    }
+
+    /// Print the owners of all claimed pins.
+    pub fn print_owners(&self) {
+        self.inner.lock(|inner| {
+            for (pin, owner) in inner.owners.iter().enumerate() {
+                if let Some(owner) = owner {
+                    info!("      {: >2}: {}", pin, owner);
+                }
+            }
+        });
+    }
+}
+
+impl Pin {
+    /// Return the pin number.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Select the function of the pin.
+    pub fn set_function(&self, function: PinFunction) {
+        self.gpio
+            .inner
+            .lock(|inner| inner.set_function(self.number, function))
+    }
+
+    /// Set the internal pull resistor.
+    pub fn set_pull(&self, pull: PinPull) {
+        self.gpio
+            .inner
+            .lock(|inner| inner.set_pull(&[self.number], pull))
+    }
+
+    /// Drive the pin high. Takes effect while the pin is an output.
+    pub fn set(&self) {
+        self.gpio.inner.lock(|inner| inner.write(self.number, true))
+    }
+
+    /// Drive the pin low. Takes effect while the pin is an output.
+    pub fn clear(&self) {
+        self.gpio
+            .inner
+            .lock(|inner| inner.write(self.number, false))
+    }
+
+    /// Return whether the pin is high.
+    pub fn read(&self) -> bool {
+        self.gpio.inner.lock(|inner| inner.read(self.number))
+    }
+
+    /// Call `handler` whenever one of `events` is detected on the pin.
+    ///
+    /// Replaces any previous events and handler. Only pins 0 to 27 support events.
+    pub fn enable_events(
+        &self,
+        events: &[PinEvent],
+        handler: PinEventHandler,
+    ) -> Result<(), &'static str> {
+        if self.number >= NUM_EVENT_PINS {
+            return Err("Events are only supported on GPIO pins 0 to 27");
+        }
+
+        self.gpio.inner.lock(|inner| {
+            inner.event_handlers[self.number] = Some(handler);
+            inner.set_events(self.number, events);
+        });
+
+        Ok(())
+    }
+
+    /// Detect level events on the pin again.
+    ///
+    /// [`PinEvent::High`] and [`PinEvent::Low`] are masked after each detection, so the handler or
+    /// whoever it defers the work to must call this once the cause of the event was dealt with.
+    /// Edge events are not masked.
+    pub fn acknowledge_events(&self) {
+        self.gpio.acknowledge_events(self.number)
+    }
+
+    /// Stop detecting events on the pin.
+    pub fn disable_events(&self) {
+        self.gpio
+            .inner
+            .lock(|inner| inner.disable_events(self.number))
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        self.gpio.inner.lock(|inner| inner.release(self.number));
     }
 }
 
@@ -225,4 +647,161 @@ impl driver::interface::DeviceDriver for GPIO {
     fn compatible(&self) -> &'static str {
         Self::COMPATIBLE
     }
+
+    fn device_file(&'static self) -> Option<&'static (dyn fs::interface::DeviceFile + Sync)> {
+        Some(self)
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
+
+        let descriptor =
+            IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, IRQPriority::DEFAULT, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for GPIO {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
+        let pending = self.inner.lock(|inner| inner.take_events());
+
+        if pending == 0 {
+            return Ok(IRQReturn::NotHandled);
+        }
+
+        // Call the handlers without holding the lock, so that they can use their pins.
+        for pin in (0..NUM_EVENT_PINS).filter(|pin| pending & (1 << pin) != 0) {
+            if let Some(handler) = self.inner.lock(|inner| inner.event_handlers[pin]) {
+                handler(pin);
+            }
+        }
+
+        Ok(IRQReturn::Handled)
+    }
+}
+
+impl fs::interface::DeviceFile for GPIO {
+    fn file_name(&self) -> &'static str {
+        "gpio"
+    }
+
+    /// Read the levels of all pins as a line of `0` and `1` characters, starting with pin 0.
+    fn read(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let mut levels = [b'\n'; NUM_PINS + 1];
+        self.inner.lock(|inner| {
+            for (pin, level) in levels.iter_mut().take(NUM_PINS).enumerate() {
+                *level = if inner.read(pin) { b'1' } else { b'0' };
+            }
+        });
+
+        let start = usize::try_from(offset).map_or(levels.len(), |o| o.min(levels.len()));
+        let n = buffer.len().min(levels.len() - start);
+        buffer[..n].copy_from_slice(&levels[start..start + n]);
+
+        Ok(n)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{vec, vec::Vec};
+    use test_macros::kernel_test;
+
+    /// Return an instance whose registers are backed by `registers` instead of the hardware.
+    fn gpio(registers: &mut Vec<u32>) -> GPIOInner {
+        *registers = vec![0; core::mem::size_of::<RegisterBlock>() / 4];
+
+        unsafe {
+            GPIOInner::new(
+                Address::new(registers.as_mut_ptr() as usize),
+                GpioVariant::Bcm2711,
+            )
+        }
+    }
+
+    /// A pin can only be claimed again after it was released.
+    #[kernel_test]
+    fn double_claims_fail() {
+        let mut registers = Vec::new();
+        let mut inner = gpio(&mut registers);
+
+        assert!(inner.claim(4, "a").is_ok());
+        assert!(inner.claim(4, "b").is_err());
+        assert_eq!(inner.owners[4], Some("a"));
+        assert!(inner.claim(NUM_PINS, "a").is_err());
+
+        // Releasing twice is harmless.
+        inner.release(4);
+        inner.release(4);
+        assert!(inner.claim(4, "b").is_ok());
+        assert_eq!(inner.owners[4], Some("b"));
+    }
+
+    /// A failed mapping releases the pins that it claimed so far, and leaves the others alone.
+    #[kernel_test]
+    fn failed_mappings_are_released() {
+        let mut registers = Vec::new();
+        let mut inner = gpio(&mut registers);
+
+        inner.claim(15, "other").unwrap();
+        assert!(inner.map_pl011_uart(UartPins::Gpio14_15).is_err());
+        assert_eq!(inner.owners[14], None);
+        assert_eq!(inner.owners[15], Some("other"));
+
+        inner.claim(50, "other").unwrap();
+        assert!(inner.map_emmc().is_err());
+        assert!(inner.owners[48..50].iter().all(Option::is_none));
+        assert_eq!(inner.owners[50], Some("other"));
+
+        // The mini UART is not blocked by the failed PL011 mapping.
+        assert!(inner.map_mini_uart(UartPins::Gpio32_33).is_ok());
+        assert_eq!(inner.owners[32], Some("Mini UART"));
+    }
+
+    /// Level events are masked once taken, until they are acknowledged. Edge events are not.
+    #[kernel_test]
+    fn level_events_are_masked_until_acknowledged() {
+        let mut registers = Vec::new();
+        let mut inner = gpio(&mut registers);
+        let (_, high_bit) = bank_bit(4);
+        let (_, edge_bit) = bank_bit(5);
+
+        inner.set_events(4, &[PinEvent::High, PinEvent::RisingEdge]);
+        inner.set_events(5, &[PinEvent::FallingEdge]);
+
+        // Pretend that both pins detected their events.
+        inner.registers.GPEDS[0].set(high_bit | edge_bit);
+        assert_eq!(inner.take_events(), high_bit | edge_bit);
+        assert_eq!(inner.registers.GPHEN[0].get(), 0);
+        assert_eq!(inner.registers.GPREN[0].get(), high_bit);
+        assert_eq!(inner.registers.GPFEN[0].get(), edge_bit);
+
+        inner.acknowledge_events(4);
+        assert_eq!(inner.registers.GPHEN[0].get(), high_bit);
+
+        // Acknowledging again, or a pin without masked events, changes nothing.
+        inner.acknowledge_events(4);
+        inner.acknowledge_events(5);
+        assert_eq!(inner.registers.GPHEN[0].get(), high_bit);
+        assert_eq!(inner.registers.GPLEN[0].get(), 0);
+
+        // Disabling the events forgets the mask.
+        inner.registers.GPEDS[0].set(high_bit);
+        inner.take_events();
+        inner.disable_events(4);
+        inner.acknowledge_events(4);
+        assert_eq!(inner.registers.GPHEN[0].get(), 0);
+    }
 }
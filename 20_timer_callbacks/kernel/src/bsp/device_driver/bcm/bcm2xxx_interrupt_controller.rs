@@ -3,17 +3,32 @@
 // Copyright (c) 2020-2023 Andre Richter <andre.o.richter@gmail.com>
 
 //! Interrupt Controller Driver.
+//!
+//! The controller has no notion of IRQ priorities. They are emulated in software: Before a handler
+//! is called, all IRQs that have the same or a lower priority are masked in the controller. IRQs
+//! are then unmasked on the executing core, so that only IRQs of higher priority can preempt the
+//! handler.
+//!
+//! IPIs are sent through the first mailbox of each core in the local interrupt controller. They
+//! are handled before device IRQs and with IRQs masked.
 
 mod local_ic;
 mod peripheral_ic;
 
 use crate::{
     bsp::device_driver::common::BoundedUsize,
-    driver,
-    exception::{self, asynchronous::IRQHandlerDescriptor},
+    cpu, driver,
+    exception::{
+        self,
+        asynchronous::{interface::IRQHandler, IPIKind, IRQPriority},
+    },
     memory::{Address, Virtual},
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
 };
-use core::fmt;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -43,6 +58,12 @@ pub enum IRQNumber {
 pub struct InterruptController {
     local: local_ic::LocalIC,
     periph: peripheral_ic::PeripheralIC,
+
+    /// The number of spurious IRQs.
+    num_spurious: AtomicU64,
+
+    /// The handler of the IRQ that is delivered as FIQ. Writable only during kernel init.
+    fiq_handler: InitStateLock<Option<&'static (dyn IRQHandler + Sync)>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -82,6 +103,21 @@ impl fmt::Display for IRQNumber {
     }
 }
 
+impl InterruptController {
+    /// Mask all IRQs that can not preempt a handler running at `priority` while executing `f`.
+    fn with_priority_masked<T>(&self, priority: IRQPriority, f: impl FnOnce() -> T) -> T {
+        let saved_local = self.local.mask_priority(priority);
+        let saved_periph = self.periph.mask_priority(priority);
+
+        let ret = f();
+
+        self.periph.restore_priority_mask(saved_periph);
+        self.local.restore_priority_mask(saved_local);
+
+        ret
+    }
+}
+
 impl InterruptController {
     // Restrict to 3 for now. This makes the code for local_ic.rs more straight forward.
     const MAX_LOCAL_IRQ_NUMBER: usize = 3;
@@ -101,6 +137,36 @@ impl InterruptController {
         Self {
             local: local_ic::LocalIC::new(local_mmio_start_addr),
             periph: peripheral_ic::PeripheralIC::new(periph_mmio_start_addr),
+            num_spurious: AtomicU64::new(0),
+            fiq_handler: InitStateLock::new(None),
+        }
+    }
+
+    /// Translate a device tree interrupt specifier of the form `<bank number>` for the peripheral
+    /// controller, or `<number flags>` for the local controller.
+    ///
+    /// Banks 1 and 2 are the GPU IRQs 0-31 and 32-63. The basic IRQs of bank 0 are not supported.
+    pub fn irq_number_from_device_tree(
+        interrupt: &fdt::Interrupt,
+    ) -> Result<IRQNumber, &'static str> {
+        let (first, second) = match (interrupt.cell(0), interrupt.cell(1)) {
+            (Some(first), Some(second)) => (first as usize, second as usize),
+            _ => return Err("Invalid interrupt specifier"),
+        };
+
+        if interrupt.controller.is_compatible("brcm,bcm2836-l1-intc") {
+            return match first {
+                0..=Self::MAX_LOCAL_IRQ_NUMBER => Ok(IRQNumber::Local(LocalIRQ::new(first))),
+                _ => Err("Local IRQ number out of range"),
+            };
+        }
+
+        match (first, second) {
+            (1 | 2, 0..=31) => Ok(IRQNumber::Peripheral(PeripheralIRQ::new(
+                (first - 1) * 32 + second,
+            ))),
+            (0, _) => Err("Basic IRQs are not supported"),
+            _ => Err("Invalid peripheral interrupt specifier"),
         }
     }
 }
@@ -133,26 +199,29 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
     ) -> Result<(), &'static str> {
         match irq_handler_descriptor.number() {
             IRQNumber::Local(lirq) => {
-                let local_descriptor = IRQHandlerDescriptor::new(
-                    lirq,
-                    irq_handler_descriptor.name(),
-                    irq_handler_descriptor.handler(),
-                );
+                let local_descriptor = irq_handler_descriptor.with_number(lirq);
 
                 self.local.register_handler(local_descriptor)
             }
             IRQNumber::Peripheral(pirq) => {
-                let periph_descriptor = IRQHandlerDescriptor::new(
-                    pirq,
-                    irq_handler_descriptor.name(),
-                    irq_handler_descriptor.handler(),
-                );
+                let periph_descriptor = irq_handler_descriptor.with_number(pirq);
 
                 self.periph.register_handler(periph_descriptor)
             }
         }
     }
 
+    fn unregister_handler(
+        &self,
+        irq: &Self::IRQNumberType,
+        handler: &'static (dyn exception::asynchronous::interface::IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.unregister_handler(lirq, handler),
+            IRQNumber::Peripheral(pirq) => self.periph.unregister_handler(pirq, handler),
+        }
+    }
+
     fn enable(&self, irq: &Self::IRQNumberType) {
         match irq {
             IRQNumber::Local(lirq) => self.local.enable(lirq),
@@ -160,16 +229,132 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         }
     }
 
+    fn disable(&self, irq: &Self::IRQNumberType) {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.disable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.disable(pirq),
+        }
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        self.local.handle_pending_irqs(ic);
-        self.periph.handle_pending_irqs(ic)
+        use exception::asynchronous::{exec_with_irq_unmasked, handle_chain};
+
+        let mut num_handled = 0;
+
+        loop {
+            for kind in self.local.take_pending_ipis() {
+                cpu::smp::handle_ipi(kind, ic);
+                num_handled += 1;
+            }
+
+            let local = self.local.highest_priority_pending();
+            let periph = self.periph.highest_priority_pending();
+
+            // Pick the pending IRQ with the highest priority. Local IRQs win on equal priority.
+            let pick_periph = match (&local, &periph) {
+                (None, None) => break,
+                (Some(_), None) => false,
+                (None, Some(_)) => true,
+                (Some((_, lprio, _)), Some((_, pprio, _))) => pprio.preempts(*lprio),
+            };
+
+            // Only IRQs of higher priority are left unmasked in the controller, so it is safe to
+            // let them preempt the handlers.
+            //
+            // Call the IRQ handlers. Panics on failure.
+            if pick_periph {
+                let (pirq, priority, chain) = periph.unwrap();
+
+                self.with_priority_masked(priority, || {
+                    self.periph.record_handler(&pirq, || unsafe {
+                        exec_with_irq_unmasked(|| handle_chain(&chain))
+                    })
+                });
+            } else {
+                let (lirq, priority, chain) = local.unwrap();
+
+                self.with_priority_masked(priority, || {
+                    self.local.record_handler(&lirq, || unsafe {
+                        exec_with_irq_unmasked(|| handle_chain(&chain))
+                    })
+                });
+            }
+
+            num_handled += 1;
+        }
+
+        if num_handled == 0 {
+            self.num_spurious.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn send_ipi(&self, target_core: usize, kind: IPIKind) -> Result<(), &'static str> {
+        self.local.send_ipi(target_core, kind)
+    }
+
+    fn set_fiq(
+        &self,
+        irq: &Self::IRQNumberType,
+        handler: &'static (dyn IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        self.fiq_handler.write(|fiq_handler| {
+            if fiq_handler.is_some() {
+                return Err("FIQ already assigned");
+            }
+            *fiq_handler = Some(handler);
+
+            match irq {
+                IRQNumber::Local(lirq) => self.local.route_to_fiq(lirq),
+                IRQNumber::Peripheral(pirq) => self.periph.route_to_fiq(pirq),
+            }
+
+            Ok(())
+        })
+    }
+
+    fn handle_pending_fiq<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        // The controller has only a single FIQ source, so there is nothing to look up or
+        // acknowledge.
+        self.fiq_handler.read(|fiq_handler| match fiq_handler {
+            None => panic!("FIQ without handler"),
+            Some(handler) => {
+                handler.handle().expect("Error handling FIQ");
+            }
+        })
     }
 
     fn print_handler(&self) {
         self.local.print_handler();
         self.periph.print_handler();
     }
+
+    fn irq_statistics(&self, irq: &Self::IRQNumberType) -> exception::asynchronous::IRQStatistics {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.irq_statistics(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.irq_statistics(pirq),
+        }
+    }
+
+    fn num_spurious_irqs(&self) -> u64 {
+        self.num_spurious.load(Ordering::Relaxed)
+    }
+
+    fn print_statistics(&self) {
+        use exception::asynchronous::IRQStatistics;
+
+        IRQStatistics::print_header();
+
+        self.local
+            .print_statistics(|i| IRQNumber::Local(LocalIRQ::new(i)));
+        self.periph
+            .print_statistics(|i| IRQNumber::Peripheral(PeripheralIRQ::new(i)));
+
+        IRQStatistics::print_spurious_row(self.num_spurious_irqs());
+    }
 }
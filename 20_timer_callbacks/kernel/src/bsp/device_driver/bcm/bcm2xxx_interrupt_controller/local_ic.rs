@@ -10,8 +10,12 @@
 
 use super::{LocalIRQ, PendingIRQs};
 use crate::{
-    bsp::device_driver::common::MMIODerefWrapper,
-    exception,
+    bsp::{self, device_driver::common::MMIODerefWrapper},
+    cpu,
+    exception::{
+        self,
+        asynchronous::{IPIKind, IRQHandlerChain, IRQHandlerTable, IRQPriority},
+    },
     memory::{Address, Virtual},
     synchronization,
     synchronization::{IRQSafeNullLock, InitStateLock},
@@ -20,7 +24,7 @@ use alloc::vec::Vec;
 use tock_registers::{
     interfaces::{Readable, Writeable},
     register_structs,
-    registers::{ReadOnly, WriteOnly},
+    registers::{ReadOnly, ReadWrite, WriteOnly},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -32,7 +36,9 @@ register_structs! {
     WORegisterBlock {
         (0x00 => _reserved1),
         (0x40 => CORE0_TIMER_INTERRUPT_CONTROL: WriteOnly<u32>),
-        (0x44 => @END),
+        (0x44 => _reserved2),
+        (0x50 => CORE_MAILBOX_INTERRUPT_CONTROL: [WriteOnly<u32>; 4]),
+        (0x60 => @END),
     }
 }
 
@@ -45,13 +51,39 @@ register_structs! {
     }
 }
 
+register_structs! {
+    #[allow(non_snake_case)]
+    MailboxRegisterBlock {
+        (0x00 => _reserved1),
+        (0x80 => CORE_MAILBOX_WRITE_SET: [WriteOnly<u32>; 16]),
+        (0xc0 => CORE_MAILBOX_READ_WRITE_CLEAR: [ReadWrite<u32>; 16]),
+        (0x100 => @END),
+    }
+}
+
 /// Abstraction for the WriteOnly parts of the associated MMIO registers.
 type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
 
 /// Abstraction for the ReadOnly parts of the associated MMIO registers.
 type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
 
-type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<LocalIRQ>>>;
+/// Abstraction for the mailbox registers.
+type MailboxRegisters = MMIODerefWrapper<MailboxRegisterBlock>;
+
+type StatisticsTable = Vec<exception::asynchronous::IRQStatisticsRecorder>;
+
+struct LocalICInner {
+    wo_registers: WriteOnlyRegisters,
+
+    /// IRQs that were enabled by the kernel.
+    enabled: u32,
+
+    /// IRQs that are temporarily masked because a handler of higher or equal priority is running.
+    priority_masked: u32,
+
+    /// The IRQ that is delivered as FIQ instead.
+    fiq: u32,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -60,23 +92,64 @@ type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<Loc
 /// Representation of the peripheral interrupt controller.
 pub struct LocalIC {
     /// Access to write registers is guarded with a lock.
-    wo_registers: IRQSafeNullLock<WriteOnlyRegisters>,
+    inner: IRQSafeNullLock<LocalICInner>,
 
     /// Register read access is unguarded.
     ro_registers: ReadOnlyRegisters,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Mailbox access is unguarded. Writes only set or clear the given bits, and each core only
+    /// clears its own mailboxes.
+    mailbox_registers: MailboxRegisters,
+
+    /// Stores registered IRQ handlers.
+    handler_table: IRQHandlerTable<LocalIRQ>,
+
+    /// Per-IRQ statistics. Allocated during kernel init, updated lock-free afterwards.
+    statistics: InitStateLock<StatisticsTable>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl LocalICInner {
+    const fn new(wo_registers: WriteOnlyRegisters) -> Self {
+        Self {
+            wo_registers,
+            enabled: 0,
+            priority_masked: 0,
+            fiq: 0,
+        }
+    }
+
+    /// Write the effective enable bits to the hardware.
+    ///
+    /// The timer interrupt control register does not support setting individual bits, so the
+    /// complete value must be written each time. The upper four bits of the register enable FIQ
+    /// delivery for the respective timer.
+    fn update_hw(&mut self) {
+        let irq_bits = self.enabled & !self.priority_masked & !self.fiq;
+        let fiq_bits = self.fiq << 4;
+
+        self.wo_registers
+            .CORE0_TIMER_INTERRUPT_CONTROL
+            .set(irq_bits | fiq_bits);
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
 
 impl LocalIC {
     // See datasheet.
     const PERIPH_IRQ_MASK: u32 = (1 << 8);
 
+    /// Each core has four mailboxes. The first one is used for IPIs, with one bit per kind.
+    const NUM_MAILBOXES_PER_CORE: usize = 4;
+    const IPI_MAILBOX: usize = 0;
+
     /// Create an instance.
     ///
     /// # Safety
@@ -84,90 +157,209 @@ impl LocalIC {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
+            inner: IRQSafeNullLock::new(LocalICInner::new(WriteOnlyRegisters::new(
+                mmio_start_addr,
+            ))),
             ro_registers: ReadOnlyRegisters::new(mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            mailbox_registers: MailboxRegisters::new(mmio_start_addr),
+            handler_table: IRQHandlerTable::new(),
+            statistics: InitStateLock::new(Vec::new()),
         }
     }
 
     /// Called by the kernel to bring up the device.
     pub fn init(&self) {
-        self.handler_table
-            .write(|table| table.resize(LocalIRQ::MAX_INCLUSIVE + 1, None));
+        self.handler_table.init(LocalIRQ::MAX_INCLUSIVE + 1);
+        self.statistics
+            .write(|table| table.resize_with(LocalIRQ::MAX_INCLUSIVE + 1, Default::default));
+
+        // Enable the IRQ of the IPI mailbox on all cores.
+        self.inner.lock(|inner| {
+            for control in inner.wo_registers.CORE_MAILBOX_INTERRUPT_CONTROL.iter() {
+                control.set(1 << Self::IPI_MAILBOX);
+            }
+        });
+    }
+
+    /// Return the IPI mailbox register of a core.
+    fn ipi_mailbox_index(core: usize) -> usize {
+        core * Self::NUM_MAILBOXES_PER_CORE + Self::IPI_MAILBOX
     }
 
     /// Query the list of pending IRQs.
     fn pending_irqs(&self) -> PendingIRQs {
         // Ignore the indicator bit for a peripheral IRQ.
-        PendingIRQs::new(
-            (self.ro_registers.CORE0_INTERRUPT_SOURCE.get() & !Self::PERIPH_IRQ_MASK).into(),
-        )
+        let pending = self.ro_registers.CORE0_INTERRUPT_SOURCE.get() & !Self::PERIPH_IRQ_MASK;
+        let unmasked = self
+            .inner
+            .lock(|inner| inner.enabled & !inner.priority_masked & !inner.fiq);
+
+        PendingIRQs::new((pending & unmasked).into())
     }
-}
 
-//------------------------------------------------------------------------------
-// OS Interface Code
-//------------------------------------------------------------------------------
-use synchronization::interface::{Mutex, ReadWriteEx};
+    /// Send an IPI to another core, or to the executing core.
+    pub fn send_ipi(&self, target_core: usize, kind: IPIKind) -> Result<(), &'static str> {
+        if target_core >= bsp::cpu::NUM_CORES {
+            return Err("Invalid target core");
+        }
+
+        self.mailbox_registers.CORE_MAILBOX_WRITE_SET[Self::ipi_mailbox_index(target_core)]
+            .set(1 << kind.index());
+
+        Ok(())
+    }
+
+    /// Return the IPIs that are pending on the executing core and acknowledge them.
+    pub fn take_pending_ipis(&self) -> impl Iterator<Item = IPIKind> {
+        let mailbox = &self.mailbox_registers.CORE_MAILBOX_READ_WRITE_CLEAR
+            [Self::ipi_mailbox_index(cpu::smp::core_id())];
 
-impl exception::asynchronous::interface::IRQManager for LocalIC {
-    type IRQNumberType = LocalIRQ;
+        let pending = mailbox.get();
+        if pending != 0 {
+            mailbox.set(pending);
+        }
+
+        PendingIRQs::new(pending.into()).filter_map(IPIKind::from_index)
+    }
 
-    fn register_handler(
+    /// Register a handler.
+    pub fn register_handler(
         &self,
-        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<LocalIRQ>,
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+        self.handler_table.register(
+            irq_handler_descriptor.number().get(),
+            irq_handler_descriptor,
+        )
+    }
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
-            }
+    /// Remove a handler. Disables the IRQ if it was the last handler.
+    pub fn unregister_handler(
+        &self,
+        irq: &LocalIRQ,
+        handler: &'static (dyn exception::asynchronous::interface::IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        if self.handler_table.unregister(irq.get(), handler)? {
+            self.disable(irq);
+        }
 
-            table[irq_number] = Some(irq_handler_descriptor);
+        Ok(())
+    }
 
-            Ok(())
-        })
+    /// Enable an interrupt.
+    pub fn enable(&self, irq: &LocalIRQ) {
+        self.inner.lock(|inner| {
+            inner.enabled |= 1 << irq.get();
+            inner.update_hw();
+        });
     }
 
-    fn enable(&self, irq: &Self::IRQNumberType) {
-        self.wo_registers.lock(|regs| {
-            let enable_bit: u32 = 1 << (irq.get());
+    /// Disable an interrupt.
+    pub fn disable(&self, irq: &LocalIRQ) {
+        self.inner.lock(|inner| {
+            inner.enabled &= !(1 << irq.get());
+            inner.update_hw();
+        });
+    }
 
-            // Writing a 1 to a bit will set the corresponding IRQ enable bit. All other IRQ enable
-            // bits are unaffected. So we don't need read and OR'ing here.
-            regs.CORE0_TIMER_INTERRUPT_CONTROL.set(enable_bit);
+    /// Deliver an IRQ as FIQ instead.
+    pub fn route_to_fiq(&self, irq: &LocalIRQ) {
+        self.inner.lock(|inner| {
+            inner.fiq = 1 << irq.get();
+            inner.update_hw();
         });
     }
 
-    fn handle_pending_irqs<'irq_context>(
-        &'irq_context self,
-        _ic: &exception::asynchronous::IRQContext<'irq_context>,
-    ) {
-        self.handler_table.read(|table| {
-            for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler().handle().expect("Error handling IRQ");
-                    }
-                }
+    /// Return the pending, unmasked IRQ with the highest priority, together with its handlers.
+    ///
+    /// Panics if a pending IRQ has no handler registered.
+    pub fn highest_priority_pending(
+        &self,
+    ) -> Option<(LocalIRQ, IRQPriority, IRQHandlerChain<LocalIRQ>)> {
+        self.pending_irqs()
+            .map(|irq_number| match self.handler_table.get(irq_number) {
+                None => panic!("No handler registered for IRQ {}", irq_number),
+                Some(chain) => (
+                    LocalIRQ::new(irq_number),
+                    exception::asynchronous::chain_priority(&chain),
+                    chain,
+                ),
+            })
+            .min_by_key(|(_, priority, _)| *priority)
+    }
+
+    /// Mask all IRQs that can not preempt a handler running at `priority`.
+    ///
+    /// Returns the previous mask, which must be passed to `restore_priority_mask()` once the
+    /// handler finished.
+    pub fn mask_priority(&self, priority: IRQPriority) -> u32 {
+        let mut mask: u32 = 0;
+        self.handler_table.for_each_priority(|i, p| {
+            if !p.preempts(priority) {
+                mask |= 1 << i;
             }
+        });
+
+        self.inner.lock(|inner| {
+            let saved = inner.priority_masked;
+
+            inner.priority_masked |= mask;
+            inner.update_hw();
+
+            saved
         })
     }
 
-    fn print_handler(&self) {
+    /// Restore a mask that was returned by `mask_priority()`.
+    pub fn restore_priority_mask(&self, saved: u32) {
+        self.inner.lock(|inner| {
+            inner.priority_masked = saved;
+            inner.update_hw();
+        });
+    }
+
+    /// Execute the provided handler closure and record statistics for the given IRQ.
+    pub fn record_handler(
+        &self,
+        irq: &LocalIRQ,
+        f: impl FnOnce() -> exception::asynchronous::IRQReturn,
+    ) -> exception::asynchronous::IRQReturn {
+        self.statistics
+            .read(|statistics| statistics[irq.get()].record(f))
+    }
+
+    /// Return the statistics of an IRQ number.
+    pub fn irq_statistics(&self, irq: &LocalIRQ) -> exception::asynchronous::IRQStatistics {
+        self.statistics
+            .read(|statistics| statistics[irq.get()].get())
+    }
+
+    /// Print the statistics of all IRQs with a registered handler.
+    ///
+    /// `to_irq_number` converts the table index into the IRQ number known to the kernel.
+    pub fn print_statistics(&self, to_irq_number: impl Fn(usize) -> super::IRQNumber) {
+        self.statistics.read(|statistics| {
+            for (i, chain) in self.handler_table.registered() {
+                statistics[i].get().print_row(&to_irq_number(i), &chain);
+            }
+        });
+    }
+
+    /// Print list of registered handlers.
+    pub fn print_handler(&self) {
         use crate::info;
 
         info!("      Local handler:");
 
-        self.handler_table.read(|table| {
-            for (i, opt) in table.iter().enumerate() {
-                if let Some(handler) = opt {
-                    info!("            {: >3}. {}", i, handler.name());
-                }
+        for (i, chain) in self.handler_table.registered() {
+            for handler in chain.iter() {
+                info!(
+                    "            {: >3}. {} (priority {})",
+                    i,
+                    handler.name(),
+                    handler.priority()
+                );
             }
-        });
+        }
     }
 }
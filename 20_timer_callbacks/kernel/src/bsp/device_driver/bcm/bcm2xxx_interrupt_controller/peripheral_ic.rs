@@ -11,7 +11,10 @@
 use super::{PendingIRQs, PeripheralIRQ};
 use crate::{
     bsp::device_driver::common::MMIODerefWrapper,
-    exception,
+    exception::{
+        self,
+        asynchronous::{IRQHandlerChain, IRQHandlerTable, IRQPriority},
+    },
     memory::{Address, Virtual},
     synchronization,
     synchronization::{IRQSafeNullLock, InitStateLock},
@@ -19,7 +22,7 @@ use crate::{
 use alloc::vec::Vec;
 use tock_registers::{
     interfaces::{Readable, Writeable},
-    register_structs,
+    register_bitfields, register_structs,
     registers::{ReadOnly, WriteOnly},
 };
 
@@ -27,13 +30,27 @@ use tock_registers::{
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+register_bitfields! {
+    u32,
+
+    /// FIQ Control
+    FIQ_CONTROL [
+        Enable OFFSET(7) NUMBITS(1) [],
+        Source OFFSET(0) NUMBITS(7) []
+    ]
+}
+
 register_structs! {
     #[allow(non_snake_case)]
     WORegisterBlock {
         (0x00 => _reserved1),
+        (0x0c => FIQ_CONTROL: WriteOnly<u32, FIQ_CONTROL::Register>),
         (0x10 => ENABLE_1: WriteOnly<u32>),
         (0x14 => ENABLE_2: WriteOnly<u32>),
-        (0x18 => @END),
+        (0x18 => _reserved2),
+        (0x1c => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => @END),
     }
 }
 
@@ -53,7 +70,20 @@ type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
 /// Abstraction for the ReadOnly parts of the associated MMIO registers.
 type ReadOnlyRegisters = MMIODerefWrapper<RORegisterBlock>;
 
-type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<PeripheralIRQ>>>;
+type StatisticsTable = Vec<exception::asynchronous::IRQStatisticsRecorder>;
+
+struct PeripheralICInner {
+    wo_registers: WriteOnlyRegisters,
+
+    /// IRQs that were enabled by the kernel.
+    enabled: u64,
+
+    /// IRQs that are temporarily masked because a handler of higher or equal priority is running.
+    priority_masked: u64,
+
+    /// The IRQ that is delivered as FIQ instead.
+    fiq: u64,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -62,18 +92,70 @@ type HandlerTable = Vec<Option<exception::asynchronous::IRQHandlerDescriptor<Per
 /// Representation of the peripheral interrupt controller.
 pub struct PeripheralIC {
     /// Access to write registers is guarded with a lock.
-    wo_registers: IRQSafeNullLock<WriteOnlyRegisters>,
+    inner: IRQSafeNullLock<PeripheralICInner>,
 
     /// Register read access is unguarded.
     ro_registers: ReadOnlyRegisters,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers.
+    handler_table: IRQHandlerTable<PeripheralIRQ>,
+
+    /// Per-IRQ statistics. Allocated during kernel init, updated lock-free afterwards.
+    statistics: InitStateLock<StatisticsTable>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl PeripheralICInner {
+    const fn new(wo_registers: WriteOnlyRegisters) -> Self {
+        Self {
+            wo_registers,
+            enabled: 0,
+            priority_masked: 0,
+            fiq: 0,
+        }
+    }
+
+    /// Set the enable bits given in `mask`.
+    ///
+    /// Writing a 1 to a bit will set the corresponding IRQ enable bit. All other IRQ enable bits
+    /// are unaffected. So we don't need read and OR'ing here.
+    fn hw_enable(&mut self, mask: u64) {
+        self.wo_registers.ENABLE_1.set(mask as u32);
+        self.wo_registers.ENABLE_2.set((mask >> 32) as u32);
+    }
+
+    /// Clear the enable bits given in `mask`.
+    ///
+    /// Same semantics as `hw_enable()`.
+    fn hw_disable(&mut self, mask: u64) {
+        self.wo_registers.DISABLE_1.set(mask as u32);
+        self.wo_registers.DISABLE_2.set((mask >> 32) as u32);
+    }
+
+    /// Change the priority mask and bring the hardware in sync.
+    fn set_priority_mask(&mut self, new_mask: u64) {
+        let to_disable = self.enabled & new_mask & !self.priority_masked & !self.fiq;
+        let to_enable = self.enabled & self.priority_masked & !new_mask & !self.fiq;
+
+        self.priority_masked = new_mask;
+
+        if to_disable != 0 {
+            self.hw_disable(to_disable);
+        }
+
+        if to_enable != 0 {
+            self.hw_enable(to_enable);
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
 
 impl PeripheralIC {
     /// Create an instance.
@@ -83,96 +165,183 @@ impl PeripheralIC {
     /// - The user must ensure to provide a correct MMIO start address.
     pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
         Self {
-            wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(mmio_start_addr)),
+            inner: IRQSafeNullLock::new(PeripheralICInner::new(WriteOnlyRegisters::new(
+                mmio_start_addr,
+            ))),
             ro_registers: ReadOnlyRegisters::new(mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            handler_table: IRQHandlerTable::new(),
+            statistics: InitStateLock::new(Vec::new()),
         }
     }
 
     /// Called by the kernel to bring up the device.
     pub fn init(&self) {
-        self.handler_table
-            .write(|table| table.resize(PeripheralIRQ::MAX_INCLUSIVE + 1, None));
+        self.handler_table.init(PeripheralIRQ::MAX_INCLUSIVE + 1);
+        self.statistics
+            .write(|table| table.resize_with(PeripheralIRQ::MAX_INCLUSIVE + 1, Default::default));
     }
 
     /// Query the list of pending IRQs.
     fn pending_irqs(&self) -> PendingIRQs {
         let pending_mask: u64 = (u64::from(self.ro_registers.PENDING_2.get()) << 32)
             | u64::from(self.ro_registers.PENDING_1.get());
+        let unmasked = self
+            .inner
+            .lock(|inner| inner.enabled & !inner.priority_masked & !inner.fiq);
 
-        PendingIRQs::new(pending_mask)
+        PendingIRQs::new(pending_mask & unmasked)
     }
-}
-
-//------------------------------------------------------------------------------
-// OS Interface Code
-//------------------------------------------------------------------------------
-use synchronization::interface::{Mutex, ReadWriteEx};
 
-impl exception::asynchronous::interface::IRQManager for PeripheralIC {
-    type IRQNumberType = PeripheralIRQ;
+    /// Register a handler.
+    pub fn register_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<PeripheralIRQ>,
+    ) -> Result<(), &'static str> {
+        self.handler_table.register(
+            irq_handler_descriptor.number().get(),
+            irq_handler_descriptor,
+        )
+    }
 
-    fn register_handler(
+    /// Remove a handler. Disables the IRQ if it was the last handler.
+    pub fn unregister_handler(
         &self,
-        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+        irq: &PeripheralIRQ,
+        handler: &'static (dyn exception::asynchronous::interface::IRQHandler + Sync),
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+        if self.handler_table.unregister(irq.get(), handler)? {
+            self.disable(irq);
+        }
+
+        Ok(())
+    }
 
-            if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
+    /// Enable an interrupt.
+    pub fn enable(&self, irq: &PeripheralIRQ) {
+        self.inner.lock(|inner| {
+            let enable_bit: u64 = 1 << irq.get();
+
+            inner.enabled |= enable_bit;
+            if (inner.priority_masked | inner.fiq) & enable_bit == 0 {
+                inner.hw_enable(enable_bit);
             }
+        });
+    }
 
-            table[irq_number] = Some(irq_handler_descriptor);
+    /// Disable an interrupt.
+    pub fn disable(&self, irq: &PeripheralIRQ) {
+        self.inner.lock(|inner| {
+            let disable_bit: u64 = 1 << irq.get();
 
-            Ok(())
-        })
+            inner.enabled &= !disable_bit;
+            inner.hw_disable(disable_bit);
+        });
     }
 
-    fn enable(&self, irq: &Self::IRQNumberType) {
-        self.wo_registers.lock(|regs| {
-            let enable_reg = if irq.get() <= 31 {
-                &regs.ENABLE_1
-            } else {
-                &regs.ENABLE_2
-            };
+    /// Deliver an IRQ as FIQ instead.
+    ///
+    /// The FIQ source numbers 0..=63 are identical to the IRQ numbers.
+    pub fn route_to_fiq(&self, irq: &PeripheralIRQ) {
+        self.inner.lock(|inner| {
+            let fiq_bit: u64 = 1 << irq.get();
 
-            let enable_bit: u32 = 1 << (irq.get() % 32);
+            // Make sure it is not signaled as IRQ as well.
+            inner.hw_disable(fiq_bit);
+            inner.fiq = fiq_bit;
 
-            // Writing a 1 to a bit will set the corresponding IRQ enable bit. All other IRQ enable
-            // bits are unaffected. So we don't need read and OR'ing here.
-            enable_reg.set(enable_bit);
+            inner
+                .wo_registers
+                .FIQ_CONTROL
+                .write(FIQ_CONTROL::Source.val(irq.get() as u32) + FIQ_CONTROL::Enable::SET);
         });
     }
 
-    fn handle_pending_irqs<'irq_context>(
-        &'irq_context self,
-        _ic: &exception::asynchronous::IRQContext<'irq_context>,
-    ) {
-        self.handler_table.read(|table| {
-            for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler().handle().expect("Error handling IRQ");
-                    }
-                }
+    /// Return the pending, unmasked IRQ with the highest priority, together with its handlers.
+    ///
+    /// Panics if a pending IRQ has no handler registered.
+    pub fn highest_priority_pending(
+        &self,
+    ) -> Option<(PeripheralIRQ, IRQPriority, IRQHandlerChain<PeripheralIRQ>)> {
+        self.pending_irqs()
+            .map(|irq_number| match self.handler_table.get(irq_number) {
+                None => panic!("No handler registered for IRQ {}", irq_number),
+                Some(chain) => (
+                    PeripheralIRQ::new(irq_number),
+                    exception::asynchronous::chain_priority(&chain),
+                    chain,
+                ),
+            })
+            .min_by_key(|(_, priority, _)| *priority)
+    }
+
+    /// Mask all IRQs that can not preempt a handler running at `priority`.
+    ///
+    /// Returns the previous mask, which must be passed to `restore_priority_mask()` once the
+    /// handler finished.
+    pub fn mask_priority(&self, priority: IRQPriority) -> u64 {
+        let mut mask: u64 = 0;
+        self.handler_table.for_each_priority(|i, p| {
+            if !p.preempts(priority) {
+                mask |= 1 << i;
             }
+        });
+
+        self.inner.lock(|inner| {
+            let saved = inner.priority_masked;
+
+            inner.set_priority_mask(saved | mask);
+
+            saved
         })
     }
 
-    fn print_handler(&self) {
+    /// Restore a mask that was returned by `mask_priority()`.
+    pub fn restore_priority_mask(&self, saved: u64) {
+        self.inner.lock(|inner| inner.set_priority_mask(saved));
+    }
+
+    /// Execute the provided handler closure and record statistics for the given IRQ.
+    pub fn record_handler(
+        &self,
+        irq: &PeripheralIRQ,
+        f: impl FnOnce() -> exception::asynchronous::IRQReturn,
+    ) -> exception::asynchronous::IRQReturn {
+        self.statistics
+            .read(|statistics| statistics[irq.get()].record(f))
+    }
+
+    /// Return the statistics of an IRQ number.
+    pub fn irq_statistics(&self, irq: &PeripheralIRQ) -> exception::asynchronous::IRQStatistics {
+        self.statistics
+            .read(|statistics| statistics[irq.get()].get())
+    }
+
+    /// Print the statistics of all IRQs with a registered handler.
+    ///
+    /// `to_irq_number` converts the table index into the IRQ number known to the kernel.
+    pub fn print_statistics(&self, to_irq_number: impl Fn(usize) -> super::IRQNumber) {
+        self.statistics.read(|statistics| {
+            for (i, chain) in self.handler_table.registered() {
+                statistics[i].get().print_row(&to_irq_number(i), &chain);
+            }
+        });
+    }
+
+    /// Print list of registered handlers.
+    pub fn print_handler(&self) {
         use crate::info;
 
         info!("      Peripheral handler:");
 
-        self.handler_table.read(|table| {
-            for (i, opt) in table.iter().enumerate() {
-                if let Some(handler) = opt {
-                    info!("            {: >3}. {}", i, handler.name());
-                }
+        for (i, chain) in self.handler_table.registered() {
+            for handler in chain.iter() {
+                info!(
+                    "            {: >3}. {} (priority {})",
+                    i,
+                    handler.name(),
+                    handler.priority()
+                );
             }
-        });
+        }
     }
 }
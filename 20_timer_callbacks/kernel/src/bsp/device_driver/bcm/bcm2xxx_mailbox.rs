@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! VideoCore mailbox driver.
+//!
+//! Implements the property interface on channel 8, through which the VideoCore firmware is queried
+//! and configured. A request is a list of tags in a buffer in memory, which the firmware overwrites
+//! with the responses. The buffer is handed over by its bus address, so it must be in memory that
+//! is not cached by the CPU.
+//!
+//! The messages themselves are encoded and decoded by the `videocore-property` library, whose items
+//! are re-exported here.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/firmware/wiki/Mailboxes>
+//! - <https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface>
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, driver,
+    exception::asynchronous::IRQNumber,
+    memory::{Address, Virtual},
+    synchronization,
+    synchronization::IRQSafeNullLock,
+    time,
+};
+use core::time::Duration;
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, WriteOnly},
+};
+
+pub use videocore_property::*;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Mailbox Status.
+    STATUS [
+        /// Set if no more messages can be written.
+        FULL OFFSET(31) NUMBITS(1) [],
+
+        /// Set if there are no messages to read.
+        EMPTY OFFSET(30) NUMBITS(1) []
+    ],
+
+    /// A mailbox message.
+    MESSAGE [
+        /// Upper 28 bits of the message. For the property channel, the bus address of the buffer.
+        DATA OFFSET(4) NUMBITS(28) [],
+
+        /// The channel.
+        CHANNEL OFFSET(0) NUMBITS(4) [
+            Property = 8
+        ]
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub RegisterBlock {
+        /// Mailbox 0 is written by the VideoCore and read by the ARM.
+        (0x00 => READ: ReadOnly<u32, MESSAGE::Register>),
+        (0x04 => _reserved1),
+        (0x18 => STATUS0: ReadOnly<u32, STATUS::Register>),
+        (0x1C => _reserved2),
+        /// Mailbox 1 is written by the ARM and read by the VideoCore.
+        (0x20 => WRITE: WriteOnly<u32, MESSAGE::Register>),
+        (0x24 => _reserved3),
+        (0x38 => STATUS1: ReadOnly<u32, STATUS::Register>),
+        (0x3C => _reserved4),
+        (0x40 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// How long to wait for the firmware to respond.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+struct MailboxInner {
+    registers: Registers,
+
+    /// The request buffer, mapped non-cacheable.
+    buffer: *mut u32,
+
+    /// Address of the request buffer as seen by the VideoCore.
+    buffer_bus_addr: u32,
+
+    /// Size of the request buffer in words.
+    buffer_words: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A framebuffer allocated by the firmware.
+#[derive(Copy, Clone, Debug)]
+pub struct FramebufferInfo {
+    /// Address of the framebuffer as seen by the VideoCore.
+    pub bus_addr: u32,
+
+    /// Size in bytes.
+    pub size: u32,
+
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+
+    /// Bytes per line.
+    pub pitch: u32,
+
+    /// Bits per pixel.
+    pub depth: u32,
+
+    /// Order of the color components.
+    pub pixel_order: PixelOrder,
+}
+
+/// Representation of the mailbox.
+pub struct Mailbox {
+    inner: IRQSafeNullLock<MailboxInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+// The raw pointer is only dereferenced while the lock is held.
+unsafe impl Send for MailboxInner {}
+
+impl MailboxInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    /// - The user must ensure to provide a non-cacheable buffer of `buffer_size` bytes, that is not
+    ///   used by anyone else and is reachable by the VideoCore at `buffer_bus_addr`.
+    pub const unsafe fn new(
+        mmio_start_addr: Address<Virtual>,
+        buffer: Address<Virtual>,
+        buffer_bus_addr: u32,
+        buffer_size: usize,
+    ) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            buffer: buffer.as_usize() as *mut u32,
+            buffer_bus_addr,
+            buffer_words: buffer_size / 4,
+        }
+    }
+
+    /// Spin until `condition` is met, or fail after [`TIMEOUT`].
+    fn wait_for(&self, condition: impl Fn(&Self) -> bool) -> Result<(), &'static str> {
+        let deadline = time::time_manager().uptime() + TIMEOUT;
+
+        while !condition(self) {
+            if time::time_manager().uptime() > deadline {
+                return Err("Mailbox timeout");
+            }
+            cpu::nop();
+        }
+
+        Ok(())
+    }
+
+    /// Send `words` to the firmware and replace them with the response.
+    fn call(&mut self, words: &mut [u32]) -> Result<(), &'static str> {
+        if words.len() > self.buffer_words {
+            return Err("Property message too large");
+        }
+
+        for (i, word) in words.iter().enumerate() {
+            unsafe { core::ptr::write_volatile(self.buffer.add(i), *word) };
+        }
+
+        // The buffer must be complete before the firmware is told about it.
+        cpu::memory_barrier();
+
+        self.wait_for(|s| !s.registers.STATUS1.matches_all(STATUS::FULL::SET))?;
+        self.registers
+            .WRITE
+            .write(MESSAGE::DATA.val(self.buffer_bus_addr >> 4) + MESSAGE::CHANNEL::Property);
+
+        // Discard responses on other channels.
+        loop {
+            self.wait_for(|s| !s.registers.STATUS0.matches_all(STATUS::EMPTY::SET))?;
+
+            let response = self.registers.READ.extract();
+            if response.matches_all(MESSAGE::CHANNEL::Property) {
+                break;
+            }
+        }
+
+        cpu::memory_barrier();
+
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = unsafe { core::ptr::read_volatile(self.buffer.add(i)) };
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Mailbox {
+    pub const COMPATIBLE: &'static str = "BCM VideoCore Mailbox";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    /// - The user must ensure to provide a non-cacheable, 16 byte aligned buffer of `buffer_size`
+    ///   bytes, that is not used by anyone else and is reachable by the VideoCore at
+    ///   `buffer_bus_addr`.
+    pub const unsafe fn new(
+        mmio_start_addr: Address<Virtual>,
+        buffer: Address<Virtual>,
+        buffer_bus_addr: u32,
+        buffer_size: usize,
+    ) -> Self {
+        assert!(buffer_bus_addr % 16 == 0);
+
+        Self {
+            inner: IRQSafeNullLock::new(MailboxInner::new(
+                mmio_start_addr,
+                buffer,
+                buffer_bus_addr,
+                buffer_size,
+            )),
+        }
+    }
+
+    /// Send `message` to the firmware. Afterwards, the responses can be retrieved from it.
+    pub fn call(&self, message: &mut PropertyMessage) -> Result<(), &'static str> {
+        let words = message.finish();
+
+        self.inner.lock(|inner| inner.call(words))
+    }
+
+    /// Send a message with only `tag` and return its response.
+    pub fn call_single<T: PropertyTag>(&self, tag: &T) -> Result<T::Response, &'static str> {
+        let mut message = PropertyMessage::new();
+        let handle = message.add(tag);
+
+        self.call(&mut message)?;
+        message.response(&handle)
+    }
+
+    /// Return the board revision.
+    pub fn get_board_revision(&self) -> Result<u32, &'static str> {
+        self.call_single(&GetBoardRevision)
+    }
+
+    /// Return the base address and size of the memory that belongs to the ARM.
+    pub fn get_arm_memory(&self) -> Result<(u32, u32), &'static str> {
+        self.call_single(&GetArmMemory)
+    }
+
+    /// Return the rate of `clock` in Hz.
+    pub fn get_clock_rate(&self, clock: ClockId) -> Result<u32, &'static str> {
+        self.call_single(&GetClockRate(clock))
+    }
+
+    /// Set the rate of `clock` and return the rate in Hz that was actually set.
+    pub fn set_clock_rate(&self, clock: ClockId, rate_hz: u32) -> Result<u32, &'static str> {
+        self.call_single(&SetClockRate { clock, rate_hz })
+    }
+
+    /// Let the firmware allocate a framebuffer of `width` x `height` pixels with `depth` bits per
+    /// pixel.
+    ///
+    /// The firmware may adjust the requested values, so the returned info must be used for
+    /// drawing.
+    pub fn allocate_framebuffer(
+        &self,
+        width: u32,
+        height: u32,
+        depth: u32,
+    ) -> Result<FramebufferInfo, &'static str> {
+        // The firmware only accepts these tags together in one message.
+        let mut message = PropertyMessage::new();
+        let physical_size = message.add(&SetPhysicalSize { width, height });
+        message.add(&SetVirtualSize { width, height });
+        message.add(&SetVirtualOffset { x: 0, y: 0 });
+        let depth = message.add(&SetDepth(depth));
+        let pixel_order = message.add(&SetPixelOrder(PixelOrder::Rgb));
+        let buffer = message.add(&AllocateBuffer(16));
+        let pitch = message.add(&GetPitch);
+        self.call(&mut message)?;
+
+        let (width, height) = message.response(&physical_size)?;
+        let (bus_addr, size) = message.response(&buffer)?;
+        if bus_addr == 0 {
+            return Err("Firmware did not allocate a framebuffer");
+        }
+
+        Ok(FramebufferInfo {
+            bus_addr,
+            size,
+            width,
+            height,
+            pitch: message.response(&pitch)?,
+            depth: message.response(&depth)?,
+            pixel_order: message.response(&pixel_order)?,
+        })
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for Mailbox {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+}
@@ -0,0 +1,500 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Mini UART driver.
+//!
+//! The mini UART is part of the auxiliary peripherals (AUX), which it shares with two SPI masters.
+//! It is a reduced 16550 with 8 byte FIFOs, 7 or 8 data bits, no parity and one stop bit. Its baud
+//! rate is derived from the VPU core clock.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf>
+//! - <https://elinux.org/BCM2835_datasheet_errata>
+
+use crate::{
+    bsp::device_driver::{
+        common::{MMIODerefWrapper, RxBuffer},
+        Parity, StopBits, UartConfig,
+    },
+    console, cpu, driver,
+    exception::{
+        self,
+        asynchronous::{IRQNumber, IRQReturn},
+    },
+    fs,
+    memory::{Address, Virtual},
+    synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use core::fmt;
+use tock_registers::{
+    interfaces::{ReadWriteable, Readable, Writeable},
+    register_bitfields, register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+use uart_divisor::MiniUartDivisor;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Auxiliary peripheral and mini UART registers.
+//
+// Descriptions taken from "BCM2837 ARM Peripherals", with corrections from the errata.
+register_bitfields! {
+    u32,
+
+    /// Auxiliary Interrupt status.
+    AUX_IRQ [
+        /// If set, the mini UART has an interrupt pending.
+        MINI_UART_IRQ OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Auxiliary enables.
+    AUX_ENABLES [
+        /// If set, the mini UART is enabled. The UART will immediately start receiving data,
+        /// especially if the UART1_RX line is low.
+        MINI_UART_ENABLE OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Mini UART Interrupt Enable.
+    AUX_MU_IER [
+        /// If set, an interrupt is generated whenever the receive FIFO holds at least one byte.
+        ///
+        /// The manual swaps the descriptions of bits 0 and 1, see the errata.
+        RX_IRQ OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Mini UART Interrupt Identify.
+    AUX_MU_IIR [
+        /// On read, the source of the pending interrupt. On write, setting bit 1 clears the receive
+        /// FIFO and setting bit 2 clears the transmit FIFO.
+        ID OFFSET(1) NUMBITS(2) [
+            NoInterrupts = 0b00,
+            TxEmpty = 0b01,
+            RxValid = 0b10,
+            ClearFifos = 0b11
+        ],
+
+        /// Clear whenever an interrupt is pending.
+        PENDING OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Line Control.
+    AUX_MU_LCR [
+        /// Data size. The manual lists 0b01 for 8 bits, but 0b11 is needed, see the errata.
+        DATA_SIZE OFFSET(0) NUMBITS(2) [
+            SevenBit = 0b00,
+            EightBit = 0b11
+        ]
+    ],
+
+    /// Mini UART Line Status.
+    AUX_MU_LSR [
+        /// Set if the transmit FIFO is empty and the transmitter is idle.
+        TX_IDLE OFFSET(6) NUMBITS(1) [],
+
+        /// Set if the transmit FIFO can accept at least one byte.
+        TX_EMPTY OFFSET(5) NUMBITS(1) [],
+
+        /// Set if the receive FIFO holds at least one symbol.
+        DATA_READY OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Mini UART Extra Control.
+    AUX_MU_CNTL [
+        /// If set, the transmitter stops while the CTS input is de-asserted.
+        CTS_FLOW OFFSET(3) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// If set, the RTS output is de-asserted while the receive FIFO is almost full.
+        RTS_FLOW OFFSET(2) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Transmitter enable.
+        TX_ENABLE OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Receiver enable.
+        RX_ENABLE OFFSET(0) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Mini UART Baudrate.
+    AUX_MU_BAUD [
+        /// The baud rate counter. Baud rate = system clock / (8 * (BAUD + 1)).
+        BAUD OFFSET(0) NUMBITS(16) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub RegisterBlock {
+        (0x00 => AUX_IRQ: ReadOnly<u32, AUX_IRQ::Register>),
+        (0x04 => AUX_ENABLES: ReadWrite<u32, AUX_ENABLES::Register>),
+        (0x08 => _reserved1),
+        (0x40 => AUX_MU_IO: ReadWrite<u32>),
+        (0x44 => AUX_MU_IER: ReadWrite<u32, AUX_MU_IER::Register>),
+        (0x48 => AUX_MU_IIR: ReadWrite<u32, AUX_MU_IIR::Register>),
+        (0x4C => AUX_MU_LCR: ReadWrite<u32, AUX_MU_LCR::Register>),
+        (0x50 => AUX_MU_MCR: ReadWrite<u32>),
+        (0x54 => AUX_MU_LSR: ReadOnly<u32, AUX_MU_LSR::Register>),
+        (0x58 => _reserved2),
+        (0x60 => AUX_MU_CNTL: ReadWrite<u32, AUX_MU_CNTL::Register>),
+        (0x64 => _reserved3),
+        (0x68 => AUX_MU_BAUD: ReadWrite<u32, AUX_MU_BAUD::Register>),
+        (0x6C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+struct MiniUartInner {
+    registers: Registers,
+    clock_hz: u32,
+    config: UartConfig,
+    rx_buffer: RxBuffer,
+    chars_written: usize,
+    chars_read: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the mini UART.
+pub struct MiniUart {
+    inner: IRQSafeNullLock<MiniUartInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl MiniUartInner {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, clock_hz: u32) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            clock_hz,
+            config: UartConfig::DEFAULT,
+            rx_buffer: RxBuffer::new(),
+            chars_written: 0,
+            chars_read: 0,
+        }
+    }
+
+    /// Apply `config`, with the baud rate divisor computed from `clock_hz`.
+    ///
+    /// Nothing is changed if the settings are not supported by the mini UART or the baud rate can
+    /// not be generated accurately enough from the clock.
+    pub fn configure(&mut self, clock_hz: u32, config: UartConfig) -> Result<(), &'static str> {
+        let divisor = MiniUartDivisor::new(clock_hz, config.baud)?;
+        let data_size = match config.data_bits {
+            7 => AUX_MU_LCR::DATA_SIZE::SevenBit,
+            8 => AUX_MU_LCR::DATA_SIZE::EightBit,
+            _ => return Err("Unsupported number of data bits"),
+        };
+        if config.parity != Parity::None || config.stop_bits != StopBits::One {
+            return Err("Only frames without parity and with one stop bit are supported");
+        }
+        let flow_control = match config.rts_cts {
+            false => AUX_MU_CNTL::RTS_FLOW::Disabled + AUX_MU_CNTL::CTS_FLOW::Disabled,
+            true => AUX_MU_CNTL::RTS_FLOW::Enabled + AUX_MU_CNTL::CTS_FLOW::Enabled,
+        };
+
+        // Transmit pending characters with the old settings first.
+        self.flush();
+
+        self.registers
+            .AUX_ENABLES
+            .modify(AUX_ENABLES::MINI_UART_ENABLE::Enabled);
+
+        // Disable the transmitter and receiver while reprogramming.
+        self.registers.AUX_MU_CNTL.set(0);
+        self.registers.AUX_MU_IER.set(0);
+
+        self.registers.AUX_MU_LCR.write(data_size);
+        self.registers.AUX_MU_MCR.set(0);
+        self.registers
+            .AUX_MU_BAUD
+            .write(AUX_MU_BAUD::BAUD.val(divisor.baud_reg as u32));
+
+        // Clear both FIFOs, and enable the RX IRQ.
+        self.registers.AUX_MU_IIR.write(AUX_MU_IIR::ID::ClearFifos);
+        self.registers.AUX_MU_IER.write(AUX_MU_IER::RX_IRQ::Enabled);
+
+        self.registers.AUX_MU_CNTL.write(
+            AUX_MU_CNTL::TX_ENABLE::Enabled + AUX_MU_CNTL::RX_ENABLE::Enabled + flow_control,
+        );
+
+        self.clock_hz = clock_hz;
+        self.config = config;
+
+        Ok(())
+    }
+
+    /// Set up the UART with the current settings.
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        self.configure(self.clock_hz, self.config)
+    }
+
+    /// Send a character.
+    fn write_char(&mut self, c: char) {
+        // Spin while the TX FIFO is full.
+        while !self
+            .registers
+            .AUX_MU_LSR
+            .matches_all(AUX_MU_LSR::TX_EMPTY::SET)
+        {
+            cpu::nop();
+        }
+
+        self.registers.AUX_MU_IO.set(c as u32);
+
+        self.chars_written += 1;
+    }
+
+    /// Send a slice of characters.
+    fn write_array(&mut self, a: &[char]) {
+        for c in a {
+            self.write_char(*c);
+        }
+    }
+
+    /// Block execution until the last buffered character has been physically put on the TX wire.
+    fn flush(&self) {
+        // A disabled mini UART does not transmit, and its registers are not accessible.
+        if !self
+            .registers
+            .AUX_ENABLES
+            .matches_all(AUX_ENABLES::MINI_UART_ENABLE::Enabled)
+        {
+            return;
+        }
+
+        while !self
+            .registers
+            .AUX_MU_LSR
+            .matches_all(AUX_MU_LSR::TX_IDLE::SET)
+        {
+            cpu::nop();
+        }
+    }
+
+    /// Retrieve a character from the RX FIFO, if any.
+    fn read_char(&mut self) -> Option<char> {
+        if !self
+            .registers
+            .AUX_MU_LSR
+            .matches_all(AUX_MU_LSR::DATA_READY::SET)
+        {
+            return None;
+        }
+
+        let ret = self.registers.AUX_MU_IO.get() as u8 as char;
+        self.chars_read += 1;
+
+        Some(ret)
+    }
+
+    /// Retrieve a character, preferring those that were already buffered by the IRQ handler.
+    fn read_char_buffered(&mut self) -> Option<char> {
+        self.rx_buffer.pop().or_else(|| self.read_char())
+    }
+}
+
+impl fmt::Write for MiniUartInner {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write_char(c);
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl MiniUart {
+    pub const COMPATIBLE: &'static str = "BCM Mini UART";
+
+    /// Create an instance.
+    ///
+    /// `clock_hz` is the frequency of the VPU core clock, from which the baud rate is derived. It
+    /// must not change while the mini UART is in use, e.g. due to frequency scaling.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, clock_hz: u32) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(MiniUartInner::new(mmio_start_addr, clock_hz)),
+        }
+    }
+
+    /// Return the current line settings.
+    pub fn config(&self) -> UartConfig {
+        self.inner.lock(|inner| inner.config)
+    }
+
+    /// Change the line settings at runtime.
+    ///
+    /// Pending output is transmitted with the old settings first. On error, the old settings stay
+    /// in effect.
+    pub fn set_config(&self, config: UartConfig) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.configure(inner.clock_hz, config))
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for MiniUart {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    fn device_file(&'static self) -> Option<&'static (dyn fs::interface::DeviceFile + Sync)> {
+        Some(self)
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init())
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
+
+        // The RX FIFO holds only 8 characters.
+        let descriptor =
+            IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, IRQPriority::HIGHEST, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl console::interface::Write for MiniUart {
+    fn write_char(&self, c: char) {
+        self.inner.lock(|inner| inner.write_char(c));
+    }
+
+    fn write_array(&self, a: &[char]) {
+        self.inner.lock(|inner| inner.write_array(a));
+    }
+
+    fn write_fmt(&self, args: core::fmt::Arguments) -> fmt::Result {
+        self.inner.lock(|inner| fmt::Write::write_fmt(inner, args))
+    }
+
+    fn flush(&self) {
+        self.inner.lock(|inner| inner.flush());
+    }
+}
+
+impl console::interface::Read for MiniUart {
+    fn read_char(&self) -> char {
+        // Don't spin with the lock held, so that the IRQ handler can fill the RX buffer meanwhile.
+        loop {
+            if let Some(c) = self.try_read_char() {
+                return c;
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        self.inner.lock(|inner| inner.read_char_buffered())
+    }
+
+    fn clear_rx(&self) {
+        while self
+            .inner
+            .lock(|inner| inner.read_char_buffered())
+            .is_some()
+        {}
+    }
+}
+
+impl console::interface::Statistics for MiniUart {
+    fn chars_written(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_written)
+    }
+
+    fn chars_read(&self) -> usize {
+        self.inner.lock(|inner| inner.chars_read)
+    }
+}
+
+impl console::interface::All for MiniUart {}
+
+impl fs::interface::DeviceFile for MiniUart {
+    fn file_name(&self) -> &'static str {
+        "uart1"
+    }
+
+    fn read(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        fs::devfs::read_console(self, buffer)
+    }
+
+    fn write(&self, _offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        fs::devfs::write_console(self, data)
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for MiniUart {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
+        self.inner.lock(|inner| {
+            // The AUX IRQ is shared with the SPI masters.
+            if !inner
+                .registers
+                .AUX_IRQ
+                .matches_all(AUX_IRQ::MINI_UART_IRQ::SET)
+            {
+                return Ok(IRQReturn::NotHandled);
+            }
+
+            // The RX IRQ is cleared by emptying the RX FIFO.
+            while let Some(c) = inner.read_char() {
+                inner.rx_buffer.push(c)
+            }
+
+            Ok(IRQReturn::Handled)
+        })
+    }
+}
@@ -10,9 +10,16 @@
 //! - <https://developer.arm.com/documentation/ddi0183/latest>
 
 use crate::{
-    bsp::device_driver::common::MMIODerefWrapper,
+    bsp::device_driver::{
+        common::{MMIODerefWrapper, RxBuffer},
+        Parity, StopBits, UartConfig,
+    },
     console, cpu, driver,
-    exception::{self, asynchronous::IRQNumber},
+    exception::{
+        self,
+        asynchronous::{IRQNumber, IRQReturn},
+    },
+    fs,
     memory::{Address, Virtual},
     synchronization,
     synchronization::IRQSafeNullLock,
@@ -23,6 +30,7 @@ use tock_registers::{
     register_bitfields, register_structs,
     registers::{ReadOnly, ReadWrite, WriteOnly},
 };
+use uart_divisor::PL011Divisor;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -100,11 +108,47 @@ register_bitfields! {
         FEN  OFFSET(4) NUMBITS(1) [
             FifosDisabled = 0,
             FifosEnabled = 1
+        ],
+
+        /// Two stop bits select. If this bit is set to 1, two stop bits are transmitted at the end
+        /// of the frame. The receive logic does not check for two stop bits being received.
+        STP2 OFFSET(3) NUMBITS(1) [
+            OneStopBit = 0,
+            TwoStopBits = 1
+        ],
+
+        /// Even parity select. Controls the type of parity the UART uses during transmission and
+        /// reception. This bit has no effect when the PEN bit disables parity checking and
+        /// generation.
+        EPS OFFSET(2) NUMBITS(1) [
+            OddParity = 0,
+            EvenParity = 1
+        ],
+
+        /// Parity enable.
+        PEN OFFSET(1) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
         ]
     ],
 
     /// Control Register.
     CR [
+        /// CTS hardware flow control enable. If this bit is set to 1, CTS hardware flow control is
+        /// enabled. Data is only transmitted when the nUARTCTS signal is asserted.
+        CTSEN OFFSET(15) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// RTS hardware flow control enable. If this bit is set to 1, RTS hardware flow control is
+        /// enabled. Data is only requested when there is space in the receive FIFO for it to be
+        /// received.
+        RTSEN OFFSET(14) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
         /// Receive enable. If this bit is set to 1, the receive section of the UART is enabled.
         /// Data reception occurs for either UART signals or SIR signals depending on the setting of
         /// the SIREN bit. When the UART is disabled in the middle of reception, it completes the
@@ -214,14 +258,11 @@ register_structs! {
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
-#[derive(PartialEq)]
-enum BlockingMode {
-    Blocking,
-    NonBlocking,
-}
-
 struct PL011UartInner {
     registers: Registers,
+    clock_hz: u32,
+    config: UartConfig,
+    rx_buffer: RxBuffer,
     chars_written: usize,
     chars_read: usize,
 }
@@ -245,38 +286,49 @@ impl PL011UartInner {
     /// # Safety
     ///
     /// - The user must ensure to provide a correct MMIO start address.
-    pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, clock_hz: u32) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
+            clock_hz,
+            config: UartConfig::DEFAULT,
+            rx_buffer: RxBuffer::new(),
             chars_written: 0,
             chars_read: 0,
         }
     }
 
-    /// Set up baud rate and characteristics.
-    ///
-    /// This results in 8N1 and 921_600 baud.
-    ///
-    /// The calculation for the BRD is (we set the clock to 48 MHz in config.txt):
-    /// `(48_000_000 / 16) / 921_600 = 3.2552083`.
-    ///
-    /// This means the integer part is `3` and goes into the `IBRD`.
-    /// The fractional part is `0.2552083`.
-    ///
-    /// `FBRD` calculation according to the PL011 Technical Reference Manual:
-    /// `INTEGER((0.2552083 * 64) + 0.5) = 16`.
+    /// Apply `config`, with the baud rate divisor computed from `clock_hz`.
     ///
-    /// Therefore, the generated baud rate divider is: `3 + 16/64 = 3.25`. Which results in a
-    /// genrated baud rate of `48_000_000 / (16 * 3.25) = 923_077`.
-    ///
-    /// Error = `((923_077 - 921_600) / 921_600) * 100 = 0.16%`.
-    pub fn init(&mut self) {
+    /// Nothing is changed if the baud rate can not be generated accurately enough from the clock.
+    pub fn configure(&mut self, clock_hz: u32, config: UartConfig) -> Result<(), &'static str> {
+        let divisor = PL011Divisor::new(clock_hz, config.baud)?;
+        let wlen = match config.data_bits {
+            5 => LCR_H::WLEN::FiveBit,
+            6 => LCR_H::WLEN::SixBit,
+            7 => LCR_H::WLEN::SevenBit,
+            8 => LCR_H::WLEN::EightBit,
+            _ => return Err("Unsupported number of data bits"),
+        };
+        let parity = match config.parity {
+            Parity::None => LCR_H::PEN::Disabled,
+            Parity::Even => LCR_H::PEN::Enabled + LCR_H::EPS::EvenParity,
+            Parity::Odd => LCR_H::PEN::Enabled + LCR_H::EPS::OddParity,
+        };
+        let stop_bits = match config.stop_bits {
+            StopBits::One => LCR_H::STP2::OneStopBit,
+            StopBits::Two => LCR_H::STP2::TwoStopBits,
+        };
+        let flow_control = match config.rts_cts {
+            false => CR::RTSEN::Disabled + CR::CTSEN::Disabled,
+            true => CR::RTSEN::Enabled + CR::CTSEN::Enabled,
+        };
+
         // Execution can arrive here while there are still characters queued in the TX FIFO and
         // actively being sent out by the UART hardware. If the UART is turned off in this case,
         // those queued characters would be lost.
         //
-        // For example, this can happen during runtime on a call to panic!(), because panic!()
-        // initializes its own UART instance and calls init().
+        // For example, this can happen during runtime on a reconfiguration or a call to panic!(),
+        // because panic!() initializes its own UART instance and calls init().
         //
         // Hence, flush first to ensure all pending characters are transmitted.
         self.flush();
@@ -293,12 +345,16 @@ impl PL011UartInner {
         // updated on a single write strobe generated by a LCR_H write. So, to internally update the
         // contents of IBRD or FBRD, a LCR_H write must always be performed at the end.
         //
-        // Set the baud rate, 8N1 and FIFO enabled.
-        self.registers.IBRD.write(IBRD::BAUD_DIVINT.val(3));
-        self.registers.FBRD.write(FBRD::BAUD_DIVFRAC.val(16));
+        // Set the baud rate, framing and FIFO enabled.
+        self.registers
+            .IBRD
+            .write(IBRD::BAUD_DIVINT.val(divisor.integer as u32));
+        self.registers
+            .FBRD
+            .write(FBRD::BAUD_DIVFRAC.val(divisor.fractional as u32));
         self.registers
             .LCR_H
-            .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
+            .write(wlen + parity + stop_bits + LCR_H::FEN::FifosEnabled);
 
         // Set RX FIFO fill level at 1/8.
         self.registers.IFLS.write(IFLS::RXIFLSEL::OneEigth);
@@ -311,7 +367,17 @@ impl PL011UartInner {
         // Turn the UART on.
         self.registers
             .CR
-            .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
+            .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled + flow_control);
+
+        self.clock_hz = clock_hz;
+        self.config = config;
+
+        Ok(())
+    }
+
+    /// Set up the UART with the current settings.
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        self.configure(self.clock_hz, self.config)
     }
 
     /// Send a character.
@@ -342,34 +408,28 @@ impl PL011UartInner {
         }
     }
 
-    /// Retrieve a character.
-    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
-        // If RX FIFO is empty,
+    /// Retrieve a character from the RX FIFO, if any.
+    ///
+    /// Characters are passed on unmodified. Translations, e.g. of carriage returns, are up to the
+    /// TTY layer.
+    fn read_char(&mut self) -> Option<char> {
         if self.registers.FR.matches_all(FR::RXFE::SET) {
-            // immediately return in non-blocking mode.
-            if blocking_mode == BlockingMode::NonBlocking {
-                return None;
-            }
-
-            // Otherwise, wait until a char was received.
-            while self.registers.FR.matches_all(FR::RXFE::SET) {
-                cpu::nop();
-            }
+            return None;
         }
 
         // Read one character.
-        let mut ret = self.registers.DR.get() as u8 as char;
-
-        // Convert carrige return to newline.
-        if ret == '\r' {
-            ret = '\n'
-        }
+        let ret = self.registers.DR.get() as u8 as char;
 
         // Update statistics.
         self.chars_read += 1;
 
         Some(ret)
     }
+
+    /// Retrieve a character, preferring those that were already buffered by the IRQ handler.
+    fn read_char_buffered(&mut self) -> Option<char> {
+        self.rx_buffer.pop().or_else(|| self.read_char())
+    }
 }
 
 /// Implementing `core::fmt::Write` enables usage of the `format_args!` macros, which in turn are
@@ -400,14 +460,31 @@ impl PL011Uart {
 
     /// Create an instance.
     ///
+    /// `clock_hz` is the frequency of the UART reference clock, from which the baud rate is
+    /// derived.
+    ///
     /// # Safety
     ///
     /// - The user must ensure to provide a correct MMIO start address.
-    pub const unsafe fn new(mmio_start_addr: Address<Virtual>) -> Self {
+    pub const unsafe fn new(mmio_start_addr: Address<Virtual>, clock_hz: u32) -> Self {
         Self {
-            inner: IRQSafeNullLock::new(PL011UartInner::new(mmio_start_addr)),
+            inner: IRQSafeNullLock::new(PL011UartInner::new(mmio_start_addr, clock_hz)),
         }
     }
+
+    /// Return the current line settings.
+    pub fn config(&self) -> UartConfig {
+        self.inner.lock(|inner| inner.config)
+    }
+
+    /// Change the line settings at runtime.
+    ///
+    /// Pending output is transmitted with the old settings first. On error, the old settings stay
+    /// in effect.
+    pub fn set_config(&self, config: UartConfig) -> Result<(), &'static str> {
+        self.inner
+            .lock(|inner| inner.configure(inner.clock_hz, config))
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -422,19 +499,24 @@ impl driver::interface::DeviceDriver for PL011Uart {
         Self::COMPATIBLE
     }
 
-    unsafe fn init(&self) -> Result<(), &'static str> {
-        self.inner.lock(|inner| inner.init());
+    fn device_file(&'static self) -> Option<&'static (dyn fs::interface::DeviceFile + Sync)> {
+        Some(self)
+    }
 
-        Ok(())
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        self.inner.lock(|inner| inner.init())
     }
 
     fn register_and_enable_irq_handler(
         &'static self,
         irq_number: &Self::IRQNumberType,
     ) -> Result<(), &'static str> {
-        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
 
-        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+        // The RX FIFO is small. Make sure incoming characters are picked up even while slower
+        // handlers, e.g. timer callbacks, are running.
+        let descriptor =
+            IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, IRQPriority::HIGHEST, self);
 
         irq_manager().register_handler(descriptor)?;
         irq_manager().enable(irq_number);
@@ -468,15 +550,25 @@ impl console::interface::Write for PL011Uart {
 
 impl console::interface::Read for PL011Uart {
     fn read_char(&self) -> char {
-        self.inner
-            .lock(|inner| inner.read_char_converting(BlockingMode::Blocking).unwrap())
+        // Don't spin with the lock held, so that the IRQ handler can fill the RX buffer meanwhile.
+        loop {
+            if let Some(c) = self.try_read_char() {
+                return c;
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        self.inner.lock(|inner| inner.read_char_buffered())
     }
 
     fn clear_rx(&self) {
-        // Read from the RX FIFO until it is indicating empty.
+        // Drain the RX buffer and the RX FIFO until both are indicating empty.
         while self
             .inner
-            .lock(|inner| inner.read_char_converting(BlockingMode::NonBlocking))
+            .lock(|inner| inner.read_char_buffered())
             .is_some()
         {}
     }
@@ -494,23 +586,41 @@ impl console::interface::Statistics for PL011Uart {
 
 impl console::interface::All for PL011Uart {}
 
+impl fs::interface::DeviceFile for PL011Uart {
+    fn file_name(&self) -> &'static str {
+        "uart0"
+    }
+
+    fn read(&self, _offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        fs::devfs::read_console(self, buffer)
+    }
+
+    fn write(&self, _offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        fs::devfs::write_console(self, data)
+    }
+}
+
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
-    fn handle(&self) -> Result<(), &'static str> {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
         self.inner.lock(|inner| {
             let pending = inner.registers.MIS.extract();
 
+            if pending.get() == 0 {
+                return Ok(IRQReturn::NotHandled);
+            }
+
             // Clear all pending IRQs.
             inner.registers.ICR.write(ICR::ALL::CLEAR);
 
             // Check for any kind of RX interrupt.
             if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
-                // Echo any received characters.
-                while let Some(c) = inner.read_char_converting(BlockingMode::NonBlocking) {
-                    inner.write_char(c)
+                // Buffer any received characters until they are read by the console user.
+                while let Some(c) = inner.read_char() {
+                    inner.rx_buffer.push(c)
                 }
             }
-        });
 
-        Ok(())
+            Ok(IRQReturn::Handled)
+        })
     }
 }
@@ -7,6 +7,13 @@
 use crate::memory::{Address, Virtual};
 use core::{fmt, marker::PhantomData, ops};
 
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the software RX buffer of UART drivers.
+const RX_BUFFER_SIZE: usize = 256;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -20,6 +27,53 @@ pub struct MMIODerefWrapper<T> {
 #[derive(Copy, Clone)]
 pub struct BoundedUsize<const MAX_INCLUSIVE: usize>(usize);
 
+/// Characters that were picked up by a UART's IRQ handler, but not yet read by the console user.
+///
+/// If full, newly received characters are dropped.
+pub struct RxBuffer {
+    buf: [char; RX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+/// Parity of a UART frame.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits of a UART frame.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Line settings of a UART.
+///
+/// Not every UART supports every setting. Drivers reject unsupported ones.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UartConfig {
+    /// The baud rate.
+    pub baud: u32,
+
+    /// Data bits per frame.
+    pub data_bits: u8,
+
+    /// The parity.
+    pub parity: Parity,
+
+    /// The number of stop bits.
+    pub stop_bits: StopBits,
+
+    /// Use RTS/CTS hardware flow control. The BSP must route the RTS and CTS signals to pins.
+    pub rts_cts: bool,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -63,3 +117,48 @@ impl<const MAX_INCLUSIVE: usize> fmt::Display for BoundedUsize<{ MAX_INCLUSIVE }
         write!(f, "{}", self.0)
     }
 }
+
+impl RxBuffer {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            buf: ['\0'; RX_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Append a character. Dropped if the buffer is full.
+    pub fn push(&mut self, c: char) {
+        if self.len == RX_BUFFER_SIZE {
+            return;
+        }
+
+        self.buf[(self.head + self.len) % RX_BUFFER_SIZE] = c;
+        self.len += 1;
+    }
+
+    /// Remove the oldest character.
+    pub fn pop(&mut self) -> Option<char> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let c = self.buf[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.len -= 1;
+
+        Some(c)
+    }
+}
+
+impl UartConfig {
+    /// 921_600 baud, 8N1 and no flow control.
+    pub const DEFAULT: Self = Self {
+        baud: 921_600,
+        data_bits: 8,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        rts_cts: false,
+    };
+}
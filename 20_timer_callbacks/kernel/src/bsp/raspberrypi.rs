@@ -9,19 +9,33 @@ pub mod driver;
 pub mod exception;
 pub mod memory;
 
+use crate::device_tree;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Part number of the Cortex-A72 cores of the RPi 4. The RPi 3 has Cortex-A53 cores.
+const CORTEX_A72_PART_NUMBER: u16 = 0xD08;
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
-/// Board identification.
+/// Board identification, from the model in the device tree.
 pub fn board_name() -> &'static str {
-    #[cfg(feature = "bsp_rpi3")]
-    {
-        "Raspberry Pi 3"
-    }
+    device_tree::device_tree()
+        .ok()
+        .and_then(|device_tree| device_tree.model())
+        .unwrap_or("Raspberry Pi")
+}
 
-    #[cfg(feature = "bsp_rpi4")]
-    {
-        "Raspberry Pi 4"
+/// The device tree of the board, for when the firmware does not pass one, e.g. in QEMU.
+///
+/// The boards are told apart by their cores.
+pub fn builtin_device_tree() -> &'static [u8] {
+    match crate::cpu::part_number() {
+        CORTEX_A72_PART_NUMBER => include_bytes!("../../../devicetree/rpi4b.dtb"),
+        _ => include_bytes!("../../../devicetree/rpi3b.dtb"),
     }
 }
@@ -4,106 +4,604 @@
 
 //! BSP driver support.
 
-use super::{exception, memory::map::mmio};
+use super::{
+    exception::asynchronous::{irq_map, InterruptController},
+    memory as bsp_memory,
+};
 use crate::{
+    block,
     bsp::device_driver,
-    console, driver as generic_driver,
-    exception::{self as generic_exception},
-    memory,
-    memory::mmu::MMIODescriptor,
+    console, device_tree, driver as generic_driver,
+    exception::{self as generic_exception, asynchronous::IRQNumber},
+    info, memory,
+    memory::{mmu::MMIODescriptor, Address, Physical, Virtual},
+    println, shell, warn,
 };
+use alloc::format;
 use core::{
     mem::MaybeUninit,
     sync::atomic::{AtomicBool, Ordering},
 };
+use uart_divisor::PL011Divisor;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Default frequency of the PL011 UART reference clock, as set with `init_uart_clock` in
+/// `config.txt`. Only used if there is no mailbox to ask for the actual rate.
+const PL011_UART_CLOCK_HZ: u32 = 48_000_000;
+
+/// Offset at which the VideoCore sees the ARM's physical memory, uncached. Matches the DMA ranges
+/// of both SoCs.
+const VIDEOCORE_BUS_ADDR_OFFSET: usize = 0xC000_0000;
+
+/// Requested size of the framebuffer console in pixels.
+const FRAMEBUFFER_WIDTH: u32 = 1024;
+const FRAMEBUFFER_HEIGHT: u32 = 768;
+
+/// The framebuffer console only draws 32 bit pixels.
+const FRAMEBUFFER_DEPTH: u32 = 32;
+
+// The console UART is routed to the 40-pin header. The other one is left for data protocols, e.g.
+// with the on-board Bluetooth controller.
+#[cfg(not(feature = "console_mini_uart"))]
+const PL011_UART_PINS: device_driver::UartPins = device_driver::UartPins::Gpio14_15;
+#[cfg(not(feature = "console_mini_uart"))]
+const MINI_UART_PINS: device_driver::UartPins = device_driver::UartPins::Gpio32_33;
+
+#[cfg(feature = "console_mini_uart")]
+const PL011_UART_PINS: device_driver::UartPins = device_driver::UartPins::Gpio32_33;
+#[cfg(feature = "console_mini_uart")]
+const MINI_UART_PINS: device_driver::UartPins = device_driver::UartPins::Gpio14_15;
+
+/// The console UART is the serial line to the host, so it also carries binary log frames.
+///
+/// In binary log mode, escape sequences, e.g. of the status line, would be interleaved with the
+/// frames and corrupt them. So ANSI is disabled for it.
+const CONSOLE_UART_FLAGS: console::ConsoleFlags = console::ConsoleFlags {
+    ansi: cfg!(not(feature = "binary_log")),
+    binary_log: true,
+    ..console::ConsoleFlags::ALL
+};
+
+/// Binary log frames must pass the console UART unmodified, so its output is raw in binary log
+/// mode. Input is still processed, so that the shell remains usable.
+#[cfg(not(feature = "binary_log"))]
+const CONSOLE_UART_TERMIOS: console::tty::Termios = console::tty::Termios::COOKED;
+#[cfg(feature = "binary_log")]
+const CONSOLE_UART_TERMIOS: console::tty::Termios = console::tty::Termios {
+    output_lf_to_crlf: false,
+    ..console::tty::Termios::COOKED
+};
+
+type DeviceResources = generic_driver::DeviceResources<IRQNumber>;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Export of the GPIO pin API.
+pub use device_driver::{
+    Pin as GpioPin, PinEvent as GpioPinEvent, PinEventHandler as GpioPinEventHandler,
+    PinFunction as GpioPinFunction, PinPull as GpioPinPull,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
+static mut MAILBOX: MaybeUninit<device_driver::Mailbox> = MaybeUninit::uninit();
+static MAILBOX_READY: AtomicBool = AtomicBool::new(false);
 static mut PL011_UART: MaybeUninit<device_driver::PL011Uart> = MaybeUninit::uninit();
+static PL011_UART_READY: AtomicBool = AtomicBool::new(false);
+static mut MINI_UART: MaybeUninit<device_driver::MiniUart> = MaybeUninit::uninit();
+static MINI_UART_READY: AtomicBool = AtomicBool::new(false);
+static mut FRAMEBUFFER_CONSOLE: MaybeUninit<device_driver::FramebufferConsole> =
+    MaybeUninit::uninit();
+static mut EMMC: MaybeUninit<device_driver::Emmc> = MaybeUninit::uninit();
 static mut GPIO: MaybeUninit<device_driver::GPIO> = MaybeUninit::uninit();
+static GPIO_READY: AtomicBool = AtomicBool::new(false);
 
-#[cfg(feature = "bsp_rpi3")]
-static mut INTERRUPT_CONTROLLER: MaybeUninit<device_driver::InterruptController> =
-    MaybeUninit::uninit();
+static mut INTERRUPT_CONTROLLER: MaybeUninit<InterruptController> = MaybeUninit::uninit();
 
-#[cfg(feature = "bsp_rpi4")]
-static mut INTERRUPT_CONTROLLER: MaybeUninit<device_driver::GICv2> = MaybeUninit::uninit();
+/// Probe state of the interrupt controller. The board has either of the supported ones.
+static INTERRUPT_CONTROLLER_PROBED: AtomicBool = AtomicBool::new(false);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// Fail if the driver whose probe state is `probed` has been probed already. The BSP has a single
+/// instance of each driver.
+fn probe_once(probed: &AtomicBool) -> Result<(), &'static str> {
+    match probed.swap(true, Ordering::Relaxed) {
+        false => Ok(()),
+        true => Err("Only a single device is supported"),
+    }
+}
+
+/// The mailbox, which other drivers query during probe.
+///
+/// Probes that need it are deferred until it is probed.
+fn mailbox() -> Result<&'static device_driver::Mailbox, &'static str> {
+    if !MAILBOX_READY.load(Ordering::Acquire) {
+        return Err(generic_driver::PROBE_DEFERRED);
+    }
+
+    Ok(unsafe { MAILBOX.assume_init_ref() })
+}
+
+/// Rate of the PL011 UART reference clock.
+///
+/// Asks the mailbox if the device tree has one, so the probe is deferred until it is probed.
+/// Otherwise, or if the reported rate cannot generate the default baud rate, falls back to the
+/// default rate.
+fn pl011_uart_clock_hz() -> Result<u32, &'static str> {
+    match mailbox() {
+        Ok(mailbox) => Ok(PL011Divisor::usable_clock_hz(
+            mailbox.get_clock_rate(device_driver::ClockId::Uart)?,
+            PL011_UART_CLOCK_HZ,
+            device_driver::UartConfig::DEFAULT.baud,
+        )),
+        Err(x) => {
+            let has_mailbox = device_tree::device_tree()?
+                .find_compatible("brcm,bcm2835-mbox")
+                .any(|node| node.is_enabled());
+
+            match has_mailbox {
+                true => Err(x),
+                false => Ok(PL011_UART_CLOCK_HZ),
+            }
+        }
+    }
+}
+
 /// This must be called only after successful init of the memory subsystem.
-unsafe fn instantiate_uart() -> Result<(), &'static str> {
-    let mmio_descriptor = MMIODescriptor::new(mmio::PL011_UART_START, mmio::PL011_UART_SIZE);
+unsafe fn instantiate_mailbox(mmio_descriptor: &MMIODescriptor) -> Result<(), &'static str> {
     let virt_addr =
-        memory::mmu::kernel_map_mmio(device_driver::PL011Uart::COMPATIBLE, &mmio_descriptor)?;
+        memory::mmu::kernel_map_mmio(device_driver::Mailbox::COMPATIBLE, mmio_descriptor)?;
+
+    // The mailbox is the only user of the DMA segment so far, so it gets all of it.
+    let dma_region = bsp_memory::mmu::virt_dma_region();
+    let buffer_virt_addr: Address<Virtual> = dma_region.start_page_addr().into_inner();
+    let buffer_phys_addr =
+        memory::mmu::try_kernel_virt_page_addr_to_phys_page_addr(dma_region.start_page_addr())?;
+    let buffer_bus_addr = (buffer_phys_addr.into_inner().as_usize() | VIDEOCORE_BUS_ADDR_OFFSET)
+        .try_into()
+        .map_err(|_| "DMA segment not reachable by the VideoCore")?;
+
+    MAILBOX.write(device_driver::Mailbox::new(
+        virt_addr,
+        buffer_virt_addr,
+        buffer_bus_addr,
+        dma_region.size(),
+    ));
+    MAILBOX_READY.store(true, Ordering::Release);
 
-    PL011_UART.write(device_driver::PL011Uart::new(virt_addr));
+    Ok(())
+}
+
+/// Return the last address of the ARM memory reported by the firmware.
+///
+/// Fails on an empty region or one that extends beyond the 32 bit address space.
+fn arm_memory_end(memory_base: u32, memory_size: u32) -> Result<u32, &'static str> {
+    memory_size
+        .checked_sub(1)
+        .and_then(|last_offset| memory_base.checked_add(last_offset))
+        .ok_or("Firmware reports invalid ARM memory")
+}
+
+/// This must be called only after successful init of the mailbox driver.
+unsafe fn post_init_mailbox() -> Result<(), &'static str> {
+    let mailbox = MAILBOX.assume_init_ref();
+    let revision = mailbox.get_board_revision()?;
+    let (memory_base, memory_size) = mailbox.get_arm_memory()?;
+
+    info!("Firmware reports board revision {:#x}", revision);
+    info!(
+        "Firmware reports ARM memory: {:#010x} - {:#010x} | {} MiB",
+        memory_base,
+        arm_memory_end(memory_base, memory_size)?,
+        memory_size / 1024 / 1024
+    );
+
+    shell::register_command(shell::Command::new(
+        "board",
+        "board [clock <name> <rate>]: Show firmware info and clocks, or set a clock rate",
+        cmd_board,
+    ))
+}
+
+fn print_board_info(mailbox: &device_driver::Mailbox) -> Result<(), &'static str> {
+    let mut message = device_driver::PropertyMessage::new();
+    let revision = message.add(&device_driver::GetBoardRevision);
+    let serial = message.add(&device_driver::GetBoardSerial);
+    let mac = message.add(&device_driver::GetMacAddress);
+    let arm_memory = message.add(&device_driver::GetArmMemory);
+    mailbox.call(&mut message)?;
+
+    let (memory_base, memory_size) = message.response(&arm_memory)?;
+    let [a, b, c, d, e, f] = message.response(&mac)?;
+
+    println!("Revision:   {:#x}", message.response(&revision)?);
+    println!("Serial:     {:#018x}", message.response(&serial)?);
+    println!(
+        "MAC:        {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        a, b, c, d, e, f
+    );
+    println!(
+        "ARM memory: {:#010x} - {:#010x} | {} Byte",
+        memory_base,
+        arm_memory_end(memory_base, memory_size)?,
+        memory_size
+    );
+
+    for clock in device_driver::ClockId::ALL {
+        match mailbox.get_clock_rate(clock) {
+            Ok(rate) => println!("Clock {:<6} {} Hz", format!("{:?}", clock), rate),
+            Err(x) => println!("Clock {:<6} {}", format!("{:?}", clock), x),
+        }
+    }
 
     Ok(())
 }
 
-/// This must be called only after successful init of the UART driver.
-unsafe fn post_init_uart() -> Result<(), &'static str> {
-    console::register_console(PL011_UART.assume_init_ref());
+fn cmd_board(args: &[&str]) -> Result<(), &'static str> {
+    let mailbox = unsafe { MAILBOX.assume_init_ref() };
+
+    match args {
+        [] => print_board_info(mailbox),
+        ["clock", name, rate] => {
+            let clock = device_driver::ClockId::ALL
+                .into_iter()
+                .find(|clock| format!("{:?}", clock).eq_ignore_ascii_case(name))
+                .ok_or("Unknown clock")?;
+            let rate = rate.parse().map_err(|_| "Invalid rate")?;
+
+            let rate = mailbox.set_clock_rate(clock, rate)?;
+            println!("Clock {:?} set to {} Hz", clock, rate);
+
+            Ok(())
+        }
+        _ => Err("Usage: board [clock <name> <rate>]"),
+    }
+}
+
+/// This must be called only after successful init of the memory subsystem.
+///
+/// `clock_hz` is the rate of the UART reference clock.
+unsafe fn instantiate_uart(
+    mmio_descriptor: &MMIODescriptor,
+    clock_hz: u32,
+) -> Result<(), &'static str> {
+    let virt_addr =
+        memory::mmu::kernel_map_mmio(device_driver::PL011Uart::COMPATIBLE, mmio_descriptor)?;
+
+    PL011_UART.write(device_driver::PL011Uart::new(virt_addr, clock_hz));
+    PL011_UART_READY.store(true, Ordering::Release);
 
     Ok(())
 }
 
 /// This must be called only after successful init of the memory subsystem.
-unsafe fn instantiate_gpio() -> Result<(), &'static str> {
-    let mmio_descriptor = MMIODescriptor::new(mmio::GPIO_START, mmio::GPIO_SIZE);
+///
+/// The MMIO region is the one of the auxiliary peripherals, which include the mini UART. `clock_hz`
+/// is the rate of the VPU core clock. The firmware keeps it fixed if `enable_uart=1` is set in
+/// `config.txt`.
+unsafe fn instantiate_mini_uart(
+    aux_mmio_descriptor: &MMIODescriptor,
+    clock_hz: u32,
+) -> Result<(), &'static str> {
     let virt_addr =
-        memory::mmu::kernel_map_mmio(device_driver::GPIO::COMPATIBLE, &mmio_descriptor)?;
+        memory::mmu::kernel_map_mmio(device_driver::MiniUart::COMPATIBLE, aux_mmio_descriptor)?;
+
+    MINI_UART.write(device_driver::MiniUart::new(virt_addr, clock_hz));
+    MINI_UART_READY.store(true, Ordering::Release);
+
+    Ok(())
+}
+
+/// This must be called only after successful init of the console UART driver.
+unsafe fn post_init_console_uart() -> Result<(), &'static str> {
+    console::tty::register_tty(console_uart(), CONSOLE_UART_TERMIOS, CONSOLE_UART_FLAGS);
+
+    shell::register_command(shell::Command::new(
+        "uart",
+        "uart <pl011|mini> [<baud> [<bits><N|E|O><stop bits>] [rtscts]]: Show or change UART settings",
+        cmd_uart,
+    ))
+}
+
+/// The UART that carries the console.
+///
+/// # Safety
+///
+/// - Must be called only after successful instantiation of the UART.
+unsafe fn console_uart() -> &'static (dyn console::interface::All + Sync) {
+    #[cfg(not(feature = "console_mini_uart"))]
+    return PL011_UART.assume_init_ref();
+
+    #[cfg(feature = "console_mini_uart")]
+    return MINI_UART.assume_init_ref();
+}
+
+/// Parse a frame format like `8N1`.
+fn parse_framing(
+    framing: &str,
+) -> Result<(u8, device_driver::Parity, device_driver::StopBits), &'static str> {
+    let mut chars = framing.chars();
+    let (data_bits, parity, stop_bits) = match (chars.next(), chars.next(), chars.next()) {
+        (Some(d), Some(p), Some(s)) if chars.next().is_none() => (d, p, s),
+        _ => return Err("Frame format must look like 8N1"),
+    };
+
+    let data_bits = data_bits
+        .to_digit(10)
+        .ok_or("Invalid number of data bits")? as u8;
+    let parity = match parity.to_ascii_uppercase() {
+        'N' => device_driver::Parity::None,
+        'E' => device_driver::Parity::Even,
+        'O' => device_driver::Parity::Odd,
+        _ => return Err("Parity must be N, E or O"),
+    };
+    let stop_bits = match stop_bits {
+        '1' => device_driver::StopBits::One,
+        '2' => device_driver::StopBits::Two,
+        _ => return Err("Stop bits must be 1 or 2"),
+    };
+
+    Ok((data_bits, parity, stop_bits))
+}
 
-    GPIO.write(device_driver::GPIO::new(virt_addr));
+/// Print `config` if `args` is empty, otherwise apply the settings from `args` with `set_config`.
+fn configure_uart(
+    mut config: device_driver::UartConfig,
+    args: &[&str],
+    set_config: impl FnOnce(device_driver::UartConfig) -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    let (baud, rest) = match args {
+        [] => {
+            println!("{:?}", config);
+            return Ok(());
+        }
+        [baud, rest @ ..] => (baud, rest),
+    };
+    config.baud = baud.parse().map_err(|_| "Invalid baud rate")?;
+    config.rts_cts = false;
+
+    for arg in rest {
+        if *arg == "rtscts" {
+            config.rts_cts = true;
+        } else {
+            (config.data_bits, config.parity, config.stop_bits) = parse_framing(arg)?;
+        }
+    }
+
+    set_config(config)
+}
+
+fn cmd_uart(args: &[&str]) -> Result<(), &'static str> {
+    match args {
+        ["pl011", rest @ ..] => {
+            if !PL011_UART_READY.load(Ordering::Acquire) {
+                return Err("PL011 UART not available");
+            }
+
+            let uart = unsafe { PL011_UART.assume_init_ref() };
+            configure_uart(uart.config(), rest, |config| uart.set_config(config))
+        }
+        ["mini", rest @ ..] => {
+            if !MINI_UART_READY.load(Ordering::Acquire) {
+                return Err("Mini UART not available");
+            }
+
+            let uart = unsafe { MINI_UART.assume_init_ref() };
+            configure_uart(uart.config(), rest, |config| uart.set_config(config))
+        }
+        _ => Err("Usage: uart <pl011|mini> [<baud> [<bits><N|E|O><stop bits>] [rtscts]]"),
+    }
+}
+
+/// This must be called only after successful init of the memory subsystem.
+unsafe fn instantiate_framebuffer_console() -> Result<(), &'static str> {
+    let info = mailbox()?.allocate_framebuffer(
+        FRAMEBUFFER_WIDTH,
+        FRAMEBUFFER_HEIGHT,
+        FRAMEBUFFER_DEPTH,
+    )?;
+    if info.depth != FRAMEBUFFER_DEPTH {
+        return Err("Unsupported framebuffer depth");
+    }
+
+    // The framebuffer is outside of the kernel image, so it is mapped into the MMIO remap region.
+    let phys_addr = Address::<Physical>::new(info.bus_addr as usize & !VIDEOCORE_BUS_ADDR_OFFSET);
+    let mmio_descriptor = MMIODescriptor::new(phys_addr, info.size as usize);
+    let virt_addr = memory::mmu::kernel_map_mmio_non_cacheable(
+        device_driver::FramebufferConsole::COMPATIBLE,
+        &mmio_descriptor,
+    )?;
+
+    FRAMEBUFFER_CONSOLE.write(device_driver::FramebufferConsole::new(virt_addr, info));
+
+    Ok(())
+}
+
+/// This must be called only after successful init of the framebuffer console driver.
+unsafe fn post_init_framebuffer_console() -> Result<(), &'static str> {
+    console::register_console(
+        FRAMEBUFFER_CONSOLE.assume_init_ref(),
+        console::ConsoleFlags::OUTPUT_ONLY,
+    );
+
+    Ok(())
+}
+
+/// Level events stay masked afterwards until `gpio <pin> ack`, so that a persisting level does not
+/// flood the console.
+fn print_gpio_event(pin: usize) {
+    info!("GPIO pin {}: Event detected", pin);
+}
+
+fn parse_gpio_event(event: &str) -> Result<GpioPinEvent, &'static str> {
+    Ok(match event {
+        "rising" => GpioPinEvent::RisingEdge,
+        "falling" => GpioPinEvent::FallingEdge,
+        "high" => GpioPinEvent::High,
+        "low" => GpioPinEvent::Low,
+        "async-rising" => GpioPinEvent::AsyncRisingEdge,
+        "async-falling" => GpioPinEvent::AsyncFallingEdge,
+        _ => return Err("Event must be rising, falling, high, low, async-rising or async-falling"),
+    })
+}
+
+fn cmd_gpio(args: &[&str]) -> Result<(), &'static str> {
+    let (number, action) = match args {
+        [] => {
+            info!("Claimed GPIO pins:");
+            unsafe { GPIO.assume_init_ref() }.print_owners();
+            return Ok(());
+        }
+        [number, action @ ..] => (number, action),
+    };
+    let number = number.parse().map_err(|_| "Invalid pin number")?;
+
+    // A watched pin stays claimed, so its level events are acknowledged without claiming it.
+    if action == ["ack"] {
+        unsafe { GPIO.assume_init_ref() }.acknowledge_events(number);
+        return Ok(());
+    }
+
+    let pin = claim_gpio_pin(number, "shell")?;
+
+    match action {
+        [] => println!("{}", if pin.read() { "high" } else { "low" }),
+        ["in"] => pin.set_function(GpioPinFunction::Input),
+        ["out"] => pin.set_function(GpioPinFunction::Output),
+        ["alt0"] => pin.set_function(GpioPinFunction::Alt0),
+        ["alt1"] => pin.set_function(GpioPinFunction::Alt1),
+        ["alt2"] => pin.set_function(GpioPinFunction::Alt2),
+        ["alt3"] => pin.set_function(GpioPinFunction::Alt3),
+        ["alt4"] => pin.set_function(GpioPinFunction::Alt4),
+        ["alt5"] => pin.set_function(GpioPinFunction::Alt5),
+        ["high"] => pin.set(),
+        ["low"] => pin.clear(),
+        ["up"] => pin.set_pull(GpioPinPull::Up),
+        ["down"] => pin.set_pull(GpioPinPull::Down),
+        ["nopull"] => pin.set_pull(GpioPinPull::None),
+        ["watch", event] => {
+            pin.enable_events(&[parse_gpio_event(event)?], print_gpio_event)?;
+
+            // Keep the pin claimed, so that the events stay enabled.
+            core::mem::forget(pin);
+        }
+        _ => return Err("Unknown action"),
+    }
+
+    Ok(())
+}
+
+/// This must be called only after successful init of the memory subsystem.
+unsafe fn instantiate_gpio(
+    mmio_descriptor: &MMIODescriptor,
+    variant: device_driver::GpioVariant,
+) -> Result<(), &'static str> {
+    let virt_addr = memory::mmu::kernel_map_mmio(device_driver::GPIO::COMPATIBLE, mmio_descriptor)?;
+
+    GPIO.write(device_driver::GPIO::new(virt_addr, variant));
 
     Ok(())
 }
 
 /// This must be called only after successful init of the GPIO driver.
 unsafe fn post_init_gpio() -> Result<(), &'static str> {
-    GPIO.assume_init_ref().map_pl011_uart();
+    let gpio = GPIO.assume_init_ref();
+    gpio.map_pl011_uart(PL011_UART_PINS)?;
+    gpio.map_mini_uart(MINI_UART_PINS)?;
+
+    GPIO_READY.store(true, Ordering::Release);
+
+    shell::register_command(shell::Command::new(
+        "gpio",
+        "gpio [<pin> [in|out|alt<0-5>|high|low|up|down|nopull|watch <event>|ack]]: Show or drive pins",
+        cmd_gpio,
+    ))
+}
+
+/// This must be called only after successful init of the memory subsystem.
+unsafe fn instantiate_emmc(
+    mmio_descriptor: &MMIODescriptor,
+    base_clock_hz: u32,
+) -> Result<(), &'static str> {
+    let virt_addr = memory::mmu::kernel_map_mmio(device_driver::Emmc::COMPATIBLE, mmio_descriptor)?;
+
+    EMMC.write(device_driver::Emmc::new(virt_addr, base_clock_hz));
+
+    Ok(())
+}
+
+/// This must be called only after successful init of the GPIO and EMMC drivers.
+///
+/// On the RPi 3, the SD card slot must be routed to the EMMC controller first.
+unsafe fn post_init_emmc_with_pins() -> Result<(), &'static str> {
+    GPIO.assume_init_ref().map_emmc()?;
+
+    post_init_emmc()
+}
+
+/// This must be called only after successful init of the EMMC driver.
+unsafe fn post_init_emmc() -> Result<(), &'static str> {
+    let emmc = EMMC.assume_init_ref();
+
+    // An empty slot is not an error.
+    if let Err(x) = emmc.init_card() {
+        warn!("SD card not available: {}", x);
+        return Ok(());
+    }
+
+    block::register_block_device("sd0", emmc)?;
+
+    // A card without a partition table is used as a whole.
+    if let Err(x) = block::partition::register_partitions("sd0", emmc) {
+        warn!("No partitions on sd0: {}", x);
+    }
+
     Ok(())
 }
 
 /// This must be called only after successful init of the memory subsystem.
-#[cfg(feature = "bsp_rpi3")]
-unsafe fn instantiate_interrupt_controller() -> Result<(), &'static str> {
-    let local_mmio_descriptor = MMIODescriptor::new(mmio::LOCAL_IC_START, mmio::LOCAL_IC_SIZE);
+unsafe fn instantiate_bcm_interrupt_controller(
+    local_mmio_descriptor: &MMIODescriptor,
+    periph_mmio_descriptor: &MMIODescriptor,
+) -> Result<(), &'static str> {
     let local_virt_addr = memory::mmu::kernel_map_mmio(
         device_driver::InterruptController::COMPATIBLE,
-        &local_mmio_descriptor,
+        local_mmio_descriptor,
     )?;
-
-    let periph_mmio_descriptor =
-        MMIODescriptor::new(mmio::PERIPHERAL_IC_START, mmio::PERIPHERAL_IC_SIZE);
     let periph_virt_addr = memory::mmu::kernel_map_mmio(
         device_driver::InterruptController::COMPATIBLE,
-        &periph_mmio_descriptor,
+        periph_mmio_descriptor,
     )?;
 
-    INTERRUPT_CONTROLLER.write(device_driver::InterruptController::new(
-        local_virt_addr,
-        periph_virt_addr,
+    INTERRUPT_CONTROLLER.write(InterruptController::Bcm(
+        device_driver::InterruptController::new(local_virt_addr, periph_virt_addr),
     ));
 
     Ok(())
 }
 
 /// This must be called only after successful init of the memory subsystem.
-#[cfg(feature = "bsp_rpi4")]
-unsafe fn instantiate_interrupt_controller() -> Result<(), &'static str> {
-    let gicd_mmio_descriptor = MMIODescriptor::new(mmio::GICD_START, mmio::GICD_SIZE);
-    let gicd_virt_addr = memory::mmu::kernel_map_mmio("GICv2 GICD", &gicd_mmio_descriptor)?;
-
-    let gicc_mmio_descriptor = MMIODescriptor::new(mmio::GICC_START, mmio::GICC_SIZE);
-    let gicc_virt_addr = memory::mmu::kernel_map_mmio("GICV2 GICC", &gicc_mmio_descriptor)?;
-
-    INTERRUPT_CONTROLLER.write(device_driver::GICv2::new(gicd_virt_addr, gicc_virt_addr));
+unsafe fn instantiate_gicv2(
+    gicd_mmio_descriptor: &MMIODescriptor,
+    gicc_mmio_descriptor: &MMIODescriptor,
+) -> Result<(), &'static str> {
+    let gicd_virt_addr = memory::mmu::kernel_map_mmio("GICv2 GICD", gicd_mmio_descriptor)?;
+    let gicc_virt_addr = memory::mmu::kernel_map_mmio("GICV2 GICC", gicc_mmio_descriptor)?;
+
+    INTERRUPT_CONTROLLER.write(InterruptController::GICv2(device_driver::GICv2::new(
+        gicd_virt_addr,
+        gicc_virt_addr,
+    )));
 
     Ok(())
 }
@@ -116,27 +614,119 @@ unsafe fn post_init_interrupt_controller() -> Result<(), &'static str> {
 }
 
 /// Function needs to ensure that driver registration happens only after correct instantiation.
-unsafe fn driver_uart() -> Result<(), &'static str> {
-    instantiate_uart()?;
+unsafe fn probe_mailbox(resources: &DeviceResources) -> Result<(), &'static str> {
+    static PROBED: AtomicBool = AtomicBool::new(false);
+    probe_once(&PROBED)?;
+
+    instantiate_mailbox(&resources.mmio(0)?)?;
+
+    let mailbox_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        MAILBOX.assume_init_ref(),
+        Some(post_init_mailbox),
+        None,
+    );
+    generic_driver::driver_manager().register_driver(mailbox_descriptor);
+
+    Ok(())
+}
+
+/// Function needs to ensure that driver registration happens only after correct instantiation.
+unsafe fn probe_uart(resources: &DeviceResources) -> Result<(), &'static str> {
+    static PROBED: AtomicBool = AtomicBool::new(false);
+    let clock_hz = pl011_uart_clock_hz()?;
+    probe_once(&PROBED)?;
+
+    let irq_number = resources.irq(0)?;
+    instantiate_uart(&resources.mmio(0)?, clock_hz)?;
 
     let uart_descriptor = generic_driver::DeviceDriverDescriptor::new(
         PL011_UART.assume_init_ref(),
-        Some(post_init_uart),
-        Some(exception::asynchronous::irq_map::PL011_UART),
-    );
+        cfg!(not(feature = "console_mini_uart"))
+            .then_some(post_init_console_uart as generic_driver::DeviceDriverPostInitCallback),
+        Some(irq_number),
+    )
+    .with_dependencies(&[device_driver::GPIO::COMPATIBLE]);
     generic_driver::driver_manager().register_driver(uart_descriptor);
 
     Ok(())
 }
 
+/// Return the MMIO region of the auxiliary peripherals, which are the clock provider of the mini
+/// UART `node`.
+fn aux_mmio(node: fdt::Node<'static>) -> Result<MMIODescriptor, &'static str> {
+    let aux = node
+        .referenced_node("clocks")
+        .ok_or("Auxiliary peripherals not found")?;
+
+    DeviceResources::new(aux, irq_map::from_device_tree)?.mmio(0)
+}
+
 /// Function needs to ensure that driver registration happens only after correct instantiation.
-unsafe fn driver_gpio() -> Result<(), &'static str> {
-    instantiate_gpio()?;
+unsafe fn probe_mini_uart(resources: &DeviceResources) -> Result<(), &'static str> {
+    static PROBED: AtomicBool = AtomicBool::new(false);
+    let clock_hz = mailbox()?.get_clock_rate(device_driver::ClockId::Core)?;
+    probe_once(&PROBED)?;
+
+    let irq_number = resources.irq(0)?;
+    instantiate_mini_uart(&aux_mmio(resources.node())?, clock_hz)?;
+
+    let uart_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        MINI_UART.assume_init_ref(),
+        cfg!(feature = "console_mini_uart")
+            .then_some(post_init_console_uart as generic_driver::DeviceDriverPostInitCallback),
+        Some(irq_number),
+    )
+    .with_dependencies(&[device_driver::GPIO::COMPATIBLE]);
+    generic_driver::driver_manager().register_driver(uart_descriptor);
+
+    Ok(())
+}
+
+/// Function needs to ensure that driver registration happens only after correct instantiation.
+///
+/// The framebuffer console starts after the console UART, so that the latter sees all output.
+unsafe fn driver_framebuffer_console() -> Result<(), &'static str> {
+    #[cfg(not(feature = "console_mini_uart"))]
+    const DEPENDENCIES: &[&str] = &[
+        device_driver::Mailbox::COMPATIBLE,
+        device_driver::PL011Uart::COMPATIBLE,
+    ];
+
+    #[cfg(feature = "console_mini_uart")]
+    const DEPENDENCIES: &[&str] = &[
+        device_driver::Mailbox::COMPATIBLE,
+        device_driver::MiniUart::COMPATIBLE,
+    ];
+
+    instantiate_framebuffer_console()?;
+
+    let framebuffer_console_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        FRAMEBUFFER_CONSOLE.assume_init_ref(),
+        Some(post_init_framebuffer_console),
+        None,
+    )
+    .with_dependencies(DEPENDENCIES);
+    generic_driver::driver_manager().register_driver(framebuffer_console_descriptor);
+
+    Ok(())
+}
+
+/// Function needs to ensure that driver registration happens only after correct instantiation.
+unsafe fn probe_gpio(resources: &DeviceResources) -> Result<(), &'static str> {
+    static PROBED: AtomicBool = AtomicBool::new(false);
+    probe_once(&PROBED)?;
+
+    let variant = match resources.node().is_compatible("brcm,bcm2711-gpio") {
+        true => device_driver::GpioVariant::Bcm2711,
+        false => device_driver::GpioVariant::Bcm2835,
+    };
+    let irq_number = resources.irq(0)?;
+    instantiate_gpio(&resources.mmio(0)?, variant)?;
 
     let gpio_descriptor = generic_driver::DeviceDriverDescriptor::new(
         GPIO.assume_init_ref(),
         Some(post_init_gpio),
-        None,
+        Some(irq_number),
     );
     generic_driver::driver_manager().register_driver(gpio_descriptor);
 
@@ -144,23 +734,117 @@ unsafe fn driver_gpio() -> Result<(), &'static str> {
 }
 
 /// Function needs to ensure that driver registration happens only after correct instantiation.
-unsafe fn driver_interrupt_controller() -> Result<(), &'static str> {
-    instantiate_interrupt_controller()?;
+unsafe fn probe_emmc(resources: &DeviceResources) -> Result<(), &'static str> {
+    static PROBED: AtomicBool = AtomicBool::new(false);
+
+    // On the RPi 4, the SD card slot is wired to EMMC2. On the RPi 3, the EMMC controller shares
+    // the slot with the SD host controller.
+    let (clock, post_init): (_, generic_driver::DeviceDriverPostInitCallback) =
+        match resources.node().is_compatible("brcm,bcm2711-emmc2") {
+            true => (device_driver::ClockId::Emmc2, post_init_emmc),
+            false => (device_driver::ClockId::Emmc, post_init_emmc_with_pins),
+        };
+    let base_clock_hz = mailbox()?.get_clock_rate(clock)?;
+    probe_once(&PROBED)?;
+
+    instantiate_emmc(&resources.mmio(0)?, base_clock_hz)?;
+
+    let emmc_descriptor =
+        generic_driver::DeviceDriverDescriptor::new(EMMC.assume_init_ref(), Some(post_init), None)
+            .with_dependencies(&[device_driver::GPIO::COMPATIBLE]);
+    generic_driver::driver_manager().register_driver(emmc_descriptor);
+
+    Ok(())
+}
+
+/// Function needs to ensure that driver registration happens only after correct instantiation.
+///
+/// The local interrupt controller is the interrupt parent of the peripheral one.
+unsafe fn probe_bcm_interrupt_controller(resources: &DeviceResources) -> Result<(), &'static str> {
+    probe_once(&INTERRUPT_CONTROLLER_PROBED)?;
+
+    let local = resources
+        .node()
+        .interrupt_parent()
+        .filter(|node| node.is_compatible("brcm,bcm2836-l1-intc"))
+        .ok_or("Local interrupt controller not found")?;
+    let local_mmio = DeviceResources::new(local, irq_map::from_device_tree)?.mmio(0)?;
+    instantiate_bcm_interrupt_controller(&local_mmio, &resources.mmio(0)?)?;
+
+    register_interrupt_controller();
+
+    Ok(())
+}
+
+/// Function needs to ensure that driver registration happens only after correct instantiation.
+unsafe fn probe_gicv2(resources: &DeviceResources) -> Result<(), &'static str> {
+    probe_once(&INTERRUPT_CONTROLLER_PROBED)?;
+
+    instantiate_gicv2(&resources.mmio(0)?, &resources.mmio(1)?)?;
 
+    register_interrupt_controller();
+
+    Ok(())
+}
+
+/// This must be called only after successful instantiation of the interrupt controller driver.
+unsafe fn register_interrupt_controller() {
     let interrupt_controller_descriptor = generic_driver::DeviceDriverDescriptor::new(
         INTERRUPT_CONTROLLER.assume_init_ref(),
         Some(post_init_interrupt_controller),
         None,
     );
     generic_driver::driver_manager().register_driver(interrupt_controller_descriptor);
+}
 
-    Ok(())
+/// Register the drivers that are instantiated from the device tree.
+///
+/// Probes that query the mailbox for clock rates are deferred until the mailbox is probed. The GPIO
+/// driver routes the pins of the UARTs and the SD card slot, so they declare it as a dependency.
+fn register_driver_matches() {
+    use generic_driver::DeviceDriverMatch;
+
+    let driver_manager = generic_driver::driver_manager();
+
+    driver_manager.register_driver_match(DeviceDriverMatch::new(
+        &["brcm,bcm2835-mbox"],
+        probe_mailbox,
+    ));
+    driver_manager.register_driver_match(DeviceDriverMatch::new(&["arm,pl011"], probe_uart));
+    driver_manager.register_driver_match(DeviceDriverMatch::new(
+        &["brcm,bcm2835-aux-uart"],
+        probe_mini_uart,
+    ));
+    driver_manager.register_driver_match(DeviceDriverMatch::new(
+        &["brcm,bcm2711-gpio", "brcm,bcm2835-gpio"],
+        probe_gpio,
+    ));
+    driver_manager.register_driver_match(DeviceDriverMatch::new(
+        &["brcm,bcm2711-emmc2", "brcm,bcm2835-sdhci"],
+        probe_emmc,
+    ));
+    driver_manager.register_driver_match(DeviceDriverMatch::new(
+        &["brcm,bcm2836-armctrl-ic"],
+        probe_bcm_interrupt_controller,
+    ));
+    driver_manager.register_driver_match(DeviceDriverMatch::new(&["arm,gic-400"], probe_gicv2));
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Claim GPIO pin `number` for `owner`, e.g. the name of a driver.
+///
+/// Fails if the GPIO driver is not initialized yet, or if the pin is claimed already.
+pub fn claim_gpio_pin(number: usize, owner: &'static str) -> Result<GpioPin, &'static str> {
+    if !GPIO_READY.load(Ordering::Acquire) {
+        return Err("GPIO driver not initialized");
+    }
+
+    unsafe { GPIO.assume_init_ref() }.claim(number, owner)
+}
+
 /// Initialize the driver subsystem.
 ///
 /// # Safety
@@ -172,9 +856,14 @@ pub unsafe fn init() -> Result<(), &'static str> {
         return Err("Init already done");
     }
 
-    driver_uart()?;
-    driver_gpio()?;
-    driver_interrupt_controller()?;
+    register_driver_matches();
+    generic_driver::driver_manager()
+        .probe_device_tree(device_tree::device_tree()?, irq_map::from_device_tree);
+
+    // The framebuffer console is optional.
+    if let Err(x) = driver_framebuffer_console() {
+        warn!("Framebuffer console not available: {}", x);
+    }
 
     INIT_DONE.store(true, Ordering::Relaxed);
     Ok(())
@@ -182,12 +871,39 @@ pub unsafe fn init() -> Result<(), &'static str> {
 
 /// Minimal code needed to bring up the console in QEMU (for testing only). This is often less steps
 /// than on real hardware due to QEMU's abstractions.
+///
+/// The console UART is taken from the built-in device tree.
 #[cfg(feature = "test_build")]
 pub fn qemu_bring_up_console() {
     use crate::cpu;
 
+    /// QEMU ignores the baud rate, so any rate of the VPU core clock will do.
+    #[cfg(feature = "console_mini_uart")]
+    const MINI_UART_CLOCK_HZ: u32 = 250_000_000;
+
+    let console_uart_node = |compatible| {
+        let device_tree = fdt::Fdt::new(super::builtin_device_tree()).ok()?;
+
+        device_tree
+            .find_compatible(compatible)
+            .find(|node| node.is_enabled())
+    };
+
     unsafe {
-        instantiate_uart().unwrap_or_else(|_| cpu::qemu_exit_failure());
-        console::register_console(PL011_UART.assume_init_ref());
+        #[cfg(not(feature = "console_mini_uart"))]
+        console_uart_node("arm,pl011")
+            .ok_or("Console UART not found")
+            .and_then(|node| DeviceResources::new(node, irq_map::from_device_tree)?.mmio(0))
+            .and_then(|mmio| instantiate_uart(&mmio, PL011_UART_CLOCK_HZ))
+            .unwrap_or_else(|_| cpu::qemu_exit_failure());
+
+        #[cfg(feature = "console_mini_uart")]
+        console_uart_node("brcm,bcm2835-aux-uart")
+            .ok_or("Console UART not found")
+            .and_then(aux_mmio)
+            .and_then(|mmio| instantiate_mini_uart(&mmio, MINI_UART_CLOCK_HZ))
+            .unwrap_or_else(|_| cpu::qemu_exit_failure());
+
+        console::register_console(console_uart(), CONSOLE_UART_FLAGS);
     };
 }
@@ -3,34 +3,240 @@
 // Copyright (c) 2020-2023 Andre Richter <andre.o.richter@gmail.com>
 
 //! BSP asynchronous exception handling.
+//!
+//! The RPi 3 has the BCM interrupt controller, the RPi 4 a GICv2. Which one is used is decided by
+//! the device tree, so the IRQ numbers and the IRQ manager of the BSP cover both.
 
-use crate::bsp;
+use crate::{
+    bsp::device_driver,
+    driver::interface::DeviceDriver,
+    exception::{
+        self,
+        asynchronous::{
+            interface::{IRQHandler, IRQManager},
+            IPIKind,
+        },
+    },
+};
+use core::fmt;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-/// Export for reuse in generic asynchronous.rs.
-pub use bsp::device_driver::IRQNumber;
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+///
+/// The variant is given by the interrupt controller that the device tree assigns to a device.
+#[derive(Copy, Clone)]
+#[allow(missing_docs)]
+pub enum IRQNumber {
+    Bcm(device_driver::BcmIRQNumber),
+    GICv2(device_driver::GICv2IRQNumber),
+}
+
+/// The interrupt controller of the board.
+///
+/// Handing an IRQ number of the other controller to it is a bug. Fallible calls return an error,
+/// the others panic.
+pub(in crate::bsp) enum InterruptController {
+    Bcm(device_driver::InterruptController),
+    GICv2(device_driver::GICv2),
+}
 
 /// The IRQ map.
-#[cfg(feature = "bsp_rpi3")]
 pub mod irq_map {
-    use super::bsp::device_driver::{IRQNumber, LocalIRQ, PeripheralIRQ};
+    use super::{device_driver, IRQNumber};
+    use crate::device_tree;
+
+    /// Return the non-secure physical timer IRQ number, which is the second interrupt of the
+    /// architected timer in the device tree.
+    pub fn arm_ns_physical_timer() -> Result<IRQNumber, &'static str> {
+        let device_tree = device_tree::device_tree()?;
+        let timer = ["arm,armv8-timer", "arm,armv7-timer"]
+            .into_iter()
+            .find_map(|compatible| device_tree.find_compatible(compatible).next())
+            .ok_or("Architected timer not found in device tree")?;
+        let interrupt = timer
+            .interrupts()?
+            .nth(1)
+            .ok_or("Non-secure physical timer IRQ missing")?;
+
+        from_device_tree(&interrupt)
+    }
 
-    /// The non-secure physical timer IRQ number.
-    pub const ARM_NS_PHYSICAL_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(1));
+    /// Translate a device tree interrupt to an IRQ number of the controller it belongs to.
+    pub(in crate::bsp) fn from_device_tree(
+        interrupt: &fdt::Interrupt,
+    ) -> Result<IRQNumber, &'static str> {
+        if interrupt.controller.is_compatible("arm,gic-400") {
+            return device_driver::GICv2::irq_number_from_device_tree(interrupt)
+                .map(IRQNumber::GICv2);
+        }
 
-    pub(in crate::bsp) const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
+        device_driver::InterruptController::irq_number_from_device_tree(interrupt)
+            .map(IRQNumber::Bcm)
+    }
 }
 
-/// The IRQ map.
-#[cfg(feature = "bsp_rpi4")]
-pub mod irq_map {
-    use super::bsp::device_driver::IRQNumber;
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+const FOREIGN_IRQ: &str = "IRQ number of another interrupt controller";
+
+fn foreign_irq(irq_number: &IRQNumber) -> ! {
+    panic!("{}: {}", FOREIGN_IRQ, irq_number)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for IRQNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Bcm(number) => write!(f, "{}", number),
+            Self::GICv2(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl DeviceDriver for InterruptController {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        match self {
+            Self::Bcm(ic) => ic.compatible(),
+            Self::GICv2(gic) => gic.compatible(),
+        }
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        match self {
+            Self::Bcm(ic) => ic.init(),
+            Self::GICv2(gic) => gic.init(),
+        }
+    }
+}
+
+impl IRQManager for InterruptController {
+    type IRQNumberType = IRQNumber;
+
+    fn register_handler(
+        &self,
+        irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
+    ) -> Result<(), &'static str> {
+        match (self, irq_handler_descriptor.number()) {
+            (Self::Bcm(ic), IRQNumber::Bcm(number)) => {
+                ic.register_handler(irq_handler_descriptor.with_number(number))
+            }
+            (Self::GICv2(gic), IRQNumber::GICv2(number)) => {
+                gic.register_handler(irq_handler_descriptor.with_number(number))
+            }
+            _ => Err(FOREIGN_IRQ),
+        }
+    }
+
+    fn unregister_handler(
+        &self,
+        irq_number: &Self::IRQNumberType,
+        handler: &'static (dyn IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        match (self, irq_number) {
+            (Self::Bcm(ic), IRQNumber::Bcm(number)) => ic.unregister_handler(number, handler),
+            (Self::GICv2(gic), IRQNumber::GICv2(number)) => gic.unregister_handler(number, handler),
+            _ => Err(FOREIGN_IRQ),
+        }
+    }
+
+    fn enable(&self, irq_number: &Self::IRQNumberType) {
+        match (self, irq_number) {
+            (Self::Bcm(ic), IRQNumber::Bcm(number)) => ic.enable(number),
+            (Self::GICv2(gic), IRQNumber::GICv2(number)) => gic.enable(number),
+            _ => foreign_irq(irq_number),
+        }
+    }
+
+    fn disable(&self, irq_number: &Self::IRQNumberType) {
+        match (self, irq_number) {
+            (Self::Bcm(ic), IRQNumber::Bcm(number)) => ic.disable(number),
+            (Self::GICv2(gic), IRQNumber::GICv2(number)) => gic.disable(number),
+            _ => foreign_irq(irq_number),
+        }
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        match self {
+            Self::Bcm(bcm) => bcm.handle_pending_irqs(ic),
+            Self::GICv2(gic) => gic.handle_pending_irqs(ic),
+        }
+    }
+
+    fn send_ipi(&self, target_core: usize, kind: IPIKind) -> Result<(), &'static str> {
+        match self {
+            Self::Bcm(ic) => ic.send_ipi(target_core, kind),
+            Self::GICv2(gic) => gic.send_ipi(target_core, kind),
+        }
+    }
+
+    fn set_fiq(
+        &self,
+        irq_number: &Self::IRQNumberType,
+        handler: &'static (dyn IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        match (self, irq_number) {
+            (Self::Bcm(ic), IRQNumber::Bcm(number)) => ic.set_fiq(number, handler),
+            (Self::GICv2(gic), IRQNumber::GICv2(number)) => gic.set_fiq(number, handler),
+            _ => Err(FOREIGN_IRQ),
+        }
+    }
+
+    fn handle_pending_fiq<'irq_context>(
+        &'irq_context self,
+        ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        match self {
+            Self::Bcm(bcm) => bcm.handle_pending_fiq(ic),
+            Self::GICv2(gic) => gic.handle_pending_fiq(ic),
+        }
+    }
+
+    fn print_handler(&self) {
+        match self {
+            Self::Bcm(ic) => ic.print_handler(),
+            Self::GICv2(gic) => gic.print_handler(),
+        }
+    }
+
+    fn irq_statistics(
+        &self,
+        irq_number: &Self::IRQNumberType,
+    ) -> exception::asynchronous::IRQStatistics {
+        match (self, irq_number) {
+            (Self::Bcm(ic), IRQNumber::Bcm(number)) => ic.irq_statistics(number),
+            (Self::GICv2(gic), IRQNumber::GICv2(number)) => gic.irq_statistics(number),
+            _ => foreign_irq(irq_number),
+        }
+    }
 
-    /// The non-secure physical timer IRQ number.
-    pub const ARM_NS_PHYSICAL_TIMER: IRQNumber = IRQNumber::new(30);
+    fn num_spurious_irqs(&self) -> u64 {
+        match self {
+            Self::Bcm(ic) => ic.num_spurious_irqs(),
+            Self::GICv2(gic) => gic.num_spurious_irqs(),
+        }
+    }
 
-    pub(in crate::bsp) const PL011_UART: IRQNumber = IRQNumber::new(153);
+    fn print_statistics(&self) {
+        match self {
+            Self::Bcm(ic) => ic.print_statistics(),
+            Self::GICv2(gic) => gic.print_statistics(),
+        }
+    }
 }
@@ -19,6 +19,7 @@
 //! |                                       | code_start @ 0x8_0000 == boot_core_stack_end_exclusive
 //! | .text                                 |
 //! | .rodata                               |
+//! | .initramfs                            |
 //! | .got                                  |
 //! | .kernel_symbols                       |
 //! |                                       |
@@ -32,7 +33,11 @@
 //! | .heap                                 |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       | heap_end_exclusive
+//! |                                       | dma_start == heap_end_exclusive
+//! | .dma                                  |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       | dma_end_exclusive
 //! |                                       |
 //!
 //!
@@ -45,6 +50,7 @@
 //! |                                       | code_start @ __kernel_virt_start_addr
 //! | .text                                 |
 //! | .rodata                               |
+//! | .initramfs                            |
 //! | .got                                  |
 //! | .kernel_symbols                       |
 //! |                                       |
@@ -58,7 +64,11 @@
 //! | .heap                                 |
 //! |                                       |
 //! +---------------------------------------+
-//! |                                       |  mmio_remap_start == heap_end_exclusive
+//! |                                       | dma_start == heap_end_exclusive
+//! | .dma                                  |
+//! |                                       |
+//! +---------------------------------------+
+//! |                                       |  mmio_remap_start == dma_end_exclusive
 //! | VA region for MMIO remapping          |
 //! |                                       |
 //! +---------------------------------------+
@@ -76,7 +86,10 @@
 //! |                                       |
 pub mod mmu;
 
-use crate::memory::{mmu::PageAddress, Address, Physical, Virtual};
+use crate::{
+    device_tree,
+    memory::{mmu::PageAddress, Address, Physical, Virtual},
+};
 use core::cell::UnsafeCell;
 
 //--------------------------------------------------------------------------------------------------
@@ -94,6 +107,9 @@ extern "Rust" {
     static __heap_start: UnsafeCell<()>;
     static __heap_end_exclusive: UnsafeCell<()>;
 
+    static __dma_start: UnsafeCell<()>;
+    static __dma_end_exclusive: UnsafeCell<()>;
+
     static __mmio_remap_start: UnsafeCell<()>;
     static __mmio_remap_end_exclusive: UnsafeCell<()>;
 
@@ -110,44 +126,17 @@ extern "Rust" {
 pub(super) mod map {
     use super::*;
 
-    /// Physical devices.
-    #[cfg(feature = "bsp_rpi3")]
+    /// Physical devices of all supported boards, so that the kernel and its translation tables
+    /// are the same for each of them. The RPi 3 has the lowest start, the RPi 4 the highest end.
+    /// The window of the board and the device addresses are read from the device tree.
+    ///
+    /// Before the device tree is parsed, `START` bounds the DRAM in which the firmware places
+    /// the blob.
     pub mod mmio {
         use super::*;
 
-        pub const PERIPHERAL_IC_START: Address<Physical> = Address::new(0x3F00_B200);
-        pub const PERIPHERAL_IC_SIZE:  usize             =              0x24;
-
-        pub const GPIO_START:          Address<Physical> = Address::new(0x3F20_0000);
-        pub const GPIO_SIZE:           usize             =              0xA0;
-
-        pub const PL011_UART_START:    Address<Physical> = Address::new(0x3F20_1000);
-        pub const PL011_UART_SIZE:     usize             =              0x48;
-
-        pub const LOCAL_IC_START:      Address<Physical> = Address::new(0x4000_0000);
-        pub const LOCAL_IC_SIZE:       usize             =              0x100;
-
-        pub const END:                 Address<Physical> = Address::new(0x4001_0000);
-    }
-
-    /// Physical devices.
-    #[cfg(feature = "bsp_rpi4")]
-    pub mod mmio {
-        use super::*;
-
-        pub const GPIO_START:       Address<Physical> = Address::new(0xFE20_0000);
-        pub const GPIO_SIZE:        usize             =              0xA0;
-
-        pub const PL011_UART_START: Address<Physical> = Address::new(0xFE20_1000);
-        pub const PL011_UART_SIZE:  usize             =              0x48;
-
-        pub const GICD_START:       Address<Physical> = Address::new(0xFF84_1000);
-        pub const GICD_SIZE:        usize             =              0x824;
-
-        pub const GICC_START:       Address<Physical> = Address::new(0xFF84_2000);
-        pub const GICC_SIZE:        usize             =              0x14;
-
-        pub const END:              Address<Physical> = Address::new(0xFF85_0000);
+        pub const START: Address<Physical> = Address::new(0x3F00_0000);
+        pub const END:   Address<Physical> = Address::new(0x1_0000_0000);
     }
 
     pub const END: Address<Physical> = mmio::END;
@@ -209,6 +198,22 @@ fn heap_size() -> usize {
     unsafe { (__heap_end_exclusive.get() as usize) - (__heap_start.get() as usize) }
 }
 
+/// Start page address of the DMA segment.
+#[inline(always)]
+fn virt_dma_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __dma_start.get() as usize })
+}
+
+/// Size of the DMA segment.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn dma_size() -> usize {
+    unsafe { (__dma_end_exclusive.get() as usize) - (__dma_start.get() as usize) }
+}
+
 /// Start page address of the MMIO remap reservation.
 ///
 /// # Safety
@@ -247,8 +252,54 @@ fn boot_core_stack_size() -> usize {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Start address of the physical MMIO region, from the bus ranges of the device tree. DRAM that the
+/// firmware shares with the kernel, e.g. for the device tree blob, is below it.
+///
+/// Before the device tree is initialized, the lowest start address of the supported boards is
+/// returned.
+pub fn phys_mmio_start_addr() -> Address<Physical> {
+    device_tree::device_tree()
+        .ok()
+        .and_then(|device_tree| device_tree.find_node("/soc"))
+        .and_then(|soc| soc.ranges().filter_map(Result::ok).map(|r| r.address).min())
+        .map(|address| Address::new(address as usize))
+        .unwrap_or(map::mmio::START)
+}
+
 /// Exclusive end address of the physical address space.
 #[inline(always)]
 pub fn phys_addr_space_end_exclusive_addr() -> PageAddress<Physical> {
     PageAddress::from(map::END)
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The MMIO window covers the buses of all supported boards.
+    #[kernel_test]
+    fn mmio_window_covers_all_boards() {
+        let blobs: [&[u8]; 2] = [
+            include_bytes!("../../../../devicetree/rpi3b.dtb"),
+            include_bytes!("../../../../devicetree/rpi4b.dtb"),
+        ];
+
+        for blob in blobs {
+            let device_tree = fdt::Fdt::new(blob).unwrap();
+            let soc = device_tree.find_node("/soc").unwrap();
+
+            for range in soc.ranges() {
+                let range = range.unwrap();
+                let end = (range.address + range.size) as usize;
+
+                assert!(range.address as usize >= map::mmio::START.as_usize());
+                assert!(end <= map::mmio::END.as_usize());
+            }
+        }
+    }
+}
@@ -132,6 +132,16 @@ pub fn virt_heap_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
+/// The DMA buffer pages, which are mapped non-cacheable.
+pub fn virt_dma_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::dma_size());
+
+    let start_page_addr = super::virt_dma_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
 /// The boot core stack pages.
 pub fn virt_boot_core_stack_region() -> MemoryRegion<Virtual> {
     let num_pages = size_to_num_pages(super::boot_core_stack_size());
@@ -187,6 +197,14 @@ pub fn kernel_add_mapping_records_for_precomputed() {
         &kernel_page_attributes(virt_heap_region.start_page_addr()),
     );
 
+    let virt_dma_region = virt_dma_region();
+    generic_mmu::kernel_add_mapping_record(
+        "Kernel DMA buffers",
+        &virt_dma_region,
+        &kernel_virt_to_phys_region(virt_dma_region),
+        &kernel_page_attributes(virt_dma_region.start_page_addr()),
+    );
+
     let virt_boot_core_stack_region = virt_boot_core_stack_region();
     generic_mmu::kernel_add_mapping_record(
         "Kernel boot-core stack",
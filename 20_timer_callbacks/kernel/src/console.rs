@@ -3,10 +3,37 @@
 // Copyright (c) 2018-2023 Andre Richter <andre.o.richter@gmail.com>
 
 //! System console.
+//!
+//! Several consoles can be registered at the same time. The kernel console returned by
+//! [`console()`] multiplexes them: Writes are fanned out to all consoles that are flagged for
+//! output, and input is merged from all consoles that are flagged for input. Log records are only
+//! written to consoles whose log level permits it.
+//!
+//! Until the first console with output is registered, output is stored in a buffer. It is replayed
+//! to each console that is registered with output afterwards.
 
 mod buffer_console;
 
-use crate::synchronization;
+pub mod ansi;
+pub mod tty;
+
+use crate::{cpu, log, synchronization};
+use alloc::vec::Vec;
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct ConsoleDescriptor {
+    console: &'static (dyn interface::All + Sync),
+    flags: ConsoleFlags,
+}
+
+/// Multiplexes a list of consoles.
+struct ConsoleMux {
+    consoles: InitStateLock<Vec<ConsoleDescriptor>>,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -38,6 +65,11 @@ pub mod interface {
             ' '
         }
 
+        /// Read a single character if one is available, without blocking.
+        fn try_read_char(&self) -> Option<char> {
+            None
+        }
+
         /// Clear RX buffers, if any.
         fn clear_rx(&self);
     }
@@ -59,35 +91,326 @@ pub mod interface {
     pub trait All: Write + Read + Statistics {}
 }
 
+/// Determines how a console is used by the kernel console.
+#[derive(Copy, Clone)]
+pub struct ConsoleFlags {
+    /// Write kernel console output to this console.
+    pub output: bool,
+
+    /// Make characters received by this console readable from the kernel console.
+    pub input: bool,
+
+    /// Least important level of log records that are written to this console. Does not affect
+    /// `print!` and `println!`.
+    pub log_level: log::Level,
+
+    /// The console understands ANSI escape sequences.
+    pub ansi: bool,
+
+    /// Write binary log frames to this console. Only serial lines whose output is decoded on the
+    /// host should set this.
+    pub binary_log: bool,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
-static CUR_CONSOLE: InitStateLock<&'static (dyn interface::All + Sync)> =
-    InitStateLock::new(&buffer_console::BUFFER_CONSOLE);
+static CONSOLE_MUX: ConsoleMux = ConsoleMux::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn is_ansi_active(flags: &ConsoleFlags) -> bool {
+    flags.ansi && ansi::is_enabled()
+}
+
+impl ConsoleMux {
+    const fn new() -> Self {
+        Self {
+            consoles: InitStateLock::new(Vec::new()),
+        }
+    }
+
+    /// Add a console to the list.
+    fn add(&self, new_console: &'static (dyn interface::All + Sync), flags: ConsoleFlags) {
+        self.consoles.write(|consoles| {
+            consoles.push(ConsoleDescriptor {
+                console: new_console,
+                flags,
+            })
+        });
+    }
+
+    /// Call `f` for each console flagged for output, or for the buffer console if there is none.
+    fn for_each_output(&self, mut f: impl FnMut(&dyn interface::All, &ConsoleFlags)) {
+        self.consoles.read(|consoles| {
+            let mut outputs = consoles.iter().filter(|desc| desc.flags.output).peekable();
+
+            if outputs.peek().is_none() {
+                // The buffer might be replayed to consoles that are not ANSI capable. Binary log
+                // frames are kept, see `register_console()`.
+                let flags = ConsoleFlags {
+                    ansi: false,
+                    binary_log: true,
+                    ..ConsoleFlags::ALL
+                };
+                f(&buffer_console::BUFFER_CONSOLE, &flags);
+                return;
+            }
+
+            for desc in outputs {
+                f(desc.console, &desc.flags);
+            }
+        })
+    }
+
+    fn for_each_input(&self, mut f: impl FnMut(&dyn interface::All)) {
+        self.consoles.read(|consoles| {
+            for desc in consoles.iter().filter(|desc| desc.flags.input) {
+                f(desc.console);
+            }
+        })
+    }
+
+    fn write_filtered(
+        &self,
+        filter: impl Fn(&ConsoleFlags) -> bool,
+        style: ansi::Style,
+        args: fmt::Arguments,
+    ) -> fmt::Result {
+        let mut result = Ok(());
+        self.for_each_output(|console, flags| {
+            if !filter(flags) {
+                return;
+            }
+
+            let res = if style != ansi::Style::NONE && is_ansi_active(flags) {
+                console.write_fmt(format_args!("{}{}{}", style, args, ansi::RESET))
+            } else {
+                console.write_fmt(args)
+            };
+            result = result.and(res);
+        });
+
+        result
+    }
+
+    fn write_log(&self, level: log::Level, args: fmt::Arguments) -> fmt::Result {
+        self.write_filtered(
+            |flags| level <= flags.log_level,
+            ansi::Style::for_level(level),
+            args,
+        )
+    }
+}
+
+impl interface::Write for ConsoleMux {
+    fn write_char(&self, c: char) {
+        self.for_each_output(|console, _| console.write_char(c));
+    }
+
+    fn write_array(&self, a: &[char]) {
+        self.for_each_output(|console, _| console.write_array(a));
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        let mut result = Ok(());
+        self.for_each_output(|console, _| result = result.and(console.write_fmt(args)));
+
+        result
+    }
+
+    fn flush(&self) {
+        self.for_each_output(|console, _| console.flush());
+    }
+}
+
+impl interface::Read for ConsoleMux {
+    fn read_char(&self) -> char {
+        loop {
+            if let Some(c) = self.try_read_char() {
+                return c;
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        let mut c = None;
+        self.for_each_input(|console| {
+            if c.is_none() {
+                c = console.try_read_char();
+            }
+        });
+
+        c
+    }
+
+    fn clear_rx(&self) {
+        self.for_each_input(|console| console.clear_rx());
+    }
+}
+
+impl interface::Statistics for ConsoleMux {
+    fn chars_written(&self) -> usize {
+        let mut sum = 0;
+        self.for_each_output(|console, _| sum += console.chars_written());
+
+        sum
+    }
+
+    fn chars_read(&self) -> usize {
+        let mut sum = 0;
+        self.for_each_input(|console| sum += console.chars_read());
+
+        sum
+    }
+}
+
+impl interface::All for ConsoleMux {}
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 use synchronization::{interface::ReadWriteEx, InitStateLock};
 
-/// Register a new console.
-pub fn register_console(new_console: &'static (dyn interface::All + Sync)) {
-    CUR_CONSOLE.write(|con| *con = new_console);
+impl ConsoleFlags {
+    /// Input, output, all log records and ANSI escape sequences. No binary log frames.
+    pub const ALL: Self = Self {
+        output: true,
+        input: true,
+        log_level: log::Level::Debug,
+        ansi: true,
+        binary_log: false,
+    };
 
-    static FIRST_SWITCH: InitStateLock<bool> = InitStateLock::new(true);
-    FIRST_SWITCH.write(|first| {
-        if *first {
-            *first = false;
+    /// Like [`Self::ALL`], but no input.
+    pub const OUTPUT_ONLY: Self = Self {
+        input: false,
+        ..Self::ALL
+    };
+}
 
-            buffer_console::BUFFER_CONSOLE.dump();
-        }
-    });
+/// Register an additional console.
+///
+/// If the console is flagged for output, the output that was buffered before the first console
+/// was registered is replayed to it. In binary log mode, the buffer contains binary log frames, so
+/// it is only replayed to consoles that are flagged for them.
+pub fn register_console(new_console: &'static (dyn interface::All + Sync), flags: ConsoleFlags) {
+    CONSOLE_MUX.add(new_console, flags);
+
+    if flags.output && (flags.binary_log || cfg!(not(feature = "binary_log"))) {
+        buffer_console::BUFFER_CONSOLE.dump(new_console);
+    }
 }
 
-/// Return a reference to the currently registered console.
+/// Return a reference to the kernel console, which multiplexes all registered consoles.
 ///
 /// This is the global console used by all printing macros.
 pub fn console() -> &'static dyn interface::All {
-    CUR_CONSOLE.read(|con| *con)
+    &CONSOLE_MUX
+}
+
+/// Write a log record to all output consoles whose log level permits it.
+pub fn write_log(level: log::Level, args: fmt::Arguments) -> fmt::Result {
+    CONSOLE_MUX.write_log(level, args)
+}
+
+/// Write to all output consoles, applying `style` on ANSI consoles.
+pub fn write_styled(style: ansi::Style, args: fmt::Arguments) -> fmt::Result {
+    CONSOLE_MUX.write_filtered(|_| true, style, args)
+}
+
+/// Write a binary log frame to all output consoles that are flagged for it.
+pub fn write_binary_log(frame: &[char]) {
+    CONSOLE_MUX.for_each_output(|console, flags| {
+        if flags.binary_log {
+            console.write_array(frame);
+        }
+    });
+}
+
+/// Write to ANSI output consoles only. Used for escape sequences that have no plain equivalent.
+pub fn write_ansi_only(args: fmt::Arguments) -> fmt::Result {
+    let mut result = Ok(());
+    CONSOLE_MUX.for_each_output(|console, flags| {
+        if is_ansi_active(flags) {
+            result = result.and(console.write_fmt(args));
+        }
+    });
+
+    result
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+    use alloc::string::String;
+    use test_macros::kernel_test;
+
+    struct TestConsole {
+        output: IRQSafeNullLock<String>,
+    }
+
+    impl interface::Write for TestConsole {
+        fn write_char(&self, c: char) {
+            self.output.lock(|output| output.push(c));
+        }
+
+        fn write_array(&self, a: &[char]) {
+            self.output.lock(|output| output.extend(a));
+        }
+
+        fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+            self.output
+                .lock(|output| fmt::Write::write_fmt(output, args))
+        }
+
+        fn flush(&self) {}
+    }
+
+    impl interface::Read for TestConsole {
+        fn clear_rx(&self) {}
+    }
+
+    impl interface::Statistics for TestConsole {}
+    impl interface::All for TestConsole {}
+
+    static TEST_CONSOLE: TestConsole = TestConsole {
+        output: IRQSafeNullLock::new(String::new()),
+    };
+
+    /// Output is written to all consoles, log records only if the console's log level permits.
+    #[kernel_test]
+    fn output_is_fanned_out() {
+        use interface::Write;
+
+        let mux = ConsoleMux::new();
+        mux.add(
+            &TEST_CONSOLE,
+            ConsoleFlags {
+                log_level: log::Level::Warn,
+                ansi: false,
+                ..ConsoleFlags::OUTPUT_ONLY
+            },
+        );
+
+        mux.write_fmt(format_args!("print ")).unwrap();
+        mux.write_log(log::Level::Info, format_args!("info "))
+            .unwrap();
+        mux.write_log(log::Level::Warn, format_args!("warn"))
+            .unwrap();
+
+        TEST_CONSOLE
+            .output
+            .lock(|output| assert_eq!(output, "print warn"));
+    }
 }
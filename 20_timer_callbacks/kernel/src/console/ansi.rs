@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! ANSI terminal rendering.
+//!
+//! Log records are colored by level, and panics are printed with a bold banner. Optionally, the top
+//! row of the terminal is reserved for a status line that is refreshed periodically.
+//!
+//! Escape sequences are only written to consoles that are flagged as ANSI capable, see
+//! [`ConsoleFlags`](super::ConsoleFlags), and only while rendering is enabled.
+
+use crate::{console, exception, log, memory, time};
+use alloc::boxed::Box;
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Select Graphic Rendition escape sequence that is written before styled output.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Style(&'static str);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn draw_status_line() {
+    let uptime = time::time_manager().uptime();
+    let (heap_used, heap_free) = memory::heap_alloc::kernel_heap_allocator().usage();
+
+    // Save the cursor, draw into the reserved top row and restore the cursor again.
+    console::write_ansi_only(format_args!(
+        "\x1b7\x1b[1;1H\x1b[2K{} uptime {}.{:03} s | heap {}/{} Byte | IRQs {} {}\x1b8",
+        Style::STATUS_LINE,
+        uptime.as_secs(),
+        uptime.subsec_millis(),
+        heap_used,
+        heap_used + heap_free,
+        exception::asynchronous::num_irqs(),
+        RESET
+    ))
+    .unwrap();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Escape sequence that resets all styles.
+pub const RESET: &str = "\x1b[0m";
+
+impl Style {
+    /// No styling.
+    pub const NONE: Self = Self("");
+
+    /// Style of the panic banner.
+    pub const PANIC: Self = Self("\x1b[1;31m");
+
+    /// Style of the status line.
+    pub const STATUS_LINE: Self = Self("\x1b[7m");
+
+    /// Return the style of log records of `level`.
+    pub fn for_level(level: log::Level) -> Self {
+        match level {
+            log::Level::Warn => Self("\x1b[33m"),
+            log::Level::Info => Self::NONE,
+            log::Level::Debug => Self("\x1b[2m"),
+        }
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// Return whether ANSI rendering is enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Enable or disable ANSI rendering on all consoles.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Reserve the top row of ANSI consoles for a status line showing uptime, heap usage and the IRQ
+/// count, and refresh it every `period`.
+pub fn enable_status_line(period: Duration) {
+    // Restrict scrolling to the rows below the status line, and move the cursor to the bottom.
+    console::write_ansi_only(format_args!("\x1b[2r\x1b[999;1H")).unwrap();
+    draw_status_line();
+
+    time::time_manager().set_timeout_periodic(period, Box::new(draw_status_line));
+}
@@ -5,7 +5,7 @@
 //! A console that buffers input during the init phase.
 
 use super::interface;
-use crate::{console, info, synchronization, synchronization::InitStateLock};
+use crate::{synchronization, synchronization::InitStateLock};
 use core::fmt;
 
 //--------------------------------------------------------------------------------------------------
@@ -68,19 +68,19 @@ impl fmt::Write for BufferConsoleInner {
 use synchronization::interface::ReadWriteEx;
 
 impl BufferConsole {
-    /// Dump the buffer.
-    ///
-    /// # Invariant
-    ///
-    /// It is expected that this is only called when self != crate::console::console().
-    pub fn dump(&self) {
+    /// Dump the buffer to `console`.
+    pub fn dump(&self, console: &dyn interface::All) {
         self.inner.read(|inner| {
-            console::console().write_array(&inner.buf[0..inner.write_ptr]);
+            console.write_array(&inner.buf[0..inner.write_ptr]);
 
             if inner.write_ptr == (BUF_SIZE - 1) {
-                info!("Pre-UART buffer overflowed");
+                console
+                    .write_fmt(format_args_nl!("[ Pre-UART buffer overflowed ]"))
+                    .unwrap();
             } else if inner.write_ptr > 0 {
-                info!("End of pre-UART buffer")
+                console
+                    .write_fmt(format_args_nl!("[ End of pre-UART buffer ]"))
+                    .unwrap();
             }
         });
     }
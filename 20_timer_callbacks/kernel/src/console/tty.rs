@@ -0,0 +1,649 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! TTY line discipline.
+//!
+//! A [`Tty`] sits between a raw character device, e.g. a UART driver, and the kernel console. It
+//! implements the console interfaces itself and processes input and output according to its
+//! [`Termios`], similar to the POSIX `termios` interface:
+//!
+//! - Canonical mode collects input into lines, which can be edited with backspace and killed with
+//!   Ctrl-U. Ctrl-D ends the input on an empty line, and otherwise makes the partial line readable.
+//!   In raw mode, characters are readable as soon as they arrive.
+//! - Received characters are optionally echoed.
+//! - Carriage returns are optionally translated to newlines on input, and newlines to carriage
+//!   return plus newline on output.
+//! - Ctrl-C optionally discards pending input and calls an interrupt handler.
+//! - With flow control, Ctrl-S stops output and Ctrl-Q resumes it. Output that is written in
+//!   between is buffered.
+//!
+//! Input is processed when it is read, so special characters take effect on the next read.
+//!
+//! [`Termios::RAW`] passes everything through unmodified, so the same device can serve a binary
+//! protocol.
+
+use super::interface;
+use crate::{
+    console, cpu, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+};
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum length of a line in canonical mode. Further characters are dropped.
+const MAX_LINE_LEN: usize = 256;
+
+/// Maximum output that is buffered while output is stopped. Further characters are dropped.
+const MAX_STOPPED_OUTPUT: usize = 4096;
+
+/// Ends a line in the readable input that Ctrl-D made readable without a newline. Canonical input
+/// contains no control characters otherwise.
+const LINE_PUSH: char = '\0';
+
+const CTRL_C: char = '\x03';
+const CTRL_D: char = '\x04';
+const CTRL_Q: char = '\x11';
+const CTRL_S: char = '\x13';
+const CTRL_U: char = '\x15';
+const BACKSPACE: char = '\x08';
+const DELETE: char = '\x7f';
+
+struct TtyInner {
+    termios: Termios,
+
+    /// The line being edited in canonical mode.
+    line: String,
+
+    /// Input that is ready to be read.
+    ready: VecDeque<char>,
+
+    /// Ctrl-D was received on an empty line.
+    eof: bool,
+
+    /// Output was stopped with Ctrl-S.
+    output_stopped: bool,
+
+    /// Output that was written while output was stopped.
+    stopped_output: VecDeque<char>,
+
+    /// Ctrl-C was received, and the interrupt handler still needs to be called.
+    interrupt_pending: bool,
+
+    interrupt_handler: Option<InterruptHandler>,
+}
+
+/// Processes output of a TTY.
+struct Output<'a> {
+    inner: &'a mut TtyInner,
+    device: &'a (dyn interface::All + Sync),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The character that the console interface returns for end of input in canonical mode.
+pub const EOF: char = CTRL_D;
+
+/// Called when Ctrl-C is received.
+pub type InterruptHandler = fn();
+
+/// TTY modes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Termios {
+    /// Collect input into lines and provide line editing.
+    pub canonical: bool,
+
+    /// Echo received characters.
+    pub echo: bool,
+
+    /// Translate carriage return to newline on input.
+    pub input_cr_to_lf: bool,
+
+    /// Translate newline to carriage return plus newline on output.
+    pub output_lf_to_crlf: bool,
+
+    /// Handle Ctrl-C.
+    pub signals: bool,
+
+    /// Handle Ctrl-S and Ctrl-Q.
+    pub flow_control: bool,
+}
+
+/// A TTY on top of a character device.
+pub struct Tty {
+    device: &'static (dyn interface::All + Sync),
+    inner: IRQSafeNullLock<TtyInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static TTYS: InitStateLock<Vec<&'static Tty>> = InitStateLock::new(Vec::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Output<'_> {
+    fn emit(&mut self, c: char) {
+        if c == '\n' && self.inner.termios.output_lf_to_crlf {
+            self.device.write_char('\r');
+        }
+
+        self.device.write_char(c);
+    }
+
+    fn erase(&mut self, num_chars: usize) {
+        for _ in 0..num_chars {
+            self.emit(BACKSPACE);
+            self.emit(' ');
+            self.emit(BACKSPACE);
+        }
+    }
+
+    fn echo(&mut self, c: char) {
+        if self.inner.termios.echo {
+            self.emit(c);
+        }
+    }
+
+    /// Process a received character.
+    fn receive(&mut self, c: char) {
+        let termios = self.inner.termios;
+        let c = if termios.input_cr_to_lf && c == '\r' {
+            '\n'
+        } else {
+            c
+        };
+
+        if termios.flow_control && (c == CTRL_S || c == CTRL_Q) {
+            match c {
+                CTRL_S => self.inner.output_stopped = true,
+                _ => self.resume(),
+            }
+            return;
+        }
+
+        if termios.signals && c == CTRL_C {
+            self.inner.line.clear();
+            self.inner.ready.clear();
+            self.inner.interrupt_pending = true;
+            if termios.echo {
+                self.emit('^');
+                self.emit('C');
+                self.emit('\n');
+            }
+            return;
+        }
+
+        if !termios.canonical {
+            self.inner.ready.push_back(c);
+            self.echo(c);
+            return;
+        }
+
+        match c {
+            BACKSPACE | DELETE => {
+                if self.inner.line.pop().is_some() && termios.echo {
+                    self.erase(1);
+                }
+            }
+            CTRL_U => {
+                if termios.echo {
+                    self.erase(self.inner.line.chars().count());
+                }
+                self.inner.line.clear();
+            }
+            CTRL_D => {
+                if self.inner.line.is_empty() {
+                    self.inner.eof = true;
+                } else {
+                    let line = core::mem::take(&mut self.inner.line);
+                    self.inner.ready.extend(line.chars());
+                    self.inner.ready.push_back(LINE_PUSH);
+                }
+            }
+            '\n' => {
+                let mut line = core::mem::take(&mut self.inner.line);
+                line.push('\n');
+                self.inner.ready.extend(line.chars());
+                self.echo('\n');
+            }
+            c if c.is_control() => (),
+            c => {
+                if self.inner.line.len() < MAX_LINE_LEN {
+                    self.inner.line.push(c);
+                    self.echo(c);
+                }
+            }
+        }
+    }
+
+    /// Process all characters that the device has received so far.
+    fn poll_input(&mut self) {
+        while let Some(c) = self.device.try_read_char() {
+            self.receive(c);
+        }
+    }
+
+    /// Resume stopped output and write what was buffered in the meantime.
+    fn resume(&mut self) {
+        self.inner.output_stopped = false;
+
+        while let Some(c) = self.inner.stopped_output.pop_front() {
+            self.emit(c);
+        }
+    }
+
+    /// Write a character, or buffer it while output is stopped.
+    fn write(&mut self, c: char) {
+        self.poll_input();
+
+        if !self.inner.output_stopped {
+            self.emit(c);
+        } else if self.inner.stopped_output.len() < MAX_STOPPED_OUTPUT {
+            self.inner.stopped_output.push_back(c);
+        }
+    }
+}
+
+impl fmt::Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.write(c);
+        }
+
+        Ok(())
+    }
+}
+
+impl Tty {
+    fn new(device: &'static (dyn interface::All + Sync), termios: Termios) -> Self {
+        Self {
+            device,
+            inner: IRQSafeNullLock::new(TtyInner {
+                termios,
+                line: String::new(),
+                ready: VecDeque::new(),
+                eof: false,
+                output_stopped: false,
+                stopped_output: VecDeque::new(),
+                interrupt_pending: false,
+                interrupt_handler: None,
+            }),
+        }
+    }
+
+    fn with_output<R>(&self, f: impl FnOnce(&mut Output) -> R) -> R {
+        let (result, handler) = self.inner.lock(|inner| {
+            let mut output = Output {
+                inner,
+                device: self.device,
+            };
+            let result = f(&mut output);
+
+            let inner = output.inner;
+            let handler = match inner.interrupt_pending {
+                false => None,
+                true => {
+                    inner.interrupt_pending = false;
+                    inner.interrupt_handler
+                }
+            };
+
+            (result, handler)
+        });
+
+        // The handler might use the console, so call it without holding the lock.
+        if let Some(handler) = handler {
+            handler();
+        }
+
+        result
+    }
+
+    /// Return the next readable character, or `Some(EOF)` on end of input.
+    ///
+    /// In canonical mode, this includes the [`LINE_PUSH`] markers.
+    fn try_read(&self) -> Option<char> {
+        self.with_output(|output| {
+            output.poll_input();
+
+            match output.inner.ready.pop_front() {
+                Some(c) => Some(c),
+                None if output.inner.eof => {
+                    output.inner.eof = false;
+                    Some(EOF)
+                }
+                None => None,
+            }
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl Termios {
+    /// No processing at all.
+    pub const RAW: Self = Self {
+        canonical: false,
+        echo: false,
+        input_cr_to_lf: false,
+        output_lf_to_crlf: false,
+        signals: false,
+        flow_control: false,
+    };
+
+    /// Line editing, echo, newline translation and special characters.
+    pub const COOKED: Self = Self {
+        canonical: true,
+        echo: true,
+        input_cr_to_lf: true,
+        output_lf_to_crlf: true,
+        signals: true,
+        flow_control: true,
+    };
+}
+
+impl Tty {
+    /// Return the current modes.
+    pub fn termios(&self) -> Termios {
+        self.inner.lock(|inner| inner.termios)
+    }
+
+    /// Change the modes.
+    ///
+    /// Leaving canonical mode makes the line that is being edited readable.
+    pub fn set_termios(&self, termios: Termios) {
+        self.with_output(|output| {
+            let inner = &mut *output.inner;
+
+            if inner.termios.canonical && !termios.canonical {
+                inner.ready.retain(|&c| c != LINE_PUSH);
+                let line = core::mem::take(&mut inner.line);
+                inner.ready.extend(line.chars());
+            }
+
+            inner.termios = termios;
+
+            if !termios.flow_control {
+                output.resume();
+            }
+        });
+    }
+
+    /// Set the handler that is called when Ctrl-C is received.
+    pub fn set_interrupt_handler(&self, handler: Option<InterruptHandler>) {
+        self.inner.lock(|inner| inner.interrupt_handler = handler);
+    }
+
+    /// Read input into `buf`, blocking until at least one character is available.
+    ///
+    /// In canonical mode, reads at most up to the end of the current line. Returns the number of
+    /// characters read, which is zero on end of input.
+    pub fn read(&self, buf: &mut [char]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+
+        let mut num_read = 0;
+        loop {
+            let canonical = self.termios().canonical;
+
+            match self.try_read() {
+                Some(EOF) if canonical => return num_read,
+                Some(LINE_PUSH) if canonical => {
+                    // The line might have been read completely by the previous call already.
+                    if num_read > 0 {
+                        return num_read;
+                    }
+                }
+                Some(c) => {
+                    buf[num_read] = c;
+                    num_read += 1;
+
+                    if num_read == buf.len() || (canonical && c == '\n') {
+                        return num_read;
+                    }
+                }
+                None if num_read > 0 && !canonical => return num_read,
+                None => cpu::nop(),
+            }
+        }
+    }
+}
+
+impl interface::Write for Tty {
+    fn write_char(&self, c: char) {
+        self.with_output(|output| output.write(c));
+    }
+
+    fn write_array(&self, a: &[char]) {
+        self.with_output(|output| {
+            for c in a {
+                output.write(*c);
+            }
+        });
+    }
+
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        self.with_output(|output| fmt::Write::write_fmt(output, args))
+    }
+
+    fn flush(&self) {
+        self.device.flush();
+    }
+}
+
+impl interface::Read for Tty {
+    fn read_char(&self) -> char {
+        loop {
+            if let Some(c) = self.try_read_char() {
+                return c;
+            }
+
+            cpu::nop();
+        }
+    }
+
+    fn try_read_char(&self) -> Option<char> {
+        loop {
+            match self.try_read() {
+                Some(LINE_PUSH) if self.termios().canonical => (),
+                c => return c,
+            }
+        }
+    }
+
+    fn clear_rx(&self) {
+        self.device.clear_rx();
+        self.inner.lock(|inner| {
+            inner.line.clear();
+            inner.ready.clear();
+            inner.eof = false;
+        });
+    }
+}
+
+impl interface::Statistics for Tty {
+    fn chars_written(&self) -> usize {
+        self.device.chars_written()
+    }
+
+    fn chars_read(&self) -> usize {
+        self.device.chars_read()
+    }
+}
+
+impl interface::All for Tty {}
+
+/// Create a TTY on top of `device` and register it as a console.
+pub fn register_tty(
+    device: &'static (dyn interface::All + Sync),
+    termios: Termios,
+    flags: console::ConsoleFlags,
+) -> &'static Tty {
+    let tty: &'static Tty = Box::leak(Box::new(Tty::new(device, termios)));
+
+    TTYS.write(|ttys| ttys.push(tty));
+    console::register_console(tty, flags);
+
+    tty
+}
+
+/// Call `f` for each registered TTY.
+pub fn for_each_tty(f: impl FnMut(&&'static Tty)) {
+    TTYS.read(|ttys| ttys.iter().for_each(f))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A device that replays scripted input and records output.
+    struct TestDevice {
+        input: IRQSafeNullLock<VecDeque<char>>,
+        output: IRQSafeNullLock<String>,
+    }
+
+    impl interface::Write for TestDevice {
+        fn write_char(&self, c: char) {
+            self.output.lock(|output| output.push(c));
+        }
+
+        fn write_array(&self, a: &[char]) {
+            self.output.lock(|output| output.extend(a));
+        }
+
+        fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+            self.output
+                .lock(|output| fmt::Write::write_fmt(output, args))
+        }
+
+        fn flush(&self) {}
+    }
+
+    impl interface::Read for TestDevice {
+        fn try_read_char(&self) -> Option<char> {
+            self.input.lock(|input| input.pop_front())
+        }
+
+        fn clear_rx(&self) {}
+    }
+
+    impl interface::Statistics for TestDevice {}
+    impl interface::All for TestDevice {}
+
+    fn tty_with_input(termios: Termios, input: &str) -> (Tty, &'static TestDevice) {
+        let device = Box::leak(Box::new(TestDevice {
+            input: IRQSafeNullLock::new(input.chars().collect()),
+            output: IRQSafeNullLock::new(String::new()),
+        }));
+
+        (Tty::new(device, termios), device)
+    }
+
+    /// Canonical mode provides line editing, line kill and end of input.
+    #[kernel_test]
+    fn canonical_mode_edits_lines() {
+        let (tty, device) = tty_with_input(Termios::COOKED, "ab\x7fc\rxy\x15z\r\x04");
+        let mut buf = ['\0'; 16];
+
+        let n = tty.read(&mut buf);
+        assert_eq!(String::from_iter(&buf[..n]), "ac\n");
+
+        let n = tty.read(&mut buf);
+        assert_eq!(String::from_iter(&buf[..n]), "z\n");
+
+        assert_eq!(tty.read(&mut buf), 0);
+
+        device
+            .output
+            .lock(|output| assert_eq!(output, "ab\x08 \x08c\r\nxy\x08 \x08\x08 \x08z\r\n"));
+    }
+
+    /// Ctrl-D on a partial line makes it readable without a newline.
+    #[kernel_test]
+    fn ctrl_d_pushes_partial_lines() {
+        let (tty, _) = tty_with_input(Termios::COOKED, "ab\x04cd\r");
+        let mut buf = ['\0'; 16];
+
+        let n = tty.read(&mut buf);
+        assert_eq!(String::from_iter(&buf[..n]), "ab");
+
+        let n = tty.read(&mut buf);
+        assert_eq!(String::from_iter(&buf[..n]), "cd\n");
+
+        // A line that filled the buffer exactly is not followed by a spurious end of input.
+        let (tty, _) = tty_with_input(Termios::COOKED, "ab\x04\x04");
+        let mut buf = ['\0'; 2];
+
+        assert_eq!(tty.read(&mut buf), 2);
+        assert_eq!(tty.read(&mut buf), 0);
+    }
+
+    /// Output that is written while stopped with Ctrl-S is written on Ctrl-Q.
+    #[kernel_test]
+    fn ctrl_s_buffers_output() {
+        use interface::Write;
+
+        let (tty, device) = tty_with_input(Termios::COOKED, "\x13");
+
+        tty.write_fmt(format_args!("ab")).unwrap();
+        device.output.lock(|output| assert_eq!(output, ""));
+
+        device.input.lock(|input| input.push_back('\x11'));
+        tty.write_char('c');
+        device.output.lock(|output| assert_eq!(output, "abc"));
+    }
+
+    /// Raw mode passes everything through unmodified.
+    #[kernel_test]
+    fn raw_mode_passes_through() {
+        use interface::{Read, Write};
+
+        let (tty, device) = tty_with_input(Termios::RAW, "\r\x03\x04");
+
+        assert_eq!(tty.try_read_char(), Some('\r'));
+        assert_eq!(tty.try_read_char(), Some('\x03'));
+        assert_eq!(tty.try_read_char(), Some('\x04'));
+        assert_eq!(tty.try_read_char(), None);
+
+        tty.write_fmt(format_args!("a\nb")).unwrap();
+        device.output.lock(|output| assert_eq!(output, "a\nb"));
+    }
+
+    /// Ctrl-C discards pending input and calls the interrupt handler.
+    #[kernel_test]
+    fn ctrl_c_calls_interrupt_handler() {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use interface::Read;
+
+        static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+        let termios = Termios {
+            canonical: false,
+            ..Termios::COOKED
+        };
+        let (tty, _) = tty_with_input(termios, "a\x03b");
+        tty.set_interrupt_handler(Some(|| INTERRUPTED.store(true, Ordering::Relaxed)));
+
+        assert_eq!(tty.try_read_char(), Some('b'));
+        assert!(INTERRUPTED.load(Ordering::Relaxed));
+    }
+}
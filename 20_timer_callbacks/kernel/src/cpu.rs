@@ -15,7 +15,8 @@ pub mod smp;
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_cpu::{nop, wait_forever};
+pub use arch_cpu::{memory_barrier, nop, part_number, wait_forever};
+pub use boot::phys_device_tree_addr;
 
 #[cfg(feature = "test_build")]
 pub use arch_cpu::{qemu_exit_failure, qemu_exit_success};
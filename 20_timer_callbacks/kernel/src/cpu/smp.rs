@@ -8,7 +8,81 @@
 #[path = "../_arch/aarch64/cpu/smp.rs"]
 mod arch_smp;
 
+use crate::{
+    bsp,
+    exception::{
+        self,
+        asynchronous::{IPIKind, IRQContext},
+    },
+    memory,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::mem;
+
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 pub use arch_smp::core_id;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A function that is executed on another core.
+pub type RemoteCall = Box<dyn FnOnce() + Send>;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CALL_QUEUE_INIT: IRQSafeNullLock<Vec<RemoteCall>> = IRQSafeNullLock::new(Vec::new());
+
+/// Calls that are waiting for execution, one queue per core.
+static CALL_QUEUES: [IRQSafeNullLock<Vec<RemoteCall>>; bsp::cpu::NUM_CORES] =
+    [CALL_QUEUE_INIT; bsp::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Execute all calls that were queued for the executing core.
+fn run_queued_calls() {
+    let calls = CALL_QUEUES[core_id::<usize>()].lock(mem::take);
+
+    for call in calls {
+        call();
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Execute `call` on the core with id `core`.
+///
+/// The call is queued and the target core is interrupted with an IPI. It runs in IRQ context with
+/// IRQs masked, so it should be short. This function does not wait for completion.
+pub fn call_on_core(core: usize, call: RemoteCall) -> Result<(), &'static str> {
+    if core >= bsp::cpu::NUM_CORES {
+        return Err("Invalid core id");
+    }
+
+    CALL_QUEUES[core].lock(|queue| queue.push(call));
+
+    exception::asynchronous::irq_manager().send_ipi(core, IPIKind::CallFunction)
+}
+
+/// Handle an IPI that was received by the executing core.
+///
+/// To be called by the IRQ manager, with IRQs masked.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+pub fn handle_ipi(kind: IPIKind, _ic: &IRQContext) {
+    match kind {
+        // There is no scheduler yet. Returning from the exception is all that is needed.
+        IPIKind::Reschedule => (),
+        IPIKind::TLBShootdown => memory::mmu::invalidate_tlb_local(),
+        IPIKind::CallFunction => run_queued_calls(),
+    }
+}
@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Device tree.
+//!
+//! The firmware describes the board in a flattened device tree blob (DTB), whose physical address
+//! it passes to the kernel at boot. [`init()`] maps and validates the blob, which is parsed by the
+//! `fdt` library crate afterwards. If the firmware passes no valid blob, e.g. in QEMU, the BSP's
+//! built-in device tree is used instead.
+
+use crate::{
+    bsp, cpu, info,
+    memory::{self, mmu::MMIODescriptor, Address, Physical},
+    synchronization::{interface::ReadWriteEx, InitStateLock},
+};
+use fdt::Fdt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct DeviceTree {
+    fdt: Fdt<'static>,
+
+    /// Why the firmware's blob is not used, if it is not.
+    firmware_error: Option<&'static str>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static DEVICE_TREE: InitStateLock<Option<DeviceTree>> = InitStateLock::new(None);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map and validate the blob at `phys_addr`.
+///
+/// # Safety
+///
+/// - See [`init()`].
+unsafe fn map(phys_addr: Address<Physical>) -> Result<Fdt<'static>, &'static str> {
+    let mmio_start = bsp::memory::phys_mmio_start_addr();
+
+    if phys_addr.as_usize() % 8 != 0 {
+        return Err("Device tree blob is misaligned");
+    }
+    if phys_addr.as_usize() + fdt::HEADER_SIZE > mmio_start.as_usize() {
+        return Err("Device tree blob is not in DRAM");
+    }
+
+    // Map the header first to learn the size of the blob.
+    let header_descriptor = MMIODescriptor::new(phys_addr, fdt::HEADER_SIZE);
+    let header_addr =
+        memory::mmu::kernel_map_mmio_cacheable("Device tree header", &header_descriptor)?;
+    let header = core::slice::from_raw_parts(header_addr.as_usize() as *const u8, fdt::HEADER_SIZE);
+
+    let size = fdt::blob_size(header)?;
+    if size < fdt::HEADER_SIZE || phys_addr.as_usize() + size > mmio_start.as_usize() {
+        return Err("Device tree blob has an invalid size");
+    }
+
+    let descriptor = MMIODescriptor::new(phys_addr, size);
+    let virt_addr = memory::mmu::kernel_map_mmio_cacheable("Device tree", &descriptor)?;
+    let blob = core::slice::from_raw_parts(virt_addr.as_usize() as *const u8, size);
+
+    Fdt::new(blob)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map and validate the device tree blob that the firmware passed, or fall back to the built-in
+/// one.
+///
+/// # Safety
+///
+/// - The firmware must not reuse the memory of the blob.
+/// - Must only be called once, during kernel init.
+pub unsafe fn init() -> Result<(), &'static str> {
+    let firmware_fdt = match cpu::phys_device_tree_addr() {
+        None => Err("No device tree blob passed by the firmware"),
+        Some(phys_addr) => map(phys_addr),
+    };
+
+    let device_tree = match firmware_fdt {
+        Ok(fdt) => DeviceTree {
+            fdt,
+            firmware_error: None,
+        },
+        Err(x) => DeviceTree {
+            fdt: Fdt::new(bsp::builtin_device_tree())?,
+            firmware_error: Some(x),
+        },
+    };
+
+    DEVICE_TREE.write(|dt| *dt = Some(device_tree));
+
+    Ok(())
+}
+
+/// Return the device tree.
+pub fn device_tree() -> Result<Fdt<'static>, &'static str> {
+    DEVICE_TREE.read(|dt| {
+        dt.as_ref()
+            .map(|dt| dt.fdt)
+            .ok_or("Device tree not initialized")
+    })
+}
+
+/// Print information about the board from the device tree.
+pub fn print_info() {
+    let (fdt, firmware_error) =
+        match DEVICE_TREE.read(|dt| dt.as_ref().map(|dt| (dt.fdt, dt.firmware_error))) {
+            None => {
+                info!("      Not initialized");
+                return;
+            }
+            Some(x) => x,
+        };
+
+    match firmware_error {
+        None => info!("      Source: Firmware"),
+        Some(x) => info!("      Source: Built-in ({})", x),
+    }
+    info!("      Model: {}", fdt.model().unwrap_or("Unknown"));
+    info!("      Size: {} Byte", fdt.total_size());
+
+    for region in fdt.memory() {
+        match region {
+            Ok(r) => info!(
+                "      Memory: {:#010x}..{:#010x}",
+                r.address,
+                r.address + r.size
+            ),
+            Err(x) => info!("      Memory: {}", x),
+        }
+    }
+
+    info!("      Boot arguments: {}", fdt.bootargs().unwrap_or(""));
+}
@@ -5,8 +5,10 @@
 //! Driver support.
 
 use crate::{
-    exception, info,
+    exception, fs, info,
+    memory::{mmu::MMIODescriptor, Address},
     synchronization::{interface::ReadWriteEx, InitStateLock},
+    warn,
 };
 use alloc::vec::Vec;
 use core::fmt;
@@ -48,6 +50,13 @@ pub mod interface {
                 self.compatible()
             )
         }
+
+        /// Return the file that represents the device in `devfs`, if the driver provides one.
+        fn device_file(
+            &'static self,
+        ) -> Option<&'static (dyn crate::fs::interface::DeviceFile + Sync)> {
+            None
+        }
     }
 }
 
@@ -62,6 +71,53 @@ where
     device_driver: &'static (dyn interface::DeviceDriver<IRQNumberType = T> + Sync),
     post_init_callback: Option<DeviceDriverPostInitCallback>,
     irq_number: Option<T>,
+    dependencies: &'static [&'static str],
+}
+
+/// The init status of a registered device driver.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DriverStatus {
+    /// Not initialized yet.
+    Pending,
+
+    /// Initialized.
+    Ok,
+
+    /// Waiting for a dependency that is not available. Retried by the next call of
+    /// [`DriverManager::init_drivers_and_irqs()`].
+    Deferred(&'static str),
+
+    /// Init failed.
+    Failed(&'static str),
+}
+
+/// Translates a device tree interrupt specifier to the IRQ number of the interrupt controller.
+pub type IRQNumberFromDeviceTree<T> = fn(&fdt::Interrupt) -> Result<T, &'static str>;
+
+/// The resources of a device, as described by its device tree node.
+pub struct DeviceResources<T> {
+    node: fdt::Node<'static>,
+    mmio: Vec<Result<MMIODescriptor, &'static str>>,
+    irqs: Vec<Result<T, &'static str>>,
+}
+
+/// Returned by a probe function if a driver that it needs has not been probed yet. The probe is
+/// retried after other devices were probed.
+pub const PROBE_DEFERRED: &str = "Dependency not probed yet";
+
+/// Called for each enabled device tree node that is compatible with the driver.
+///
+/// The function instantiates the driver for the device and registers it with
+/// [`DriverManager::register_driver()`].
+pub type DeviceDriverProbe<T> = unsafe fn(&DeviceResources<T>) -> Result<(), &'static str>;
+
+/// A descriptor for drivers that are instantiated from the device tree.
+pub struct DeviceDriverMatch<T>
+where
+    T: 'static,
+{
+    compatible: &'static [&'static str],
+    probe: DeviceDriverProbe<T>,
 }
 
 /// Provides device driver management functions.
@@ -70,6 +126,15 @@ where
     T: 'static,
 {
     descriptors: InitStateLock<Vec<DeviceDriverDescriptor<T>>>,
+    statuses: InitStateLock<Vec<DriverStatus>>,
+    matches: InitStateLock<Vec<DeviceDriverMatch<T>>>,
+}
+
+/// Whether the dependencies of a driver allow it to be initialized.
+enum Dependencies {
+    Ready,
+    Waiting,
+    Failed,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -93,8 +158,85 @@ impl<T> DeviceDriverDescriptor<T> {
             device_driver,
             post_init_callback,
             irq_number,
+            dependencies: &[],
         }
     }
+
+    /// Declare the drivers, by their compatibility strings, that must be initialized before this
+    /// one.
+    ///
+    /// Drivers with an IRQ number do not need to declare the interrupt controller. Their interrupt
+    /// handlers are registered only after all drivers were initialized.
+    pub fn with_dependencies(mut self, dependencies: &'static [&'static str]) -> Self {
+        self.dependencies = dependencies;
+        self
+    }
+}
+
+impl fmt::Display for DriverStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Ok => write!(f, "ok"),
+            Self::Deferred(x) => write!(f, "deferred ({})", x),
+            Self::Failed(x) => write!(f, "failed ({})", x),
+        }
+    }
+}
+
+impl<T: Copy> DeviceResources<T> {
+    /// Resolve the resources of `node`.
+    ///
+    /// Addresses are translated to physical addresses, and interrupts to IRQ numbers with
+    /// `irq_number`. Resources that can not be resolved are reported when a driver asks for them.
+    pub fn new(
+        node: fdt::Node<'static>,
+        irq_number: IRQNumberFromDeviceTree<T>,
+    ) -> Result<Self, &'static str> {
+        let mmio = node
+            .reg()
+            .map(|region| {
+                let region = region?;
+                let start = usize::try_from(region.address).map_err(|_| "Address too large")?;
+                let size = usize::try_from(region.size).map_err(|_| "Size too large")?;
+                if size == 0 {
+                    return Err("Empty MMIO region");
+                }
+
+                Ok(MMIODescriptor::new(Address::new(start), size))
+            })
+            .collect();
+        let irqs = node.interrupts()?.map(|irq| irq_number(&irq)).collect();
+
+        Ok(Self { node, mmio, irqs })
+    }
+
+    /// Return the device tree node.
+    pub fn node(&self) -> fdt::Node<'static> {
+        self.node
+    }
+
+    /// Return the MMIO region `index` of the `reg` property.
+    pub fn mmio(&self, index: usize) -> Result<MMIODescriptor, &'static str> {
+        self.mmio
+            .get(index)
+            .copied()
+            .unwrap_or(Err("MMIO region missing"))
+    }
+
+    /// Return IRQ `index` of the `interrupts` property.
+    pub fn irq(&self, index: usize) -> Result<T, &'static str> {
+        self.irqs.get(index).copied().unwrap_or(Err("IRQ missing"))
+    }
+}
+
+impl<T> DeviceDriverMatch<T> {
+    /// Create an instance.
+    ///
+    /// `probe` is called for nodes with any of the `compatible` strings, e.g. `arm,pl011`.
+    pub const fn new(compatible: &'static [&'static str], probe: DeviceDriverProbe<T>) -> Self {
+        Self { compatible, probe }
+    }
 }
 
 /// Return a reference to the global DriverManager.
@@ -110,69 +252,451 @@ where
     pub const fn new() -> Self {
         Self {
             descriptors: InitStateLock::new(Vec::new()),
+            statuses: InitStateLock::new(Vec::new()),
+            matches: InitStateLock::new(Vec::new()),
         }
     }
 
+    /// Register a driver that is instantiated from the device tree.
+    pub fn register_driver_match(&self, driver_match: DeviceDriverMatch<T>) {
+        self.matches.write(|matches| matches.push(driver_match));
+    }
+
     /// Register a device driver with the kernel.
     pub fn register_driver(&self, descriptor: DeviceDriverDescriptor<T>) {
         self.descriptors
             .write(|descriptors| descriptors.push(descriptor));
+        self.statuses
+            .write(|statuses| statuses.push(DriverStatus::Pending));
+    }
+
+    fn status(&self, index: usize) -> DriverStatus {
+        self.statuses.read(|statuses| statuses[index])
+    }
+
+    fn set_status(&self, index: usize, status: DriverStatus) {
+        self.statuses.write(|statuses| statuses[index] = status);
+    }
+
+    /// Return the index of the driver that is compatible with `compatible`.
+    fn find(descriptors: &[DeviceDriverDescriptor<T>], compatible: &str) -> Option<usize> {
+        descriptors
+            .iter()
+            .position(|descriptor| descriptor.device_driver.compatible() == compatible)
+    }
+
+    fn dependencies(
+        &self,
+        descriptors: &[DeviceDriverDescriptor<T>],
+        index: usize,
+    ) -> Dependencies {
+        let mut result = Dependencies::Ready;
+
+        for dependency in descriptors[index].dependencies {
+            match Self::find(descriptors, dependency).map(|i| self.status(i)) {
+                Some(DriverStatus::Ok) => (),
+                Some(DriverStatus::Failed(_)) => return Dependencies::Failed,
+                _ => result = Dependencies::Waiting,
+            }
+        }
+
+        result
+    }
+
+    /// Initialize a driver and call its post init callback.
+    unsafe fn init_driver(descriptor: &DeviceDriverDescriptor<T>) -> Result<(), &'static str> {
+        descriptor.device_driver.init()?;
+
+        if let Some(callback) = &descriptor.post_init_callback {
+            callback()?;
+        }
+
+        Ok(())
+    }
+
+    /// Classify the drivers that are still waiting after init. They either wait, directly or
+    /// through other drivers, for a dependency that is not registered, or are part of a cycle.
+    fn defer_or_fail_waiting(&self, descriptors: &[DeviceDriverDescriptor<T>]) {
+        let waiting: Vec<usize> = (0..descriptors.len())
+            .filter(|&i| {
+                matches!(
+                    self.status(i),
+                    DriverStatus::Pending | DriverStatus::Deferred(_)
+                )
+            })
+            .collect();
+        let mut deferred: Vec<usize> = Vec::new();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &i in &waiting {
+                if deferred.contains(&i) {
+                    continue;
+                }
+
+                let waits_for_missing = descriptors[i].dependencies.iter().any(|dependency| {
+                    match Self::find(descriptors, dependency) {
+                        None => true,
+                        Some(j) => deferred.contains(&j),
+                    }
+                });
+                if waits_for_missing {
+                    deferred.push(i);
+                    changed = true;
+                }
+            }
+        }
+
+        for i in waiting {
+            let status = match deferred.contains(&i) {
+                true => DriverStatus::Deferred("Dependency not available"),
+                false => DriverStatus::Failed("Dependency cycle"),
+            };
+            self.set_status(i, status);
+        }
+    }
+
+    /// Call the probe function of the matching driver for each enabled node of `device_tree`.
+    ///
+    /// Drivers are probed in the order of their registration. Within a driver, nodes are probed in
+    /// the order of its compatible strings, most specific first. A node that fails to probe does
+    /// not stop the others. Probes that return [`PROBE_DEFERRED`] are retried after the others.
+    ///
+    /// # Safety
+    ///
+    /// - During probe, drivers might do stuff with system-wide impact.
+    pub unsafe fn probe_device_tree(
+        &self,
+        device_tree: fdt::Fdt<'static>,
+        irq_number: IRQNumberFromDeviceTree<T>,
+    ) where
+        T: Copy,
+    {
+        let probe = |driver_match: &DeviceDriverMatch<T>, node: fdt::Node<'static>| {
+            DeviceResources::new(node, irq_number)
+                .and_then(|resources| (driver_match.probe)(&resources))
+        };
+
+        self.matches.read(|matches| {
+            let mut deferred = Vec::new();
+
+            for driver_match in matches {
+                for (i, compatible) in driver_match.compatible.iter().enumerate() {
+                    // A node that also matches an earlier string was probed already.
+                    let nodes = device_tree.find_compatible(compatible).filter(|node| {
+                        node.is_enabled()
+                            && !driver_match.compatible[..i]
+                                .iter()
+                                .any(|earlier| node.is_compatible(earlier))
+                    });
+
+                    for node in nodes {
+                        match probe(driver_match, node) {
+                            Err(PROBE_DEFERRED) => deferred.push((driver_match, node)),
+                            Err(x) => warn!("Error probing device {}: {}", node.name(), x),
+                            Ok(()) => (),
+                        }
+                    }
+                }
+            }
+
+            // Retry deferred probes for as long as some of them succeed.
+            let mut progress = true;
+            while progress && !deferred.is_empty() {
+                progress = false;
+
+                for (driver_match, node) in core::mem::take(&mut deferred) {
+                    match probe(driver_match, node) {
+                        Err(PROBE_DEFERRED) => deferred.push((driver_match, node)),
+                        Err(x) => warn!("Error probing device {}: {}", node.name(), x),
+                        Ok(()) => progress = true,
+                    }
+                }
+            }
+
+            for (_, node) in deferred {
+                warn!("Error probing device {}: {}", node.name(), PROBE_DEFERRED);
+            }
+        })
     }
 
     /// Fully initialize all drivers and their interrupts handlers.
     ///
+    /// Drivers are initialized after their dependencies, and otherwise in the order of their
+    /// registration. Failures are recorded in the driver's status instead of stopping the boot, and
+    /// drivers that depend on a failed one are not initialized. Calling this again initializes
+    /// drivers that were registered since, and retries the deferred ones.
+    ///
     /// # Safety
     ///
     /// - During init, drivers might do stuff with system-wide impact.
     pub unsafe fn init_drivers_and_irqs(&self) {
         self.descriptors.read(|descriptors| {
-            for descriptor in descriptors {
-                // 1. Initialize driver.
-                if let Err(x) = descriptor.device_driver.init() {
-                    panic!(
+            let mut initialized = Vec::new();
+
+            // 1. Initialize drivers and call their post init callbacks. The next one is always the
+            //    first registered driver whose dependencies are ready.
+            loop {
+                let next = (0..descriptors.len()).find_map(|i| {
+                    match self.status(i) {
+                        DriverStatus::Pending | DriverStatus::Deferred(_) => (),
+                        _ => return None,
+                    }
+
+                    match self.dependencies(descriptors, i) {
+                        Dependencies::Ready => Some((i, true)),
+                        Dependencies::Failed => Some((i, false)),
+                        Dependencies::Waiting => None,
+                    }
+                });
+
+                let (i, dependencies_ready) = match next {
+                    None => break,
+                    Some(x) => x,
+                };
+
+                let status = match dependencies_ready {
+                    false => DriverStatus::Failed("Dependency failed"),
+                    true => match Self::init_driver(&descriptors[i]) {
+                        Ok(()) => {
+                            initialized.push(i);
+                            DriverStatus::Ok
+                        }
+                        Err(x) => DriverStatus::Failed(x),
+                    },
+                };
+
+                if let DriverStatus::Failed(x) = status {
+                    warn!(
                         "Error initializing driver: {}: {}",
-                        descriptor.device_driver.compatible(),
+                        descriptors[i].device_driver.compatible(),
                         x
                     );
                 }
-
-                // 2. Call corresponding post init callback.
-                if let Some(callback) = &descriptor.post_init_callback {
-                    if let Err(x) = callback() {
-                        panic!(
-                            "Error during driver post-init callback: {}: {}",
-                            descriptor.device_driver.compatible(),
-                            x
-                        );
-                    }
-                }
+                self.set_status(i, status);
             }
 
+            // 2. Drivers that are left are waiting for a dependency.
+            self.defer_or_fail_waiting(descriptors);
+
             // 3. After all post-init callbacks were done, the interrupt controller should be
             //    registered and functional. So let drivers register with it now.
-            for descriptor in descriptors {
+            for i in initialized {
+                let descriptor = &descriptors[i];
+
                 if let Some(irq_number) = &descriptor.irq_number {
-                    if let Err(x) = descriptor
-                        .device_driver
-                        .register_and_enable_irq_handler(irq_number)
-                    {
-                        panic!(
+                    let result = match exception::asynchronous::is_irq_manager_registered() {
+                        false => Err("No IRQ manager registered"),
+                        true => descriptor
+                            .device_driver
+                            .register_and_enable_irq_handler(irq_number),
+                    };
+
+                    if let Err(x) = result {
+                        warn!(
                             "Error during driver interrupt handler registration: {}: {}",
                             descriptor.device_driver.compatible(),
                             x
                         );
+                        self.set_status(i, DriverStatus::Failed(x));
                     }
                 }
             }
         })
     }
 
-    /// Enumerate all registered device drivers.
+    /// Return the device files of all registered device drivers that provide one.
+    pub fn device_files(&self) -> Vec<&'static (dyn fs::interface::DeviceFile + Sync)> {
+        self.descriptors.read(|descriptors| {
+            descriptors
+                .iter()
+                .filter_map(|descriptor| descriptor.device_driver.device_file())
+                .collect()
+        })
+    }
+
+    /// Enumerate all registered device drivers and their status.
     pub fn enumerate(&self) {
         self.descriptors.read(|descriptors| {
             for (i, desc) in descriptors.iter().enumerate() {
-                info!("      {}. {}", i + 1, desc.device_driver.compatible());
+                info!(
+                    "      {}. {}: {}",
+                    i + 1,
+                    desc.device_driver.compatible(),
+                    self.status(i)
+                );
             }
         });
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bsp,
+        synchronization::{interface::Mutex, IRQSafeNullLock},
+    };
+    use test_macros::kernel_test;
+
+    static INIT_ORDER: IRQSafeNullLock<Vec<&'static str>> = IRQSafeNullLock::new(Vec::new());
+
+    struct TestDriver {
+        compatible: &'static str,
+        result: Result<(), &'static str>,
+    }
+
+    impl interface::DeviceDriver for TestDriver {
+        type IRQNumberType = u32;
+
+        fn compatible(&self) -> &'static str {
+            self.compatible
+        }
+
+        unsafe fn init(&self) -> Result<(), &'static str> {
+            INIT_ORDER.lock(|order| order.push(self.compatible));
+            self.result
+        }
+    }
+
+    static GPIO: TestDriver = TestDriver {
+        compatible: "gpio",
+        result: Ok(()),
+    };
+    static UART: TestDriver = TestDriver {
+        compatible: "uart",
+        result: Ok(()),
+    };
+    static BROKEN: TestDriver = TestDriver {
+        compatible: "broken",
+        result: Err("Broken"),
+    };
+    static A: TestDriver = TestDriver {
+        compatible: "a",
+        result: Ok(()),
+    };
+    static B: TestDriver = TestDriver {
+        compatible: "b",
+        result: Ok(()),
+    };
+
+    unsafe fn broken_post_init() -> Result<(), &'static str> {
+        Err("Post init failed")
+    }
+
+    fn descriptor(driver: &'static TestDriver) -> DeviceDriverDescriptor<u32> {
+        DeviceDriverDescriptor::new(driver, None, None)
+    }
+
+    fn statuses(driver_manager: &DriverManager<u32>) -> Vec<DriverStatus> {
+        driver_manager.statuses.read(|statuses| statuses.clone())
+    }
+
+    /// Drivers are initialized after their dependencies, and otherwise in registration order.
+    #[kernel_test]
+    fn drivers_are_initialized_in_dependency_order() {
+        let driver_manager = DriverManager::new();
+        driver_manager.register_driver(descriptor(&UART).with_dependencies(&["gpio"]));
+        driver_manager.register_driver(descriptor(&A));
+        driver_manager.register_driver(descriptor(&GPIO));
+
+        INIT_ORDER.lock(|order| order.clear());
+        unsafe { driver_manager.init_drivers_and_irqs() };
+
+        assert_eq!(
+            INIT_ORDER.lock(|order| order.clone()),
+            ["a", "gpio", "uart"]
+        );
+        assert_eq!(statuses(&driver_manager), [DriverStatus::Ok; 3]);
+    }
+
+    /// Failures do not stop the init of unrelated drivers, but of the ones that depend on them.
+    #[kernel_test]
+    fn failures_are_recorded() {
+        let driver_manager = DriverManager::new();
+        driver_manager.register_driver(descriptor(&BROKEN));
+        driver_manager.register_driver(descriptor(&UART).with_dependencies(&["broken"]));
+        driver_manager.register_driver(DeviceDriverDescriptor::new(
+            &GPIO,
+            Some(broken_post_init),
+            None,
+        ));
+        driver_manager.register_driver(descriptor(&A));
+
+        INIT_ORDER.lock(|order| order.clear());
+        unsafe { driver_manager.init_drivers_and_irqs() };
+
+        assert_eq!(
+            INIT_ORDER.lock(|order| order.clone()),
+            ["broken", "gpio", "a"]
+        );
+        assert_eq!(
+            statuses(&driver_manager),
+            [
+                DriverStatus::Failed("Broken"),
+                DriverStatus::Failed("Dependency failed"),
+                DriverStatus::Failed("Post init failed"),
+                DriverStatus::Ok
+            ]
+        );
+    }
+
+    /// Drivers in a dependency cycle fail, and drivers with a missing dependency are retried.
+    #[kernel_test]
+    fn cycles_fail_and_missing_dependencies_defer() {
+        let driver_manager = DriverManager::new();
+        driver_manager.register_driver(descriptor(&A).with_dependencies(&["b"]));
+        driver_manager.register_driver(descriptor(&B).with_dependencies(&["a"]));
+        driver_manager.register_driver(descriptor(&UART).with_dependencies(&["gpio"]));
+
+        INIT_ORDER.lock(|order| order.clear());
+        unsafe { driver_manager.init_drivers_and_irqs() };
+
+        assert!(INIT_ORDER.lock(|order| order.is_empty()));
+        assert_eq!(
+            statuses(&driver_manager),
+            [
+                DriverStatus::Failed("Dependency cycle"),
+                DriverStatus::Failed("Dependency cycle"),
+                DriverStatus::Deferred("Dependency not available")
+            ]
+        );
+
+        driver_manager.register_driver(descriptor(&GPIO));
+        unsafe { driver_manager.init_drivers_and_irqs() };
+
+        assert_eq!(INIT_ORDER.lock(|order| order.clone()), ["gpio", "uart"]);
+        assert_eq!(statuses(&driver_manager)[2..], [DriverStatus::Ok; 2]);
+    }
+
+    fn irq_number(interrupt: &fdt::Interrupt) -> Result<u32, &'static str> {
+        interrupt
+            .cell(interrupt.len() - 1)
+            .ok_or("Invalid specifier")
+    }
+
+    /// The resources of a device are resolved from its device tree node.
+    #[kernel_test]
+    fn device_resources_are_resolved() {
+        let device_tree = fdt::Fdt::new(bsp::builtin_device_tree()).unwrap();
+        let node = device_tree.find_compatible("arm,pl011").next().unwrap();
+        let resources = DeviceResources::new(node, irq_number).unwrap();
+
+        let mmio = resources.mmio(0).unwrap();
+        assert!(mmio.start_addr() >= bsp::memory::phys_mmio_start_addr());
+        assert_eq!(
+            mmio.end_addr_exclusive().as_usize() - mmio.start_addr().as_usize(),
+            0x200
+        );
+        assert_eq!(resources.mmio(1).err(), Some("MMIO region missing"));
+
+        assert!(resources.irq(0).is_ok());
+        assert_eq!(resources.irq(1).err(), Some("IRQ missing"));
+    }
+}
@@ -7,18 +7,28 @@
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/exception/asynchronous.rs"]
 mod arch_asynchronous;
+mod handler_table;
 mod null_irq_manager;
 
-use crate::{bsp, synchronization};
-use core::marker::PhantomData;
+pub mod deferred_work;
+
+use crate::{bsp, info, synchronization, time};
+use alloc::{format, vec::Vec};
+use core::{
+    fmt,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
 pub use arch_asynchronous::{
-    is_local_irq_masked, local_irq_mask, local_irq_mask_save, local_irq_restore, local_irq_unmask,
-    print_state,
+    is_local_irq_masked, local_fiq_mask, local_fiq_unmask, local_irq_mask, local_irq_mask_save,
+    local_irq_restore, local_irq_unmask, local_irq_unmask_save, print_state,
 };
+pub use handler_table::{chain_priority, handle_chain, IRQHandlerChain, IRQHandlerTable};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -27,6 +37,13 @@ pub use arch_asynchronous::{
 /// Interrupt number as defined by the BSP.
 pub type IRQNumber = bsp::exception::asynchronous::IRQNumber;
 
+/// Interrupt priority.
+///
+/// Follows the convention of the ARM GIC: Lower numbers mean higher priority. An IRQ can only
+/// preempt the handler of another IRQ if its priority is strictly higher.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct IRQPriority(u8);
+
 /// Interrupt descriptor.
 #[derive(Copy, Clone)]
 pub struct IRQHandlerDescriptor<T>
@@ -39,14 +56,75 @@ where
     /// Descriptive name.
     name: &'static str,
 
+    /// The IRQ priority.
+    priority: IRQPriority,
+
+    /// Whether the handler agrees to share the IRQ line with other handlers.
+    shared: bool,
+
     /// Reference to handler trait object.
     handler: &'static (dyn interface::IRQHandler + Sync),
 }
 
+/// Reported by an IRQ handler to tell whether its device was the source of the IRQ.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IRQReturn {
+    /// The device raised the IRQ and it was serviced.
+    Handled,
+
+    /// The device did not raise the IRQ, e.g. because another device on a shared line did.
+    NotHandled,
+}
+
+/// The kinds of inter-processor interrupts (IPIs).
+///
+/// IPIs are handled with IRQs masked on the receiving core and can therefore not be preempted.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IPIKind {
+    /// Ask the target core to reconsider which task to run.
+    Reschedule,
+
+    /// Ask the target core to invalidate its TLB after translation tables were changed.
+    TLBShootdown,
+
+    /// Ask the target core to execute the calls queued by
+    /// [`call_on_core()`](crate::cpu::smp::call_on_core).
+    CallFunction,
+}
+
+/// Statistics of a single IRQ number.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IRQStatistics {
+    /// The number of times the IRQ fired.
+    pub fires: u64,
+
+    /// The accumulated execution time of the handler.
+    pub total_time: Duration,
+
+    /// The longest execution time of the handler.
+    pub max_time: Duration,
+
+    /// The number of spurious IRQs of this number, i.e. times the IRQ fired, but no handler
+    /// reported it as handled.
+    pub spurious: u64,
+}
+
+/// Collects [`IRQStatistics`] from IRQ context without taking a lock.
+///
+/// Execution time is measured with the timer of the [`TimeManager`](time::TimeManager). If a
+/// handler is preempted by an IRQ of higher priority, the time spent in the preempting handler is
+/// included.
+pub struct IRQStatisticsRecorder {
+    fires: AtomicU64,
+    total_time_ns: AtomicU64,
+    max_time_ns: AtomicU64,
+    spurious: AtomicU64,
+}
+
 /// IRQContext token.
 ///
 /// An instance of this type indicates that the local core is currently executing in IRQ
-/// context, aka executing an interrupt vector or subcalls of it.
+/// context, aka executing an interrupt vector or subcalls of it. This includes the FIQ vector.
 ///
 /// Concept and implementation derived from the `CriticalSection` introduced in
 /// <https://github.com/rust-embedded/bare-metal>
@@ -61,7 +139,10 @@ pub mod interface {
     /// Implemented by types that handle IRQs.
     pub trait IRQHandler {
         /// Called when the corresponding interrupt is asserted.
-        fn handle(&self) -> Result<(), &'static str>;
+        ///
+        /// Must return [`IRQReturn::NotHandled`](super::IRQReturn::NotHandled) if the device
+        /// did not raise the IRQ, which is possible on shared lines.
+        fn handle(&self) -> Result<super::IRQReturn, &'static str>;
     }
 
     /// IRQ management functions.
@@ -73,19 +154,38 @@ pub mod interface {
         type IRQNumberType: Copy;
 
         /// Register a handler.
+        ///
+        /// Can be called at any time. If a handler is already registered for the IRQ number, the
+        /// new one is chained behind it, given that all of them were created as shared.
         fn register_handler(
             &self,
             irq_handler_descriptor: super::IRQHandlerDescriptor<Self::IRQNumberType>,
         ) -> Result<(), &'static str>;
 
+        /// Remove a handler.
+        ///
+        /// If it was the last handler of the IRQ number, the IRQ is disabled as well.
+        fn unregister_handler(
+            &self,
+            irq_number: &Self::IRQNumberType,
+            handler: &'static (dyn IRQHandler + Sync),
+        ) -> Result<(), &'static str>;
+
         /// Enable an interrupt in the controller.
         fn enable(&self, irq_number: &Self::IRQNumberType);
 
+        /// Disable an interrupt in the controller.
+        fn disable(&self, irq_number: &Self::IRQNumberType);
+
         /// Handle pending interrupts.
         ///
         /// This function is called directly from the CPU's IRQ exception vector. On AArch64,
         /// this means that the respective CPU core has disabled exception handling.
-        /// This function can therefore not be preempted and runs start to finish.
+        ///
+        /// Implementations are expected to unmask IRQs on the executing core while a handler is
+        /// running, after having ensured that only IRQs of strictly higher priority can be
+        /// signaled. This means that handlers can be preempted by more important IRQs, which in
+        /// turn results in nested calls of this function.
         ///
         /// Takes an IRQContext token to ensure it can only be called from IRQ context.
         #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -94,8 +194,49 @@ pub mod interface {
             ic: &super::IRQContext<'irq_context>,
         );
 
+        /// Send an inter-processor interrupt to the core with id `target_core`.
+        ///
+        /// The executing core can send IPIs to itself.
+        fn send_ipi(&self, target_core: usize, kind: super::IPIKind) -> Result<(), &'static str>;
+
+        /// Deliver an IRQ as FIQ to the executing core and call `handler` for it.
+        ///
+        /// Only a single source can be designated as FIQ. FIQs are not masked by
+        /// [`IRQSafeNullLock`](crate::synchronization::IRQSafeNullLock), so `handler` must not
+        /// access any data that is protected by one. Only allowed during kernel init.
+        fn set_fiq(
+            &self,
+            irq_number: &Self::IRQNumberType,
+            handler: &'static (dyn IRQHandler + Sync),
+        ) -> Result<(), &'static str>;
+
+        /// Handle the pending FIQ.
+        ///
+        /// Called directly from the CPU's FIQ exception vector, with IRQs and FIQs masked.
+        /// Implementations must not take any locks.
+        #[allow(clippy::trivially_copy_pass_by_ref)]
+        fn handle_pending_fiq<'irq_context>(
+            &'irq_context self,
+            ic: &super::IRQContext<'irq_context>,
+        );
+
         /// Print list of registered handlers.
         fn print_handler(&self) {}
+
+        /// Return the statistics of an IRQ number.
+        fn irq_statistics(&self, irq_number: &Self::IRQNumberType) -> super::IRQStatistics;
+
+        /// Return the number of IRQ exceptions that were taken without an IRQ being pending.
+        ///
+        /// These are counted globally, as there is no IRQ number to attribute them to: The
+        /// interrupt was withdrawn before it was acknowledged, so the GIC reports the special ID
+        /// 1023 and the BCM controller has no pending bit set. Spurious IRQs that do have a number
+        /// are counted per IRQ, see [`IRQStatistics::spurious`](super::IRQStatistics::spurious).
+        fn num_spurious_irqs(&self) -> u64;
+
+        /// Print the statistics of all IRQs with a registered handler, akin to
+        /// `/proc/interrupts`.
+        fn print_statistics(&self) {}
     }
 }
 
@@ -107,11 +248,160 @@ static CUR_IRQ_MANAGER: InitStateLock<
     &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
 > = InitStateLock::new(&null_irq_manager::NULL_IRQ_MANAGER);
 
+static IRQ_MANAGER_REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Number of IRQs that were dispatched to handlers, over all IRQ numbers.
+static NUM_IRQS: AtomicU64 = AtomicU64::new(0);
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 use synchronization::{interface::ReadWriteEx, InitStateLock};
 
+impl IRQPriority {
+    /// The number of supported priority levels.
+    ///
+    /// The GIC-400 of the RPi4 implements at least 16 levels, so this is what is exposed.
+    pub const NUM_LEVELS: u8 = 16;
+
+    /// The priority of the FIQ source.
+    ///
+    /// Reserved for it and strictly higher than any IRQ priority, so that the FIQ is never held off
+    /// by an active IRQ.
+    pub const FIQ: Self = Self(0);
+
+    /// The highest priority of an IRQ.
+    pub const HIGHEST: Self = Self(1);
+
+    /// The priority used by handlers that do not have special requirements.
+    pub const DEFAULT: Self = Self(Self::NUM_LEVELS / 2);
+
+    /// The lowest priority.
+    pub const LOWEST: Self = Self(Self::NUM_LEVELS - 1);
+
+    /// Create an instance.
+    ///
+    /// Level zero is reserved for the FIQ, see [`IRQPriority::FIQ`].
+    pub const fn new(level: u8) -> Self {
+        assert!(level > Self::FIQ.0 && level < Self::NUM_LEVELS);
+
+        Self(level)
+    }
+
+    /// Return the priority level. Zero is the highest priority.
+    pub const fn level(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if `self` is important enough to preempt a handler running at `other`.
+    pub fn preempts(self, other: Self) -> bool {
+        self.0 < other.0
+    }
+}
+
+impl fmt::Display for IRQPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl IPIKind {
+    /// All kinds of IPIs.
+    pub const ALL: [Self; 3] = [Self::Reschedule, Self::TLBShootdown, Self::CallFunction];
+
+    /// Return a unique index in the range `0..IPIKind::ALL.len()`.
+    ///
+    /// Used by IRQ managers to encode the kind in hardware, e.g. as SGI number.
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Return the kind with the given index, if any.
+    pub fn from_index(index: usize) -> Option<Self> {
+        Self::ALL.get(index).copied()
+    }
+}
+
+impl IRQStatisticsRecorder {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            fires: AtomicU64::new(0),
+            total_time_ns: AtomicU64::new(0),
+            max_time_ns: AtomicU64::new(0),
+            spurious: AtomicU64::new(0),
+        }
+    }
+
+    /// Execute the provided handler closure and record its execution time and outcome.
+    pub fn record(&self, f: impl FnOnce() -> IRQReturn) -> IRQReturn {
+        let start = time::time_manager().uptime();
+        let ret = f();
+        let elapsed = (time::time_manager().uptime() - start).as_nanos() as u64;
+
+        self.fires.fetch_add(1, Ordering::Relaxed);
+        NUM_IRQS.fetch_add(1, Ordering::Relaxed);
+        self.total_time_ns.fetch_add(elapsed, Ordering::Relaxed);
+        self.max_time_ns.fetch_max(elapsed, Ordering::Relaxed);
+
+        if ret == IRQReturn::NotHandled {
+            self.spurious.fetch_add(1, Ordering::Relaxed);
+        }
+
+        ret
+    }
+
+    /// Return a snapshot of the collected statistics.
+    pub fn get(&self) -> IRQStatistics {
+        IRQStatistics {
+            fires: self.fires.load(Ordering::Relaxed),
+            total_time: Duration::from_nanos(self.total_time_ns.load(Ordering::Relaxed)),
+            max_time: Duration::from_nanos(self.max_time_ns.load(Ordering::Relaxed)),
+            spurious: self.spurious.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for IRQStatisticsRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IRQStatistics {
+    /// Print the header of the statistics table.
+    pub fn print_header() {
+        info!(
+            "      {:>16} {:>10} {:>10} {:>14} {:>12}  Handler",
+            "IRQ", "Fires", "Spurious", "Total [us]", "Max [us]"
+        );
+    }
+
+    /// Print the statistics as a row of the statistics table.
+    pub fn print_row<T>(&self, irq_number: &dyn fmt::Display, chain: &[IRQHandlerDescriptor<T>])
+    where
+        T: Copy,
+    {
+        let names: Vec<&str> = chain.iter().map(|descriptor| descriptor.name()).collect();
+
+        info!(
+            "      {:>16} {:>10} {:>10} {:>14} {:>12}  {}",
+            format!("{}", irq_number),
+            self.fires,
+            self.spurious,
+            self.total_time.as_micros(),
+            self.max_time.as_micros(),
+            names.join(", ")
+        );
+    }
+
+    /// Print the row for the spurious IRQs without a number, see
+    /// [`num_spurious_irqs()`](interface::IRQManager::num_spurious_irqs).
+    pub fn print_spurious_row(num_spurious: u64) {
+        info!("      {:>16} {:>10}", "No IRQ pending", num_spurious);
+    }
+}
+
 impl<T> IRQHandlerDescriptor<T>
 where
     T: Copy,
@@ -120,11 +410,30 @@ where
     pub const fn new(
         number: T,
         name: &'static str,
+        priority: IRQPriority,
         handler: &'static (dyn interface::IRQHandler + Sync),
     ) -> Self {
         Self {
             number,
             name,
+            priority,
+            shared: false,
+            handler,
+        }
+    }
+
+    /// Create an instance for a handler that can share its IRQ line with other handlers.
+    pub const fn new_shared(
+        number: T,
+        name: &'static str,
+        priority: IRQPriority,
+        handler: &'static (dyn interface::IRQHandler + Sync),
+    ) -> Self {
+        Self {
+            number,
+            name,
+            priority,
+            shared: true,
             handler,
         }
     }
@@ -134,11 +443,37 @@ where
         self.number
     }
 
+    /// Return a copy of the descriptor that uses a different IRQ number type.
+    ///
+    /// Useful for IRQ managers that are composed of multiple controllers.
+    pub fn with_number<U>(&self, number: U) -> IRQHandlerDescriptor<U>
+    where
+        U: Copy,
+    {
+        IRQHandlerDescriptor {
+            number,
+            name: self.name,
+            priority: self.priority,
+            shared: self.shared,
+            handler: self.handler,
+        }
+    }
+
     /// Return the name.
     pub const fn name(&self) -> &'static str {
         self.name
     }
 
+    /// Return the priority.
+    pub const fn priority(&self) -> IRQPriority {
+        self.priority
+    }
+
+    /// Return whether the handler can share the IRQ line.
+    pub const fn is_shared(&self) -> bool {
+        self.shared
+    }
+
     /// Return the handler.
     pub const fn handler(&self) -> &'static (dyn interface::IRQHandler + Sync) {
         self.handler
@@ -175,11 +510,36 @@ pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
     ret
 }
 
+/// Executes the provided closure while IRQs are unmasked on the executing core.
+///
+/// Used by IRQ managers to allow nesting of interrupts of higher priority.
+///
+/// # Safety
+///
+/// - Must not be called while a lock that relies on masked IRQs is held, e.g. an
+///   [`IRQSafeNullLock`](synchronization::IRQSafeNullLock).
+/// - The interrupt controller must be configured to not signal the currently handled IRQ again,
+///   else the core ends up in an endless cycle of nested IRQ exceptions.
+#[inline(always)]
+pub unsafe fn exec_with_irq_unmasked<T>(f: impl FnOnce() -> T) -> T {
+    let saved = local_irq_unmask_save();
+    let ret = f();
+    local_irq_restore(saved);
+
+    ret
+}
+
 /// Register a new IRQ manager.
 pub fn register_irq_manager(
     new_manager: &'static (dyn interface::IRQManager<IRQNumberType = IRQNumber> + Sync),
 ) {
     CUR_IRQ_MANAGER.write(|manager| *manager = new_manager);
+    IRQ_MANAGER_REGISTERED.store(true, Ordering::Relaxed);
+}
+
+/// Return whether an IRQ manager was registered.
+pub fn is_irq_manager_registered() -> bool {
+    IRQ_MANAGER_REGISTERED.load(Ordering::Relaxed)
 }
 
 /// Return a reference to the currently registered IRQ manager.
@@ -188,3 +548,8 @@ pub fn register_irq_manager(
 pub fn irq_manager() -> &'static dyn interface::IRQManager<IRQNumberType = IRQNumber> {
     CUR_IRQ_MANAGER.read(|manager| *manager)
 }
+
+/// Return the number of IRQs that were dispatched to handlers since boot, over all IRQ numbers.
+pub fn num_irqs() -> u64 {
+    NUM_IRQS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Deferred interrupt work.
+//!
+//! IRQ handlers should do as little as possible, because they run with IRQs of the same or lower
+//! priority masked. Work that does not need to happen immediately can be scheduled from a handler
+//! and is then executed after all hardware IRQs have been handled, but with IRQs unmasked. This is
+//! what other kernels call softirqs, tasklets or bottom halves.
+//!
+//! Each core has its own queue. It is drained when the outermost IRQ exception of the core
+//! returns, so nested IRQs do not start draining on top of a running handler.
+
+use crate::{
+    bsp, cpu,
+    exception::asynchronous::{exec_with_irq_unmasked, IRQContext},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::mem;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct DeferredWorkQueue {
+    items: Vec<DeferredWork>,
+
+    /// Number of IRQ exceptions that are currently being handled on the core.
+    irq_nesting: usize,
+
+    /// True while the queue is drained on the core.
+    draining: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A work item that is executed in deferred IRQ context.
+pub type DeferredWork = Box<dyn FnOnce() + Send>;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[allow(clippy::declare_interior_mutable_const)]
+const QUEUE_INIT: IRQSafeNullLock<DeferredWorkQueue> =
+    IRQSafeNullLock::new(DeferredWorkQueue::new());
+
+static QUEUES: [IRQSafeNullLock<DeferredWorkQueue>; bsp::cpu::NUM_CORES] =
+    [QUEUE_INIT; bsp::cpu::NUM_CORES];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl DeferredWorkQueue {
+    const fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            irq_nesting: 0,
+            draining: false,
+        }
+    }
+}
+
+fn local_queue() -> &'static IRQSafeNullLock<DeferredWorkQueue> {
+    &QUEUES[cpu::smp::core_id::<usize>()]
+}
+
+/// Run all queued work items of the executing core.
+///
+/// Items that are scheduled while draining, e.g. from a preempting IRQ, are picked up as well.
+fn drain() {
+    loop {
+        let batch = local_queue().lock(|q| {
+            let batch = mem::take(&mut q.items);
+
+            // Clearing the flag in the same critical section as observing the empty queue ensures
+            // that no item can be scheduled in between and then be left behind.
+            if batch.is_empty() {
+                q.draining = false;
+            }
+
+            batch
+        });
+
+        if batch.is_empty() {
+            break;
+        }
+
+        for work in batch {
+            // Safety: No locks are held at this point, and the IRQ that scheduled the work has
+            // already been completed in the interrupt controller.
+            unsafe { exec_with_irq_unmasked(work) };
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Schedule work to be executed in deferred IRQ context on the executing core.
+///
+/// The work runs when the core returns from handling IRQs. If called outside of IRQ context, it
+/// runs on return from the next IRQ.
+pub fn schedule(work: DeferredWork) {
+    local_queue().lock(|q| q.items.push(work));
+}
+
+/// Return the number of work items that are waiting for execution on the executing core.
+pub fn num_pending() -> usize {
+    local_queue().lock(|q| q.items.len())
+}
+
+/// Bookkeeping on entry of an IRQ exception.
+///
+/// To be called by the arch code before pending IRQs are handled.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+pub fn irq_enter(_ic: &IRQContext) {
+    local_queue().lock(|q| q.irq_nesting += 1);
+}
+
+/// Bookkeeping on exit of an IRQ exception.
+///
+/// To be called by the arch code after pending IRQs are handled. If this is the outermost IRQ
+/// exception and the queue is not drained already, deferred work is executed now.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+pub fn irq_exit(_ic: &IRQContext) {
+    let start_draining = local_queue().lock(|q| {
+        q.irq_nesting -= 1;
+
+        if q.irq_nesting != 0 || q.draining || q.items.is_empty() {
+            return false;
+        }
+
+        q.draining = true;
+        true
+    });
+
+    if start_draining {
+        drain();
+    }
+}
@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ handler table.
+//!
+//! Used by IRQ managers to store the handlers of their IRQ numbers. Each IRQ number has a chain of
+//! handlers, which has more than one entry only if the line is shared by multiple devices.
+//!
+//! The table can be modified at any time, including from IRQ context. Chains are immutable once
+//! created and are replaced as a whole when a handler is added or removed. Handling an IRQ only
+//! takes a reference-counted snapshot of the chain under the lock. Handlers are therefore executed
+//! without holding the lock, which allows them to run with IRQs unmasked and to modify the table
+//! themselves.
+
+use super::{interface, IRQHandlerDescriptor, IRQPriority, IRQReturn};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+use alloc::{sync::Arc, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The handlers registered for a single IRQ number.
+pub type IRQHandlerChain<T> = Arc<[IRQHandlerDescriptor<T>]>;
+
+/// A table of IRQ handler chains, indexed by IRQ number.
+pub struct IRQHandlerTable<T>
+where
+    T: Copy,
+{
+    chains: IRQSafeNullLock<Vec<Option<IRQHandlerChain<T>>>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn is_same_handler(
+    a: &'static (dyn interface::IRQHandler + Sync),
+    b: &'static (dyn interface::IRQHandler + Sync),
+) -> bool {
+    // Only compare the data pointers. Vtable pointers of the same type are not guaranteed to be
+    // unique.
+    core::ptr::eq(a as *const _ as *const (), b as *const _ as *const ())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the priority of a chain, which is the highest priority of all its handlers.
+pub fn chain_priority<T>(chain: &[IRQHandlerDescriptor<T>]) -> IRQPriority
+where
+    T: Copy,
+{
+    chain
+        .iter()
+        .map(|descriptor| descriptor.priority())
+        .min()
+        .unwrap_or(IRQPriority::LOWEST)
+}
+
+/// Call all handlers of a chain.
+///
+/// Every handler is called, even if an earlier one already reported the IRQ as handled, because
+/// multiple devices on a shared line might have raised it at the same time. Panics if a handler
+/// fails.
+pub fn handle_chain<T>(chain: &[IRQHandlerDescriptor<T>]) -> IRQReturn
+where
+    T: Copy,
+{
+    chain
+        .iter()
+        .map(|descriptor| descriptor.handler().handle().expect("Error handling IRQ"))
+        .fold(IRQReturn::NotHandled, |acc, ret| {
+            if ret == IRQReturn::Handled {
+                ret
+            } else {
+                acc
+            }
+        })
+}
+
+impl<T> IRQHandlerTable<T>
+where
+    T: Copy,
+{
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            chains: IRQSafeNullLock::new(Vec::new()),
+        }
+    }
+
+    /// Allocate the table for `num_irqs` IRQ numbers.
+    pub fn init(&self, num_irqs: usize) {
+        self.chains.lock(|chains| chains.resize(num_irqs, None));
+    }
+
+    /// Add a handler to the chain of the IRQ with the given index.
+    ///
+    /// Adding to a non-empty chain requires that the new handler and all handlers that are already
+    /// registered agree to share the line.
+    pub fn register(
+        &self,
+        index: usize,
+        descriptor: IRQHandlerDescriptor<T>,
+    ) -> Result<(), &'static str> {
+        self.chains.lock(|chains| {
+            let new_chain: IRQHandlerChain<T> = match &chains[index] {
+                None => Arc::from(&[descriptor][..]),
+                Some(chain) => {
+                    if !descriptor.is_shared() || chain.iter().any(|d| !d.is_shared()) {
+                        return Err("IRQ handler already registered");
+                    }
+
+                    if chain
+                        .iter()
+                        .any(|d| is_same_handler(d.handler(), descriptor.handler()))
+                    {
+                        return Err("IRQ handler registered twice");
+                    }
+
+                    chain
+                        .iter()
+                        .copied()
+                        .chain(core::iter::once(descriptor))
+                        .collect()
+                }
+            };
+
+            chains[index] = Some(new_chain);
+
+            Ok(())
+        })
+    }
+
+    /// Remove a handler from the chain of the IRQ with the given index.
+    ///
+    /// Returns true if the chain is empty afterwards.
+    pub fn unregister(
+        &self,
+        index: usize,
+        handler: &'static (dyn interface::IRQHandler + Sync),
+    ) -> Result<bool, &'static str> {
+        self.chains.lock(|chains| {
+            let chain = chains[index].as_ref().ok_or("No IRQ handler registered")?;
+
+            if !chain.iter().any(|d| is_same_handler(d.handler(), handler)) {
+                return Err("IRQ handler not registered");
+            }
+
+            let new_chain: Vec<_> = chain
+                .iter()
+                .copied()
+                .filter(|d| !is_same_handler(d.handler(), handler))
+                .collect();
+
+            if new_chain.is_empty() {
+                chains[index] = None;
+                Ok(true)
+            } else {
+                chains[index] = Some(new_chain.into());
+                Ok(false)
+            }
+        })
+    }
+
+    /// Return a snapshot of the chain of the IRQ with the given index.
+    pub fn get(&self, index: usize) -> Option<IRQHandlerChain<T>> {
+        self.chains.lock(|chains| chains[index].clone())
+    }
+
+    /// Return the priority of the IRQ with the given index.
+    pub fn priority(&self, index: usize) -> Option<IRQPriority> {
+        self.chains
+            .lock(|chains| chains[index].as_deref().map(chain_priority))
+    }
+
+    /// Call `f` with the index and priority of each IRQ that has handlers registered.
+    ///
+    /// Executed while holding the lock, so `f` must not access the table.
+    pub fn for_each_priority(&self, mut f: impl FnMut(usize, IRQPriority)) {
+        self.chains.lock(|chains| {
+            for (i, chain) in chains.iter().enumerate() {
+                if let Some(chain) = chain {
+                    f(i, chain_priority(chain));
+                }
+            }
+        })
+    }
+
+    /// Return a snapshot of all chains, together with their IRQ index.
+    pub fn registered(&self) -> Vec<(usize, IRQHandlerChain<T>)> {
+        self.chains.lock(|chains| {
+            chains
+                .iter()
+                .enumerate()
+                .filter_map(|(i, chain)| chain.clone().map(|chain| (i, chain)))
+                .collect()
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    struct TestHandler(IRQReturn);
+
+    impl interface::IRQHandler for TestHandler {
+        fn handle(&self) -> Result<IRQReturn, &'static str> {
+            Ok(self.0)
+        }
+    }
+
+    static HANDLED: TestHandler = TestHandler(IRQReturn::Handled);
+    static NOT_HANDLED: TestHandler = TestHandler(IRQReturn::NotHandled);
+
+    /// Exclusive handlers must not be chained.
+    #[kernel_test]
+    fn exclusive_handler_is_not_chained() {
+        let table = IRQHandlerTable::new();
+        table.init(1);
+
+        let a = IRQHandlerDescriptor::new(0, "a", IRQPriority::DEFAULT, &HANDLED);
+        let b = IRQHandlerDescriptor::new_shared(0, "b", IRQPriority::DEFAULT, &NOT_HANDLED);
+
+        assert!(table.register(0, a).is_ok());
+        assert!(table.register(0, b).is_err());
+        assert_eq!(table.get(0).unwrap().len(), 1);
+    }
+
+    /// Shared handlers are chained, and the chain reports handled if any of them did.
+    #[kernel_test]
+    fn shared_handlers_are_chained() {
+        let table = IRQHandlerTable::new();
+        table.init(1);
+
+        let a = IRQHandlerDescriptor::new_shared(0, "a", IRQPriority::LOWEST, &NOT_HANDLED);
+        let b = IRQHandlerDescriptor::new_shared(0, "b", IRQPriority::HIGHEST, &HANDLED);
+
+        assert!(table.register(0, a).is_ok());
+        assert_eq!(handle_chain(&table.get(0).unwrap()), IRQReturn::NotHandled);
+
+        assert!(table.register(0, b).is_ok());
+        assert!(table.register(0, b).is_err());
+        assert_eq!(handle_chain(&table.get(0).unwrap()), IRQReturn::Handled);
+        assert_eq!(table.priority(0), Some(IRQPriority::HIGHEST));
+    }
+
+    /// Unregistering removes only the given handler and reports when the chain is empty.
+    #[kernel_test]
+    fn unregister_works() {
+        let table = IRQHandlerTable::new();
+        table.init(1);
+
+        let a = IRQHandlerDescriptor::new_shared(0, "a", IRQPriority::DEFAULT, &NOT_HANDLED);
+        let b = IRQHandlerDescriptor::new_shared(0, "b", IRQPriority::DEFAULT, &HANDLED);
+
+        table.register(0, a).unwrap();
+        table.register(0, b).unwrap();
+
+        assert_eq!(table.unregister(0, &HANDLED), Ok(false));
+        assert!(table.unregister(0, &HANDLED).is_err());
+        assert_eq!(table.get(0).unwrap()[0].name(), "a");
+
+        assert_eq!(table.unregister(0, &NOT_HANDLED), Ok(true));
+        assert!(table.get(0).is_none());
+    }
+}
@@ -4,7 +4,7 @@
 
 //! Null IRQ Manager.
 
-use super::{interface, IRQContext, IRQHandlerDescriptor};
+use super::{interface, IPIKind, IRQContext, IRQHandlerDescriptor, IRQStatistics};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -32,11 +32,47 @@ impl interface::IRQManager for NullIRQManager {
         panic!("No IRQ Manager registered yet");
     }
 
+    fn unregister_handler(
+        &self,
+        _irq_number: &Self::IRQNumberType,
+        _handler: &'static (dyn interface::IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        panic!("No IRQ Manager registered yet");
+    }
+
     fn enable(&self, _irq_number: &Self::IRQNumberType) {
         panic!("No IRQ Manager registered yet");
     }
 
+    fn disable(&self, _irq_number: &Self::IRQNumberType) {
+        panic!("No IRQ Manager registered yet");
+    }
+
     fn handle_pending_irqs<'irq_context>(&'irq_context self, _ic: &IRQContext<'irq_context>) {
         panic!("No IRQ Manager registered yet");
     }
+
+    fn send_ipi(&self, _target_core: usize, _kind: IPIKind) -> Result<(), &'static str> {
+        panic!("No IRQ Manager registered yet");
+    }
+
+    fn set_fiq(
+        &self,
+        _irq_number: &Self::IRQNumberType,
+        _handler: &'static (dyn interface::IRQHandler + Sync),
+    ) -> Result<(), &'static str> {
+        panic!("No IRQ Manager registered yet");
+    }
+
+    fn handle_pending_fiq<'irq_context>(&'irq_context self, _ic: &IRQContext<'irq_context>) {
+        panic!("No IRQ Manager registered yet");
+    }
+
+    fn irq_statistics(&self, _irq_number: &Self::IRQNumberType) -> IRQStatistics {
+        panic!("No IRQ Manager registered yet");
+    }
+
+    fn num_spurious_irqs(&self) -> u64 {
+        panic!("No IRQ Manager registered yet");
+    }
 }
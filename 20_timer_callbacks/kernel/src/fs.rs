@@ -0,0 +1,578 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! File systems.
+//!
+//! The virtual file system (VFS) provides a single file API on top of all mounted file systems.
+//! File systems implement [`interface::FileSystem`] and are mounted at an absolute path with
+//! [`mount()`]. A path is resolved to the file system with the longest matching mount point.
+//!
+//! Files are opened with [`open()`], which returns a [`FileDescriptor`] for [`read()`],
+//! [`write()`], [`seek()`] and [`close()`].
+
+pub mod devfs;
+pub mod fat32;
+pub mod initramfs;
+pub mod ramfs;
+
+use crate::{
+    info,
+    synchronization::{
+        interface::{Mutex, ReadWriteEx},
+        IRQSafeNullLock, InitStateLock,
+    },
+};
+use alloc::{string::String, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct MountDescriptor {
+    path: String,
+    fs: &'static (dyn interface::FileSystem + Sync),
+}
+
+struct OpenFile {
+    fs: &'static (dyn interface::FileSystem + Sync),
+
+    /// The path within the file system.
+    path: String,
+
+    flags: OpenFlags,
+    offset: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// File system interfaces.
+pub mod interface {
+    use super::{DirEntry, FileType, Metadata};
+    use alloc::vec::Vec;
+
+    /// File system functions.
+    ///
+    /// Paths are absolute and relative to the mount point, e.g. `/uart0` for `/dev/uart0`.
+    pub trait FileSystem {
+        /// Return the name of the file system type, e.g. `ramfs`.
+        fn name(&self) -> &'static str;
+
+        /// Return the metadata of the file or directory at `path`.
+        fn stat(&self, path: &str) -> Result<Metadata, &'static str>;
+
+        /// Return the entries of the directory at `path`.
+        fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, &'static str>;
+
+        /// Read from the file at `path`, starting at `offset`.
+        ///
+        /// Returns the number of bytes read, which is zero at the end of the file.
+        fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str>;
+
+        /// Write to the file at `path`, starting at `offset`.
+        ///
+        /// Returns the number of bytes written.
+        fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, &'static str>;
+
+        /// Create an empty file or directory at `path`. The parent directory must exist.
+        fn create(&self, path: &str, file_type: FileType) -> Result<(), &'static str>;
+
+        /// Set the size of the file at `path` to `len` bytes.
+        fn truncate(&self, path: &str, len: u64) -> Result<(), &'static str>;
+    }
+
+    /// Functions of devices that are accessible as files in [`devfs`](super::devfs).
+    pub trait DeviceFile {
+        /// Return the name of the file, e.g. `uart0`.
+        fn file_name(&self) -> &'static str;
+
+        /// Read without blocking. Stream devices ignore `offset`.
+        ///
+        /// Returns the number of bytes read, which is zero if no data is available.
+        fn read(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize, &'static str> {
+            Err("Device is not readable")
+        }
+
+        /// Write `data`. Stream devices ignore `offset`.
+        ///
+        /// Returns the number of bytes written.
+        fn write(&self, _offset: u64, _data: &[u8]) -> Result<usize, &'static str> {
+            Err("Device is not writable")
+        }
+    }
+}
+
+/// The type of a file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileType {
+    /// A regular file.
+    File,
+
+    /// A directory.
+    Directory,
+
+    /// A device in `devfs`.
+    Device,
+}
+
+/// Metadata of a file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// The type of the file.
+    pub file_type: FileType,
+
+    /// Size in bytes. Zero for directories and devices.
+    pub size: u64,
+}
+
+/// An entry of a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The file name, without the path of the directory.
+    pub name: String,
+
+    /// The metadata of the file.
+    pub metadata: Metadata,
+}
+
+/// Flags for opening files.
+#[derive(Copy, Clone)]
+pub struct OpenFlags {
+    /// Allow reading.
+    pub read: bool,
+
+    /// Allow writing.
+    pub write: bool,
+
+    /// Create the file if it does not exist.
+    pub create: bool,
+
+    /// Discard the contents of the file when opening it.
+    pub truncate: bool,
+
+    /// Write at the end of the file, regardless of the offset.
+    pub append: bool,
+}
+
+/// An offset for [`seek()`].
+#[derive(Copy, Clone, Debug)]
+pub enum SeekFrom {
+    /// Relative to the start of the file.
+    Start(u64),
+
+    /// Relative to the current offset.
+    Current(i64),
+
+    /// Relative to the end of the file.
+    End(i64),
+}
+
+/// Refers to an open file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FileDescriptor(usize);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static MOUNTS: InitStateLock<Vec<MountDescriptor>> = InitStateLock::new(Vec::new());
+
+static OPEN_FILES: IRQSafeNullLock<Vec<Option<OpenFile>>> = IRQSafeNullLock::new(Vec::new());
+
+static ROOT_FS: ramfs::RamFs = ramfs::RamFs::new();
+
+static DEV_FS: devfs::DevFs = devfs::DevFs::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Resolve `.` and `..`, and remove duplicate and trailing slashes.
+fn normalize(path: &str) -> Result<String, &'static str> {
+    if !path.starts_with('/') {
+        return Err("Path is not absolute");
+    }
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+
+    let mut normalized = String::new();
+    for component in components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
+}
+
+/// Return whether the normalized `path` is `mount_point` or below it.
+fn is_below(path: &str, mount_point: &str) -> bool {
+    mount_point == "/"
+        || path
+            .strip_prefix(mount_point)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Return the file system that contains the normalized `path`, and the path within it.
+fn resolve(
+    path: &str,
+) -> Result<(&'static (dyn interface::FileSystem + Sync), String), &'static str> {
+    MOUNTS.read(|mounts| {
+        let mount = mounts
+            .iter()
+            .filter(|m| is_below(path, &m.path))
+            .max_by_key(|m| m.path.len())
+            .ok_or("No file system mounted")?;
+
+        let relative = match mount.path.as_str() {
+            "/" => path,
+            mount_point => &path[mount_point.len()..],
+        };
+
+        Ok((
+            mount.fs,
+            String::from(if relative.is_empty() { "/" } else { relative }),
+        ))
+    })
+}
+
+fn with_open_file<R>(
+    fd: FileDescriptor,
+    f: impl FnOnce(&mut OpenFile) -> Result<R, &'static str>,
+) -> Result<R, &'static str> {
+    OPEN_FILES.lock(|files| {
+        files
+            .get_mut(fd.0)
+            .and_then(Option::as_mut)
+            .ok_or("Bad file descriptor")
+            .and_then(f)
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl OpenFlags {
+    /// Read only.
+    pub const READ: Self = Self {
+        read: true,
+        write: false,
+        create: false,
+        truncate: false,
+        append: false,
+    };
+
+    /// Write only, to an existing file.
+    pub const WRITE: Self = Self {
+        read: false,
+        write: true,
+        ..Self::READ
+    };
+
+    /// Like [`Self::WRITE`], but create the file or discard its contents.
+    pub const CREATE: Self = Self {
+        create: true,
+        truncate: true,
+        ..Self::WRITE
+    };
+
+    /// Like [`Self::WRITE`], but create the file and write at its end.
+    pub const APPEND: Self = Self {
+        create: true,
+        append: true,
+        ..Self::WRITE
+    };
+}
+
+impl core::fmt::Display for FileType {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let name = match self {
+            Self::File => "file",
+            Self::Directory => "dir",
+            Self::Device => "dev",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Mount the root `ramfs`, `devfs` at `/dev` and the `initramfs` at `/initramfs`.
+pub fn init() -> Result<(), &'static str> {
+    mount("/", &ROOT_FS)?;
+    mount("/dev", &DEV_FS)?;
+    mount("/initramfs", initramfs::Initramfs::embedded()?)
+}
+
+/// Mount `fs` at `path`.
+pub fn mount(
+    path: &str,
+    fs: &'static (dyn interface::FileSystem + Sync),
+) -> Result<(), &'static str> {
+    let path = normalize(path)?;
+
+    MOUNTS.write(|mounts| {
+        if mounts.iter().any(|m| m.path == path) {
+            return Err("A file system is mounted there already");
+        }
+
+        mounts.push(MountDescriptor { path, fs });
+
+        Ok(())
+    })
+}
+
+/// Open the file at `path`.
+pub fn open(path: &str, flags: OpenFlags) -> Result<FileDescriptor, &'static str> {
+    let (fs, path) = resolve(&normalize(path)?)?;
+
+    let metadata = match fs.stat(&path) {
+        Ok(metadata) => metadata,
+        Err(_) if flags.create => {
+            fs.create(&path, FileType::File)?;
+            fs.stat(&path)?
+        }
+        Err(x) => return Err(x),
+    };
+
+    match metadata.file_type {
+        FileType::Directory if flags.write => return Err("Is a directory"),
+        FileType::File if flags.truncate => fs.truncate(&path, 0)?,
+        _ => (),
+    }
+
+    let file = OpenFile {
+        fs,
+        path,
+        flags,
+        offset: 0,
+    };
+
+    // Reuse the lowest free descriptor.
+    let fd = OPEN_FILES.lock(|files| match files.iter().position(Option::is_none) {
+        Some(i) => {
+            files[i] = Some(file);
+            i
+        }
+        None => {
+            files.push(Some(file));
+            files.len() - 1
+        }
+    });
+
+    Ok(FileDescriptor(fd))
+}
+
+/// Read from the current offset of `fd`, and advance it.
+///
+/// Returns the number of bytes read, which is zero at the end of the file.
+pub fn read(fd: FileDescriptor, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let (fs, path, offset) = with_open_file(fd, |file| match file.flags.read {
+        true => Ok((file.fs, file.path.clone(), file.offset)),
+        false => Err("File is not open for reading"),
+    })?;
+
+    let n = fs.read(&path, offset, buffer)?;
+    with_open_file(fd, |file| {
+        file.offset = offset + n as u64;
+        Ok(n)
+    })
+}
+
+/// Write at the current offset of `fd`, or at the end of the file if it was opened for appending,
+/// and advance the offset.
+///
+/// Returns the number of bytes written.
+pub fn write(fd: FileDescriptor, data: &[u8]) -> Result<usize, &'static str> {
+    let (fs, path, flags, offset) = with_open_file(fd, |file| match file.flags.write {
+        true => Ok((file.fs, file.path.clone(), file.flags, file.offset)),
+        false => Err("File is not open for writing"),
+    })?;
+
+    let offset = match flags.append {
+        true => fs.stat(&path)?.size,
+        false => offset,
+    };
+
+    let n = fs.write(&path, offset, data)?;
+    with_open_file(fd, |file| {
+        file.offset = offset + n as u64;
+        Ok(n)
+    })
+}
+
+/// Set the offset of `fd`. Returns the new offset.
+pub fn seek(fd: FileDescriptor, position: SeekFrom) -> Result<u64, &'static str> {
+    let (fs, path, offset) =
+        with_open_file(fd, |file| Ok((file.fs, file.path.clone(), file.offset)))?;
+
+    let (base, delta) = match position {
+        SeekFrom::Start(new_offset) => (new_offset, 0),
+        SeekFrom::Current(delta) => (offset, delta),
+        SeekFrom::End(delta) => (fs.stat(&path)?.size, delta),
+    };
+
+    let new_offset = match delta >= 0 {
+        true => base.checked_add(delta as u64),
+        false => base.checked_sub(delta.unsigned_abs()),
+    }
+    .ok_or("Invalid offset")?;
+
+    with_open_file(fd, |file| {
+        file.offset = new_offset;
+        Ok(new_offset)
+    })
+}
+
+/// Close `fd`.
+pub fn close(fd: FileDescriptor) -> Result<(), &'static str> {
+    OPEN_FILES.lock(|files| {
+        files
+            .get_mut(fd.0)
+            .and_then(Option::take)
+            .map(|_| ())
+            .ok_or("Bad file descriptor")
+    })
+}
+
+/// Return the metadata of the file or directory at `path`.
+pub fn stat(path: &str) -> Result<Metadata, &'static str> {
+    let (fs, path) = resolve(&normalize(path)?)?;
+
+    fs.stat(&path)
+}
+
+/// Return the entries of the directory at `path`.
+///
+/// Mount points in the directory are listed as directories.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, &'static str> {
+    let path = normalize(path)?;
+    let (fs, fs_path) = resolve(&path)?;
+    let mut entries = fs.read_dir(&fs_path)?;
+
+    MOUNTS.read(|mounts| {
+        for mount in mounts {
+            let (parent, name) = match mount.path.rsplit_once('/') {
+                Some((parent, name)) if !name.is_empty() => (parent, name),
+                _ => continue,
+            };
+
+            let parent = if parent.is_empty() { "/" } else { parent };
+            if parent == path && !entries.iter().any(|e| e.name == name) {
+                entries.push(DirEntry {
+                    name: String::from(name),
+                    metadata: Metadata {
+                        file_type: FileType::Directory,
+                        size: 0,
+                    },
+                });
+            }
+        }
+    });
+
+    Ok(entries)
+}
+
+/// Create an empty directory at `path`. The parent directory must exist.
+pub fn create_dir(path: &str) -> Result<(), &'static str> {
+    let (fs, path) = resolve(&normalize(path)?)?;
+
+    fs.create(&path, FileType::Directory)
+}
+
+/// Print all mounted file systems.
+pub fn print_mounts() {
+    MOUNTS.read(|mounts| {
+        for mount in mounts {
+            info!("      {:<12} {}", mount.path, mount.fs.name());
+        }
+    });
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Paths are normalized before they are resolved.
+    #[kernel_test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("/").unwrap(), "/");
+        assert_eq!(
+            normalize("//boot/./config.txt/").unwrap(),
+            "/boot/config.txt"
+        );
+        assert_eq!(normalize("/dev/../boot/..").unwrap(), "/");
+        assert!(normalize("boot").is_err());
+
+        assert!(is_below("/dev/uart0", "/dev"));
+        assert!(is_below("/dev", "/dev"));
+        assert!(!is_below("/devices", "/dev"));
+    }
+
+    /// Files can be created, written, read and sought through the VFS.
+    #[kernel_test]
+    fn files_are_accessed_through_mounts() {
+        static TEST_FS: ramfs::RamFs = ramfs::RamFs::new();
+        mount("/vfs_test", &TEST_FS).unwrap();
+        assert!(mount("/vfs_test/", &TEST_FS).is_err());
+
+        create_dir("/vfs_test/logs").unwrap();
+        let fd = open("/vfs_test/logs/log.txt", OpenFlags::CREATE).unwrap();
+        assert_eq!(write(fd, b"Hello, ").unwrap(), 7);
+        assert_eq!(write(fd, b"world").unwrap(), 5);
+        assert!(read(fd, &mut [0; 4]).is_err());
+        close(fd).unwrap();
+        assert!(close(fd).is_err());
+
+        let fd = open("/vfs_test/logs/log.txt", OpenFlags::APPEND).unwrap();
+        write(fd, b"!").unwrap();
+        close(fd).unwrap();
+
+        let fd = open("/vfs_test/./logs/../logs/log.txt", OpenFlags::READ).unwrap();
+        let mut buffer = [0; 32];
+        assert_eq!(read(fd, &mut buffer).unwrap(), 13);
+        assert_eq!(&buffer[..13], b"Hello, world!");
+        assert_eq!(read(fd, &mut buffer).unwrap(), 0);
+
+        assert_eq!(seek(fd, SeekFrom::End(-6)).unwrap(), 7);
+        assert_eq!(read(fd, &mut buffer[..5]).unwrap(), 5);
+        assert_eq!(&buffer[..5], b"world");
+        assert!(seek(fd, SeekFrom::Current(-20)).is_err());
+        close(fd).unwrap();
+
+        assert_eq!(
+            stat("/vfs_test/logs/log.txt").unwrap(),
+            Metadata {
+                file_type: FileType::File,
+                size: 13
+            }
+        );
+        assert!(open("/vfs_test/missing.txt", OpenFlags::READ).is_err());
+        assert!(open("/vfs_test/logs", OpenFlags::WRITE).is_err());
+
+        let names: Vec<_> = read_dir("/vfs_test")
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["logs"]);
+    }
+}
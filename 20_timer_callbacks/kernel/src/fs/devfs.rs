@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Device file system.
+//!
+//! Lists the registered drivers that provide a [`DeviceFile`](interface::DeviceFile), e.g.
+//! `/dev/uart0`. The directory is flat, and devices cannot be created or truncated.
+
+use super::{interface, DirEntry, FileType, Metadata};
+use crate::{console, driver};
+use alloc::{string::String, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The device file system.
+pub struct DevFs;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+const DEVICE_METADATA: Metadata = Metadata {
+    file_type: FileType::Device,
+    size: 0,
+};
+
+fn device_file(path: &str) -> Result<&'static (dyn interface::DeviceFile + Sync), &'static str> {
+    let name = path.strip_prefix('/').ok_or("Path is not absolute")?;
+
+    driver::driver_manager()
+        .device_files()
+        .into_iter()
+        .find(|file| file.file_name() == name)
+        .ok_or("No such device")
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DevFs {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+/// Read the characters that a console has buffered, without blocking.
+///
+/// Helper for [`DeviceFile`](interface::DeviceFile) implementations of console drivers. Characters
+/// are truncated to bytes.
+pub fn read_console(
+    console: &(impl console::interface::Read + ?Sized),
+    buffer: &mut [u8],
+) -> Result<usize, &'static str> {
+    let mut n = 0;
+
+    while n < buffer.len() {
+        match console.try_read_char() {
+            Some(c) => buffer[n] = c as u8,
+            None => break,
+        }
+        n += 1;
+    }
+
+    Ok(n)
+}
+
+/// Write bytes to a console, one character per byte.
+///
+/// Helper for [`DeviceFile`](interface::DeviceFile) implementations of console drivers.
+pub fn write_console(
+    console: &(impl console::interface::Write + ?Sized),
+    data: &[u8],
+) -> Result<usize, &'static str> {
+    for &byte in data {
+        console.write_char(byte as char);
+    }
+
+    Ok(data.len())
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl interface::FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, &'static str> {
+        match path {
+            "/" => Ok(Metadata {
+                file_type: FileType::Directory,
+                size: 0,
+            }),
+            _ => device_file(path).map(|_| DEVICE_METADATA),
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, &'static str> {
+        if path != "/" {
+            return Err("Not a directory");
+        }
+
+        Ok(driver::driver_manager()
+            .device_files()
+            .into_iter()
+            .map(|file| DirEntry {
+                name: String::from(file.file_name()),
+                metadata: DEVICE_METADATA,
+            })
+            .collect())
+    }
+
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        device_file(path)?.read(offset, buffer)
+    }
+
+    fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        device_file(path)?.write(offset, data)
+    }
+
+    fn create(&self, _path: &str, _file_type: FileType) -> Result<(), &'static str> {
+        Err("Cannot create files in devfs")
+    }
+
+    fn truncate(&self, _path: &str, _len: u64) -> Result<(), &'static str> {
+        Err("Cannot truncate devices")
+    }
+}
@@ -0,0 +1,1406 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! FAT32 file system.
+//!
+//! Files and directories are addressed by absolute paths like `/logs/crash.txt`. Names are matched
+//! case-insensitively, and long file names are supported for both reading and creating entries.
+//!
+//! The cluster chains of recently used files are cached. Every modifying operation writes the
+//! changed FAT sectors to all copies of the FAT before it returns.
+//!
+//! IRQs stay unmasked during file system operations, which span many blocks. The file system must
+//! therefore not be used from IRQ context.
+
+use crate::{
+    block::interface::BlockDevice,
+    fs,
+    synchronization::{interface::Mutex, NullLock},
+};
+use alloc::{format, string::String, vec, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Minimum number of clusters of a FAT32 file system. Anything smaller is FAT12 or FAT16.
+const MIN_CLUSTERS: u32 = 65525;
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT_OFFSET: usize = 488;
+const FSINFO_NEXT_FREE_OFFSET: usize = 492;
+const FSINFO_UNKNOWN: u32 = u32::MAX;
+
+/// The first data cluster. Clusters 0 and 1 are reserved.
+const FIRST_CLUSTER: u32 = 2;
+
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_FREE: u32 = 0;
+const FAT_END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+const FAT_END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+const DIR_ENTRY_SIZE: usize = 32;
+const DIR_ENTRY_END: u8 = 0x00;
+const DIR_ENTRY_DELETED: u8 = 0xE5;
+
+/// Stored instead of 0xE5 as the first byte of short names that start with 0xE5.
+const DIR_ENTRY_KANJI_E5: u8 = 0x05;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ATTR_LONG_NAME_MASK: u8 = 0x3F;
+
+/// Flags in the reserved byte of short entries, which mark lowercase base names and extensions.
+const NAME_LOWERCASE_BASE: u8 = 0x08;
+const NAME_LOWERCASE_EXT: u8 = 0x10;
+
+/// Marks the last long name entry, which is stored first.
+const LFN_LAST: u8 = 0x40;
+const LFN_SEQUENCE_MASK: u8 = 0x1F;
+
+/// Byte offsets of the UTF-16 characters in a long name entry.
+const LFN_CHAR_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+const MAX_NAME_LEN: usize = 255;
+
+/// Characters that are valid in short names, besides uppercase letters and digits.
+const SHORT_NAME_SPECIAL_CHARS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Characters that are invalid in long names, besides control characters.
+const LONG_NAME_INVALID_CHARS: &str = "\"*/:<>?\\|";
+
+/// Date of new entries, 1980-01-01. There is no real-time clock to provide the current one.
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+
+/// Number of cluster chains that are cached.
+const CHAIN_CACHE_ENTRIES: usize = 16;
+
+/// Layout of the file system, from the boot sector.
+struct Geometry {
+    bytes_per_sector: usize,
+    sectors_per_cluster: usize,
+    fat_start: u64,
+    fat_size: u64,
+    num_fats: u64,
+
+    /// The only FAT that is used, if mirroring is disabled.
+    active_fat: Option<u64>,
+
+    data_start: u64,
+    num_clusters: u32,
+    root_cluster: u32,
+    fsinfo_sector: Option<u64>,
+}
+
+/// A cached sector of the FAT, relative to the start of the FAT.
+struct FatSector {
+    sector: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+/// Location of a short directory entry on the device.
+#[derive(Copy, Clone)]
+struct EntryLocation {
+    sector: u64,
+    offset: usize,
+}
+
+/// Assembles a long name from its entries, which are stored in reverse order.
+#[derive(Default)]
+struct LongNameBuilder {
+    units: Vec<u16>,
+    checksum: u8,
+    next_sequence: u8,
+}
+
+struct Fat32Inner {
+    device: &'static (dyn BlockDevice + Sync),
+    geometry: Geometry,
+    fat_cache: Option<FatSector>,
+
+    /// Most recently used chains last, keyed by their first cluster.
+    chain_cache: Vec<(u32, Vec<u32>)>,
+
+    free_count: u32,
+    next_free: u32,
+    fsinfo_dirty: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A mounted FAT32 file system.
+pub struct Fat32 {
+    label: String,
+    inner: NullLock<Fat32Inner>,
+}
+
+/// A file or directory.
+#[derive(Clone)]
+pub struct DirEntry {
+    name: String,
+    short_name: [u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+    size: u32,
+
+    /// `None` for the root directory, which has no entry.
+    location: Option<EntryLocation>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn le_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn set_le_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn set_le_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Checksum of a short name, which is stored in the long name entries that belong to it.
+fn short_name_checksum(short_name: &[u8]) -> u8 {
+    short_name
+        .iter()
+        .fold(0_u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// Convert a short name to its readable form, e.g. `README  TXT` to `README.TXT`.
+fn readable_short_name(short_name: &[u8], flags: u8) -> String {
+    let part = |bytes: &[u8], lowercase: bool| -> String {
+        let s: String = bytes
+            .iter()
+            .map(|&c| match lowercase {
+                true => c.to_ascii_lowercase() as char,
+                false => c as char,
+            })
+            .collect();
+
+        String::from(s.trim_end())
+    };
+
+    let mut base = [0; 8];
+    base.copy_from_slice(&short_name[0..8]);
+    if base[0] == DIR_ENTRY_KANJI_E5 {
+        base[0] = DIR_ENTRY_DELETED;
+    }
+
+    let mut name = part(&base, flags & NAME_LOWERCASE_BASE != 0);
+    let ext = part(&short_name[8..11], flags & NAME_LOWERCASE_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+
+    name
+}
+
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || SHORT_NAME_SPECIAL_CHARS.contains(&c)
+}
+
+/// Return the short name of `name` if it is a valid short name already, e.g. `README.TXT`.
+fn exact_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+
+    if base.is_empty()
+        || base.len() > 8
+        || ext.len() > 3
+        || !base.bytes().chain(ext.bytes()).all(is_short_name_char)
+    {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+
+    Some(short_name)
+}
+
+/// Generate a short name like `LONGNA~1.TXT` for a long name, which is unique among `existing`.
+fn generated_short_name(name: &str, existing: &[DirEntry]) -> Result<[u8; 11], &'static str> {
+    let convert = |s: &str, max_len: usize| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let c = if c.is_ascii() { c as u8 } else { b'_' };
+                if is_short_name_char(c.to_ascii_uppercase()) {
+                    c.to_ascii_uppercase()
+                } else {
+                    b'_'
+                }
+            })
+            .take(max_len)
+            .collect()
+    };
+
+    let (base, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+    let base = convert(base, 8);
+    let ext = convert(ext, 3);
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{}", n);
+        let base_len = base.len().min(8 - tail.len());
+
+        let mut short_name = [b' '; 11];
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+
+        if !existing.iter().any(|e| e.short_name == short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err("No unique short name available")
+}
+
+/// Return the long name entries for `name`, in the order in which they are stored.
+fn long_name_entries(name: &str, checksum: u8) -> Vec<[u8; DIR_ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let num_entries = units.len().div_ceil(LFN_CHAR_OFFSETS.len());
+
+    (1..=num_entries)
+        .rev()
+        .map(|sequence| {
+            let mut raw = [0; DIR_ENTRY_SIZE];
+            raw[0] = sequence as u8;
+            if sequence == num_entries {
+                raw[0] |= LFN_LAST;
+            }
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = checksum;
+
+            // The name is terminated by a zero, unless it fills the last entry, and padded with
+            // 0xFFFF.
+            for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+                let index = (sequence - 1) * LFN_CHAR_OFFSETS.len() + i;
+                let unit = match index.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[index],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                set_le_u16(&mut raw, offset, unit);
+            }
+
+            raw
+        })
+        .collect()
+}
+
+fn check_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name == "." || name == ".." {
+        return Err("Invalid file name");
+    }
+
+    if name.encode_utf16().count() > MAX_NAME_LEN {
+        return Err("File name too long");
+    }
+
+    if name
+        .chars()
+        .any(|c| c.is_control() || LONG_NAME_INVALID_CHARS.contains(c))
+    {
+        return Err("Invalid character in file name");
+    }
+
+    Ok(())
+}
+
+/// Return the components of an absolute path.
+fn components(path: &str) -> Result<impl Iterator<Item = &str>, &'static str> {
+    if !path.starts_with('/') {
+        return Err("Path is not absolute");
+    }
+
+    Ok(path.split('/').filter(|c| !c.is_empty()))
+}
+
+/// Split an absolute path into the path of the parent directory and the name.
+fn split_parent(path: &str) -> Result<(&str, &str), &'static str> {
+    let path = path.trim_end_matches('/');
+
+    match path.rsplit_once('/') {
+        Some((parent, name)) if !name.is_empty() => Ok((parent, name)),
+        _ => Err("Path has no parent directory"),
+    }
+}
+
+impl LongNameBuilder {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn add(&mut self, raw: &[u8]) {
+        let sequence = raw[0] & LFN_SEQUENCE_MASK;
+
+        if raw[0] & LFN_LAST != 0 {
+            self.units = vec![0xFFFF; sequence as usize * LFN_CHAR_OFFSETS.len()];
+            self.checksum = raw[13];
+            self.next_sequence = sequence;
+        }
+
+        // Orphaned or out-of-order entries invalidate the name.
+        if sequence == 0 || sequence != self.next_sequence || raw[13] != self.checksum {
+            self.reset();
+            return;
+        }
+
+        let start = (sequence as usize - 1) * LFN_CHAR_OFFSETS.len();
+        for (i, &offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            self.units[start + i] = le_u16(raw, offset);
+        }
+        self.next_sequence -= 1;
+    }
+
+    /// Return the long name if it is complete and belongs to the short entry with `checksum`.
+    fn take(&mut self, checksum: u8) -> Option<String> {
+        let complete = !self.units.is_empty() && self.next_sequence == 0;
+        let matches = self.checksum == checksum;
+
+        let units = core::mem::take(&mut self.units);
+        self.reset();
+        if !complete || !matches {
+            return None;
+        }
+
+        let units = units.into_iter().take_while(|&c| c != 0);
+        Some(
+            char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        )
+    }
+}
+
+impl DirEntry {
+    fn root(root_cluster: u32) -> Self {
+        Self {
+            name: String::from("/"),
+            short_name: [b' '; 11],
+            attributes: ATTR_DIRECTORY,
+            first_cluster: root_cluster,
+            size: 0,
+            location: None,
+        }
+    }
+
+    fn from_raw(raw: &[u8], name: Option<String>, location: EntryLocation) -> Self {
+        let mut short_name = [0; 11];
+        short_name.copy_from_slice(&raw[0..11]);
+
+        Self {
+            name: name.unwrap_or_else(|| readable_short_name(&short_name, raw[12])),
+            short_name,
+            attributes: raw[11],
+            first_cluster: ((le_u16(raw, 20) as u32) << 16) | le_u16(raw, 26) as u32,
+            size: le_u32(raw, 28),
+            location: Some(location),
+        }
+    }
+
+    /// Return the raw short entry.
+    fn to_raw(&self) -> [u8; DIR_ENTRY_SIZE] {
+        let mut raw = [0; DIR_ENTRY_SIZE];
+        raw[0..11].copy_from_slice(&self.short_name);
+        raw[11] = self.attributes;
+
+        // Creation, access and modification date.
+        set_le_u16(&mut raw, 16, DEFAULT_DATE);
+        set_le_u16(&mut raw, 18, DEFAULT_DATE);
+        set_le_u16(&mut raw, 24, DEFAULT_DATE);
+
+        set_le_u16(&mut raw, 20, (self.first_cluster >> 16) as u16);
+        set_le_u16(&mut raw, 26, self.first_cluster as u16);
+        set_le_u32(&mut raw, 28, self.size);
+
+        raw
+    }
+}
+
+impl Geometry {
+    fn cluster_size(&self) -> usize {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster as u64
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..FIRST_CLUSTER + self.num_clusters).contains(&cluster)
+    }
+
+    /// The FATs that are written on updates.
+    fn fats(&self) -> core::ops::Range<u64> {
+        match self.active_fat {
+            Some(fat) => fat..fat + 1,
+            None => 0..self.num_fats,
+        }
+    }
+}
+
+impl Fat32Inner {
+    /// Return the device sectors covering `len` bytes at `offset` within `cluster`.
+    fn sector_span(&self, cluster: u32, offset: usize, len: usize) -> (u64, usize) {
+        let bps = self.geometry.bytes_per_sector;
+        let first = offset / bps;
+        let last = (offset + len).div_ceil(bps);
+
+        (
+            self.geometry.cluster_sector(cluster) + first as u64,
+            (last - first) * bps,
+        )
+    }
+
+    /// Read `buffer.len()` bytes at `offset` within `cluster`.
+    fn read_in_cluster(
+        &self,
+        cluster: u32,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Result<(), &'static str> {
+        let (sector, span_len) = self.sector_span(cluster, offset, buffer.len());
+        let skip = offset % self.geometry.bytes_per_sector;
+
+        if skip == 0 && span_len == buffer.len() {
+            return self.device.read_blocks(sector, buffer);
+        }
+
+        let mut span = vec![0; span_len];
+        self.device.read_blocks(sector, &mut span)?;
+        buffer.copy_from_slice(&span[skip..skip + buffer.len()]);
+
+        Ok(())
+    }
+
+    /// Write `data` at `offset` within `cluster`.
+    fn write_in_cluster(
+        &self,
+        cluster: u32,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        let (sector, span_len) = self.sector_span(cluster, offset, data.len());
+        let skip = offset % self.geometry.bytes_per_sector;
+
+        if skip == 0 && span_len == data.len() {
+            return self.device.write_blocks(sector, data);
+        }
+
+        let mut span = vec![0; span_len];
+        self.device.read_blocks(sector, &mut span)?;
+        span[skip..skip + data.len()].copy_from_slice(data);
+
+        self.device.write_blocks(sector, &span)
+    }
+
+    fn flush_fat_cache(&mut self) -> Result<(), &'static str> {
+        let cache = match &mut self.fat_cache {
+            Some(cache) if cache.dirty => cache,
+            _ => return Ok(()),
+        };
+
+        for fat in self.geometry.fats() {
+            let sector = self.geometry.fat_start + fat * self.geometry.fat_size + cache.sector;
+            self.device.write_blocks(sector, &cache.data)?;
+        }
+        cache.dirty = false;
+
+        Ok(())
+    }
+
+    /// Return the cached FAT sector that contains the entry of `cluster`, and the entry's offset.
+    fn fat_sector(&mut self, cluster: u32) -> Result<(&mut FatSector, usize), &'static str> {
+        let bps = self.geometry.bytes_per_sector;
+        let sector = (cluster as usize * 4 / bps) as u64;
+        let offset = cluster as usize * 4 % bps;
+
+        if self.fat_cache.as_ref().map(|c| c.sector) != Some(sector) {
+            self.flush_fat_cache()?;
+
+            let fat = self.geometry.fats().start;
+            let mut data = vec![0; bps];
+            self.device.read_blocks(
+                self.geometry.fat_start + fat * self.geometry.fat_size + sector,
+                &mut data,
+            )?;
+
+            self.fat_cache = Some(FatSector {
+                sector,
+                data,
+                dirty: false,
+            });
+        }
+
+        Ok((self.fat_cache.as_mut().unwrap(), offset))
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, &'static str> {
+        let (cache, offset) = self.fat_sector(cluster)?;
+
+        Ok(le_u32(&cache.data, offset) & FAT_ENTRY_MASK)
+    }
+
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), &'static str> {
+        let (cache, offset) = self.fat_sector(cluster)?;
+
+        // The upper four bits are reserved and must be preserved.
+        let old = le_u32(&cache.data, offset);
+        set_le_u32(
+            &mut cache.data,
+            offset,
+            (old & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK),
+        );
+        cache.dirty = true;
+
+        Ok(())
+    }
+
+    /// Write all cached metadata to the device.
+    fn sync(&mut self) -> Result<(), &'static str> {
+        self.flush_fat_cache()?;
+
+        if let (true, Some(sector)) = (self.fsinfo_dirty, self.geometry.fsinfo_sector) {
+            let mut data = vec![0; self.geometry.bytes_per_sector];
+            self.device.read_blocks(sector, &mut data)?;
+            set_le_u32(&mut data, FSINFO_FREE_COUNT_OFFSET, self.free_count);
+            set_le_u32(&mut data, FSINFO_NEXT_FREE_OFFSET, self.next_free);
+            self.device.write_blocks(sector, &data)?;
+        }
+        self.fsinfo_dirty = false;
+
+        self.device.flush()
+    }
+
+    /// Return the chain of clusters starting at `first_cluster`.
+    fn chain(&mut self, first_cluster: u32) -> Result<Vec<u32>, &'static str> {
+        if first_cluster == 0 {
+            return Ok(Vec::new());
+        }
+
+        if let Some(i) = self.chain_cache.iter().position(|e| e.0 == first_cluster) {
+            let entry = self.chain_cache.remove(i);
+            let chain = entry.1.clone();
+            self.chain_cache.push(entry);
+
+            return Ok(chain);
+        }
+
+        let mut chain = Vec::new();
+        let mut cluster = first_cluster;
+        loop {
+            if !self.geometry.is_valid_cluster(cluster) {
+                return Err("Invalid cluster in chain");
+            }
+            if chain.len() >= self.geometry.num_clusters as usize {
+                return Err("Loop in cluster chain");
+            }
+
+            chain.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+            if cluster >= FAT_END_OF_CHAIN_MIN {
+                break;
+            }
+        }
+
+        if self.chain_cache.len() == CHAIN_CACHE_ENTRIES {
+            self.chain_cache.remove(0);
+        }
+        self.chain_cache.push((first_cluster, chain.clone()));
+
+        Ok(chain)
+    }
+
+    fn invalidate_chain(&mut self, first_cluster: u32) {
+        self.chain_cache.retain(|e| e.0 != first_cluster);
+    }
+
+    /// Allocate a free cluster and append it to the chain ending at `previous`.
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32, &'static str> {
+        let num_clusters = self.geometry.num_clusters;
+        let start = if self.geometry.is_valid_cluster(self.next_free) {
+            self.next_free - FIRST_CLUSTER
+        } else {
+            0
+        };
+
+        for i in 0..num_clusters {
+            let cluster = FIRST_CLUSTER + (start + i) % num_clusters;
+            if self.fat_entry(cluster)? != FAT_FREE {
+                continue;
+            }
+
+            self.set_fat_entry(cluster, FAT_END_OF_CHAIN)?;
+            if let Some(previous) = previous {
+                self.set_fat_entry(previous, cluster)?;
+            }
+
+            self.next_free = cluster + 1;
+            if self.free_count != FSINFO_UNKNOWN {
+                self.free_count = self.free_count.saturating_sub(1);
+            }
+            self.fsinfo_dirty = true;
+
+            return Ok(cluster);
+        }
+
+        Err("File system is full")
+    }
+
+    /// Grow `chain` to `num_clusters` clusters. The chain must not be empty.
+    fn extend_chain(
+        &mut self,
+        chain: &mut Vec<u32>,
+        num_clusters: usize,
+    ) -> Result<(), &'static str> {
+        self.invalidate_chain(chain[0]);
+
+        while chain.len() < num_clusters {
+            let cluster = self.allocate_cluster(chain.last().copied())?;
+            chain.push(cluster);
+        }
+
+        Ok(())
+    }
+
+    /// Free the clusters of the chain starting at `cluster`.
+    fn free_chain(&mut self, mut cluster: u32) -> Result<(), &'static str> {
+        self.invalidate_chain(cluster);
+
+        for _ in 0..self.geometry.num_clusters {
+            if !self.geometry.is_valid_cluster(cluster) {
+                return Err("Invalid cluster in chain");
+            }
+
+            let next = self.fat_entry(cluster)?;
+            self.set_fat_entry(cluster, FAT_FREE)?;
+            // An unknown count stays unknown, since it is the maximum value.
+            self.free_count = self.free_count.saturating_add(1);
+            self.fsinfo_dirty = true;
+
+            if next >= FAT_END_OF_CHAIN_MIN {
+                return Ok(());
+            }
+            cluster = next;
+        }
+
+        Err("Loop in cluster chain")
+    }
+
+    /// Read the raw contents of the directory starting at `first_cluster`.
+    fn read_dir_raw(&mut self, first_cluster: u32) -> Result<(Vec<u8>, Vec<u32>), &'static str> {
+        let chain = self.chain(first_cluster)?;
+        let cluster_size = self.geometry.cluster_size();
+
+        let mut data = vec![0; chain.len() * cluster_size];
+        for (cluster, buffer) in chain.iter().zip(data.chunks_exact_mut(cluster_size)) {
+            self.read_in_cluster(*cluster, 0, buffer)?;
+        }
+
+        Ok((data, chain))
+    }
+
+    fn location(&self, chain: &[u32], offset: usize) -> EntryLocation {
+        let cluster_size = self.geometry.cluster_size();
+        let bps = self.geometry.bytes_per_sector;
+        let within = offset % cluster_size;
+
+        EntryLocation {
+            sector: self.geometry.cluster_sector(chain[offset / cluster_size])
+                + (within / bps) as u64,
+            offset: within % bps,
+        }
+    }
+
+    /// Return the entries of the directory starting at `first_cluster`, without `.` and `..`.
+    fn read_dir(&mut self, first_cluster: u32) -> Result<Vec<DirEntry>, &'static str> {
+        let (data, chain) = self.read_dir_raw(first_cluster)?;
+
+        let mut entries = Vec::new();
+        let mut long_name = LongNameBuilder::default();
+        for (i, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            match raw[0] {
+                DIR_ENTRY_END => break,
+                DIR_ENTRY_DELETED => {
+                    long_name.reset();
+                    continue;
+                }
+                _ => (),
+            }
+
+            let attributes = raw[11];
+            if attributes & ATTR_LONG_NAME_MASK == ATTR_LONG_NAME {
+                long_name.add(raw);
+                continue;
+            }
+
+            let name = long_name.take(short_name_checksum(&raw[0..11]));
+            if attributes & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+                continue;
+            }
+
+            let location = self.location(&chain, i * DIR_ENTRY_SIZE);
+            entries.push(DirEntry::from_raw(raw, name, location));
+        }
+
+        Ok(entries)
+    }
+
+    /// Find the entry at `path`.
+    fn lookup(&mut self, path: &str) -> Result<DirEntry, &'static str> {
+        let mut entry = DirEntry::root(self.geometry.root_cluster);
+
+        for name in components(path)? {
+            if entry.attributes & ATTR_DIRECTORY == 0 {
+                return Err("Not a directory");
+            }
+
+            entry = self
+                .read_dir(entry.first_cluster)?
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(name))
+                .ok_or("No such file or directory")?;
+        }
+
+        Ok(entry)
+    }
+
+    fn lookup_file(&mut self, path: &str) -> Result<DirEntry, &'static str> {
+        let entry = self.lookup(path)?;
+        if entry.attributes & ATTR_DIRECTORY != 0 {
+            return Err("Is a directory");
+        }
+
+        Ok(entry)
+    }
+
+    /// Write `entries` to consecutive free slots of the directory starting at `first_cluster`,
+    /// growing it if needed. Returns the location of the last entry.
+    fn insert_dir_entries(
+        &mut self,
+        first_cluster: u32,
+        entries: &[[u8; DIR_ENTRY_SIZE]],
+    ) -> Result<EntryLocation, &'static str> {
+        let (mut data, mut chain) = self.read_dir_raw(first_cluster)?;
+        let num_slots = data.len() / DIR_ENTRY_SIZE;
+
+        // All slots after the end marker are free.
+        let mut run = 0;
+        let mut end_seen = false;
+        let mut start = None;
+        for i in 0..num_slots {
+            let first_byte = data[i * DIR_ENTRY_SIZE];
+            end_seen |= first_byte == DIR_ENTRY_END;
+
+            if end_seen || first_byte == DIR_ENTRY_DELETED {
+                run += 1;
+                if run == entries.len() {
+                    start = Some(i + 1 - run);
+                    break;
+                }
+            } else {
+                run = 0;
+            }
+        }
+
+        // Otherwise, continue the trailing free slots into new, zeroed clusters.
+        let start = match start {
+            Some(start) => start,
+            None => {
+                let cluster_size = self.geometry.cluster_size();
+                let zeros = vec![0; cluster_size];
+
+                self.invalidate_chain(first_cluster);
+                while data.len() < (num_slots - run + entries.len()) * DIR_ENTRY_SIZE {
+                    let cluster = self.allocate_cluster(chain.last().copied())?;
+                    self.write_in_cluster(cluster, 0, &zeros)?;
+                    chain.push(cluster);
+                    data.extend_from_slice(&zeros);
+                }
+
+                num_slots - run
+            }
+        };
+
+        for (i, entry) in entries.iter().enumerate() {
+            let offset = (start + i) * DIR_ENTRY_SIZE;
+            let location = self.location(&chain, offset);
+
+            let mut sector = vec![0; self.geometry.bytes_per_sector];
+            self.device.read_blocks(location.sector, &mut sector)?;
+            sector[location.offset..location.offset + DIR_ENTRY_SIZE].copy_from_slice(entry);
+            self.device.write_blocks(location.sector, &sector)?;
+        }
+
+        Ok(self.location(&chain, (start + entries.len() - 1) * DIR_ENTRY_SIZE))
+    }
+
+    /// Write the first cluster and size of `entry` back to its directory.
+    fn update_dir_entry(&mut self, entry: &DirEntry) -> Result<(), &'static str> {
+        let location = entry.location.ok_or("The root directory has no entry")?;
+
+        let mut sector = vec![0; self.geometry.bytes_per_sector];
+        self.device.read_blocks(location.sector, &mut sector)?;
+
+        let raw = &mut sector[location.offset..location.offset + DIR_ENTRY_SIZE];
+        set_le_u16(raw, 20, (entry.first_cluster >> 16) as u16);
+        set_le_u16(raw, 26, entry.first_cluster as u16);
+        set_le_u32(raw, 28, entry.size);
+
+        self.device.write_blocks(location.sector, &sector)
+    }
+
+    /// Create an empty file or directory at `path`.
+    fn create(&mut self, path: &str, attributes: u8) -> Result<DirEntry, &'static str> {
+        let (parent_path, name) = split_parent(path)?;
+        check_name(name)?;
+
+        let parent = self.lookup(if parent_path.is_empty() {
+            "/"
+        } else {
+            parent_path
+        })?;
+        if parent.attributes & ATTR_DIRECTORY == 0 {
+            return Err("Not a directory");
+        }
+
+        let existing = self.read_dir(parent.first_cluster)?;
+        if existing.iter().any(|e| e.name.eq_ignore_ascii_case(name)) {
+            return Err("File exists");
+        }
+
+        let (short_name, mut raw_entries) = match exact_short_name(name) {
+            Some(short_name) => (short_name, Vec::new()),
+            None => {
+                let short_name = generated_short_name(name, &existing)?;
+                let checksum = short_name_checksum(&short_name);
+
+                (short_name, long_name_entries(name, checksum))
+            }
+        };
+
+        let mut entry = DirEntry {
+            name: String::from(name),
+            short_name,
+            attributes,
+            first_cluster: 0,
+            size: 0,
+            location: None,
+        };
+
+        if attributes & ATTR_DIRECTORY != 0 {
+            entry.first_cluster = self.allocate_cluster(None)?;
+        }
+        raw_entries.push(entry.to_raw());
+
+        let result = match entry.first_cluster {
+            0 => Ok(()),
+            cluster => self.init_dir_cluster(cluster, &parent),
+        }
+        .and_then(|_| self.insert_dir_entries(parent.first_cluster, &raw_entries));
+
+        match result {
+            Ok(location) => entry.location = Some(location),
+            Err(x) => {
+                // Do not leak the cluster of a new directory. The original error is reported even
+                // if freeing fails, too.
+                if entry.first_cluster != 0 {
+                    let _ = self.free_chain(entry.first_cluster);
+                }
+
+                return Err(x);
+            }
+        }
+
+        Ok(entry)
+    }
+
+    /// Write the contents of a new, empty directory to `cluster`.
+    ///
+    /// Directories contain `.` and `..` from the start. `..` refers to the root as cluster 0.
+    fn init_dir_cluster(&mut self, cluster: u32, parent: &DirEntry) -> Result<(), &'static str> {
+        let mut data = vec![0; self.geometry.cluster_size()];
+
+        let parent_cluster = match parent.location {
+            None => 0,
+            Some(_) => parent.first_cluster,
+        };
+        for (i, (dots, cluster)) in [(".", cluster), ("..", parent_cluster)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut dot_entry = DirEntry::root(cluster);
+            dot_entry.short_name[..dots.len()].copy_from_slice(dots.as_bytes());
+            data[i * DIR_ENTRY_SIZE..(i + 1) * DIR_ENTRY_SIZE].copy_from_slice(&dot_entry.to_raw());
+        }
+
+        self.write_in_cluster(cluster, 0, &data)
+    }
+
+    fn read_file(
+        &mut self,
+        entry: &DirEntry,
+        offset: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        let size = entry.size as u64;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = (size - offset).min(buffer.len() as u64) as usize;
+        let chain = self.chain(entry.first_cluster)?;
+        let cluster_size = self.geometry.cluster_size();
+        if (chain.len() as u64) * (cluster_size as u64) < size {
+            return Err("Cluster chain shorter than file");
+        }
+
+        let mut done = 0;
+        while done < len {
+            let position = offset as usize + done;
+            let within = position % cluster_size;
+            let n = (cluster_size - within).min(len - done);
+
+            self.read_in_cluster(
+                chain[position / cluster_size],
+                within,
+                &mut buffer[done..done + n],
+            )?;
+            done += n;
+        }
+
+        Ok(len)
+    }
+
+    fn write_file(
+        &mut self,
+        entry: &mut DirEntry,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        let end = offset + data.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err("File too large");
+        }
+
+        // Writing beyond the end leaves a gap, which is filled with zeros.
+        if offset > entry.size as u64 {
+            self.resize_file(entry, offset)?;
+        }
+
+        let cluster_size = self.geometry.cluster_size();
+        let mut chain = self.chain(entry.first_cluster)?;
+        let num_clusters = (end as usize).div_ceil(cluster_size);
+
+        if chain.len() < num_clusters {
+            if chain.is_empty() {
+                entry.first_cluster = self.allocate_cluster(None)?;
+                chain.push(entry.first_cluster);
+            }
+            self.extend_chain(&mut chain, num_clusters)?;
+        }
+
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset as usize + done;
+            let within = position % cluster_size;
+            let n = (cluster_size - within).min(data.len() - done);
+
+            self.write_in_cluster(
+                chain[position / cluster_size],
+                within,
+                &data[done..done + n],
+            )?;
+            done += n;
+        }
+
+        entry.size = entry.size.max(end as u32);
+        self.update_dir_entry(entry)
+    }
+
+    /// Shrink the file, freeing clusters, or grow it with zeros.
+    fn resize_file(&mut self, entry: &mut DirEntry, len: u64) -> Result<(), &'static str> {
+        let size = entry.size as u64;
+
+        if len > size {
+            let zeros = vec![0; self.geometry.cluster_size()];
+            let mut position = size;
+            while position < len {
+                let n = (len - position).min(zeros.len() as u64) as usize;
+                self.write_file(entry, position, &zeros[..n])?;
+                position += n as u64;
+            }
+
+            return Ok(());
+        }
+
+        let num_clusters = (len as usize).div_ceil(self.geometry.cluster_size());
+        let chain = self.chain(entry.first_cluster)?;
+        if num_clusters == 0 {
+            if entry.first_cluster != 0 {
+                self.free_chain(entry.first_cluster)?;
+            }
+            entry.first_cluster = 0;
+        } else if num_clusters < chain.len() {
+            self.invalidate_chain(entry.first_cluster);
+            self.set_fat_entry(chain[num_clusters - 1], FAT_END_OF_CHAIN)?;
+            self.free_chain(chain[num_clusters])?;
+        }
+
+        entry.size = len as u32;
+        self.update_dir_entry(entry)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DirEntry {
+    /// Return the name. This is the long name if there is one.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Return whether the entry is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    /// Return the size of the file in bytes. Zero for directories.
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+}
+
+impl Fat32 {
+    /// Mount the FAT32 file system on `device`.
+    pub fn mount(device: &'static (dyn BlockDevice + Sync)) -> Result<Self, &'static str> {
+        let bps = device.block_size();
+        if bps < 512 {
+            return Err("Block size too small");
+        }
+
+        let mut boot = vec![0; bps];
+        device.read_blocks(0, &mut boot)?;
+
+        if boot[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2] != BOOT_SIGNATURE {
+            return Err("No boot sector signature");
+        }
+
+        if le_u16(&boot, 11) as usize != bps {
+            return Err("Sector size differs from the block size");
+        }
+
+        let sectors_per_cluster = boot[13] as usize;
+        if !sectors_per_cluster.is_power_of_two() {
+            return Err("Invalid cluster size");
+        }
+
+        // FAT12 and FAT16 have a fixed root directory and a 16 bit FAT size.
+        let fat_size = le_u32(&boot, 36) as u64;
+        if le_u16(&boot, 17) != 0 || le_u16(&boot, 22) != 0 || fat_size == 0 {
+            return Err("Not a FAT32 file system");
+        }
+
+        let num_fats = boot[16] as u64;
+        let ext_flags = le_u16(&boot, 40);
+        let active_fat = (ext_flags & 0x80 != 0).then_some((ext_flags & 0xF) as u64);
+        if num_fats == 0 || matches!(active_fat, Some(fat) if fat >= num_fats) {
+            return Err("Invalid number of FATs");
+        }
+
+        let total_sectors = match le_u16(&boot, 19) {
+            0 => le_u32(&boot, 32) as u64,
+            n => n as u64,
+        }
+        .min(device.num_blocks());
+        let fat_start = le_u16(&boot, 14) as u64;
+        let data_start = fat_start + num_fats * fat_size;
+        if total_sectors <= data_start {
+            return Err("Invalid file system size");
+        }
+
+        // The FAT might not cover all clusters of the data region.
+        let num_clusters = ((total_sectors - data_start) / sectors_per_cluster as u64)
+            .min(fat_size * bps as u64 / 4 - FIRST_CLUSTER as u64)
+            as u32;
+        if num_clusters < MIN_CLUSTERS {
+            return Err("Not a FAT32 file system");
+        }
+
+        let fsinfo_sector = match le_u16(&boot, 48) {
+            0 | 0xFFFF => None,
+            n => Some(n as u64),
+        };
+
+        let geometry = Geometry {
+            bytes_per_sector: bps,
+            sectors_per_cluster,
+            fat_start,
+            fat_size,
+            num_fats,
+            active_fat,
+            data_start,
+            num_clusters,
+            root_cluster: le_u32(&boot, 44),
+            fsinfo_sector,
+        };
+        if !geometry.is_valid_cluster(geometry.root_cluster) {
+            return Err("Invalid root directory cluster");
+        }
+
+        // The free cluster hints are optional.
+        let (mut free_count, mut next_free) = (FSINFO_UNKNOWN, FSINFO_UNKNOWN);
+        if let Some(sector) = geometry.fsinfo_sector {
+            let mut fsinfo = vec![0; bps];
+            device.read_blocks(sector, &mut fsinfo)?;
+
+            if le_u32(&fsinfo, 0) == FSINFO_LEAD_SIGNATURE
+                && le_u32(&fsinfo, 484) == FSINFO_STRUCT_SIGNATURE
+            {
+                free_count = le_u32(&fsinfo, FSINFO_FREE_COUNT_OFFSET);
+                next_free = le_u32(&fsinfo, FSINFO_NEXT_FREE_OFFSET);
+            }
+        }
+
+        let label: String = boot[71..82].iter().map(|&c| c as char).collect();
+        let label = String::from(label.trim_end());
+
+        Ok(Self {
+            label,
+            inner: NullLock::new(Fat32Inner {
+                device,
+                geometry,
+                fat_cache: None,
+                chain_cache: Vec::new(),
+                free_count,
+                next_free,
+                fsinfo_dirty: false,
+            }),
+        })
+    }
+
+    /// Return the volume label from the boot sector.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Return the entry at `path`.
+    pub fn stat(&self, path: &str) -> Result<DirEntry, &'static str> {
+        self.inner.lock(|inner| inner.lookup(path))
+    }
+
+    /// Return the entries of the directory at `path`.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, &'static str> {
+        self.inner.lock(|inner| {
+            let entry = inner.lookup(path)?;
+            if !entry.is_dir() {
+                return Err("Not a directory");
+            }
+
+            inner.read_dir(entry.first_cluster)
+        })
+    }
+
+    /// Read from the file at `path`, starting at `offset`.
+    ///
+    /// Returns the number of bytes read, which is zero at the end of the file.
+    pub fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.inner.lock(|inner| {
+            let entry = inner.lookup_file(path)?;
+
+            inner.read_file(&entry, offset, buffer)
+        })
+    }
+
+    /// Create an empty file at `path`. The parent directory must exist.
+    pub fn create_file(&self, path: &str) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            inner.create(path, ATTR_ARCHIVE)?;
+            inner.sync()
+        })
+    }
+
+    /// Create an empty directory at `path`. The parent directory must exist.
+    pub fn create_dir(&self, path: &str) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            inner.create(path, ATTR_DIRECTORY)?;
+            inner.sync()
+        })
+    }
+
+    /// Write `data` to the file at `path`, starting at `offset`.
+    ///
+    /// The file grows as needed. A gap between its end and `offset` is filled with zeros.
+    pub fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            let mut entry = inner.lookup_file(path)?;
+            inner.write_file(&mut entry, offset, data)?;
+            inner.sync()
+        })
+    }
+
+    /// Append `data` to the file at `path`.
+    pub fn append(&self, path: &str, data: &[u8]) -> Result<(), &'static str> {
+        self.inner.lock(|inner| {
+            let mut entry = inner.lookup_file(path)?;
+            let size = entry.size as u64;
+            inner.write_file(&mut entry, size, data)?;
+            inner.sync()
+        })
+    }
+
+    /// Set the size of the file at `path` to `len` bytes, freeing clusters or filling with zeros.
+    pub fn truncate(&self, path: &str, len: u64) -> Result<(), &'static str> {
+        if len > u32::MAX as u64 {
+            return Err("File too large");
+        }
+
+        self.inner.lock(|inner| {
+            let mut entry = inner.lookup_file(path)?;
+            inner.resize_file(&mut entry, len)?;
+            inner.sync()
+        })
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl From<&DirEntry> for fs::Metadata {
+    fn from(entry: &DirEntry) -> Self {
+        match entry.is_dir() {
+            true => Self {
+                file_type: fs::FileType::Directory,
+                size: 0,
+            },
+            false => Self {
+                file_type: fs::FileType::File,
+                size: entry.size(),
+            },
+        }
+    }
+}
+
+impl fs::interface::FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn stat(&self, path: &str) -> Result<fs::Metadata, &'static str> {
+        Fat32::stat(self, path).map(|entry| fs::Metadata::from(&entry))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<fs::DirEntry>, &'static str> {
+        let entries = Fat32::read_dir(self, path)?;
+
+        Ok(entries
+            .iter()
+            .map(|entry| fs::DirEntry {
+                name: String::from(entry.name()),
+                metadata: entry.into(),
+            })
+            .collect())
+    }
+
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        Fat32::read(self, path, offset, buffer)
+    }
+
+    fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        Fat32::write(self, path, offset, data).map(|_| data.len())
+    }
+
+    fn create(&self, path: &str, file_type: fs::FileType) -> Result<(), &'static str> {
+        match file_type {
+            fs::FileType::File => self.create_file(path),
+            fs::FileType::Directory => self.create_dir(path),
+            fs::FileType::Device => Err("Device files are not supported"),
+        }
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), &'static str> {
+        Fat32::truncate(self, path, len)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Short names are used as is if possible, and generated uniquely otherwise.
+    #[kernel_test]
+    fn short_names_are_generated() {
+        assert_eq!(exact_short_name("README.TXT"), Some(*b"README  TXT"));
+        assert_eq!(exact_short_name("KERNEL8"), Some(*b"KERNEL8    "));
+        assert!(exact_short_name("config.txt").is_none());
+        assert!(exact_short_name("TOOLONGNAME.TXT").is_none());
+        assert!(exact_short_name("A.B.C").is_none());
+
+        let mut existing = Vec::new();
+        let short_name = generated_short_name("A long file name.text", &existing).unwrap();
+        assert_eq!(&short_name, b"ALONGF~1TEX");
+
+        existing.push(DirEntry {
+            short_name,
+            ..DirEntry::root(FIRST_CLUSTER)
+        });
+        let short_name = generated_short_name("A long file name.text", &existing).unwrap();
+        assert_eq!(&short_name, b"ALONGF~2TEX");
+        assert_eq!(readable_short_name(&short_name, 0), "ALONGF~2.TEX");
+    }
+
+    /// Long names survive a round trip through their directory entries.
+    #[kernel_test]
+    fn long_names_round_trip() {
+        let short_name = *b"ALONGF~1TEX";
+        let checksum = short_name_checksum(&short_name);
+
+        for name in ["A long file name.text", "exactly 13 ch", "Grüße.txt"] {
+            let mut builder = LongNameBuilder::default();
+            for raw in long_name_entries(name, checksum) {
+                builder.add(&raw);
+            }
+
+            assert_eq!(builder.take(checksum).as_deref(), Some(name));
+        }
+
+        // A long name that belongs to another short entry is ignored.
+        let mut builder = LongNameBuilder::default();
+        for raw in long_name_entries("config.txt", checksum) {
+            builder.add(&raw);
+        }
+        assert!(builder.take(checksum.wrapping_add(1)).is_none());
+    }
+}
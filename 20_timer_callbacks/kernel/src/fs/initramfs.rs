@@ -0,0 +1,405 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Initial RAM file system.
+//!
+//! The Makefile packs the `initramfs` directory into a cpio archive in the "newc" format, which is
+//! embedded into the kernel image. The files are read-only and are served directly from the
+//! archive, without copying.
+
+use super::{interface, DirEntry, FileType, Metadata};
+use alloc::{string::String, vec::Vec};
+use core::str;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Magic numbers of archives without and with checksums.
+const MAGIC: [&[u8]; 2] = [b"070701", b"070702"];
+
+const HEADER_SIZE: usize = 110;
+const HEADER_FIELD_SIZE: usize = 8;
+const HEADER_FIELD_MODE: usize = 1;
+const HEADER_FIELD_FILE_SIZE: usize = 6;
+const HEADER_FIELD_NAME_SIZE: usize = 11;
+
+const TRAILER_NAME: &str = "TRAILER!!!";
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_FILE: u32 = 0o100000;
+
+const ARCHIVE_SIZE: usize = include_bytes!(env!("INITRAMFS_PATH")).len();
+
+/// A file or directory of the archive.
+#[derive(Copy, Clone)]
+struct Entry {
+    /// The path without a leading slash, e.g. `etc/motd`.
+    name: &'static str,
+
+    file_type: FileType,
+    data: &'static [u8],
+}
+
+/// Iterates over the entries of an archive.
+struct Entries {
+    rest: &'static [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A read-only file system on a cpio archive.
+pub struct Initramfs {
+    archive: &'static [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The archive, placed by the linker script.
+#[link_section = ".initramfs"]
+static ARCHIVE: [u8; ARCHIVE_SIZE] = *include_bytes!(env!("INITRAMFS_PATH"));
+
+static EMBEDDED: Initramfs = Initramfs { archive: &ARCHIVE };
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn header_field(header: &[u8], index: usize) -> Result<usize, &'static str> {
+    let start = MAGIC[0].len() + index * HEADER_FIELD_SIZE;
+    let field = str::from_utf8(&header[start..start + HEADER_FIELD_SIZE])
+        .map_err(|_| "Invalid cpio header")?;
+
+    usize::from_str_radix(field, 16).map_err(|_| "Invalid cpio header")
+}
+
+fn parse_entry(
+    data: &'static [u8],
+) -> Result<(&'static str, u32, &'static [u8], usize), &'static str> {
+    let header = data.get(..HEADER_SIZE).ok_or("Truncated cpio header")?;
+    if !MAGIC.contains(&&header[..MAGIC[0].len()]) {
+        return Err("Invalid cpio magic");
+    }
+
+    let mode = header_field(header, HEADER_FIELD_MODE)? as u32;
+    let file_size = header_field(header, HEADER_FIELD_FILE_SIZE)?;
+    let name_size = header_field(header, HEADER_FIELD_NAME_SIZE)?;
+
+    // The name includes a terminating NUL. Name and data are padded to four bytes.
+    let name_end = HEADER_SIZE + name_size;
+    let name = match data.get(HEADER_SIZE..name_end) {
+        Some([name @ .., 0]) => str::from_utf8(name).map_err(|_| "Invalid cpio file name")?,
+        _ => return Err("Truncated cpio file name"),
+    };
+
+    let data_start = name_end.next_multiple_of(4);
+    let data_end = data_start + file_size;
+    let file_data = data
+        .get(data_start..data_end)
+        .ok_or("Truncated cpio file")?;
+
+    Ok((name, mode, file_data, data_end.next_multiple_of(4)))
+}
+
+impl Iterator for Entries {
+    type Item = Result<Entry, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // An empty archive is accepted, for builds without one.
+            if self.rest.is_empty() {
+                return None;
+            }
+
+            let (name, mode, data, size) = match parse_entry(self.rest) {
+                Ok(x) => x,
+                Err(x) => {
+                    self.rest = &[];
+                    return Some(Err(x));
+                }
+            };
+            self.rest = self.rest.get(size..).unwrap_or(&[]);
+
+            if name == TRAILER_NAME {
+                self.rest = &[];
+                return None;
+            }
+
+            // Archives created with `find . | cpio` contain `.` and names starting with `./`.
+            let name = name.trim_start_matches("./").trim_start_matches('/');
+            if name.is_empty() || name == "." {
+                continue;
+            }
+
+            let file_type = match mode & MODE_TYPE_MASK {
+                MODE_DIRECTORY => FileType::Directory,
+                MODE_FILE => FileType::File,
+                // Links and device nodes are not supported.
+                _ => continue,
+            };
+
+            return Some(Ok(Entry {
+                name: name.trim_end_matches('/'),
+                file_type,
+                data,
+            }));
+        }
+    }
+}
+
+impl Entry {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            file_type: self.file_type,
+            size: match self.file_type {
+                FileType::File => self.data.len() as u64,
+                _ => 0,
+            },
+        }
+    }
+}
+
+impl Initramfs {
+    fn entries(&self) -> Entries {
+        Entries { rest: self.archive }
+    }
+
+    /// Return the entry at the absolute `path`. Directories without an entry of their own, but with
+    /// entries below them, are reported as directories.
+    fn lookup(&self, path: &str) -> Result<Entry, &'static str> {
+        let name = path.trim_matches('/');
+        let directory = Entry {
+            name: "",
+            file_type: FileType::Directory,
+            data: &[],
+        };
+
+        if name.is_empty() {
+            return Ok(directory);
+        }
+
+        for entry in self.entries() {
+            let entry = entry?;
+            if entry.name == name {
+                return Ok(entry);
+            }
+
+            if let Some(rest) = entry.name.strip_prefix(name) {
+                if rest.starts_with('/') {
+                    return Ok(directory);
+                }
+            }
+        }
+
+        Err("No such file or directory")
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Initramfs {
+    /// Create an instance on `archive`, checking that all entries can be parsed.
+    pub fn new(archive: &'static [u8]) -> Result<Self, &'static str> {
+        let initramfs = Self { archive };
+        for entry in initramfs.entries() {
+            entry?;
+        }
+
+        Ok(initramfs)
+    }
+
+    /// Return the file system of the archive embedded into the kernel image.
+    pub fn embedded() -> Result<&'static Self, &'static str> {
+        for entry in EMBEDDED.entries() {
+            entry?;
+        }
+
+        Ok(&EMBEDDED)
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl interface::FileSystem for Initramfs {
+    fn name(&self) -> &'static str {
+        "initramfs"
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, &'static str> {
+        self.lookup(path).map(|entry| entry.metadata())
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, &'static str> {
+        let directory = self.lookup(path)?;
+        if directory.file_type != FileType::Directory {
+            return Err("Not a directory");
+        }
+
+        let prefix = path.trim_matches('/');
+        let mut entries: Vec<DirEntry> = Vec::new();
+
+        for entry in self.entries() {
+            let entry = entry?;
+            let rest = match prefix {
+                "" => entry.name,
+                _ => match entry.name.strip_prefix(prefix) {
+                    Some(rest) if rest.starts_with('/') => &rest[1..],
+                    _ => continue,
+                },
+            };
+
+            // Entries deeper below show up as their top-level directory.
+            let (name, metadata) = match rest.split_once('/') {
+                Some((name, _)) => (
+                    name,
+                    Metadata {
+                        file_type: FileType::Directory,
+                        size: 0,
+                    },
+                ),
+                None => (rest, entry.metadata()),
+            };
+
+            if !entries.iter().any(|e| e.name == name) {
+                entries.push(DirEntry {
+                    name: String::from(name),
+                    metadata,
+                });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let entry = self.lookup(path)?;
+        if entry.file_type != FileType::File {
+            return Err("Is a directory");
+        }
+
+        let start = usize::try_from(offset).map_or(entry.data.len(), |o| o.min(entry.data.len()));
+        let n = buffer.len().min(entry.data.len() - start);
+        buffer[..n].copy_from_slice(&entry.data[start..start + n]);
+
+        Ok(n)
+    }
+
+    fn write(&self, _path: &str, _offset: u64, _data: &[u8]) -> Result<usize, &'static str> {
+        Err("Read-only file system")
+    }
+
+    fn create(&self, _path: &str, _file_type: FileType) -> Result<(), &'static str> {
+        Err("Read-only file system")
+    }
+
+    fn truncate(&self, _path: &str, _len: u64) -> Result<(), &'static str> {
+        Err("Read-only file system")
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, format, vec::Vec};
+    use interface::FileSystem;
+    use test_macros::kernel_test;
+
+    fn push_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let header = format!(
+            "070701{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}{:08X}",
+            1,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        );
+
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    }
+
+    fn archive() -> &'static [u8] {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, ".", MODE_DIRECTORY | 0o755, &[]);
+        push_entry(&mut archive, "./etc", MODE_DIRECTORY | 0o755, &[]);
+        push_entry(&mut archive, "./etc/motd", MODE_FILE | 0o644, b"Hello\n");
+        push_entry(&mut archive, "./bin/init", MODE_FILE | 0o755, &[0xAA; 5]);
+        push_entry(&mut archive, "./link", 0o120777, b"etc/motd");
+        push_entry(&mut archive, TRAILER_NAME, 0, &[]);
+
+        // Some tools pad the archive to a block size.
+        archive.resize(archive.len() + 512, 0);
+
+        Box::leak(archive.into_boxed_slice())
+    }
+
+    /// Files and directories are found, including directories without an entry of their own.
+    #[kernel_test]
+    fn archive_is_listed_and_read() {
+        let fs = Initramfs::new(archive()).unwrap();
+
+        let names: Vec<_> = fs
+            .read_dir("/")
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["etc", "bin"]);
+        assert_eq!(fs.stat("/bin").unwrap().file_type, FileType::Directory);
+        assert_eq!(
+            fs.stat("/bin/init").unwrap(),
+            Metadata {
+                file_type: FileType::File,
+                size: 5
+            }
+        );
+        assert!(fs.stat("/link").is_err());
+        assert!(fs.stat("/et").is_err());
+
+        let mut buffer = [0; 16];
+        assert_eq!(fs.read("/etc/motd", 0, &mut buffer).unwrap(), 6);
+        assert_eq!(&buffer[..6], b"Hello\n");
+        assert_eq!(fs.read("/etc/motd", 4, &mut buffer).unwrap(), 2);
+        assert_eq!(fs.read("/etc/motd", 10, &mut buffer).unwrap(), 0);
+        assert!(fs.read("/etc", 0, &mut buffer).is_err());
+
+        assert!(fs.write("/etc/motd", 0, b"x").is_err());
+        assert!(fs.create("/new", FileType::File).is_err());
+    }
+
+    /// Truncated and corrupt archives are rejected.
+    #[kernel_test]
+    fn corrupt_archives_are_rejected() {
+        let archive = archive();
+
+        assert!(Initramfs::new(&archive[..200]).is_err());
+        assert!(Initramfs::new(&archive[1..]).is_err());
+        assert!(Initramfs::new(&[]).is_ok());
+    }
+}
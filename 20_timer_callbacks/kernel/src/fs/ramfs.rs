@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! In-memory file system.
+//!
+//! Files and directories are stored on the kernel heap and are lost on reboot.
+
+use super::{interface, DirEntry, FileType, Metadata};
+use crate::synchronization::{interface::Mutex, IRQSafeNullLock};
+use alloc::{string::String, vec::Vec};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+enum Node {
+    File(Vec<u8>),
+    Directory(Vec<(String, Node)>),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An in-memory file system.
+pub struct RamFs {
+    root: IRQSafeNullLock<Node>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Node {
+    fn metadata(&self) -> Metadata {
+        match self {
+            Self::File(data) => Metadata {
+                file_type: FileType::File,
+                size: data.len() as u64,
+            },
+            Self::Directory(_) => Metadata {
+                file_type: FileType::Directory,
+                size: 0,
+            },
+        }
+    }
+
+    fn lookup(&mut self, path: &str) -> Result<&mut Node, &'static str> {
+        let mut node = self;
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = match node {
+                Self::Directory(children) => children
+                    .iter_mut()
+                    .find(|(name, _)| name == component)
+                    .map(|(_, child)| child)
+                    .ok_or("No such file or directory")?,
+                Self::File(_) => return Err("Not a directory"),
+            };
+        }
+
+        Ok(node)
+    }
+
+    fn file(&mut self, path: &str) -> Result<&mut Vec<u8>, &'static str> {
+        match self.lookup(path)? {
+            Self::File(data) => Ok(data),
+            Self::Directory(_) => Err("Is a directory"),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RamFs {
+    /// Create an instance with an empty root directory.
+    pub const fn new() -> Self {
+        Self {
+            root: IRQSafeNullLock::new(Node::Directory(Vec::new())),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl interface::FileSystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn stat(&self, path: &str) -> Result<Metadata, &'static str> {
+        self.root.lock(|root| Ok(root.lookup(path)?.metadata()))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, &'static str> {
+        self.root.lock(|root| match root.lookup(path)? {
+            Node::Directory(children) => Ok(children
+                .iter()
+                .map(|(name, child)| DirEntry {
+                    name: name.clone(),
+                    metadata: child.metadata(),
+                })
+                .collect()),
+            Node::File(_) => Err("Not a directory"),
+        })
+    }
+
+    fn read(&self, path: &str, offset: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        self.root.lock(|root| {
+            let data = root.file(path)?;
+            let start = usize::try_from(offset).map_or(data.len(), |o| o.min(data.len()));
+            let n = buffer.len().min(data.len() - start);
+
+            buffer[..n].copy_from_slice(&data[start..start + n]);
+
+            Ok(n)
+        })
+    }
+
+    fn write(&self, path: &str, offset: u64, data: &[u8]) -> Result<usize, &'static str> {
+        self.root.lock(|root| {
+            let file = root.file(path)?;
+            let start = usize::try_from(offset).map_err(|_| "File too large")?;
+            let end = start.checked_add(data.len()).ok_or("File too large")?;
+
+            // Writing past the end leaves a gap of zeros.
+            if file.len() < end {
+                file.resize(end, 0);
+            }
+            file[start..end].copy_from_slice(data);
+
+            Ok(data.len())
+        })
+    }
+
+    fn create(&self, path: &str, file_type: FileType) -> Result<(), &'static str> {
+        let (parent, name) = path.rsplit_once('/').ok_or("Path is not absolute")?;
+        if name.is_empty() {
+            return Err("Invalid file name");
+        }
+
+        let node = match file_type {
+            FileType::File => Node::File(Vec::new()),
+            FileType::Directory => Node::Directory(Vec::new()),
+            FileType::Device => return Err("Device files are not supported"),
+        };
+
+        self.root.lock(|root| match root.lookup(parent)? {
+            Node::Directory(children) if children.iter().any(|(n, _)| n == name) => {
+                Err("File exists")
+            }
+            Node::Directory(children) => {
+                children.push((String::from(name), node));
+                Ok(())
+            }
+            Node::File(_) => Err("Not a directory"),
+        })
+    }
+
+    fn truncate(&self, path: &str, len: u64) -> Result<(), &'static str> {
+        let len = usize::try_from(len).map_err(|_| "File too large")?;
+
+        self.root.lock(|root| {
+            root.file(path)?.resize(len, 0);
+            Ok(())
+        })
+    }
+}
@@ -137,14 +137,19 @@ mod panic_wait;
 mod synchronization;
 
 pub mod backtrace;
+pub mod block;
 pub mod bsp;
 pub mod common;
 pub mod console;
 pub mod cpu;
+pub mod device_tree;
 pub mod driver;
 pub mod exception;
+pub mod fs;
+pub mod log;
 pub mod memory;
 pub mod print;
+pub mod shell;
 pub mod state;
 pub mod symbols;
 pub mod time;
@@ -0,0 +1,454 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Kernel log.
+//!
+//! All messages of the `info!`, `warn!` and `debug!` macros are stored as records in a ring buffer
+//! of fixed size, which overwrites the oldest records once it is full. This keeps the log
+//! available for later inspection, similar to `dmesg` on Linux. Records are additionally printed to
+//! the console if their level is at or above the console log level, which can be changed at
+//! runtime.
+//!
+//! The ring buffer is statically allocated, so logging works before the kernel heap is available.
+//! For the same reason, messages are formatted into a fixed size buffer of 256 bytes. Longer
+//! messages are truncated and end with ` [...]`.
+//!
+//! With the `binary_log` feature, the macros bypass the ring buffer and send binary frames over the
+//! console instead, see [`binary`].
+
+#[cfg(feature = "binary_log")]
+pub mod binary;
+
+use crate::{console, cpu, synchronization, synchronization::IRQSafeNullLock, time};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum length of a single message in bytes, including the [`TRUNCATION_MARKER`].
+///
+/// Longer messages are cut at a character boundary and end with the marker instead.
+const MAX_MESSAGE_LEN: usize = 256;
+
+/// Appended to messages that were truncated.
+const TRUNCATION_MARKER: &str = " [...]";
+
+const NUM_RECORDS: usize = 512;
+const TEXT_SIZE: usize = 32 * 1024;
+
+/// Metadata of a record. The message is stored in the text ring of the [`LogBuffer`].
+#[derive(Copy, Clone)]
+struct RecordHeader {
+    level: Level,
+    timestamp: Duration,
+    core: u8,
+    module_path: &'static str,
+    text_start: usize,
+    text_len: usize,
+}
+
+/// Ring buffer of records.
+///
+/// Headers and message text are stored in two separate rings. A record is evicted if either of
+/// them does not have room for a new record.
+struct LogBuffer<const NUM_RECORDS: usize, const TEXT_SIZE: usize> {
+    headers: [Option<RecordHeader>; NUM_RECORDS],
+    oldest: usize,
+    num_records: usize,
+
+    text: [u8; TEXT_SIZE],
+    text_write_pos: usize,
+    text_used: usize,
+
+    num_dropped: u64,
+}
+
+/// A stack buffer that is used to format a message. Truncated messages end with the
+/// [`TRUNCATION_MARKER`].
+struct MessageBuffer {
+    buf: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Log levels. A lower level is more important.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[allow(missing_docs)]
+pub enum Level {
+    Warn = 0,
+    Info = 1,
+    Debug = 2,
+}
+
+/// A record of the kernel log.
+pub struct Record<'a> {
+    /// The log level.
+    pub level: Level,
+
+    /// Time since boot at which the record was created.
+    pub timestamp: Duration,
+
+    /// Id of the core that created the record.
+    pub core: u8,
+
+    /// Module path of the code that created the record.
+    pub module_path: &'static str,
+
+    /// The message.
+    pub message: &'a str,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static LOG_BUFFER: IRQSafeNullLock<LogBuffer<NUM_RECORDS, TEXT_SIZE>> =
+    IRQSafeNullLock::new(LogBuffer::new());
+
+static CONSOLE_LEVEL: AtomicU8 = AtomicU8::new(Level::Debug as u8);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<const NUM_RECORDS: usize, const TEXT_SIZE: usize> LogBuffer<NUM_RECORDS, TEXT_SIZE> {
+    const fn new() -> Self {
+        Self {
+            headers: [None; NUM_RECORDS],
+            oldest: 0,
+            num_records: 0,
+            text: [0; TEXT_SIZE],
+            text_write_pos: 0,
+            text_used: 0,
+            num_dropped: 0,
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(header) = self.headers[self.oldest].take() {
+            self.text_used -= header.text_len;
+        }
+
+        self.oldest = (self.oldest + 1) % NUM_RECORDS;
+        self.num_records -= 1;
+        self.num_dropped += 1;
+    }
+
+    fn push(&mut self, record: &Record) {
+        let message = &record.message.as_bytes()[..record.message.len().min(TEXT_SIZE)];
+
+        while self.num_records > 0
+            && (self.num_records == NUM_RECORDS || TEXT_SIZE - self.text_used < message.len())
+        {
+            self.drop_oldest();
+        }
+
+        let text_start = self.text_write_pos;
+        for (i, byte) in message.iter().enumerate() {
+            self.text[(text_start + i) % TEXT_SIZE] = *byte;
+        }
+        self.text_write_pos = (text_start + message.len()) % TEXT_SIZE;
+        self.text_used += message.len();
+
+        let index = (self.oldest + self.num_records) % NUM_RECORDS;
+        self.headers[index] = Some(RecordHeader {
+            level: record.level,
+            timestamp: record.timestamp,
+            core: record.core,
+            module_path: record.module_path,
+            text_start,
+            text_len: message.len(),
+        });
+        self.num_records += 1;
+    }
+
+    /// Call `f` for each record, from oldest to newest.
+    fn for_each(&self, mut f: impl FnMut(&Record)) {
+        for i in 0..self.num_records {
+            let header = match &self.headers[(self.oldest + i) % NUM_RECORDS] {
+                None => continue,
+                Some(header) => header,
+            };
+
+            // The message might wrap around the end of the text ring, so copy it into a contiguous
+            // buffer first.
+            let mut message = MessageBuffer::new();
+            for j in 0..header.text_len {
+                message.push(self.text[(header.text_start + j) % TEXT_SIZE]);
+            }
+
+            f(&Record {
+                level: header.level,
+                timestamp: header.timestamp,
+                core: header.core,
+                module_path: header.module_path,
+                message: message.as_str(),
+            });
+        }
+    }
+}
+
+impl MessageBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MAX_MESSAGE_LEN],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < MAX_MESSAGE_LEN {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only complete UTF-8 sequences are ever written, see `write_str()`.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("<invalid UTF-8>")
+    }
+
+    /// Cut the message at a character boundary so that the marker fits, and append it.
+    fn truncate(&mut self) {
+        self.len = self.len.min(MAX_MESSAGE_LEN - TRUNCATION_MARKER.len());
+        while self.len > 0 && (self.buf[self.len] & 0xc0) == 0x80 {
+            self.len -= 1;
+        }
+
+        for byte in TRUNCATION_MARKER.as_bytes() {
+            self.push(*byte);
+        }
+        self.truncated = true;
+    }
+}
+
+impl fmt::Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+
+        if s.len() > MAX_MESSAGE_LEN - self.len {
+            // Copy what fits, `truncate()` cuts it back to a character boundary.
+            let len = MAX_MESSAGE_LEN - self.len;
+            self.buf[self.len..].copy_from_slice(&s.as_bytes()[..len]);
+            self.len = MAX_MESSAGE_LEN;
+            self.truncate();
+
+            return Ok(());
+        }
+
+        for byte in s.as_bytes() {
+            self.push(*byte);
+        }
+
+        Ok(())
+    }
+}
+
+fn print_to_console(record: &Record) {
+    let (open, close) = match record.level {
+        Level::Warn => ("[W", ']'),
+        Level::Info => ("[ ", ']'),
+        Level::Debug => ("<D", '>'),
+    };
+
+    console::write_log(
+        record.level,
+        format_args_nl!(
+            "{} {:>3}.{:06}{} {}",
+            open,
+            record.timestamp.as_secs(),
+            record.timestamp.subsec_micros(),
+            close,
+            record.message
+        ),
+    )
+    .unwrap();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl Level {
+    fn from_u8(level: u8) -> Self {
+        match level {
+            0 => Self::Warn,
+            1 => Self::Info,
+            _ => Self::Debug,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>4}.{:06}] core {} {:<5} {}: {}",
+            self.timestamp.as_secs(),
+            self.timestamp.subsec_micros(),
+            self.core,
+            self.level,
+            self.module_path,
+            self.message
+        )
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &'static str, args: fmt::Arguments) {
+    let mut message = MessageBuffer::new();
+    fmt::Write::write_fmt(&mut message, args).unwrap();
+
+    let record = Record {
+        level,
+        timestamp: time::time_manager().uptime(),
+        core: cpu::smp::core_id(),
+        module_path,
+        message: message.as_str(),
+    };
+
+    LOG_BUFFER.lock(|buf| buf.push(&record));
+
+    if level <= console_level() {
+        print_to_console(&record);
+    }
+}
+
+/// Return the console log level. Only records at this or a more important level are printed.
+pub fn console_level() -> Level {
+    Level::from_u8(CONSOLE_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Set the console log level.
+///
+/// Records of less important levels are still stored in the log.
+pub fn set_console_level(level: Level) {
+    CONSOLE_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Call `f` for each record in the log, from oldest to newest.
+///
+/// Executed while holding the lock of the log, so `f` must not log.
+pub fn for_each_record(f: impl FnMut(&Record)) {
+    LOG_BUFFER.lock(|buf| buf.for_each(f))
+}
+
+/// Return the number of records that were overwritten because the log was full.
+pub fn num_dropped() -> u64 {
+    LOG_BUFFER.lock(|buf| buf.num_dropped)
+}
+
+/// Print the complete log to the console, akin to `dmesg`.
+pub fn dump() {
+    let num_dropped = num_dropped();
+    if num_dropped > 0 {
+        crate::println!("[ {} older records dropped ]", num_dropped);
+    }
+
+    for_each_record(|record| crate::println!("{}", record));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::String, vec::Vec};
+    use test_macros::kernel_test;
+
+    fn record(message: &str) -> Record {
+        Record {
+            level: Level::Info,
+            timestamp: Duration::ZERO,
+            core: 0,
+            module_path: module_path!(),
+            message,
+        }
+    }
+
+    fn messages<const N: usize, const T: usize>(buf: &LogBuffer<N, T>) -> Vec<String> {
+        let mut messages = Vec::new();
+        buf.for_each(|r| messages.push(String::from(r.message)));
+
+        messages
+    }
+
+    /// The oldest record is overwritten if the maximum number of records is reached.
+    #[kernel_test]
+    fn oldest_record_is_overwritten() {
+        let mut buf: LogBuffer<2, 64> = LogBuffer::new();
+
+        buf.push(&record("a"));
+        buf.push(&record("b"));
+        buf.push(&record("c"));
+
+        assert_eq!(messages(&buf), ["b", "c"]);
+        assert_eq!(buf.num_dropped, 1);
+    }
+
+    /// Records are evicted if the text ring is full, and messages can wrap around its end.
+    #[kernel_test]
+    fn text_ring_wraps_around() {
+        let mut buf: LogBuffer<8, 8> = LogBuffer::new();
+
+        buf.push(&record("abc"));
+        buf.push(&record("def"));
+        buf.push(&record("ghij"));
+
+        assert_eq!(messages(&buf), ["def", "ghij"]);
+        assert_eq!(buf.num_dropped, 1);
+    }
+
+    /// Messages are truncated at a character boundary and end with the marker.
+    #[kernel_test]
+    fn long_message_is_truncated() {
+        let mut message = MessageBuffer::new();
+        let long = "ä".repeat(MAX_MESSAGE_LEN);
+
+        fmt::Write::write_str(&mut message, &long).unwrap();
+        fmt::Write::write_str(&mut message, "tail").unwrap();
+
+        let text = message.as_str().strip_suffix(TRUNCATION_MARKER).unwrap();
+        assert!(text.chars().all(|c| c == 'ä'));
+        assert!(message.len > MAX_MESSAGE_LEN - TRUNCATION_MARKER.len() - 'ä'.len_utf8());
+    }
+
+    /// A message that fills the buffer exactly is not truncated.
+    #[kernel_test]
+    fn full_message_is_not_truncated() {
+        let mut message = MessageBuffer::new();
+        let full = "a".repeat(MAX_MESSAGE_LEN);
+
+        fmt::Write::write_str(&mut message, &full).unwrap();
+
+        assert_eq!(message.as_str(), full);
+    }
+}
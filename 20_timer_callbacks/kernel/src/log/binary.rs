@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Binary log mode.
+//!
+//! Enabled with the `binary_log` feature. Instead of formatting messages on the target, the log
+//! macros send a compact frame over the console UART that contains the id of the format string and
+//! the raw arguments. Formatting is deferred to the host, where `tools/binary_log_decoder` turns
+//! the frames back into text.
+//!
+//! # Format string interning
+//!
+//! Each log call site places its module path and format string, both NUL-terminated, into the
+//! `.log_format_strings` section of the kernel ELF. The id of a format string is its offset into
+//! that section.
+//!
+//! # Frame layout
+//!
+//! | Field     | Encoding                                 |
+//! |-----------|------------------------------------------|
+//! | Start     | `0xff`, which never occurs in UTF-8 text |
+//! | Level     | `u8`                                     |
+//! | Core      | `u8`                                     |
+//! | Timestamp | Microseconds since boot, LEB128          |
+//! | Id        | LEB128                                   |
+//! | Num args  | `u8`                                     |
+//! | Args      | Tag `u8` and payload, see `Tag`          |
+//!
+//! Integers, bools and chars are sent raw. All other arguments are formatted with `Display` on the
+//! target and sent as strings. Width, fill and alignment are applied on the host.
+//!
+//! # Escaping
+//!
+//! All fields after the start byte are escaped, so that `0xff` only ever marks the start of a frame
+//! and the decoder can resynchronize after a corrupted frame. `0xff` and the escape byte `0xfe`,
+//! which does not occur in UTF-8 text either, are sent as `0xfe` followed by the byte XOR `0x20`.
+
+use super::Level;
+use crate::{console, cpu, time};
+use core::{cell::UnsafeCell, fmt};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __log_format_strings_start: UnsafeCell<()>;
+}
+
+const FRAME_START: u8 = 0xff;
+const ESCAPE: u8 = 0xfe;
+const ESCAPE_XOR: u8 = 0x20;
+
+/// Maximum size of a frame. Arguments that do not fit anymore are dropped.
+const MAX_FRAME_SIZE: usize = 256;
+
+/// Argument tags.
+#[repr(u8)]
+enum Tag {
+    /// LEB128 encoded.
+    Unsigned = 0,
+
+    /// Zigzag and LEB128 encoded.
+    Signed = 1,
+
+    /// LEB128 encoded length, followed by UTF-8 bytes.
+    Str = 2,
+
+    /// `u8`, zero is false.
+    Bool = 3,
+
+    /// LEB128 encoded code point.
+    Char = 4,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A frame that is being assembled by a log macro.
+pub struct Frame {
+    buf: [u8; MAX_FRAME_SIZE],
+    len: usize,
+    num_args_pos: usize,
+    truncated: bool,
+}
+
+/// Wraps a log macro argument to select its encoding.
+pub struct Arg<'a, T: ?Sized>(pub &'a T);
+
+/// Arguments that are sent raw.
+pub trait RawArg {
+    /// Append the argument to the frame.
+    fn encode_raw(&self, frame: &mut Frame);
+}
+
+/// Encoding of arguments that are sent raw. Takes precedence over [`EncodeFormatted`].
+pub trait EncodeRaw {
+    /// Append the argument to the frame.
+    fn encode(&self, frame: &mut Frame);
+}
+
+/// Encoding of all other arguments, which are formatted on the target.
+pub trait EncodeFormatted {
+    /// Append the argument to the frame.
+    fn encode(&self, frame: &mut Frame);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Frame {
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == MAX_FRAME_SIZE {
+            return false;
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+
+        true
+    }
+
+    fn push_leb128(&mut self, mut value: u64) -> bool {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                return self.push(byte);
+            }
+
+            if !self.push(byte | 0x80) {
+                return false;
+            }
+        }
+    }
+
+    /// Append a complete argument, or nothing at all if it does not fit.
+    fn push_arg(&mut self, f: impl FnOnce(&mut Self) -> bool) {
+        let saved_len = self.len;
+
+        if self.truncated || !f(self) {
+            self.len = saved_len;
+            self.truncated = true;
+            return;
+        }
+
+        self.buf[self.num_args_pos] += 1;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.push_arg(|frame| {
+            frame.push(Tag::Str as u8)
+                && frame.push_leb128(s.len() as u64)
+                && s.bytes().all(|b| frame.push(b))
+        });
+    }
+}
+
+/// Formats an argument directly into a frame.
+struct StrArgWriter<'a> {
+    frame: &'a mut Frame,
+}
+
+impl fmt::Write for StrArgWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !s.bytes().all(|b| self.frame.push(b)) {
+            return Err(fmt::Error);
+        }
+
+        Ok(())
+    }
+}
+
+macro_rules! impl_raw_unsigned {
+    ($($t:ty),*) => {$(
+        impl RawArg for $t {
+            fn encode_raw(&self, frame: &mut Frame) {
+                frame.push_arg(|f| f.push(Tag::Unsigned as u8) && f.push_leb128(*self as u64));
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_raw_signed {
+    ($($t:ty),*) => {$(
+        impl RawArg for $t {
+            fn encode_raw(&self, frame: &mut Frame) {
+                let value = *self as i64;
+                let zigzag = ((value << 1) ^ (value >> 63)) as u64;
+
+                frame.push_arg(|f| f.push(Tag::Signed as u8) && f.push_leb128(zigzag));
+            }
+        }
+    )*};
+}
+
+impl_raw_unsigned!(u8, u16, u32, u64, usize);
+impl_raw_signed!(i8, i16, i32, i64, isize);
+
+impl RawArg for bool {
+    fn encode_raw(&self, frame: &mut Frame) {
+        frame.push_arg(|f| f.push(Tag::Bool as u8) && f.push(*self as u8));
+    }
+}
+
+impl RawArg for char {
+    fn encode_raw(&self, frame: &mut Frame) {
+        frame.push_arg(|f| f.push(Tag::Char as u8) && f.push_leb128(*self as u64));
+    }
+}
+
+impl RawArg for str {
+    fn encode_raw(&self, frame: &mut Frame) {
+        frame.push_str(self);
+    }
+}
+
+impl RawArg for &str {
+    fn encode_raw(&self, frame: &mut Frame) {
+        frame.push_str(self);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Convert an interned string into an array, so that it can be placed into a link section.
+pub const fn intern<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut array = [0; N];
+
+    let mut i = 0;
+    while i < N {
+        array[i] = bytes[i];
+        i += 1;
+    }
+
+    array
+}
+
+impl Frame {
+    /// Start a frame for the interned format string at `format`.
+    pub fn new(level: Level, format: &'static [u8]) -> Self {
+        // Safety: Only the address of the linker symbol is taken.
+        let section_start = unsafe { __log_format_strings_start.get() as usize };
+        let id = format.as_ptr() as usize - section_start;
+
+        let mut frame = Self {
+            buf: [0; MAX_FRAME_SIZE],
+            len: 0,
+            num_args_pos: 0,
+            truncated: false,
+        };
+
+        frame.push(FRAME_START);
+        frame.push(level as u8);
+        frame.push(cpu::smp::core_id());
+        frame.push_leb128(time::time_manager().uptime().as_micros() as u64);
+        frame.push_leb128(id as u64);
+
+        frame.num_args_pos = frame.len;
+        frame.push(0);
+
+        frame
+    }
+
+    /// Send the frame over the console UART.
+    pub fn send(&self) {
+        // Escaping at most doubles the size of the frame.
+        let mut chars = ['\0'; 2 * MAX_FRAME_SIZE];
+        let mut len = 0;
+        let mut emit = |b: u8| {
+            // The UART transmits the lower byte of the character.
+            chars[len] = char::from(b);
+            len += 1;
+        };
+
+        emit(FRAME_START);
+        for b in &self.buf[1..self.len] {
+            match *b {
+                FRAME_START | ESCAPE => {
+                    emit(ESCAPE);
+                    emit(*b ^ ESCAPE_XOR);
+                }
+                b => emit(b),
+            }
+        }
+
+        console::write_binary_log(&chars[..len]);
+    }
+}
+
+impl<T> EncodeRaw for Arg<'_, T>
+where
+    T: RawArg + ?Sized,
+{
+    fn encode(&self, frame: &mut Frame) {
+        self.0.encode_raw(frame);
+    }
+}
+
+impl<T> EncodeFormatted for &Arg<'_, T>
+where
+    T: fmt::Display + ?Sized,
+{
+    fn encode(&self, frame: &mut Frame) {
+        let value = self.0;
+
+        frame.push_arg(|frame| {
+            // The length is not known in advance, so reserve two LEB128 bytes, which suffice for
+            // any length that fits into a frame, and patch them afterwards.
+            if !frame.push(Tag::Str as u8) {
+                return false;
+            }
+            let len_pos = frame.len;
+            if !frame.push(0x80) || !frame.push(0) {
+                return false;
+            }
+
+            let mut writer = StrArgWriter { frame };
+            if fmt::Write::write_fmt(&mut writer, format_args!("{}", value)).is_err() {
+                return false;
+            }
+
+            let len = frame.len - len_pos - 2;
+            frame.buf[len_pos] = 0x80 | (len & 0x7f) as u8;
+            frame.buf[len_pos + 1] = (len >> 7) as u8;
+
+            true
+        });
+    }
+}
@@ -15,7 +15,9 @@
 
 extern crate alloc;
 
-use libkernel::{bsp, cpu, driver, exception, info, memory, state, time};
+use libkernel::{
+    block, bsp, console, device_tree, driver, exception, fs, info, memory, shell, state, time, warn,
+};
 
 /// Early init code.
 ///
@@ -30,6 +32,11 @@ unsafe fn kernel_init() -> ! {
     exception::handling_init();
     memory::init();
 
+    // Map the device tree that describes the board.
+    if let Err(x) = device_tree::init() {
+        panic!("Error initializing device tree: {}", x);
+    }
+
     // Initialize the timer subsystem.
     if let Err(x) = time::init() {
         panic!("Error initializing timer subsystem: {}", x);
@@ -45,6 +52,14 @@ unsafe fn kernel_init() -> ! {
 
     bsp::memory::mmu::kernel_add_mapping_records_for_precomputed();
 
+    // Mount the root and device file systems, and the boot partition if there is one.
+    if let Err(x) = fs::init() {
+        panic!("Error initializing file systems: {}", x);
+    }
+    if let Err(x) = mount_boot_partition() {
+        warn!("Boot partition not mounted: {}", x);
+    }
+
     // Unmask interrupts on the boot CPU core.
     exception::asynchronous::local_irq_unmask();
 
@@ -55,6 +70,16 @@ unsafe fn kernel_init() -> ! {
     kernel_main()
 }
 
+/// Mount the FAT32 file system of the first SD card partition at `/boot`.
+fn mount_boot_partition() -> Result<(), &'static str> {
+    use alloc::boxed::Box;
+
+    let device = block::block_device("sd0p1").ok_or("No partition sd0p1")?;
+    let boot_fs = fs::fat32::Fat32::mount(device)?;
+
+    fs::mount("/boot", Box::leak(Box::new(boot_fs)))
+}
+
 /// The main function running after the early init.
 fn kernel_main() -> ! {
     use alloc::boxed::Box;
@@ -63,6 +88,9 @@ fn kernel_main() -> ! {
     info!("{}", libkernel::version());
     info!("Booting on: {}", bsp::board_name());
 
+    info!("Device tree:");
+    device_tree::print_info();
+
     info!("MMU online:");
     memory::mmu::kernel_print_mappings();
 
@@ -80,6 +108,12 @@ fn kernel_main() -> ! {
     info!("Drivers loaded:");
     driver::driver_manager().enumerate();
 
+    info!("Partitions:");
+    block::partition::print_partitions();
+
+    info!("Mounted file systems:");
+    fs::print_mounts();
+
     info!("Registered IRQ handlers:");
     exception::asynchronous::irq_manager().print_handler();
 
@@ -88,9 +122,9 @@ fn kernel_main() -> ! {
 
     time::time_manager().set_timeout_once(Duration::from_secs(5), Box::new(|| info!("Once 5")));
     time::time_manager().set_timeout_once(Duration::from_secs(3), Box::new(|| info!("Once 2")));
-    time::time_manager()
-        .set_timeout_periodic(Duration::from_secs(1), Box::new(|| info!("Periodic 1 sec")));
 
-    info!("Echoing input now");
-    cpu::wait_forever();
+    console::ansi::enable_status_line(Duration::from_secs(1));
+
+    info!("Starting shell. Type 'help' for a list of commands");
+    shell::run();
 }
@@ -80,11 +80,16 @@ impl HeapAllocator {
         }
     }
 
+    /// Return the number of used and free bytes.
+    pub fn usage(&self) -> (usize, usize) {
+        KERNEL_HEAP_ALLOCATOR
+            .inner
+            .lock(|inner| (inner.used(), inner.free()))
+    }
+
     /// Print the current heap usage.
     pub fn print_usage(&self) {
-        let (used, free) = KERNEL_HEAP_ALLOCATOR
-            .inner
-            .lock(|inner| (inner.used(), inner.free()));
+        let (used, free) = self.usage();
 
         if used >= 1024 {
             let (used_h, used_unit) = common::size_human_readable_ceil(used);
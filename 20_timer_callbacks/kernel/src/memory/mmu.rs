@@ -52,6 +52,9 @@ pub mod interface {
 
         /// Returns true if the MMU is enabled, false otherwise.
         fn is_enabled(&self) -> bool;
+
+        /// Invalidate all TLB entries of the executing core.
+        fn invalidate_tlb_local(&self);
     }
 }
 
@@ -113,6 +116,51 @@ fn try_kernel_virt_addr_to_phys_addr(
         .read(|tables| tables.try_virt_addr_to_phys_addr(virt_addr))
 }
 
+/// MMIO remapping in the kernel translation tables with the given memory attributes.
+///
+/// # Safety
+///
+/// - Same as `kernel_map_at_unchecked()`, minus the aliasing part.
+unsafe fn kernel_map_mmio_with_attributes(
+    name: &'static str,
+    mmio_descriptor: &MMIODescriptor,
+    mem_attributes: MemAttributes,
+) -> Result<Address<Virtual>, &'static str> {
+    let phys_region = MemoryRegion::from(*mmio_descriptor);
+    let offset_into_start_page = mmio_descriptor.start_addr().offset_into_page();
+
+    // Check if an identical region has been mapped for another driver. If so, reuse it.
+    let virt_addr = if let Some(addr) =
+        mapping_record::kernel_find_and_insert_mmio_duplicate(mmio_descriptor, mem_attributes, name)
+    {
+        addr
+    // Otherwise, allocate a new region and map it.
+    } else {
+        let num_pages = match NonZeroUsize::new(phys_region.num_pages()) {
+            None => return Err("Requested 0 pages"),
+            Some(x) => x,
+        };
+
+        let virt_region =
+            page_alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.alloc(num_pages))?;
+
+        kernel_map_at_unchecked(
+            name,
+            &virt_region,
+            &phys_region,
+            &AttributeFields {
+                mem_attributes,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        )?;
+
+        virt_region.start_addr()
+    };
+
+    Ok(virt_addr + offset_into_start_page)
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -189,39 +237,36 @@ pub unsafe fn kernel_map_mmio(
     name: &'static str,
     mmio_descriptor: &MMIODescriptor,
 ) -> Result<Address<Virtual>, &'static str> {
-    let phys_region = MemoryRegion::from(*mmio_descriptor);
-    let offset_into_start_page = mmio_descriptor.start_addr().offset_into_page();
-
-    // Check if an identical region has been mapped for another driver. If so, reuse it.
-    let virt_addr = if let Some(addr) =
-        mapping_record::kernel_find_and_insert_mmio_duplicate(mmio_descriptor, name)
-    {
-        addr
-    // Otherwise, allocate a new region and map it.
-    } else {
-        let num_pages = match NonZeroUsize::new(phys_region.num_pages()) {
-            None => return Err("Requested 0 pages"),
-            Some(x) => x,
-        };
-
-        let virt_region =
-            page_alloc::kernel_mmio_va_allocator().lock(|allocator| allocator.alloc(num_pages))?;
-
-        kernel_map_at_unchecked(
-            name,
-            &virt_region,
-            &phys_region,
-            &AttributeFields {
-                mem_attributes: MemAttributes::Device,
-                acc_perms: AccessPermissions::ReadWrite,
-                execute_never: true,
-            },
-        )?;
+    kernel_map_mmio_with_attributes(name, mmio_descriptor, MemAttributes::Device)
+}
 
-        virt_region.start_addr()
-    };
+/// Like [`kernel_map_mmio()`], but maps the region as normal non-cacheable memory. Unlike device
+/// memory, this allows write combining and unaligned accesses.
+///
+/// Typically used for frame buffers.
+///
+/// # Safety
+///
+/// - Same as `kernel_map_at_unchecked()`, minus the aliasing part.
+pub unsafe fn kernel_map_mmio_non_cacheable(
+    name: &'static str,
+    mmio_descriptor: &MMIODescriptor,
+) -> Result<Address<Virtual>, &'static str> {
+    kernel_map_mmio_with_attributes(name, mmio_descriptor, MemAttributes::NonCacheableDRAM)
+}
 
-    Ok(virt_addr + offset_into_start_page)
+/// Like [`kernel_map_mmio()`], but maps the region as normal cacheable memory.
+///
+/// Typically used for data that the firmware left in DRAM, like the device tree blob.
+///
+/// # Safety
+///
+/// - Same as `kernel_map_at_unchecked()`, minus the aliasing part.
+pub unsafe fn kernel_map_mmio_cacheable(
+    name: &'static str,
+    mmio_descriptor: &MMIODescriptor,
+) -> Result<Address<Virtual>, &'static str> {
+    kernel_map_mmio_with_attributes(name, mmio_descriptor, MemAttributes::CacheableDRAM)
 }
 
 /// Try to translate a kernel virtual page address to a physical page address.
@@ -260,3 +305,11 @@ pub unsafe fn enable_mmu_and_caching(
 ) -> Result<(), MMUEnableError> {
     arch_mmu::mmu().enable_mmu_and_caching(phys_tables_base_addr)
 }
+
+/// Invalidate all TLB entries of the executing core.
+///
+/// Used to handle TLB shootdown requests from other cores.
+#[inline(always)]
+pub fn invalidate_tlb_local() {
+    arch_mmu::mmu().invalidate_tlb_local()
+}
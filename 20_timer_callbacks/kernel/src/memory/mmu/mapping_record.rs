@@ -75,10 +75,11 @@ impl MappingRecord {
     fn find_duplicate(
         &mut self,
         phys_region: &MemoryRegion<Physical>,
+        mem_attributes: MemAttributes,
     ) -> Option<&mut MappingRecordEntry> {
         self.inner
             .iter_mut()
-            .filter(|x| x.attribute_fields.mem_attributes == MemAttributes::Device)
+            .filter(|x| x.attribute_fields.mem_attributes == mem_attributes)
             .find(|x| {
                 if x.phys_start_addr != phys_region.start_addr() {
                     return false;
@@ -128,6 +129,7 @@ impl MappingRecord {
 
             let attr = match i.attribute_fields.mem_attributes {
                 MemAttributes::CacheableDRAM => "C",
+                MemAttributes::NonCacheableDRAM => "NC",
                 MemAttributes::Device => "Dev",
             };
 
@@ -185,12 +187,13 @@ pub fn kernel_add(
 
 pub fn kernel_find_and_insert_mmio_duplicate(
     mmio_descriptor: &MMIODescriptor,
+    mem_attributes: MemAttributes,
     new_user: &'static str,
 ) -> Option<Address<Virtual>> {
     let phys_region: MemoryRegion<Physical> = (*mmio_descriptor).into();
 
     KERNEL_MAPPING_RECORD.write(|mr| {
-        let dup = mr.find_duplicate(&phys_region)?;
+        let dup = mr.find_duplicate(&phys_region, mem_attributes)?;
 
         dup.add_user(new_user);
 
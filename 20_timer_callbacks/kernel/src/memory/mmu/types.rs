@@ -32,6 +32,7 @@ pub struct MemoryRegion<ATYPE: AddressType> {
 #[derive(Copy, Clone, Debug, Eq, PartialOrd, PartialEq)]
 pub enum MemAttributes {
     CacheableDRAM,
+    NonCacheableDRAM,
     Device,
 }
 
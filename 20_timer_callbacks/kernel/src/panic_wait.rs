@@ -4,7 +4,7 @@
 
 //! A panic handler that infinitely waits.
 
-use crate::{backtrace, cpu, exception, println};
+use crate::{backtrace, console, cpu, exception, println};
 use core::panic::PanicInfo;
 
 //--------------------------------------------------------------------------------------------------
@@ -70,13 +70,20 @@ fn panic(info: &PanicInfo) -> ! {
         _ => ("???", 0, 0),
     };
 
+    console::write_styled(
+        console::ansi::Style::PANIC,
+        format_args_nl!(
+            "[  {:>3}.{:06}] Kernel panic!",
+            timestamp.as_secs(),
+            timestamp.subsec_micros()
+        ),
+    )
+    .unwrap();
+
     println!(
-        "[  {:>3}.{:06}] Kernel panic!\n\n\
-        Panic location:\n      File '{}', line {}, column {}\n\n\
+        "\nPanic location:\n      File '{}', line {}, column {}\n\n\
         {}\n\n\
         {}",
-        timestamp.as_secs(),
-        timestamp.subsec_micros(),
         location,
         line,
         column,
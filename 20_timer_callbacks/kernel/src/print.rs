@@ -35,78 +35,57 @@ macro_rules! println {
     })
 }
 
-/// Prints an info, with a newline.
+/// Logs a message at the given level.
+///
+/// Depending on the `binary_log` feature, the message is either formatted and stored in the kernel
+/// log, or sent as a binary frame. See [`log`](crate::log).
+#[doc(hidden)]
 #[macro_export]
-macro_rules! info {
-    ($string:expr) => ({
-        let timestamp = $crate::time::time_manager().uptime();
+macro_rules! __log {
+    ($level:expr, $format_string:literal $(, $arg:expr)* $(,)?) => ({
+        #[cfg(not(feature = "binary_log"))]
+        $crate::log::_log($level, module_path!(), format_args!($format_string $(, $arg)*));
+
+        #[cfg(feature = "binary_log")]
+        {
+            #[allow(unused_imports)]
+            use $crate::log::binary::{EncodeFormatted as _, EncodeRaw as _};
+
+            const ENTRY: &str = concat!(module_path!(), "\0", $format_string, "\0");
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:06}] ", $string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        let timestamp = $crate::time::time_manager().uptime();
+            #[link_section = ".log_format_strings"]
+            static FORMAT: [u8; ENTRY.len()] = $crate::log::binary::intern(ENTRY);
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:06}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-            $($arg)*
-        ));
+            #[allow(unused_mut)]
+            let mut frame = $crate::log::binary::Frame::new($level, &FORMAT);
+            $((&$crate::log::binary::Arg(&$arg)).encode(&mut frame);)*
+            frame.send();
+        }
     })
 }
 
-/// Prints a warning, with a newline.
+/// Logs an info, with a newline.
 #[macro_export]
-macro_rules! warn {
-    ($string:expr) => ({
-        let timestamp = $crate::time::time_manager().uptime();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:06}] ", $string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-        ));
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
-        let timestamp = $crate::time::time_manager().uptime();
+macro_rules! info {
+    ($($arg:tt)*) => ({
+        $crate::__log!($crate::log::Level::Info, $($arg)*);
+    })
+}
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[W {:>3}.{:06}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp.subsec_micros(),
-            $($arg)*
-        ));
+/// Logs a warning, with a newline.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ({
+        $crate::__log!($crate::log::Level::Warn, $($arg)*);
     })
 }
 
-/// Debug print, with a newline.
+/// Debug log, with a newline.
 #[macro_export]
 macro_rules! debug {
-    ($string:expr) => ({
-        if cfg!(feature = "debug_prints") {
-            let timestamp = $crate::time::time_manager().uptime();
-
-            $crate::print::_print(format_args_nl!(
-                concat!("<[>D {:>3}.{:06}> ", $string),
-                timestamp.as_secs(),
-                timestamp.subsec_micros(),
-            ));
-        }
-    });
-    ($format_string:expr, $($arg:tt)*) => ({
+    ($($arg:tt)*) => ({
         if cfg!(feature = "debug_prints") {
-            let timestamp = $crate::time::time_manager().uptime();
-
-            $crate::print::_print(format_args_nl!(
-                concat!("<D {:>3}.{:06}> ", $format_string),
-                timestamp.as_secs(),
-                timestamp.subsec_micros(),
-                $($arg)*
-            ));
+            $crate::__log!($crate::log::Level::Debug, $($arg)*);
         }
     })
 }
@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Interactive kernel shell over the console.
+//!
+//! Reads lines from the console with basic line editing, see [`line_editor`], and executes the
+//! named command. Besides the built-in commands, which wrap the kernel's introspection functions,
+//! subsystems can register their own commands at runtime with [`register_command()`].
+
+mod line_editor;
+
+use crate::{
+    backtrace, block, console, driver, exception, fs, memory, print, println, symbols,
+    synchronization, synchronization::IRQSafeNullLock, time,
+};
+use alloc::{vec, vec::Vec};
+use console::tty::Termios;
+use core::fmt;
+use line_editor::LineEditor;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const PROMPT: &str = "kernel> ";
+
+/// Passes the echo of the line editor to the console.
+struct ConsoleWriter;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Signature of command functions. Receives the arguments following the command name.
+pub type CommandFn = fn(args: &[&str]) -> Result<(), &'static str>;
+
+/// A shell command.
+#[derive(Copy, Clone)]
+pub struct Command {
+    name: &'static str,
+    help: &'static str,
+    func: CommandFn,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+const BUILTIN_COMMANDS: &[Command] = &[
+    Command::new("help", "List all commands", cmd_help),
+    Command::new(
+        "mappings",
+        "Print the kernel's virtual memory layout",
+        cmd_mappings,
+    ),
+    Command::new("irqs", "Print the registered IRQ handlers", cmd_irqs),
+    Command::new("drivers", "Print the loaded drivers", cmd_drivers),
+    Command::new(
+        "blk",
+        "blk [<device> <block>]: Print the block devices, or dump a block",
+        cmd_blk,
+    ),
+    Command::new("ls", "ls [<path>]: List a directory", cmd_ls),
+    Command::new("cat", "cat <path>: Print a file", cmd_cat),
+    Command::new(
+        "write",
+        "write <path> <text>: Append a line of text to a file",
+        cmd_write,
+    ),
+    Command::new("heap", "Print the kernel heap usage", cmd_heap),
+    Command::new("uptime", "Print the time since boot", cmd_uptime),
+    Command::new(
+        "sym",
+        "sym <addr>: Print the symbol containing <addr>",
+        cmd_sym,
+    ),
+    Command::new("bt", "Print a backtrace of the shell", cmd_bt),
+];
+
+static REGISTERED_COMMANDS: IRQSafeNullLock<Vec<Command>> = IRQSafeNullLock::new(Vec::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        console::console().write_fmt(format_args!("{}", s))
+    }
+}
+
+/// Return all commands, built-in ones first.
+fn commands() -> Vec<Command> {
+    let mut commands = Vec::from(BUILTIN_COMMANDS);
+    REGISTERED_COMMANDS.lock(|registered| commands.extend_from_slice(registered));
+
+    commands
+}
+
+fn execute(line: &str) {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        None => return,
+        Some(name) => name,
+    };
+    let args: Vec<&str> = words.collect();
+
+    // Look up the function first, so that the lock is not held while it executes.
+    match commands().iter().find(|command| command.name == name) {
+        None => println!("Unknown command: {}. Try 'help'.", name),
+        Some(command) => {
+            if let Err(x) = (command.func)(&args) {
+                println!("{}: {}", name, x);
+            }
+        }
+    }
+}
+
+fn cmd_help(_args: &[&str]) -> Result<(), &'static str> {
+    for command in commands() {
+        println!("  {:<10} {}", command.name, command.help);
+    }
+
+    Ok(())
+}
+
+fn cmd_mappings(_args: &[&str]) -> Result<(), &'static str> {
+    memory::mmu::kernel_print_mappings();
+
+    Ok(())
+}
+
+fn cmd_irqs(_args: &[&str]) -> Result<(), &'static str> {
+    exception::asynchronous::irq_manager().print_handler();
+
+    Ok(())
+}
+
+fn cmd_drivers(_args: &[&str]) -> Result<(), &'static str> {
+    driver::driver_manager().enumerate();
+
+    Ok(())
+}
+
+fn cmd_blk(args: &[&str]) -> Result<(), &'static str> {
+    let (name, block) = match args {
+        [] => {
+            block::print_block_devices();
+            return Ok(());
+        }
+        [name, block] => (name, block),
+        _ => return Err("Usage: blk [<device> <block>]"),
+    };
+
+    let device = block::block_device(name).ok_or("Unknown block device")?;
+    let block = block.parse().map_err(|_| "Invalid block number")?;
+    let mut buffer = vec![0; device.block_size()];
+    device.read_blocks(block, &mut buffer)?;
+
+    for (i, line) in buffer.chunks(16).enumerate() {
+        print!("{:08x}:", i * 16);
+        for byte in line {
+            print!(" {:02x}", byte);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn cmd_ls(args: &[&str]) -> Result<(), &'static str> {
+    let path = match args {
+        [] => "/",
+        [path] => path,
+        _ => return Err("Usage: ls [<path>]"),
+    };
+
+    for entry in fs::read_dir(path)? {
+        let metadata = entry.metadata;
+        println!(
+            "{:<4} {:>10} {}",
+            metadata.file_type, metadata.size, entry.name
+        );
+    }
+
+    Ok(())
+}
+
+fn cmd_cat(args: &[&str]) -> Result<(), &'static str> {
+    let path = match args {
+        [path] => path,
+        _ => return Err("Usage: cat <path>"),
+    };
+
+    let fd = fs::open(path, fs::OpenFlags::READ)?;
+    let mut buffer = [0; 256];
+    let result = loop {
+        match fs::read(fd, &mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(n) => print!("{}", alloc::string::String::from_utf8_lossy(&buffer[..n])),
+            Err(x) => break Err(x),
+        }
+    };
+    fs::close(fd)?;
+
+    result
+}
+
+fn cmd_write(args: &[&str]) -> Result<(), &'static str> {
+    let (path, words) = match args {
+        [path, words @ ..] if !words.is_empty() => (path, words),
+        _ => return Err("Usage: write <path> <text>"),
+    };
+
+    let mut line = words.join(" ");
+    line.push('\n');
+
+    let fd = fs::open(path, fs::OpenFlags::APPEND)?;
+    let result = fs::write(fd, line.as_bytes());
+    fs::close(fd)?;
+
+    result.map(|_| ())
+}
+
+fn cmd_heap(_args: &[&str]) -> Result<(), &'static str> {
+    memory::heap_alloc::kernel_heap_allocator().print_usage();
+
+    Ok(())
+}
+
+fn cmd_uptime(_args: &[&str]) -> Result<(), &'static str> {
+    let uptime = time::time_manager().uptime();
+    println!("{}.{:06} s", uptime.as_secs(), uptime.subsec_micros());
+
+    Ok(())
+}
+
+fn cmd_sym(args: &[&str]) -> Result<(), &'static str> {
+    let addr = match args {
+        [addr] => addr,
+        _ => return Err("Usage: sym <addr>"),
+    };
+
+    let digits = addr.trim_start_matches("0x");
+    let addr = usize::from_str_radix(digits, 16).map_err(|_| "Invalid hexadecimal address")?;
+
+    match symbols::lookup_symbol(memory::Address::new(addr)) {
+        None => println!("{:#x}: Symbol not found", addr),
+        Some(sym) => println!("{:#x}: {} (size {:#x})", addr, sym.name(), sym.size()),
+    }
+
+    Ok(())
+}
+
+fn cmd_bt(_args: &[&str]) -> Result<(), &'static str> {
+    println!("{}", backtrace::Backtrace);
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl Command {
+    /// Create an instance.
+    ///
+    /// `help` is shown by the `help` command.
+    pub const fn new(name: &'static str, help: &'static str, func: CommandFn) -> Self {
+        Self { name, help, func }
+    }
+}
+
+/// Register a command.
+///
+/// Fails if a command of the same name exists already.
+pub fn register_command(command: Command) -> Result<(), &'static str> {
+    REGISTERED_COMMANDS.lock(|registered| {
+        let is_taken = |c: &Command| c.name == command.name;
+        if BUILTIN_COMMANDS.iter().any(is_taken) || registered.iter().any(is_taken) {
+            return Err("Command already registered");
+        }
+
+        registered.push(command);
+
+        Ok(())
+    })
+}
+
+/// Run the shell on the console. Never returns.
+pub fn run() -> ! {
+    let mut editor = LineEditor::new();
+
+    // The line editor works on single characters and does its own echo and Ctrl-C handling.
+    console::tty::for_each_tty(|tty| {
+        tty.set_termios(Termios {
+            canonical: false,
+            echo: false,
+            signals: false,
+            ..tty.termios()
+        })
+    });
+
+    console::console().clear_rx();
+    print!("{}", PROMPT);
+
+    loop {
+        let c = console::console().read_char();
+
+        let names: Vec<&str> = commands().iter().map(|command| command.name).collect();
+        let line = match editor.feed(c, &names, PROMPT, &mut ConsoleWriter) {
+            Ok(Some(line)) => line,
+            _ => continue,
+        };
+
+        execute(&line);
+        print!("{}", PROMPT);
+    }
+}
@@ -0,0 +1,293 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Line editing for the shell.
+//!
+//! Supports backspace, a history that is browsed with the up and down arrow keys, tab completion of
+//! command names and discarding the current line with Ctrl-C. Editing only happens at the end of
+//! the line, so the only control sequence that is emitted is the backspace character, which works
+//! on any terminal.
+
+use alloc::{collections::VecDeque, string::String};
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const HISTORY_SIZE: usize = 16;
+
+const BACKSPACE: char = '\x08';
+const DELETE: char = '\x7f';
+const ESCAPE: char = '\x1b';
+const CTRL_C: char = '\x03';
+
+/// State of the parser for arrow key escape sequences.
+#[derive(Copy, Clone, PartialEq)]
+enum Escape {
+    None,
+    Started,
+    Csi,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An editable line with history.
+pub struct LineEditor {
+    line: String,
+    history: VecDeque<String>,
+
+    /// Index of the history entry that is currently shown, if any.
+    history_pos: Option<usize>,
+
+    /// The line that was being edited before browsing the history started.
+    saved_line: String,
+
+    escape: Escape,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl LineEditor {
+    fn erase(out: &mut dyn fmt::Write, num_chars: usize) -> fmt::Result {
+        for _ in 0..num_chars {
+            write!(out, "{} {}", BACKSPACE, BACKSPACE)?;
+        }
+
+        Ok(())
+    }
+
+    fn replace_line(&mut self, new_line: String, out: &mut dyn fmt::Write) -> fmt::Result {
+        Self::erase(out, self.line.chars().count())?;
+        self.line = new_line;
+
+        out.write_str(&self.line)
+    }
+
+    fn history_up(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let pos = match self.history_pos {
+            None if self.history.is_empty() => return Ok(()),
+            None => {
+                self.saved_line = self.line.clone();
+                self.history.len() - 1
+            }
+            Some(0) => return Ok(()),
+            Some(pos) => pos - 1,
+        };
+
+        self.history_pos = Some(pos);
+        self.replace_line(self.history[pos].clone(), out)
+    }
+
+    fn history_down(&mut self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let new_line = match self.history_pos {
+            None => return Ok(()),
+            Some(pos) if pos + 1 < self.history.len() => {
+                self.history_pos = Some(pos + 1);
+                self.history[pos + 1].clone()
+            }
+            Some(_) => {
+                self.history_pos = None;
+                core::mem::take(&mut self.saved_line)
+            }
+        };
+
+        self.replace_line(new_line, out)
+    }
+
+    fn complete(&mut self, names: &[&str], prompt: &str, out: &mut dyn fmt::Write) -> fmt::Result {
+        // Only the command name is completed.
+        if self.line.contains(' ') {
+            return Ok(());
+        }
+
+        let mut matches = names.iter().filter(|name| name.starts_with(&*self.line));
+        let first = match matches.next() {
+            None => return Ok(()),
+            Some(first) => first,
+        };
+
+        // Longest common prefix of all matches.
+        let mut prefix_len = first.len();
+        let mut num_matches = 1;
+        for name in matches {
+            prefix_len = first
+                .char_indices()
+                .zip(name.chars())
+                .take_while(|((_, a), b)| a == b)
+                .map(|((i, a), _)| i + a.len_utf8())
+                .last()
+                .unwrap_or(0)
+                .min(prefix_len);
+            num_matches += 1;
+        }
+
+        let completion = &first[self.line.len()..prefix_len];
+        self.line.push_str(completion);
+        out.write_str(completion)?;
+
+        if num_matches == 1 {
+            self.line.push(' ');
+            return out.write_char(' ');
+        }
+
+        // Ambiguous and nothing left to complete: List all candidates.
+        if completion.is_empty() {
+            writeln!(out)?;
+            for name in names.iter().filter(|name| name.starts_with(&*self.line)) {
+                write!(out, "{}  ", name)?;
+            }
+            write!(out, "\n{}{}", prompt, self.line)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish_line(&mut self) -> String {
+        let line = core::mem::take(&mut self.line);
+        self.history_pos = None;
+        self.saved_line.clear();
+
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && self.history.back().map(|s| s.as_str()) != Some(trimmed) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(String::from(trimmed));
+        }
+
+        line
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LineEditor {
+    /// Create an instance.
+    pub fn new() -> Self {
+        Self {
+            line: String::new(),
+            history: VecDeque::new(),
+            history_pos: None,
+            saved_line: String::new(),
+            escape: Escape::None,
+        }
+    }
+
+    /// Process an input character.
+    ///
+    /// The echo is written to `out`. `names` are the candidates for tab completion, and `prompt` is
+    /// reprinted if the line needs to be redrawn. Returns the line once it is complete.
+    pub fn feed(
+        &mut self,
+        c: char,
+        names: &[&str],
+        prompt: &str,
+        out: &mut dyn fmt::Write,
+    ) -> Result<Option<String>, fmt::Error> {
+        match (self.escape, c) {
+            (Escape::None, ESCAPE) => self.escape = Escape::Started,
+            (Escape::Started, '[') => self.escape = Escape::Csi,
+            (Escape::Csi, '0'..='9' | ';') => (),
+            (Escape::Csi, _) => {
+                self.escape = Escape::None;
+                match c {
+                    'A' => self.history_up(out)?,
+                    'B' => self.history_down(out)?,
+                    _ => (),
+                }
+            }
+            (Escape::Started, _) => self.escape = Escape::None,
+
+            (Escape::None, '\n') => {
+                writeln!(out)?;
+                return Ok(Some(self.finish_line()));
+            }
+            (Escape::None, BACKSPACE | DELETE) => {
+                if self.line.pop().is_some() {
+                    Self::erase(out, 1)?;
+                }
+            }
+            (Escape::None, '\t') => self.complete(names, prompt, out)?,
+            (Escape::None, CTRL_C) => {
+                self.line.clear();
+                self.history_pos = None;
+                write!(out, "^C\n{}", prompt)?;
+            }
+            (Escape::None, c) if !c.is_control() => {
+                self.line.push(c);
+                out.write_char(c)?;
+            }
+            (Escape::None, _) => (),
+        }
+
+        Ok(None)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use test_macros::kernel_test;
+
+    const NAMES: &[&str] = &["heap", "help", "irqs"];
+
+    fn feed_str(editor: &mut LineEditor, input: &str, out: &mut dyn fmt::Write) -> Vec<String> {
+        input
+            .chars()
+            .filter_map(|c| editor.feed(c, NAMES, "> ", out).unwrap())
+            .collect()
+    }
+
+    /// Backspace removes characters from the line and the terminal.
+    #[kernel_test]
+    fn backspace_edits_line() {
+        let mut editor = LineEditor::new();
+        let mut out = String::new();
+
+        assert_eq!(feed_str(&mut editor, "irqx\x7fs\n", &mut out), ["irqs"]);
+        assert_eq!(out, "irqx\x08 \x08s\n");
+    }
+
+    /// Up and down arrows browse the history and restore the line that was being edited.
+    #[kernel_test]
+    fn history_is_browsable() {
+        let mut editor = LineEditor::new();
+        let mut out = String::new();
+
+        feed_str(&mut editor, "heap\nirqs\nirqs\n", &mut out);
+        assert_eq!(editor.history, ["heap", "irqs"]);
+
+        feed_str(&mut editor, "ab\x1b[A\x1b[A", &mut out);
+        assert_eq!(editor.line, "heap");
+
+        feed_str(&mut editor, "\x1b[B\x1b[B", &mut out);
+        assert_eq!(editor.line, "ab");
+    }
+
+    /// Tab completes unique names and common prefixes.
+    #[kernel_test]
+    fn tab_completes_command_names() {
+        let mut editor = LineEditor::new();
+        let mut out = String::new();
+
+        feed_str(&mut editor, "i\t", &mut out);
+        assert_eq!(editor.line, "irqs ");
+
+        let mut editor = LineEditor::new();
+        feed_str(&mut editor, "h\t", &mut out);
+        assert_eq!(editor.line, "he");
+    }
+}
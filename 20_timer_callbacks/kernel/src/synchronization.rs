@@ -59,6 +59,17 @@ where
     data: UnsafeCell<T>,
 }
 
+/// A pseudo-lock like [`IRQSafeNullLock`], but IRQs stay unmasked while the lock is held.
+///
+/// Intended for data that is never accessed from IRQ context, and whose operations take long, e.g.
+/// block I/O.
+pub struct NullLock<T>
+where
+    T: ?Sized,
+{
+    data: UnsafeCell<T>,
+}
+
 /// A pseudo-lock that is RW during the single-core kernel init phase and RO afterwards.
 ///
 /// Intended to encapsulate data that is populated during kernel init when no concurrency exists.
@@ -85,6 +96,18 @@ impl<T> IRQSafeNullLock<T> {
     }
 }
 
+unsafe impl<T> Send for NullLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for NullLock<T> where T: ?Sized + Send {}
+
+impl<T> NullLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
 unsafe impl<T> Send for InitStateLock<T> where T: ?Sized + Send {}
 unsafe impl<T> Sync for InitStateLock<T> where T: ?Sized + Send {}
 
@@ -115,6 +138,18 @@ impl<T> interface::Mutex for IRQSafeNullLock<T> {
     }
 }
 
+impl<T> interface::Mutex for NullLock<T> {
+    type Data = T;
+
+    fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+        // In a real lock, there would be code encapsulating this line that ensures that this
+        // mutable reference will ever only be given out once at a time.
+        let data = unsafe { &mut *self.data.get() };
+
+        f(data)
+    }
+}
+
 impl<T> interface::ReadWriteEx for InitStateLock<T> {
     type Data = T;
 
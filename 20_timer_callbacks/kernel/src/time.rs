@@ -15,7 +15,7 @@ mod arch_time;
 
 use crate::{
     driver, exception,
-    exception::asynchronous::IRQNumber,
+    exception::asynchronous::{IRQNumber, IRQReturn},
     synchronization::{interface::Mutex, IRQSafeNullLock},
     warn,
 };
@@ -50,6 +50,7 @@ pub type TimeoutCallback = Box<dyn Fn() + Send>;
 /// Provides time management functions.
 pub struct TimeManager {
     queue: IRQSafeNullLock<OrderedTimeoutQueue>,
+    deferred_callbacks: AtomicBool,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -115,6 +116,7 @@ impl TimeManager {
     pub const fn new() -> Self {
         Self {
             queue: IRQSafeNullLock::new(OrderedTimeoutQueue::new()),
+            deferred_callbacks: AtomicBool::new(false),
         }
     }
 
@@ -135,6 +137,15 @@ impl TimeManager {
         arch_time::spin_for(duration)
     }
 
+    /// Select the context in which timeout callbacks are executed.
+    ///
+    /// By default, callbacks run directly in the timer's IRQ handler. If `deferred` is true, they
+    /// are scheduled as [deferred work](exception::asynchronous::deferred_work) instead, which
+    /// means they run with IRQs unmasked and do not add to the latency of other IRQs.
+    pub fn set_deferred_callbacks(&self, deferred: bool) {
+        self.deferred_callbacks.store(deferred, Ordering::Relaxed);
+    }
+
     /// Set a timeout.
     fn set_timeout(&self, timeout: Timeout) {
         self.queue.lock(|queue| {
@@ -144,6 +155,26 @@ impl TimeManager {
         });
     }
 
+    /// Requeue periodic timeouts after their callback was executed and arm the timer again.
+    fn conclude_timeout(&self, timeout: Timeout) {
+        self.queue.lock(|queue| {
+            if timeout.is_periodic() {
+                // There might be some overhead involved in the periodic path, because the timeout
+                // item is first popped from the underlying Vec and then pushed back again. It could
+                // be faster to keep the item in the queue and find a way to work with a reference
+                // to it.
+                //
+                // We are not going this route on purpose, though. It allows to keep the code simple
+                // and the focus on the high-level concepts.
+                queue.push(timeout);
+            };
+
+            if let Some(due_time) = queue.peek_next_due_time() {
+                arch_time::set_timeout_irq(due_time);
+            }
+        });
+    }
+
     /// Set a one-shot timeout.
     pub fn set_timeout_once(&self, delay: Duration, callback: TimeoutCallback) {
         let timeout = Timeout {
@@ -168,6 +199,8 @@ impl TimeManager {
 }
 
 /// Initialize the timer subsystem.
+///
+/// The timer IRQ is read from the device tree, so it must be initialized already.
 pub fn init() -> Result<(), &'static str> {
     static INIT_DONE: AtomicBool = AtomicBool::new(false);
     if INIT_DONE.load(Ordering::Relaxed) {
@@ -175,7 +208,7 @@ pub fn init() -> Result<(), &'static str> {
     }
 
     let timer_descriptor =
-        driver::DeviceDriverDescriptor::new(time_manager(), None, Some(arch_time::timeout_irq()));
+        driver::DeviceDriverDescriptor::new(time_manager(), None, Some(arch_time::timeout_irq()?));
     driver::driver_manager().register_driver(timer_descriptor);
 
     INIT_DONE.store(true, Ordering::Relaxed);
@@ -197,9 +230,10 @@ impl driver::interface::DeviceDriver for TimeManager {
         &'static self,
         irq_number: &Self::IRQNumberType,
     ) -> Result<(), &'static str> {
-        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor, IRQPriority};
 
-        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+        let descriptor =
+            IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, IRQPriority::DEFAULT, self);
 
         irq_manager().register_handler(descriptor)?;
         irq_manager().enable(irq_number);
@@ -209,7 +243,7 @@ impl driver::interface::DeviceDriver for TimeManager {
 }
 
 impl exception::asynchronous::interface::IRQHandler for TimeManager {
-    fn handle(&self) -> Result<(), &'static str> {
+    fn handle(&self) -> Result<IRQReturn, &'static str> {
         arch_time::conclude_timeout_irq();
 
         let maybe_timeout: Option<Timeout> = self.queue.lock(|queue| {
@@ -231,33 +265,34 @@ impl exception::asynchronous::interface::IRQHandler for TimeManager {
         let timeout = match maybe_timeout {
             None => {
                 warn!("Spurious timeout IRQ");
-                return Ok(());
+                return Ok(IRQReturn::NotHandled);
             }
             Some(t) => t,
         };
 
+        if self.deferred_callbacks.load(Ordering::Relaxed) {
+            exception::asynchronous::deferred_work::schedule(Box::new(move || {
+                (timeout.callback)();
+                time_manager().conclude_timeout(timeout);
+            }));
+
+            // Until the callback was executed, the timeout is not part of the queue. Arm the
+            // timer for the remaining timeouts.
+            self.queue.lock(|queue| {
+                if let Some(due_time) = queue.peek_next_due_time() {
+                    arch_time::set_timeout_irq(due_time);
+                }
+            });
+
+            return Ok(IRQReturn::Handled);
+        }
+
         // Important: Call the callback while not holding any lock, because the callback might
         // attempt to modify data that is protected by a lock (in particular, the timeout queue
         // itself).
         (timeout.callback)();
+        self.conclude_timeout(timeout);
 
-        self.queue.lock(|queue| {
-            if timeout.is_periodic() {
-                // There might be some overhead involved in the periodic path, because the timeout
-                // item is first popped from the underlying Vec and then pushed back again. It could
-                // be faster to keep the item in the queue and find a way to work with a reference
-                // to it.
-                //
-                // We are not going this route on purpose, though. It allows to keep the code simple
-                // and the focus on the high-level concepts.
-                queue.push(timeout);
-            };
-
-            if let Some(due_time) = queue.peek_next_due_time() {
-                arch_time::set_timeout_irq(due_time);
-            }
-        });
-
-        Ok(())
+        Ok(IRQReturn::Handled)
     }
 }
@@ -65,3 +65,20 @@ fn local_irq_mask_save_works() {
     exception::asynchronous::local_irq_restore(first);
     assert!(exception::asynchronous::is_local_irq_masked());
 }
+
+/// Check that IRQ unmask save restores the masked state afterwards.
+#[kernel_test]
+fn local_irq_unmask_save_works() {
+    // Precondition: IRQs are masked.
+    exception::asynchronous::local_irq_mask();
+    assert!(!exception::asynchronous::is_local_irq_masked());
+
+    let saved = exception::asynchronous::local_irq_unmask_save();
+    assert!(exception::asynchronous::is_local_irq_masked());
+
+    exception::asynchronous::local_irq_restore(saved);
+    assert!(!exception::asynchronous::is_local_irq_masked());
+
+    // Restore earlier state.
+    exception::asynchronous::local_irq_unmask();
+}
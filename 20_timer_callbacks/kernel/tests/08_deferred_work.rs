@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Deferred IRQ work sanity tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, device_tree, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    device_tree::init().unwrap();
+
+    time::init().unwrap();
+    bsp::driver::init().unwrap();
+    driver::driver_manager().init_drivers_and_irqs();
+
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Check that deferred timer callbacks are executed, and that they run with IRQs unmasked.
+#[kernel_test]
+fn deferred_timer_callback_runs_unmasked() {
+    static CALLBACK_RAN: AtomicBool = AtomicBool::new(false);
+    static IRQS_UNMASKED: AtomicBool = AtomicBool::new(false);
+
+    time::time_manager().set_deferred_callbacks(true);
+    time::time_manager().set_timeout_once(
+        Duration::from_millis(10),
+        Box::new(|| {
+            // Inverted semantics, see `is_local_irq_masked()`.
+            IRQS_UNMASKED.store(
+                exception::asynchronous::is_local_irq_masked(),
+                Ordering::Relaxed,
+            );
+            CALLBACK_RAN.store(true, Ordering::Relaxed);
+        }),
+    );
+
+    time::time_manager().spin_for(Duration::from_millis(100));
+    time::time_manager().set_deferred_callbacks(false);
+
+    assert!(CALLBACK_RAN.load(Ordering::Relaxed));
+    assert!(IRQS_UNMASKED.load(Ordering::Relaxed));
+    assert_eq!(exception::asynchronous::deferred_work::num_pending(), 0);
+}
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ statistics sanity tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::time::Duration;
+use libkernel::{bsp, cpu, device_tree, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    device_tree::init().unwrap();
+
+    time::init().unwrap();
+    bsp::driver::init().unwrap();
+    driver::driver_manager().init_drivers_and_irqs();
+
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Check that a firing timer IRQ is accounted for.
+#[kernel_test]
+fn timer_irq_is_counted() {
+    use bsp::exception::asynchronous::irq_map;
+
+    let timer_irq = irq_map::arm_ns_physical_timer().unwrap();
+    let irq_manager = exception::asynchronous::irq_manager();
+    let before = irq_manager.irq_statistics(&timer_irq);
+
+    time::time_manager().set_timeout_once(
+        Duration::from_millis(10),
+        Box::new(|| time::time_manager().spin_for(Duration::from_millis(1))),
+    );
+    time::time_manager().spin_for(Duration::from_millis(100));
+
+    let after = irq_manager.irq_statistics(&timer_irq);
+
+    assert_eq!(after.fires, before.fires + 1);
+    assert!(after.max_time >= Duration::from_millis(1));
+    assert!(after.total_time >= after.max_time);
+}
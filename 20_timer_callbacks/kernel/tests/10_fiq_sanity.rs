@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! FIQ sanity tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use aarch64_cpu::registers::CNTP_CTL_EL0;
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, device_tree, driver, exception, memory, time};
+use test_macros::kernel_test;
+use tock_registers::interfaces::ReadWriteable;
+
+struct TimerFIQHandler;
+
+static FIQ_HANDLER: TimerFIQHandler = TimerFIQHandler;
+static FIQ_FIRED: AtomicBool = AtomicBool::new(false);
+
+impl exception::asynchronous::interface::IRQHandler for TimerFIQHandler {
+    fn handle(&self) -> Result<exception::asynchronous::IRQReturn, &'static str> {
+        // The level-triggered timer IRQ stays asserted until the timer is masked.
+        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::IMASK::SET);
+        FIQ_FIRED.store(true, Ordering::Relaxed);
+
+        Ok(exception::asynchronous::IRQReturn::Handled)
+    }
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    device_tree::init().unwrap();
+
+    time::init().unwrap();
+    bsp::driver::init().unwrap();
+    driver::driver_manager().init_drivers_and_irqs();
+
+    exception::asynchronous::irq_manager()
+        .set_fiq(
+            &bsp::exception::asynchronous::irq_map::arm_ns_physical_timer().unwrap(),
+            &FIQ_HANDLER,
+        )
+        .unwrap();
+
+    exception::asynchronous::local_irq_unmask();
+    exception::asynchronous::local_fiq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Check that the timer IRQ is delivered through the FIQ vector once routed there.
+#[kernel_test]
+fn timer_fires_as_fiq() {
+    time::time_manager().set_timeout_once(Duration::from_millis(10), Box::new(|| {}));
+    time::time_manager().spin_for(Duration::from_millis(100));
+
+    assert!(FIQ_FIRED.load(Ordering::Relaxed));
+}
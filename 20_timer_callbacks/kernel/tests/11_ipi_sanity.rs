@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! IPI sanity tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, device_tree, driver, exception, memory, time};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    device_tree::init().unwrap();
+
+    time::init().unwrap();
+    bsp::driver::init().unwrap();
+    driver::driver_manager().init_drivers_and_irqs();
+
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Only the boot core is running, so check that it can send a function call IPI to itself.
+#[kernel_test]
+fn call_on_own_core_works() {
+    static CALL_RAN: AtomicBool = AtomicBool::new(false);
+
+    let core = cpu::smp::core_id::<usize>();
+    cpu::smp::call_on_core(core, Box::new(|| CALL_RAN.store(true, Ordering::Relaxed))).unwrap();
+
+    time::time_manager().spin_for(Duration::from_millis(10));
+
+    assert!(CALL_RAN.load(Ordering::Relaxed));
+}
+
+/// IPIs to cores that do not exist must fail.
+#[kernel_test]
+fn invalid_target_core_is_rejected() {
+    assert!(cpu::smp::call_on_core(bsp::cpu::NUM_CORES, Box::new(|| {})).is_err());
+    assert!(exception::asynchronous::irq_manager()
+        .send_ipi(
+            bsp::cpu::NUM_CORES,
+            exception::asynchronous::IPIKind::Reschedule
+        )
+        .is_err());
+}
+
+/// Reschedule and TLB shootdown IPIs are handled without side effects on a correct kernel.
+#[kernel_test]
+fn other_ipi_kinds_are_handled() {
+    use exception::asynchronous::IPIKind;
+
+    let core = cpu::smp::core_id::<usize>();
+    for kind in [IPIKind::Reschedule, IPIKind::TLBShootdown] {
+        exception::asynchronous::irq_manager()
+            .send_ipi(core, kind)
+            .unwrap();
+    }
+
+    time::time_manager().spin_for(Duration::from_millis(10));
+}
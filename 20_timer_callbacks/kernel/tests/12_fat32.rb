@@ -0,0 +1,99 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+require 'English'
+require 'console_io_test'
+
+SD_IMAGE = 'target/sd.img'
+PARTITION_OFFSET = 2048 * 512
+MTOOLS_IMAGE = "#{SD_IMAGE}@@#{PARTITION_OFFSET}"
+
+# Return the contents of a file in the FAT32 partition of the SD card image.
+def mtype(path)
+    contents = IO.popen({ 'MTOOLS_SKIP_CHECK' => '1' }, ['mtype', '-i', MTOOLS_IMAGE, "::#{path}"],
+                        &:read)
+    raise "mtype failed for #{path}" unless $CHILD_STATUS.success?
+
+    contents
+end
+
+# Verify that the partition is mounted.
+class MountTest < SubtestBase
+    def name
+        'Mount FAT32 partition'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Mounted MINGO')
+    end
+end
+
+# Verify that files created by mkfs.fat and mtools are read correctly.
+class ReadTest < SubtestBase
+    def name
+        'Read files with short and long names'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Read config')
+        expect_or_raise(qemu_out, 'Read long file')
+    end
+end
+
+# Verify that the kernel finishes writing.
+class WriteTest < SubtestBase
+    def name
+        'Create, append and truncate files'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expect_or_raise(qemu_out, 'Writes done', 10)
+    end
+end
+
+# Verify the written files with mtools. Depends on the previous test being run first.
+class MtoolsTest < SubtestBase
+    def name
+        'Written files are read back by mtools'
+    end
+
+    def expect_contents(path, expected)
+        raise "Unexpected contents of #{path}" unless mtype(path) == expected
+    end
+
+    def run(_qemu_out, _qemu_in)
+        expect_contents('/logs/Crash log.txt', (0...200).map { |i| "Crash log line #{i}\n" }.join)
+        expect_contents('/logs/TRUNC.TXT', 'x' * 100)
+        expect_contents('/config.txt', "arm_64bit=1\nkernel=kernel8.img\nenable_uart=1\n")
+    end
+end
+
+# Verify that all changes to the FAT were mirrored.
+class FatMirrorTest < SubtestBase
+    def name
+        'FAT copies are identical'
+    end
+
+    def run(_qemu_out, _qemu_in)
+        File.open(SD_IMAGE, 'rb') do |file|
+            file.seek(PARTITION_OFFSET)
+            boot = file.read(512)
+            bytes_per_sector, _, reserved, num_fats = boot[11, 6].unpack('vCvC')
+            fat_size = boot[36, 4].unpack1('V') * bytes_per_sector
+
+            file.seek(PARTITION_OFFSET + (reserved * bytes_per_sector))
+            fats = Array.new(num_fats) { file.read(fat_size) }
+            raise 'FAT copies differ' unless fats.uniq.length == 1
+        end
+    end
+end
+
+## -------------------------------------------------------------------------------------------------
+## Test registration
+## -------------------------------------------------------------------------------------------------
+def subtest_collection
+    [MountTest.new, ReadTest.new, WriteTest.new, MtoolsTest.new, FatMirrorTest.new]
+end
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! FAT32 tests, run against the SD card image created by `make sd_image`.
+//!
+//! The files written here are checked with mtools by the I/O harness.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+extern crate alloc;
+
+/// Console tests should time out on the I/O harness in case of panic.
+mod panic_wait_forever;
+
+use alloc::{format, string::String, vec};
+use libkernel::{
+    block, bsp, cpu, device_tree, driver, exception, fs::fat32::Fat32, memory, println, time,
+};
+
+const CRASH_LOG: &str = "/logs/Crash log.txt";
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    device_tree::init().unwrap();
+
+    time::init().unwrap();
+    bsp::driver::init().unwrap();
+    driver::driver_manager().init_drivers_and_irqs();
+
+    // The image has an MBR with a single FAT32 partition.
+    let fs = Fat32::mount(block::block_device("sd0p1").unwrap()).unwrap();
+    println!("Mounted {}", fs.label());
+
+    // Names are matched case-insensitively.
+    let mut buffer = [0; 64];
+    let n = fs.read("/CONFIG.TXT", 0, &mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"arm_64bit=1\nkernel=kernel8.img\n");
+    println!("Read config");
+
+    // Long names, and a file that spans many clusters.
+    let path = "/Directory with a long name/A file with a long name.txt";
+    let expected: String = (0..100)
+        .map(|i| {
+            format!(
+                "Line {:04}: The quick brown fox jumps over the lazy dog\n",
+                i
+            )
+        })
+        .collect();
+    let mut data = vec![0; fs.stat(path).unwrap().size() as usize];
+    assert_eq!(fs.read(path, 0, &mut data).unwrap(), data.len());
+    assert_eq!(data, expected.as_bytes());
+    println!("Read long file");
+
+    fs.create_dir("/logs").unwrap();
+    fs.create_file(CRASH_LOG).unwrap();
+    for i in 0..200 {
+        fs.append(CRASH_LOG, format!("Crash log line {}\n", i).as_bytes())
+            .unwrap();
+    }
+
+    fs.create_file("/logs/TRUNC.TXT").unwrap();
+    fs.write("/logs/TRUNC.TXT", 0, &[b'x'; 1500]).unwrap();
+    fs.truncate("/logs/TRUNC.TXT", 100).unwrap();
+
+    fs.append("/config.txt", b"enable_uart=1\n").unwrap();
+    println!("Writes done");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    cpu::wait_forever();
+}
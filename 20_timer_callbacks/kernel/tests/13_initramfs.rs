@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Initramfs tests, run against the archive that the Makefile packs from the `initramfs` directory.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use libkernel::{bsp, cpu, device_tree, driver, exception, fs, memory, time};
+use test_macros::kernel_test;
+
+const MOTD: &[u8] = include_bytes!("../../initramfs/etc/motd");
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    exception::handling_init();
+    memory::init();
+    device_tree::init().unwrap();
+
+    time::init().unwrap();
+    bsp::driver::init().unwrap();
+    driver::driver_manager().init_drivers_and_irqs();
+
+    fs::init().unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The embedded archive is mounted and its directories are listed.
+#[kernel_test]
+fn initramfs_is_listed() {
+    let names: Vec<_> = fs::read_dir("/")
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+    assert!(names.iter().any(|name| name == "initramfs"));
+
+    let entries = fs::read_dir("/initramfs/etc").unwrap();
+    let motd = entries.iter().find(|e| e.name == "motd").unwrap();
+    assert_eq!(motd.metadata.file_type, fs::FileType::File);
+    assert_eq!(motd.metadata.size, MOTD.len() as u64);
+}
+
+/// A known file is read completely, and cannot be written.
+#[kernel_test]
+fn initramfs_file_is_read() {
+    let fd = fs::open("/initramfs/etc/motd", fs::OpenFlags::READ).unwrap();
+    let mut data = Vec::new();
+    let mut buffer = [0; 8];
+    loop {
+        match fs::read(fd, &mut buffer).unwrap() {
+            0 => break,
+            n => data.extend_from_slice(&buffer[..n]),
+        }
+    }
+    fs::close(fd).unwrap();
+    assert_eq!(data, MOTD);
+
+    let fd = fs::open("/initramfs/etc/motd", fs::OpenFlags::WRITE).unwrap();
+    assert!(fs::write(fd, b"x").is_err());
+    fs::close(fd).unwrap();
+    assert!(fs::open("/initramfs/etc/new", fs::OpenFlags::CREATE).is_err());
+}
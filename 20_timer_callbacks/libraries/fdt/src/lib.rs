@@ -0,0 +1,1002 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Flattened device tree parser.
+//!
+//! Parses the device tree blob (DTB) that the firmware passes to the kernel, as specified by the
+//! [Devicetree Specification](https://www.devicetree.org/specifications/). The parser does not
+//! allocate. Names, strings and property values are returned as slices of the blob.
+//!
+//! [`Fdt::new()`] validates the complete blob, so that walking the tree afterwards cannot fail on
+//! malformed data.
+//!
+//! Kept in a separate crate so that it can be tested on the host with `cargo test`, against the
+//! device trees of the supported boards in the top-level `devicetree` directory.
+
+#![no_std]
+
+use core::str;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MAGIC: u32 = 0xd00d_feed;
+
+/// Version 17 is the first one with the size of the structure block in the header.
+const MIN_VERSION: u32 = 17;
+
+const TOKEN_BEGIN_NODE: u32 = 0x1;
+const TOKEN_END_NODE: u32 = 0x2;
+const TOKEN_PROP: u32 = 0x3;
+const TOKEN_NOP: u32 = 0x4;
+const TOKEN_END: u32 = 0x9;
+
+/// Maximum nesting depth of nodes. Looking up the parent of a node needs a stack of this size.
+const MAX_DEPTH: usize = 16;
+
+/// Defaults if a node has no `#address-cells` or `#size-cells` property.
+const DEFAULT_ADDRESS_CELLS: u32 = 2;
+const DEFAULT_SIZE_CELLS: u32 = 1;
+
+#[derive(Copy, Clone)]
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(Property<'a>),
+    End,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the header at the start of the blob.
+pub const HEADER_SIZE: usize = 40;
+
+/// A validated device tree blob.
+#[derive(Copy, Clone)]
+pub struct Fdt<'a> {
+    structure: &'a [u8],
+    strings: &'a [u8],
+    reservations: &'a [u8],
+    total_size: usize,
+    boot_cpuid: u32,
+}
+
+/// A node of the device tree.
+#[derive(Copy, Clone)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+
+    /// Offset of the node's begin token in the structure block.
+    offset: usize,
+
+    /// Offset of the first token after the node's name.
+    body: usize,
+}
+
+/// A property of a node.
+#[derive(Copy, Clone)]
+pub struct Property<'a> {
+    /// The name, e.g. `compatible`.
+    pub name: &'a str,
+
+    /// The raw value.
+    pub value: &'a [u8],
+}
+
+/// A region of the physical address space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// The start address.
+    pub address: u64,
+
+    /// The size in bytes.
+    pub size: u64,
+}
+
+/// An interrupt of a device.
+#[derive(Copy, Clone)]
+pub struct Interrupt<'a> {
+    /// The interrupt controller, which interprets the specifier.
+    pub controller: Node<'a>,
+
+    specifier: &'a [u8],
+}
+
+/// Iterates over the properties of a node.
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    pos: usize,
+}
+
+/// Iterates over the children of a node.
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    pos: usize,
+}
+
+/// Iterates over all nodes of the tree, depth first.
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    pos: usize,
+}
+
+/// Iterates over the strings of a string list property, like `compatible`.
+#[derive(Clone)]
+pub struct Strings<'a> {
+    rest: &'a [u8],
+}
+
+/// Iterates over the regions of a `reg` property, translated to physical addresses.
+pub struct Reg<'a> {
+    bus: Option<Node<'a>>,
+    address_cells: u32,
+    size_cells: u32,
+    rest: &'a [u8],
+}
+
+/// Iterates over the interrupts of a node.
+pub struct Interrupts<'a> {
+    controller: Option<Node<'a>>,
+    cells: usize,
+    rest: &'a [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn be64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Read a number of `cells` 32 bit cells. Numbers wider than 64 bits are not supported.
+fn read_cells(data: &[u8], cells: u32) -> Option<u64> {
+    match cells {
+        0 => Some(0),
+        1 => be32(data, 0).map(u64::from),
+        2 => be64(data, 0),
+        _ => None,
+    }
+}
+
+/// Return the NUL-terminated string at `offset`.
+fn c_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+
+    str::from_utf8(&bytes[..len]).ok()
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+impl<'a> Fdt<'a> {
+    /// Parse the token at `pos`. Returns the token and the position of the next one.
+    fn token(&self, mut pos: usize) -> Option<(Token<'a>, usize)> {
+        loop {
+            let token = be32(self.structure, pos)?;
+            pos += 4;
+
+            let token = match token {
+                TOKEN_BEGIN_NODE => {
+                    let name = c_str(self.structure, pos)?;
+                    pos = align4(pos + name.len() + 1);
+
+                    Token::BeginNode(name)
+                }
+                TOKEN_END_NODE => Token::EndNode,
+                TOKEN_PROP => {
+                    let len = be32(self.structure, pos)? as usize;
+                    let name_offset = be32(self.structure, pos + 4)? as usize;
+                    let value_start = pos + 8;
+                    let value = self
+                        .structure
+                        .get(value_start..value_start.checked_add(len)?)?;
+                    pos = align4(value_start + len);
+
+                    Token::Prop(Property {
+                        name: c_str(self.strings, name_offset)?,
+                        value,
+                    })
+                }
+                TOKEN_NOP => continue,
+                TOKEN_END => Token::End,
+                _ => return None,
+            };
+
+            return Some((token, pos));
+        }
+    }
+
+    /// Return the node whose begin token is at `offset`.
+    fn node_at(&self, offset: usize) -> Option<Node<'a>> {
+        match self.token(offset)? {
+            (Token::BeginNode(name), body) => Some(Node {
+                fdt: *self,
+                name,
+                offset,
+                body,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Return the position after the end token of the node whose contents start at `pos`.
+    fn skip_node(&self, mut pos: usize) -> Option<usize> {
+        let mut depth = 1;
+
+        while depth > 0 {
+            let (token, next) = self.token(pos)?;
+
+            match token {
+                Token::BeginNode(_) => depth += 1,
+                Token::EndNode => depth -= 1,
+                Token::Prop(_) => (),
+                Token::End => return None,
+            }
+            pos = next;
+        }
+
+        Some(pos)
+    }
+
+    /// Walk the whole structure block, checking the nesting of nodes.
+    fn check_structure(&self) -> Result<(), &'static str> {
+        let mut pos = 0;
+        let mut depth = 0;
+
+        loop {
+            let (token, next) = self.token(pos).ok_or("Malformed device tree structure")?;
+
+            match token {
+                Token::BeginNode(name) => {
+                    if pos == 0 && !name.is_empty() {
+                        return Err("Device tree root node has a name");
+                    }
+                    if pos != 0 && depth == 0 {
+                        return Err("Device tree has more than one root node");
+                    }
+
+                    depth += 1;
+                    if depth > MAX_DEPTH {
+                        return Err("Device tree nodes are nested too deeply");
+                    }
+                }
+                Token::EndNode if depth > 0 => depth -= 1,
+                Token::EndNode => return Err("Unbalanced device tree node end"),
+                Token::Prop(_) if depth > 0 => (),
+                Token::Prop(_) => return Err("Device tree property outside of a node"),
+                Token::End if depth == 0 && pos != 0 => return Ok(()),
+                Token::End => return Err("Device tree structure ends inside a node"),
+            }
+
+            pos = next;
+        }
+    }
+
+    fn check_reservations(&self) -> Result<(), &'static str> {
+        for offset in (0..).step_by(16) {
+            let address = be64(self.reservations, offset);
+            let size = be64(self.reservations, offset + 8);
+
+            match (address, size) {
+                (Some(0), Some(0)) => return Ok(()),
+                (Some(_), Some(_)) => (),
+                _ => return Err("Unterminated memory reservation block"),
+            }
+        }
+
+        unreachable!()
+    }
+
+    /// Return the parent of the node whose begin token is at `offset`.
+    fn parent_of(&self, offset: usize) -> Option<Node<'a>> {
+        let mut stack = [0; MAX_DEPTH];
+        let mut depth = 0;
+        let mut pos = 0;
+
+        loop {
+            let (token, next) = self.token(pos)?;
+
+            match token {
+                Token::BeginNode(_) if pos == offset => {
+                    return match depth {
+                        0 => None,
+                        _ => self.node_at(stack[depth - 1]),
+                    };
+                }
+                Token::BeginNode(_) => {
+                    *stack.get_mut(depth)? = pos;
+                    depth += 1;
+                }
+                Token::EndNode => depth = depth.checked_sub(1)?,
+                Token::Prop(_) => (),
+                Token::End => return None,
+            }
+
+            pos = next;
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    fn property_u32(&self, name: &str) -> Option<u32> {
+        self.property(name)?.as_u32()
+    }
+
+    /// Translate `address` from the address space of `bus`, i.e. of the bus's children, to the
+    /// physical address space, following the `ranges` properties up to the root.
+    fn translate(mut bus: Node<'a>, mut address: u64) -> Result<u64, &'static str> {
+        loop {
+            let parent = match bus.parent() {
+                None => return Ok(address),
+                Some(parent) => parent,
+            };
+
+            let ranges = bus
+                .property("ranges")
+                .ok_or("Address of a bus without ranges")?;
+
+            // An empty ranges property means identical address spaces.
+            if !ranges.value.is_empty() {
+                let child_cells = bus.address_cells();
+                let parent_cells = parent.address_cells();
+                let size_cells = bus.size_cells();
+                let entry_size = (child_cells + parent_cells + size_cells) as usize * 4;
+
+                address = ranges
+                    .value
+                    .chunks_exact(entry_size)
+                    .find_map(|entry| {
+                        let child = read_cells(entry, child_cells)?;
+                        let parent = read_cells(&entry[child_cells as usize * 4..], parent_cells)?;
+                        let size = read_cells(
+                            &entry[(child_cells + parent_cells) as usize * 4..],
+                            size_cells,
+                        )?;
+
+                        match address.checked_sub(child)? < size {
+                            true => parent.checked_add(address - child),
+                            false => None,
+                        }
+                    })
+                    .ok_or("Address outside of the bus ranges")?;
+            }
+
+            bus = parent;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the size of the blob from its header, e.g. to map the rest of it.
+pub fn blob_size(header: &[u8]) -> Result<usize, &'static str> {
+    if be32(header, 0).ok_or("Device tree header too short")? != MAGIC {
+        return Err("Invalid device tree magic");
+    }
+
+    Ok(be32(header, 4).ok_or("Device tree header too short")? as usize)
+}
+
+impl<'a> Fdt<'a> {
+    /// Validate the blob at the start of `data` and create an instance for it.
+    ///
+    /// `data` may extend beyond the end of the blob.
+    pub fn new(data: &'a [u8]) -> Result<Self, &'static str> {
+        let total_size = blob_size(data)?;
+        let blob = data
+            .get(..total_size)
+            .ok_or("Device tree blob is truncated")?;
+        if total_size < HEADER_SIZE {
+            return Err("Device tree header too short");
+        }
+
+        let field = |index: usize| be32(blob, index * 4).unwrap_or(0) as usize;
+        let (off_structure, off_strings, off_reservations) = (field(2), field(3), field(4));
+        let (version, last_compatible_version) = (field(5) as u32, field(6) as u32);
+        let (size_strings, size_structure) = (field(8), field(9));
+
+        if version < MIN_VERSION || last_compatible_version > MIN_VERSION {
+            return Err("Unsupported device tree version");
+        }
+        if off_structure % 4 != 0 || off_reservations % 8 != 0 {
+            return Err("Misaligned device tree block");
+        }
+
+        let block = |offset: usize, size: usize| {
+            offset
+                .checked_add(size)
+                .and_then(|end| blob.get(offset..end))
+                .ok_or("Device tree block out of bounds")
+        };
+
+        let fdt = Self {
+            structure: block(off_structure, size_structure)?,
+            strings: block(off_strings, size_strings)?,
+            reservations: block(
+                off_reservations,
+                total_size.saturating_sub(off_reservations),
+            )?,
+            total_size,
+            boot_cpuid: field(7) as u32,
+        };
+
+        fdt.check_reservations()?;
+        fdt.check_structure()?;
+
+        Ok(fdt)
+    }
+
+    /// Return the size of the blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    /// Return the ID of the boot CPU, as in the `reg` property of its node.
+    pub fn boot_cpuid(&self) -> u32 {
+        self.boot_cpuid
+    }
+
+    /// Return the root node.
+    pub fn root(&self) -> Node<'a> {
+        // The structure block was checked to start with the root node.
+        self.node_at(0).unwrap()
+    }
+
+    /// Return all nodes, depth first, starting with the root node.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes { fdt: *self, pos: 0 }
+    }
+
+    /// Return the node at `path`, e.g. `/soc/serial@7e201000`.
+    ///
+    /// Components without a unit address also match nodes with one, e.g. `/soc/serial` matches the
+    /// first `serial@...` node. Paths that do not start with `/` are looked up in `/aliases`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let path = match path.starts_with('/') {
+            true => path,
+            false => {
+                let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+                let node = self.find_node("/aliases")?.property(alias)?.as_str()?;
+
+                return match rest {
+                    "" => self.find_node(node),
+                    rest => self.find_node(node)?.find_child_path(rest),
+                };
+            }
+        };
+
+        self.root().find_child_path(path)
+    }
+
+    /// Return the node with the given `phandle`, which other nodes use to refer to it.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.nodes().find(|node| {
+            let value = node
+                .property_u32("phandle")
+                .or_else(|| node.property_u32("linux,phandle"));
+
+            value == Some(phandle)
+        })
+    }
+
+    /// Return the nodes that are compatible with `compatible`.
+    pub fn find_compatible<'b>(&self, compatible: &'b str) -> impl Iterator<Item = Node<'a>> + 'b
+    where
+        'a: 'b,
+    {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+
+    /// Return the board model from the root node, e.g. `Raspberry Pi 3 Model B Rev 1.2`.
+    pub fn model(&self) -> Option<&'a str> {
+        self.root().property("model")?.as_str()
+    }
+
+    /// Return the regions of main memory, from the `reg` properties of the `/memory` nodes.
+    pub fn memory(&self) -> impl Iterator<Item = Result<Region, &'static str>> + 'a {
+        self.root()
+            .children()
+            .filter(|node| {
+                let device_type = node.property("device_type").and_then(|p| p.as_str());
+
+                device_type == Some("memory")
+            })
+            .flat_map(|node| node.reg())
+    }
+
+    /// Return the memory regions that are reserved for the firmware.
+    pub fn memory_reservations(&self) -> impl Iterator<Item = Region> + 'a {
+        let reservations = self.reservations;
+
+        (0..)
+            .step_by(16)
+            .map(move |offset| Region {
+                address: be64(reservations, offset).unwrap_or(0),
+                size: be64(reservations, offset + 8).unwrap_or(0),
+            })
+            .take_while(|region| {
+                *region
+                    != Region {
+                        address: 0,
+                        size: 0,
+                    }
+            })
+    }
+
+    /// Return the kernel command line from `/chosen`.
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.find_node("/chosen")?.property("bootargs")?.as_str()
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Return the name including the unit address, e.g. `serial@7e201000`. Empty for the root.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Return the parent node, or `None` for the root.
+    pub fn parent(&self) -> Option<Node<'a>> {
+        self.fdt.parent_of(self.offset)
+    }
+
+    /// Return the properties.
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            pos: self.body,
+        }
+    }
+
+    /// Return the property `name`.
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|p| p.name == name)
+    }
+
+    /// Return the child nodes.
+    pub fn children(&self) -> Children<'a> {
+        Children {
+            fdt: self.fdt,
+            pos: self.body,
+        }
+    }
+
+    /// Return the node at the relative `path` below this node.
+    pub fn find_child_path(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = *self;
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| {
+                child.name == component
+                    || (!component.contains('@') && child.name.split('@').next() == Some(component))
+            })?;
+        }
+
+        Some(node)
+    }
+
+    /// Return the entries of the `compatible` property, most specific first.
+    pub fn compatible(&self) -> Strings<'a> {
+        match self.property("compatible") {
+            Some(property) => property.strings(),
+            None => Strings { rest: &[] },
+        }
+    }
+
+    /// Return whether `compatible` is one of the entries of the `compatible` property.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// Return whether the device is enabled. Nodes without a `status` property are.
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").and_then(|p| p.as_str()) {
+            None | Some("okay") | Some("ok") => true,
+            Some(_) => false,
+        }
+    }
+
+    /// Return the number of cells of addresses in the `reg` properties of the children.
+    pub fn address_cells(&self) -> u32 {
+        self.property_u32("#address-cells")
+            .unwrap_or(DEFAULT_ADDRESS_CELLS)
+    }
+
+    /// Return the number of cells of sizes in the `reg` properties of the children.
+    pub fn size_cells(&self) -> u32 {
+        self.property_u32("#size-cells")
+            .unwrap_or(DEFAULT_SIZE_CELLS)
+    }
+
+    /// Return the regions of the `reg` property, translated to physical addresses.
+    ///
+    /// Empty if there is no `reg` property.
+    pub fn reg(&self) -> Reg<'a> {
+        let bus = self.parent();
+        let (address_cells, size_cells) = match bus {
+            Some(bus) => (bus.address_cells(), bus.size_cells()),
+            None => (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS),
+        };
+
+        Reg {
+            bus,
+            address_cells,
+            size_cells,
+            rest: self.property("reg").map_or(&[], |p| p.value),
+        }
+    }
+
+    /// Return the regions of the `ranges` property, i.e. where the bus maps the address space of
+    /// its children, translated to physical addresses.
+    ///
+    /// Empty if there is no `ranges` property, or if the address spaces are identical.
+    pub fn ranges(&self) -> impl Iterator<Item = Result<Region, &'static str>> + 'a {
+        let parent = self.parent();
+        let child_cells = self.address_cells() as usize;
+        let parent_cells = parent.map_or(DEFAULT_ADDRESS_CELLS, |p| p.address_cells());
+        let size_cells = self.size_cells();
+        let entry_size = (child_cells + parent_cells as usize + size_cells as usize) * 4;
+
+        self.property("ranges")
+            .map_or(&[][..], |p| p.value)
+            .chunks(entry_size.max(1))
+            .map(move |entry| {
+                if entry.len() != entry_size {
+                    return Err("Ranges do not match #address-cells and #size-cells");
+                }
+
+                let address = read_cells(&entry[child_cells * 4..], parent_cells);
+                let size = read_cells(&entry[entry_size - size_cells as usize * 4..], size_cells);
+                let (address, size) = match (address, size) {
+                    (Some(address), Some(size)) => (address, size),
+                    _ => return Err("Unsupported number of address or size cells"),
+                };
+
+                let address = match parent {
+                    Some(parent) => Node::translate(parent, address)?,
+                    None => address,
+                };
+
+                Ok(Region { address, size })
+            })
+    }
+
+    /// Return the node that the first cell of `property` refers to by phandle, e.g. the clock
+    /// provider of `clocks`.
+    pub fn referenced_node(&self, property: &str) -> Option<Node<'a>> {
+        let phandle = self.property(property)?.cells().next()?;
+
+        self.fdt.find_phandle(phandle)
+    }
+
+    /// Return the interrupt controller of the device, from the `interrupt-parent` property of the
+    /// node or its closest ancestor with one.
+    pub fn interrupt_parent(&self) -> Option<Node<'a>> {
+        let mut node = *self;
+
+        loop {
+            if let Some(phandle) = node.property_u32("interrupt-parent") {
+                return self.fdt.find_phandle(phandle);
+            }
+
+            node = node.parent()?;
+        }
+    }
+
+    /// Return the interrupts of the `interrupts` property.
+    ///
+    /// Empty if there is no `interrupts` property. Fails if the interrupt controller cannot be
+    /// found, or if the property does not fit its `#interrupt-cells`.
+    pub fn interrupts(&self) -> Result<Interrupts<'a>, &'static str> {
+        let property = match self.property("interrupts") {
+            None => {
+                return Ok(Interrupts {
+                    controller: None,
+                    cells: 1,
+                    rest: &[],
+                })
+            }
+            Some(property) => property,
+        };
+
+        let controller = self
+            .interrupt_parent()
+            .ok_or("Interrupt controller not found")?;
+        let cells = controller
+            .property_u32("#interrupt-cells")
+            .ok_or("Interrupt controller without #interrupt-cells")? as usize;
+
+        if cells == 0 || property.value.len() % (cells * 4) != 0 {
+            return Err("Interrupts do not match #interrupt-cells");
+        }
+
+        Ok(Interrupts {
+            controller: Some(controller),
+            cells,
+            rest: property.value,
+        })
+    }
+}
+
+impl<'a> Property<'a> {
+    /// Return the value as a single 32 bit cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => be32(self.value, 0),
+            _ => None,
+        }
+    }
+
+    /// Return the value as one or two 32 bit cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 => be32(self.value, 0).map(u64::from),
+            8 => be64(self.value, 0),
+            _ => None,
+        }
+    }
+
+    /// Return the value as a NUL-terminated string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self.value {
+            [string @ .., 0] => str::from_utf8(string).ok(),
+            _ => None,
+        }
+    }
+
+    /// Return the value as a list of NUL-terminated strings.
+    pub fn strings(&self) -> Strings<'a> {
+        Strings { rest: self.value }
+    }
+
+    /// Return the value as a list of 32 bit cells.
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+    }
+}
+
+impl<'a> Interrupt<'a> {
+    /// Return the number of cells of the specifier.
+    pub fn len(&self) -> usize {
+        self.specifier.len() / 4
+    }
+
+    /// Return whether the specifier has no cells.
+    pub fn is_empty(&self) -> bool {
+        self.specifier.is_empty()
+    }
+
+    /// Return cell `index` of the specifier. Its meaning depends on the interrupt controller.
+    pub fn cell(&self, index: usize) -> Option<u32> {
+        be32(self.specifier, index.checked_mul(4)?)
+    }
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Properties precede the child nodes.
+        match self.fdt.token(self.pos)? {
+            (Token::Prop(property), next) => {
+                self.pos = next;
+                Some(property)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next) = self.fdt.token(self.pos)?;
+
+            match token {
+                Token::Prop(_) => self.pos = next,
+                Token::BeginNode(_) => {
+                    let child = self.fdt.node_at(self.pos);
+                    self.pos = self.fdt.skip_node(next)?;
+
+                    return child;
+                }
+                Token::EndNode | Token::End => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next) = self.fdt.token(self.pos)?;
+            let pos = self.pos;
+            self.pos = next;
+
+            match token {
+                Token::BeginNode(_) => return self.fdt.node_at(pos),
+                Token::EndNode | Token::Prop(_) => (),
+                Token::End => return None,
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Strings<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let len = self.rest.iter().position(|&b| b == 0)?;
+            let string = &self.rest[..len];
+            self.rest = &self.rest[len + 1..];
+
+            // Skip strings that are not valid UTF-8.
+            if let Ok(string) = str::from_utf8(string) {
+                return Some(string);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = Result<Region, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let entry_size = (self.address_cells + self.size_cells) as usize * 4;
+        let entry = match self.rest.get(..entry_size) {
+            Some(entry) => entry,
+            None => {
+                self.rest = &[];
+                return Some(Err("Reg does not match #address-cells and #size-cells"));
+            }
+        };
+        self.rest = &self.rest[entry_size..];
+
+        let address = read_cells(entry, self.address_cells);
+        let size = read_cells(&entry[self.address_cells as usize * 4..], self.size_cells);
+        let (address, size) = match (address, size) {
+            (Some(address), Some(size)) => (address, size),
+            _ => return Some(Err("Unsupported number of address or size cells")),
+        };
+
+        let address = match self.bus {
+            Some(bus) => Node::translate(bus, address),
+            None => Ok(address),
+        };
+
+        Some(address.map(|address| Region { address, size }))
+    }
+}
+
+impl<'a> Iterator for Interrupts<'a> {
+    type Item = Interrupt<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let controller = self.controller?;
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let (specifier, rest) = self.rest.split_at(self.cells * 4);
+        self.rest = rest;
+
+        Some(Interrupt {
+            controller,
+            specifier,
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A blob with an empty root node, built by hand.
+    const MINIMAL: [u8; 72] = {
+        let mut blob = [0; 72];
+        let words: [u32; 18] = [
+            MAGIC,
+            72,
+            56,
+            72,
+            40,
+            17,
+            16,
+            0,
+            0,
+            16, // Header.
+            0,
+            0,
+            0,
+            0, // Memory reservation terminator.
+            TOKEN_BEGIN_NODE,
+            0,
+            TOKEN_END_NODE,
+            TOKEN_END, // Structure.
+        ];
+
+        let mut i = 0;
+        while i < words.len() {
+            let bytes = words[i].to_be_bytes();
+            blob[i * 4] = bytes[0];
+            blob[i * 4 + 1] = bytes[1];
+            blob[i * 4 + 2] = bytes[2];
+            blob[i * 4 + 3] = bytes[3];
+            i += 1;
+        }
+
+        blob
+    };
+
+    /// The smallest valid blob is accepted.
+    #[test]
+    fn minimal_blob_is_valid() {
+        let fdt = Fdt::new(&MINIMAL).unwrap();
+
+        assert_eq!(fdt.total_size(), 72);
+        assert_eq!(fdt.root().name(), "");
+        assert_eq!(fdt.nodes().count(), 1);
+        assert_eq!(fdt.memory_reservations().count(), 0);
+        assert!(fdt.root().parent().is_none());
+        assert!(fdt.model().is_none());
+    }
+
+    /// Corrupt headers and structure blocks are rejected.
+    #[test]
+    fn corrupt_blobs_are_rejected() {
+        assert!(Fdt::new(&MINIMAL[..40]).is_err());
+
+        let mut blob = MINIMAL;
+        blob[0] = 0;
+        assert!(Fdt::new(&blob).is_err());
+
+        // Version 16.
+        let mut blob = MINIMAL;
+        blob[23] = 16;
+        assert!(Fdt::new(&blob).is_err());
+
+        // Missing end token.
+        let mut blob = MINIMAL;
+        blob[71] = TOKEN_END_NODE as u8;
+        assert!(Fdt::new(&blob).is_err());
+
+        // Structure block out of bounds.
+        let mut blob = MINIMAL;
+        blob[39] = 17;
+        assert!(Fdt::new(&blob).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Parse the device trees of the supported boards.
+
+use fdt::{Fdt, Node, Region};
+
+const RPI3: &[u8] = include_bytes!("../../../devicetree/rpi3b.dtb");
+const RPI4: &[u8] = include_bytes!("../../../devicetree/rpi4b.dtb");
+
+fn regions(node: &Node) -> Vec<Region> {
+    node.reg().collect::<Result<_, _>>().unwrap()
+}
+
+/// Return the interrupts of `node` as (controller, specifier) pairs.
+fn interrupts(node: &Node) -> Vec<(String, Vec<u32>)> {
+    node.interrupts()
+        .unwrap()
+        .map(|irq| {
+            let cells = (0..irq.len()).map(|i| irq.cell(i).unwrap()).collect();
+
+            (irq.controller.name().to_string(), cells)
+        })
+        .collect()
+}
+
+fn region(address: u64, size: u64) -> Region {
+    Region { address, size }
+}
+
+/// The blobs are valid and have the expected board information.
+#[test]
+fn board_information() {
+    let rpi3 = Fdt::new(RPI3).unwrap();
+    let rpi4 = Fdt::new(RPI4).unwrap();
+
+    assert_eq!(rpi3.total_size(), RPI3.len());
+    assert_eq!(rpi3.model(), Some("Raspberry Pi 3 Model B Rev 1.2"));
+    assert!(rpi3.root().is_compatible("brcm,bcm2837"));
+    assert_eq!(rpi4.model(), Some("Raspberry Pi 4 Model B Rev 1.4"));
+    assert_eq!(
+        rpi4.root().compatible().collect::<Vec<_>>(),
+        ["raspberrypi,4-model-b", "brcm,bcm2711"]
+    );
+
+    for fdt in [rpi3, rpi4] {
+        assert!(fdt.bootargs().unwrap().contains("console=ttyS0,115200"));
+        assert_eq!(
+            fdt.memory_reservations().collect::<Vec<_>>(),
+            [region(0, 0x1000)]
+        );
+        assert_eq!(fdt.find_node("/cpus").unwrap().children().count(), 4);
+    }
+}
+
+/// Main memory is read from the `/memory` nodes, with the root's cell sizes.
+#[test]
+fn memory() {
+    let rpi3 = Fdt::new(RPI3).unwrap();
+    let rpi4 = Fdt::new(RPI4).unwrap();
+
+    assert_eq!(
+        rpi3.memory().collect::<Result<Vec<_>, _>>().unwrap(),
+        [region(0, 0x3b40_0000)]
+    );
+    assert_eq!(
+        rpi4.memory().collect::<Result<Vec<_>, _>>().unwrap(),
+        [region(0, 0x3b40_0000), region(0x4000_0000, 0xbc00_0000)]
+    );
+}
+
+/// Nodes are found by path, with or without unit address, and by alias.
+#[test]
+fn node_lookup() {
+    let fdt = Fdt::new(RPI3).unwrap();
+
+    let uart = fdt.find_node("/soc/serial@7e201000").unwrap();
+    assert_eq!(uart.name(), "serial@7e201000");
+    assert_eq!(uart.parent().unwrap().name(), "soc");
+    assert_eq!(fdt.find_node("serial1").unwrap().name(), uart.name());
+    assert_eq!(fdt.find_node("/soc/gpio").unwrap().name(), "gpio@7e200000");
+    assert!(fdt.find_node("/soc/serial@7e201001").is_none());
+    assert!(fdt.find_node("nonexistent").is_none());
+
+    let names: Vec<_> = fdt
+        .find_compatible("brcm,bcm2835-aux-uart")
+        .map(|n| n.name())
+        .collect();
+    assert_eq!(names, ["serial@7e215040"]);
+
+    let aux = fdt.find_node("serial0").unwrap().referenced_node("clocks");
+    assert_eq!(aux.unwrap().name(), "aux@7e215000");
+    assert!(uart.referenced_node("clocks").is_none());
+
+    let spi = fdt.find_node("/soc/spi").unwrap();
+    assert!(!spi.is_enabled());
+    assert!(uart.is_enabled());
+    assert_eq!(
+        uart.property("arm,primecell-periphid").unwrap().as_u32(),
+        Some(0x241011)
+    );
+}
+
+/// Bus addresses are translated to physical addresses through the `ranges` of the buses.
+#[test]
+fn reg_is_translated() {
+    let rpi3 = Fdt::new(RPI3).unwrap();
+    let rpi4 = Fdt::new(RPI4).unwrap();
+
+    let reg = |fdt: &Fdt, path| regions(&fdt.find_node(path).unwrap());
+
+    assert_eq!(
+        reg(&rpi3, "/soc/serial@7e201000"),
+        [region(0x3f20_1000, 0x200)]
+    );
+    assert_eq!(reg(&rpi3, "/soc/gpio"), [region(0x3f20_0000, 0xb4)]);
+    assert_eq!(reg(&rpi3, "/soc/mmc"), [region(0x3f30_0000, 0x100)]);
+    assert_eq!(reg(&rpi3, "/soc/local_intc"), [region(0x4000_0000, 0x100)]);
+
+    assert_eq!(
+        reg(&rpi4, "/soc/serial@7e201000"),
+        [region(0xfe20_1000, 0x200)]
+    );
+    assert_eq!(reg(&rpi4, "/soc/gpio"), [region(0xfe20_0000, 0xb4)]);
+    assert_eq!(reg(&rpi4, "/emmc2bus/mmc"), [region(0xfe34_0000, 0x100)]);
+    assert_eq!(
+        reg(&rpi4, "/soc/interrupt-controller")[..2],
+        [region(0xff84_1000, 0x1000), region(0xff84_2000, 0x2000)]
+    );
+
+    // Nodes without reg have no regions.
+    assert_eq!(reg(&rpi4, "/soc").len(), 0);
+}
+
+/// The `ranges` of a bus are read in the physical address space.
+#[test]
+fn ranges() {
+    let rpi3 = Fdt::new(RPI3).unwrap();
+    let rpi4 = Fdt::new(RPI4).unwrap();
+
+    let ranges = |fdt: &Fdt, path| {
+        fdt.find_node(path)
+            .unwrap()
+            .ranges()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    };
+
+    assert_eq!(
+        ranges(&rpi3, "/soc"),
+        [region(0x3f00_0000, 0x100_0000), region(0x4000_0000, 0x1000)]
+    );
+    assert_eq!(
+        ranges(&rpi4, "/soc"),
+        [
+            region(0xfe00_0000, 0x180_0000),
+            region(0xfc00_0000, 0x200_0000),
+            region(0xff80_0000, 0x80_0000)
+        ]
+    );
+    assert_eq!(
+        ranges(&rpi4, "/emmc2bus"),
+        [region(0xfe00_0000, 0x180_0000)]
+    );
+
+    // Nodes without ranges have none.
+    assert_eq!(ranges(&rpi4, "/soc/gpio").len(), 0);
+}
+
+/// Interrupts are decoded with the `#interrupt-cells` of the interrupt parent.
+#[test]
+fn interrupts_are_decoded() {
+    let rpi3 = Fdt::new(RPI3).unwrap();
+    let rpi4 = Fdt::new(RPI4).unwrap();
+
+    let irqs = |fdt: &Fdt, path| interrupts(&fdt.find_node(path).unwrap());
+    let intc = "interrupt-controller@7e00b200".to_string();
+    let gic = "interrupt-controller@40041000".to_string();
+
+    assert_eq!(irqs(&rpi3, "serial1"), [(intc.clone(), vec![2, 25])]);
+    assert_eq!(irqs(&rpi3, "serial0"), [(intc.clone(), vec![1, 29])]);
+    assert_eq!(irqs(&rpi3, "/soc/gpio").len(), 4);
+    assert_eq!(
+        irqs(&rpi3, "/timer")[0],
+        ("local_intc@40000000".to_string(), vec![0, 4])
+    );
+
+    assert_eq!(irqs(&rpi4, "serial1"), [(gic.clone(), vec![0, 121, 4])]);
+    assert_eq!(irqs(&rpi4, "serial0"), [(gic.clone(), vec![0, 93, 4])]);
+    assert_eq!(irqs(&rpi4, "mmc0"), [(gic, vec![0, 126, 4])]);
+
+    // Nodes without interrupts have none.
+    assert_eq!(irqs(&rpi4, "/soc/aux").len(), 0);
+}
+
+/// Truncated or modified blobs are rejected.
+#[test]
+fn corrupt_blobs_are_rejected() {
+    assert!(Fdt::new(&RPI3[..RPI3.len() - 1]).is_err());
+
+    // Unknown token in the structure block, in place of the root node's first property.
+    let mut blob = RPI3.to_vec();
+    let off_structure = u32::from_be_bytes(blob[8..12].try_into().unwrap()) as usize;
+    blob[off_structure + 11] = 0x7;
+    assert!(Fdt::new(&blob).is_err());
+
+    // Property name outside of the strings block.
+    let mut blob = RPI3.to_vec();
+    blob[off_structure + 16..off_structure + 20].copy_from_slice(&0xffffu32.to_be_bytes());
+    assert!(Fdt::new(&blob).is_err());
+}
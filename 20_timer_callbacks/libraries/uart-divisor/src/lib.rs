@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Baud rate divisor calculations for UARTs.
+//!
+//! Kept in a separate crate so that the math can be unit tested on the host with `cargo test`.
+
+#![no_std]
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum deviation of the generated from the requested baud rate, in basis points (1/100 %).
+///
+/// Both ends of a link sample in the middle of each bit, so a combined deviation of a few percent
+/// is tolerated. Allow half of that for each end.
+pub const MAX_ERROR_BASIS_POINTS: u32 = 200;
+
+/// Baud rate divisor of the PL011, in units of 1/64.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PL011Divisor {
+    /// Value for the `IBRD` register.
+    pub integer: u16,
+
+    /// Value for the `FBRD` register.
+    pub fractional: u8,
+}
+
+/// Baud rate divisor of the BCM283x mini UART.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MiniUartDivisor {
+    /// Value for the `AUX_MU_BAUD_REG` register.
+    pub baud_reg: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PL011Divisor {
+    /// Compute the divisor for `baud` from the UART reference clock.
+    ///
+    /// According to the PL011 Technical Reference Manual, the divisor is
+    /// `clock / (16 * baud)`. It is rounded to the nearest 1/64.
+    ///
+    /// Fails if the divisor is out of range or the generated baud rate deviates more than
+    /// [`MAX_ERROR_BASIS_POINTS`].
+    pub fn new(clock_hz: u32, baud: u32) -> Result<Self, &'static str> {
+        if baud == 0 {
+            return Err("Baud rate must not be zero");
+        }
+
+        // clock * 64 / (16 * baud), rounded.
+        let clock = clock_hz as u64;
+        let baud = baud as u64;
+        let divisor_64ths = (clock * 4 + baud / 2) / baud;
+
+        let integer = divisor_64ths >> 6;
+        let fractional = divisor_64ths & 0x3f;
+
+        // The maximum divisor is 0xffff with a zero fractional part.
+        if integer == 0 || divisor_64ths > (0xffff << 6) {
+            return Err("Baud rate not achievable with this clock");
+        }
+
+        let divisor = Self {
+            integer: integer as u16,
+            fractional: fractional as u8,
+        };
+
+        if error_basis_points(divisor.baud(clock_hz), baud as u32) > MAX_ERROR_BASIS_POINTS {
+            return Err("Baud rate error too large with this clock");
+        }
+
+        Ok(divisor)
+    }
+
+    /// Return `clock_hz` if `baud` can be generated from it, and `fallback_hz` otherwise.
+    ///
+    /// For reference clock rates that are reported by the firmware, but might not be the actual
+    /// one. E.g. QEMU's mailbox reports 3 MHz, but the emulated UART ignores the divisor.
+    pub fn usable_clock_hz(clock_hz: u32, fallback_hz: u32, baud: u32) -> u32 {
+        match Self::new(clock_hz, baud) {
+            Ok(_) => clock_hz,
+            Err(_) => fallback_hz,
+        }
+    }
+
+    /// Return the baud rate that is generated from the UART reference clock.
+    pub fn baud(&self, clock_hz: u32) -> u32 {
+        let divisor_64ths = ((self.integer as u64) << 6) | self.fractional as u64;
+
+        ((clock_hz as u64 * 4) / divisor_64ths) as u32
+    }
+}
+
+impl MiniUartDivisor {
+    /// Compute the divisor for `baud` from the system (VPU core) clock.
+    ///
+    /// According to the BCM2835 ARM Peripherals manual, the baud rate is
+    /// `clock / (8 * (baud_reg + 1))`. The divisor is an integer, so the error is much larger than
+    /// with the PL011 for high baud rates.
+    ///
+    /// Fails if the divisor is out of range or the generated baud rate deviates more than
+    /// [`MAX_ERROR_BASIS_POINTS`].
+    pub fn new(clock_hz: u32, baud: u32) -> Result<Self, &'static str> {
+        if baud == 0 {
+            return Err("Baud rate must not be zero");
+        }
+
+        // clock / (8 * baud), rounded.
+        let clock = clock_hz as u64;
+        let baud = baud as u64;
+        let divisor = (clock + 4 * baud) / (8 * baud);
+
+        if divisor == 0 || divisor > 0x1_0000 {
+            return Err("Baud rate not achievable with this clock");
+        }
+
+        let divisor = Self {
+            baud_reg: (divisor - 1) as u16,
+        };
+
+        if error_basis_points(divisor.baud(clock_hz), baud as u32) > MAX_ERROR_BASIS_POINTS {
+            return Err("Baud rate error too large with this clock");
+        }
+
+        Ok(divisor)
+    }
+
+    /// Return the baud rate that is generated from the system clock.
+    pub fn baud(&self, clock_hz: u32) -> u32 {
+        clock_hz / (8 * (self.baud_reg as u32 + 1))
+    }
+}
+
+/// Return the deviation of `actual` from `requested`, in basis points (1/100 %), rounded up.
+pub fn error_basis_points(actual: u32, requested: u32) -> u32 {
+    let diff = actual.abs_diff(requested) as u64;
+
+    ((diff * 10_000 + requested as u64 - 1) / requested as u64) as u32
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example from the former hard-coded setup: 921_600 baud from a 48 MHz clock.
+    #[test]
+    fn divisor_for_921600_at_48mhz() {
+        let divisor = PL011Divisor::new(48_000_000, 921_600).unwrap();
+
+        assert_eq!(
+            divisor,
+            PL011Divisor {
+                integer: 3,
+                fractional: 16
+            }
+        );
+        assert_eq!(divisor.baud(48_000_000), 923_076);
+        assert_eq!(error_basis_points(923_076, 921_600), 17);
+    }
+
+    /// Common baud rates at the default clocks of the Raspberry Pi 3 and 4.
+    #[test]
+    fn common_baud_rates() {
+        for clock in [3_000_000, 48_000_000] {
+            for baud in [9_600, 19_200, 38_400, 57_600, 115_200] {
+                let divisor = PL011Divisor::new(clock, baud).unwrap();
+
+                assert!(error_basis_points(divisor.baud(clock), baud) <= MAX_ERROR_BASIS_POINTS);
+            }
+        }
+
+        assert_eq!(
+            PL011Divisor::new(48_000_000, 115_200).unwrap(),
+            PL011Divisor {
+                integer: 26,
+                fractional: 3
+            }
+        );
+    }
+
+    /// Divisors out of range are rejected.
+    #[test]
+    fn out_of_range_divisors_are_rejected() {
+        assert!(PL011Divisor::new(48_000_000, 0).is_err());
+
+        // Smallest divisor is 1.
+        assert!(PL011Divisor::new(48_000_000, 3_000_000).is_ok());
+        assert!(PL011Divisor::new(3_000_000, 921_600).is_err());
+
+        // Largest divisor is 0xffff.
+        assert!(PL011Divisor::new(48_000_000, 46).is_ok());
+        assert!(PL011Divisor::new(48_000_000, 45).is_err());
+    }
+
+    /// A reported clock that cannot generate the baud rate is replaced by the fallback.
+    #[test]
+    fn unusable_clocks_fall_back() {
+        assert_eq!(
+            PL011Divisor::usable_clock_hz(3_000_000, 48_000_000, 921_600),
+            48_000_000
+        );
+        assert_eq!(
+            PL011Divisor::usable_clock_hz(3_000_000, 48_000_000, 115_200),
+            3_000_000
+        );
+        assert_eq!(
+            PL011Divisor::usable_clock_hz(48_000_000, 3_000_000, 921_600),
+            48_000_000
+        );
+    }
+
+    /// The mini UART at the default core clocks of the Raspberry Pi 3 and 4.
+    #[test]
+    fn mini_uart_divisors() {
+        assert_eq!(
+            MiniUartDivisor::new(250_000_000, 115_200).unwrap(),
+            MiniUartDivisor { baud_reg: 270 }
+        );
+        assert_eq!(
+            MiniUartDivisor::new(500_000_000, 115_200).unwrap(),
+            MiniUartDivisor { baud_reg: 542 }
+        );
+        assert_eq!(MiniUartDivisor { baud_reg: 270 }.baud(250_000_000), 115_313);
+
+        // 250 MHz / (8 * 10) = 3_125_000 is more than 4 % off.
+        assert!(MiniUartDivisor::new(250_000_000, 3_000_000).is_err());
+
+        assert!(MiniUartDivisor::new(250_000_000, 0).is_err());
+        assert!(MiniUartDivisor::new(250_000_000, 400_000_000).is_err());
+        assert!(MiniUartDivisor::new(500_000_000, 954).is_ok());
+        assert!(MiniUartDivisor::new(500_000_000, 900).is_err());
+    }
+}
@@ -0,0 +1,555 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Messages of the VideoCore mailbox property interface.
+//!
+//! A request is a list of tags in a buffer of 32 bit words, which the firmware overwrites with the
+//! responses. Sending the buffer is up to the mailbox driver of the kernel.
+//!
+//! Kept in a separate crate so that the encoding and decoding can be unit tested on the host with
+//! `cargo test`.
+//!
+//! # Resources
+//!
+//! - <https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface>
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Buffer code of requests.
+const CODE_REQUEST: u32 = 0;
+
+/// Buffer code of successfully processed requests.
+const CODE_RESPONSE_SUCCESS: u32 = 0x8000_0000;
+
+/// Set in a tag's code by the firmware once the tag was processed.
+const TAG_CODE_RESPONSE: u32 = 0x8000_0000;
+
+/// Terminates the list of tags.
+const TAG_END: u32 = 0;
+
+/// Words in a tag before the value buffer: Identifier, value buffer size and code.
+const TAG_HEADER_WORDS: usize = 3;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A property tag.
+pub trait PropertyTag {
+    /// The tag identifier.
+    const ID: u32;
+
+    /// Size of the value buffer in words. Must fit both the request and the response.
+    const VALUE_WORDS: usize;
+
+    /// The decoded response.
+    type Response;
+
+    /// Write the request values into `value`.
+    fn encode_request(&self, value: &mut [u32]);
+
+    /// Decode the response values.
+    fn decode_response(value: &[u32]) -> Self::Response;
+}
+
+/// A property request with any number of tags.
+pub struct PropertyMessage {
+    words: Vec<u32>,
+
+    /// Whether the end tag was appended.
+    finished: bool,
+}
+
+/// Refers to a tag of a [`PropertyMessage`] for retrieving its response.
+pub struct TagHandle<T: PropertyTag> {
+    offset: usize,
+    _tag: PhantomData<T>,
+}
+
+/// Clocks of the SoC.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    Emmc = 1,
+    Uart = 2,
+    Arm = 3,
+    Core = 4,
+    V3d = 5,
+    H264 = 6,
+    Isp = 7,
+    Sdram = 8,
+    Pixel = 9,
+    Pwm = 10,
+    Emmc2 = 12,
+}
+
+/// Get the board revision.
+pub struct GetBoardRevision;
+
+/// Get the board serial number.
+pub struct GetBoardSerial;
+
+/// Get the MAC address of the on-board network interface.
+pub struct GetMacAddress;
+
+/// Get the base address and size of the memory that belongs to the ARM.
+pub struct GetArmMemory;
+
+/// Get the rate of a clock in Hz.
+pub struct GetClockRate(pub ClockId);
+
+/// Set the rate of a clock in Hz. The firmware may pick the closest supported rate.
+pub struct SetClockRate {
+    /// The clock.
+    pub clock: ClockId,
+
+    /// The requested rate.
+    pub rate_hz: u32,
+}
+
+/// Order of the color components of a pixel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelOrder {
+    /// Blue in the lowest byte.
+    Bgr = 0,
+
+    /// Red in the lowest byte.
+    Rgb = 1,
+}
+
+/// Allocate the framebuffer with the given alignment in bytes.
+pub struct AllocateBuffer(pub u32);
+
+/// Set the size of the display in pixels.
+pub struct SetPhysicalSize {
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+}
+
+/// Set the size of the framebuffer in pixels. Can be larger than the display.
+pub struct SetVirtualSize {
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+}
+
+/// Set the part of the framebuffer that is shown on the display.
+pub struct SetVirtualOffset {
+    /// Offset from the left in pixels.
+    pub x: u32,
+
+    /// Offset from the top in pixels.
+    pub y: u32,
+}
+
+/// Set the bits per pixel.
+pub struct SetDepth(pub u32);
+
+/// Set the order of the color components.
+pub struct SetPixelOrder(pub PixelOrder);
+
+/// Get the number of bytes per line of the framebuffer.
+pub struct GetPitch;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PropertyMessage {
+    /// Create an empty message.
+    pub fn new() -> Self {
+        // Buffer size and code are filled in by `finish()`.
+        Self {
+            words: Vec::from([0, CODE_REQUEST]),
+            finished: false,
+        }
+    }
+
+    /// Append `tag`. Returns a handle for retrieving the response after the message was sent.
+    pub fn add<T: PropertyTag>(&mut self, tag: &T) -> TagHandle<T> {
+        // The end tag must stay last.
+        if self.finished {
+            self.words.pop();
+            self.finished = false;
+        }
+
+        let offset = self.words.len();
+
+        self.words
+            .extend_from_slice(&[T::ID, (T::VALUE_WORDS * 4) as u32, CODE_REQUEST]);
+        self.words
+            .resize(offset + TAG_HEADER_WORDS + T::VALUE_WORDS, 0);
+        tag.encode_request(&mut self.words[offset + TAG_HEADER_WORDS..]);
+
+        TagHandle {
+            offset,
+            _tag: PhantomData,
+        }
+    }
+
+    /// Terminate the tag list and return the words to send. The firmware writes the responses
+    /// into the same words.
+    ///
+    /// The end tag is only appended once, so this can be called again, e.g. for a retry.
+    pub fn finish(&mut self) -> &mut [u32] {
+        if !self.finished {
+            self.words.push(TAG_END);
+            self.finished = true;
+        }
+        self.words[0] = (self.words.len() * 4) as u32;
+
+        &mut self.words
+    }
+
+    /// Return the response of the tag that `handle` refers to.
+    pub fn response<T: PropertyTag>(
+        &self,
+        handle: &TagHandle<T>,
+    ) -> Result<T::Response, &'static str> {
+        if self.words[1] != CODE_RESPONSE_SUCCESS {
+            return Err("Property request failed");
+        }
+
+        if self.words[handle.offset + 2] & TAG_CODE_RESPONSE == 0 {
+            return Err("Property tag not supported by the firmware");
+        }
+
+        let value_start = handle.offset + TAG_HEADER_WORDS;
+
+        Ok(T::decode_response(
+            &self.words[value_start..value_start + T::VALUE_WORDS],
+        ))
+    }
+}
+
+impl Default for PropertyMessage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PropertyTag for GetBoardRevision {
+    const ID: u32 = 0x0001_0002;
+    const VALUE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn encode_request(&self, _value: &mut [u32]) {}
+
+    fn decode_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl PropertyTag for GetBoardSerial {
+    const ID: u32 = 0x0001_0004;
+    const VALUE_WORDS: usize = 2;
+    type Response = u64;
+
+    fn encode_request(&self, _value: &mut [u32]) {}
+
+    fn decode_response(value: &[u32]) -> u64 {
+        ((value[1] as u64) << 32) | value[0] as u64
+    }
+}
+
+impl PropertyTag for GetMacAddress {
+    const ID: u32 = 0x0001_0003;
+    const VALUE_WORDS: usize = 2;
+    type Response = [u8; 6];
+
+    fn encode_request(&self, _value: &mut [u32]) {}
+
+    fn decode_response(value: &[u32]) -> [u8; 6] {
+        // The address is in network byte order.
+        let [a, b, c, d] = value[0].to_le_bytes();
+        let [e, f, _, _] = value[1].to_le_bytes();
+
+        [a, b, c, d, e, f]
+    }
+}
+
+impl PropertyTag for GetArmMemory {
+    const ID: u32 = 0x0001_0005;
+    const VALUE_WORDS: usize = 2;
+
+    /// Base address and size in bytes.
+    type Response = (u32, u32);
+
+    fn encode_request(&self, _value: &mut [u32]) {}
+
+    fn decode_response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl PropertyTag for GetClockRate {
+    const ID: u32 = 0x0003_0002;
+    const VALUE_WORDS: usize = 2;
+    type Response = u32;
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode_response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+impl PropertyTag for SetClockRate {
+    const ID: u32 = 0x0003_8002;
+    const VALUE_WORDS: usize = 3;
+    type Response = u32;
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.clock as u32;
+        value[1] = self.rate_hz;
+
+        // Do not skip setting turbo mode.
+        value[2] = 0;
+    }
+
+    fn decode_response(value: &[u32]) -> u32 {
+        value[1]
+    }
+}
+
+impl PropertyTag for AllocateBuffer {
+    const ID: u32 = 0x0004_0001;
+    const VALUE_WORDS: usize = 2;
+
+    /// Bus address and size in bytes.
+    type Response = (u32, u32);
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.0;
+    }
+
+    fn decode_response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl PropertyTag for SetPhysicalSize {
+    const ID: u32 = 0x0004_8003;
+    const VALUE_WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.width;
+        value[1] = self.height;
+    }
+
+    fn decode_response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl PropertyTag for SetVirtualSize {
+    const ID: u32 = 0x0004_8004;
+    const VALUE_WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.width;
+        value[1] = self.height;
+    }
+
+    fn decode_response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl PropertyTag for SetVirtualOffset {
+    const ID: u32 = 0x0004_8009;
+    const VALUE_WORDS: usize = 2;
+    type Response = (u32, u32);
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.x;
+        value[1] = self.y;
+    }
+
+    fn decode_response(value: &[u32]) -> (u32, u32) {
+        (value[0], value[1])
+    }
+}
+
+impl PropertyTag for SetDepth {
+    const ID: u32 = 0x0004_8005;
+    const VALUE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.0;
+    }
+
+    fn decode_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl PropertyTag for SetPixelOrder {
+    const ID: u32 = 0x0004_8006;
+    const VALUE_WORDS: usize = 1;
+    type Response = PixelOrder;
+
+    fn encode_request(&self, value: &mut [u32]) {
+        value[0] = self.0 as u32;
+    }
+
+    fn decode_response(value: &[u32]) -> PixelOrder {
+        match value[0] {
+            0 => PixelOrder::Bgr,
+            _ => PixelOrder::Rgb,
+        }
+    }
+}
+
+impl PropertyTag for GetPitch {
+    const ID: u32 = 0x0004_0008;
+    const VALUE_WORDS: usize = 1;
+    type Response = u32;
+
+    fn encode_request(&self, _value: &mut [u32]) {}
+
+    fn decode_response(value: &[u32]) -> u32 {
+        value[0]
+    }
+}
+
+impl ClockId {
+    /// All clocks.
+    pub const ALL: [Self; 11] = [
+        Self::Emmc,
+        Self::Uart,
+        Self::Arm,
+        Self::Core,
+        Self::V3d,
+        Self::H264,
+        Self::Isp,
+        Self::Sdram,
+        Self::Pixel,
+        Self::Pwm,
+        Self::Emmc2,
+    ];
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Let `words` look like the firmware processed the request, with `values` as the responses.
+    fn respond(words: &mut [u32], values: &[(usize, &[u32])]) {
+        words[1] = CODE_RESPONSE_SUCCESS;
+
+        for (offset, value) in values {
+            words[offset + 2] = TAG_CODE_RESPONSE | (value.len() * 4) as u32;
+            words[offset + TAG_HEADER_WORDS..][..value.len()].copy_from_slice(value);
+        }
+    }
+
+    /// Tags are encoded with their header and request values, and the message is terminated.
+    #[test]
+    fn message_is_encoded() {
+        let mut message = PropertyMessage::new();
+        message.add(&GetBoardRevision);
+        message.add(&SetClockRate {
+            clock: ClockId::Uart,
+            rate_hz: 48_000_000,
+        });
+
+        let expected = [
+            52,
+            CODE_REQUEST,
+            0x0001_0002,
+            4,
+            CODE_REQUEST,
+            0,
+            0x0003_8002,
+            12,
+            CODE_REQUEST,
+            2,
+            48_000_000,
+            0,
+            TAG_END,
+        ];
+        assert_eq!(message.finish(), expected);
+    }
+
+    /// Finishing twice does not append a second end tag, and tags added afterwards stay in front
+    /// of it.
+    #[test]
+    fn finish_is_idempotent() {
+        let mut message = PropertyMessage::new();
+        message.add(&GetPitch);
+
+        let first = message.finish().to_vec();
+        assert_eq!(message.finish(), first);
+
+        message.add(&GetPitch);
+        let words = message.finish();
+        assert_eq!(words.len(), first.len() + 4);
+        assert_eq!(words[0] as usize, words.len() * 4);
+        assert_eq!(words[words.len() - 1], TAG_END);
+        assert_eq!(words[first.len() - 1], GetPitch::ID);
+    }
+
+    /// Responses are decoded from the value buffers of their tags.
+    #[test]
+    fn responses_are_decoded() {
+        let mut message = PropertyMessage::new();
+        let serial = message.add(&GetBoardSerial);
+        let mac = message.add(&GetMacAddress);
+        let order = message.add(&SetPixelOrder(PixelOrder::Rgb));
+
+        respond(
+            message.finish(),
+            &[
+                (2, &[0x89ab_cdef, 0x0123_4567]),
+                (7, &[0x4433_2211, 0x6655]),
+                (12, &[0]),
+            ],
+        );
+
+        assert_eq!(message.response(&serial), Ok(0x0123_4567_89ab_cdef));
+        assert_eq!(
+            message.response(&mac),
+            Ok([0x11, 0x22, 0x33, 0x44, 0x55, 0x66])
+        );
+        assert_eq!(message.response(&order), Ok(PixelOrder::Bgr));
+    }
+
+    /// Failed requests and tags that the firmware did not process are reported as errors.
+    #[test]
+    fn failures_are_reported() {
+        let mut message = PropertyMessage::new();
+        let revision = message.add(&GetBoardRevision);
+        let memory = message.add(&GetArmMemory);
+
+        assert!(message.response(&revision).is_err());
+
+        respond(message.finish(), &[(2, &[0xa020d3])]);
+        assert_eq!(message.response(&revision), Ok(0xa020d3));
+        assert!(message.response(&memory).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Minimal ELF64 little endian parsing, just enough to extract a section by name.
+
+use std::convert::TryInto;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, String> {
+    data.get(offset..offset + 8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| "Truncated ELF file".to_string())
+}
+
+struct SectionHeader {
+    name_offset: usize,
+    offset: usize,
+    size: usize,
+}
+
+fn section_header(data: &[u8], index: usize) -> Result<SectionHeader, String> {
+    let shoff = read_u64(data, 0x28)? as usize;
+    let shentsize = read_u16(data, 0x3a)? as usize;
+    let header = shoff + index * shentsize;
+
+    Ok(SectionHeader {
+        name_offset: read_u32(data, header)? as usize,
+        offset: read_u64(data, header + 0x18)? as usize,
+        size: read_u64(data, header + 0x20)? as usize,
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return the contents of the section with the given name.
+pub fn section_by_name<'a>(data: &'a [u8], name: &str) -> Result<&'a [u8], String> {
+    if data.get(0..4) != Some(b"\x7fELF") || data.get(4) != Some(&2) || data.get(5) != Some(&1) {
+        return Err("Not a 64 bit little endian ELF file".to_string());
+    }
+
+    let shnum = read_u16(data, 0x3c)? as usize;
+    let shstrndx = read_u16(data, 0x3e)? as usize;
+    let shstrtab = section_header(data, shstrndx)?;
+
+    for index in 0..shnum {
+        let header = section_header(data, index)?;
+
+        let name_start = shstrtab.offset + header.name_offset;
+        let section_name = data
+            .get(name_start..)
+            .and_then(|s| s.split(|b| *b == 0).next())
+            .ok_or_else(|| "Invalid section name".to_string())?;
+
+        if section_name == name.as_bytes() {
+            return data
+                .get(header.offset..header.offset + header.size)
+                .ok_or_else(|| "Truncated ELF file".to_string());
+        }
+    }
+
+    Err(format!("Section {} not found", name))
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Formatting of decoded arguments according to Rust format strings.
+//!
+//! Supports positional `{}` placeholders with the format spec
+//! `[[fill]align][#][0][width][.precision][type]`, with type being one of `x`, `X`, `o`, `b` or
+//! `?`. Named and explicitly indexed arguments are not supported.
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A decoded log argument.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Str(String),
+    Bool(bool),
+    Char(char),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    ty: Option<char>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let mut result = Spec::default();
+    let chars: Vec<char> = spec.chars().collect();
+    let mut i = 0;
+
+    let is_align = |c: char| c == '<' || c == '>' || c == '^';
+
+    if chars.len() >= 2 && is_align(chars[1]) {
+        result.fill = Some(chars[0]);
+        result.align = Some(chars[1]);
+        i = 2;
+    } else if !chars.is_empty() && is_align(chars[0]) {
+        result.align = Some(chars[0]);
+        i = 1;
+    }
+
+    if chars.get(i) == Some(&'#') {
+        result.alternate = true;
+        i += 1;
+    }
+
+    if chars.get(i) == Some(&'0') {
+        result.zero = true;
+        i += 1;
+    }
+
+    let start = i;
+    while chars.get(i).map_or(false, |c| c.is_ascii_digit()) {
+        i += 1;
+    }
+    if i > start {
+        result.width = chars[start..i].iter().collect::<String>().parse().unwrap();
+    }
+
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let start = i;
+        while chars.get(i).map_or(false, |c| c.is_ascii_digit()) {
+            i += 1;
+        }
+        result.precision = Some(
+            chars[start..i]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .map_err(|_| format!("Invalid precision in {{:{}}}", spec))?,
+        );
+    }
+
+    match chars.get(i) {
+        None => (),
+        Some(c) if "xXob?".contains(*c) && i + 1 == chars.len() => result.ty = Some(*c),
+        Some(_) => return Err(format!("Unsupported format spec {{:{}}}", spec)),
+    }
+
+    Ok(result)
+}
+
+fn format_unsigned(value: u64, spec: &Spec) -> (String, &'static str) {
+    match spec.ty {
+        Some('x') => (format!("{:x}", value), "0x"),
+        Some('X') => (format!("{:X}", value), "0x"),
+        Some('o') => (format!("{:o}", value), "0o"),
+        Some('b') => (format!("{:b}", value), "0b"),
+        _ => (value.to_string(), ""),
+    }
+}
+
+fn format_value(value: &Value, spec: &Spec) -> String {
+    // Numbers are right-aligned by default and support zero padding.
+    let (sign, prefix, digits) = match value {
+        Value::Unsigned(v) => {
+            let (digits, prefix) = format_unsigned(*v, spec);
+            ("", prefix, Some(digits))
+        }
+        Value::Signed(v) => {
+            let (digits, prefix) = format_unsigned(v.unsigned_abs(), spec);
+            (if *v < 0 { "-" } else { "" }, prefix, Some(digits))
+        }
+        _ => ("", "", None),
+    };
+
+    if let Some(digits) = digits {
+        let prefix = if spec.alternate { prefix } else { "" };
+
+        if spec.zero {
+            let len = sign.len() + prefix.len() + digits.len();
+            let zeros = "0".repeat(spec.width.saturating_sub(len));
+
+            return format!("{}{}{}{}", sign, prefix, zeros, digits);
+        }
+
+        return pad(&format!("{}{}{}", sign, prefix, digits), spec, '>');
+    }
+
+    let text = match (value, spec.ty) {
+        (Value::Str(s), Some('?')) => format!("{:?}", s),
+        (Value::Char(c), Some('?')) => format!("{:?}", c),
+        (Value::Str(s), _) => s.clone(),
+        (Value::Char(c), _) => c.to_string(),
+        (Value::Bool(b), _) => b.to_string(),
+        _ => unreachable!(),
+    };
+
+    let text = match spec.precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text,
+    };
+
+    pad(&text, spec, '<')
+}
+
+fn pad(text: &str, spec: &Spec, default_align: char) -> String {
+    let len = text.chars().count();
+    if len >= spec.width {
+        return text.to_string();
+    }
+
+    let padding = spec.width - len;
+    let (left, right) = match spec.align.unwrap_or(default_align) {
+        '<' => (0, padding),
+        '^' => (padding / 2, padding - padding / 2),
+        _ => (padding, 0),
+    };
+
+    let fill = spec.fill.unwrap_or(' ').to_string();
+
+    format!("{}{}{}", fill.repeat(left), text, fill.repeat(right))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Format `args` according to `format_string`.
+pub fn format(format_string: &str, args: &[Value]) -> Result<String, String> {
+    let mut output = String::new();
+    let mut args = args.iter();
+    let mut chars = format_string.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let placeholder: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let spec = match placeholder.split_once(':') {
+                    Some(("", spec)) => parse_spec(spec)?,
+                    None if placeholder.is_empty() => Spec::default(),
+                    _ => return Err(format!("Unsupported placeholder {{{}}}", placeholder)),
+                };
+
+                match args.next() {
+                    Some(value) => output.push_str(&format_value(value, &spec)),
+                    // Arguments that did not fit into the frame.
+                    None => output.push_str("<truncated>"),
+                }
+            }
+            _ => output.push(c),
+        }
+    }
+
+    Ok(output)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output must match what `core::fmt` produces on the target.
+    #[test]
+    fn matches_core_fmt() {
+        let cases: &[(&str, Value, String)] = &[
+            ("{}", Value::Unsigned(42), format!("{}", 42)),
+            ("{:>3}", Value::Unsigned(7), format!("{:>3}", 7)),
+            ("{:06}", Value::Signed(-42), format!("{:06}", -42)),
+            ("{:#x}", Value::Unsigned(0xbeef), format!("{:#x}", 0xbeef)),
+            (
+                "{:#010x}",
+                Value::Unsigned(0xbeef),
+                format!("{:#010x}", 0xbeef),
+            ),
+            ("{:<5}|", Value::Str("ab".into()), format!("{:<5}|", "ab")),
+            ("{:^7}", Value::Str("mid".into()), format!("{:^7}", "mid")),
+            ("{:*^7}", Value::Bool(true), format!("{:*^7}", true)),
+            ("{:?}", Value::Str("a\"b".into()), format!("{:?}", "a\"b")),
+            ("{:.2}", Value::Str("abcd".into()), format!("{:.2}", "abcd")),
+        ];
+
+        for (format_string, value, expected) in cases {
+            assert_eq!(
+                &format(format_string, std::slice::from_ref(value)).unwrap(),
+                expected
+            );
+        }
+    }
+
+    /// Escaped braces and missing arguments.
+    #[test]
+    fn escapes_and_truncation() {
+        assert_eq!(
+            format("{{{}}} {}", &[Value::Char('x')]).unwrap(),
+            "{x} <truncated>"
+        );
+    }
+}
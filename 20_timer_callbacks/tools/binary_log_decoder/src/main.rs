@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+//! Decoder for the kernel's binary log mode.
+//!
+//! Reads the serial output of a kernel that was built with the `binary_log` feature from stdin and
+//! writes it as text to stdout. Binary log frames are formatted the same way as the kernel prints
+//! them in text mode. All other output, e.g. panic messages, is passed through unchanged.
+//!
+//! Usage: `binary_log_decoder [--verbose] <kernel ELF>`
+//!
+//! With `--verbose`, the core id and module path of each message are printed as well.
+
+mod elf;
+mod format;
+
+use format::Value;
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    iter::Peekable,
+    process,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const FRAME_START: u8 = 0xff;
+const ESCAPE: u8 = 0xfe;
+const ESCAPE_XOR: u8 = 0x20;
+const FORMAT_STRINGS_SECTION: &str = ".log_format_strings";
+
+struct Frame {
+    level: u8,
+    core: u8,
+    timestamp_us: u64,
+    id: usize,
+    args: Vec<Value>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn next_raw_byte(input: &mut impl Iterator<Item = io::Result<u8>>) -> Result<u8, String> {
+    match input.next() {
+        Some(Ok(byte)) => Ok(byte),
+        Some(Err(e)) => Err(e.to_string()),
+        None => Err("Unexpected end of input".to_string()),
+    }
+}
+
+/// Return the next unescaped byte of a frame.
+///
+/// An unescaped start byte means that the current frame is corrupted. It is left in the input, so
+/// that decoding resumes with the next frame.
+fn next_byte<I>(input: &mut Peekable<I>) -> Result<u8, String>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    if let Some(Ok(FRAME_START)) = input.peek() {
+        return Err("Frame interrupted".to_string());
+    }
+
+    match next_raw_byte(input)? {
+        ESCAPE => match input.peek() {
+            Some(Ok(FRAME_START)) => Err("Frame interrupted".to_string()),
+            _ => Ok(next_raw_byte(input)? ^ ESCAPE_XOR),
+        },
+        byte => Ok(byte),
+    }
+}
+
+fn next_leb128<I>(input: &mut Peekable<I>) -> Result<u64, String>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let byte = next_byte(input)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err("Invalid LEB128 value".to_string())
+}
+
+fn next_value<I>(input: &mut Peekable<I>) -> Result<Value, String>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let value = match next_byte(input)? {
+        0 => Value::Unsigned(next_leb128(input)?),
+        1 => {
+            let zigzag = next_leb128(input)?;
+            Value::Signed(((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64))
+        }
+        2 => {
+            let len = next_leb128(input)? as usize;
+            let bytes = (0..len)
+                .map(|_| next_byte(input))
+                .collect::<Result<Vec<u8>, String>>()?;
+
+            Value::Str(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        3 => Value::Bool(next_byte(input)? != 0),
+        4 => {
+            let code_point = next_leb128(input)? as u32;
+            Value::Char(char::from_u32(code_point).ok_or("Invalid char")?)
+        }
+        tag => return Err(format!("Unknown argument tag {}", tag)),
+    };
+
+    Ok(value)
+}
+
+/// Decode a frame. The start byte has already been consumed.
+fn next_frame<I>(input: &mut Peekable<I>) -> Result<Frame, String>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let level = next_byte(input)?;
+    let core = next_byte(input)?;
+    let timestamp_us = next_leb128(input)?;
+    let id = next_leb128(input)? as usize;
+    let num_args = next_byte(input)?;
+
+    let args = (0..num_args)
+        .map(|_| next_value(input))
+        .collect::<Result<Vec<Value>, String>>()?;
+
+    Ok(Frame {
+        level,
+        core,
+        timestamp_us,
+        id,
+        args,
+    })
+}
+
+/// Return the module path and format string with the given id.
+fn lookup(format_strings: &[u8], id: usize) -> Result<(&str, &str), String> {
+    let mut parts = format_strings
+        .get(id..)
+        .ok_or_else(|| format!("Invalid format string id {}", id))?
+        .split(|b| *b == 0)
+        .map(|s| std::str::from_utf8(s).map_err(|e| e.to_string()));
+
+    match (parts.next(), parts.next()) {
+        (Some(module_path), Some(format_string)) => Ok((module_path?, format_string?)),
+        _ => Err(format!("Invalid format string id {}", id)),
+    }
+}
+
+fn render(frame: &Frame, format_strings: &[u8], verbose: bool) -> Result<String, String> {
+    let (module_path, format_string) = lookup(format_strings, frame.id)?;
+    let message = format::format(format_string, &frame.args)?;
+
+    // Same as the kernel's text mode, see `print_to_console()` in `log.rs`.
+    let (open, close) = match frame.level {
+        0 => ("[W", ']'),
+        1 => ("[ ", ']'),
+        _ => ("<D", '>'),
+    };
+
+    let secs = frame.timestamp_us / 1_000_000;
+    let micros = frame.timestamp_us % 1_000_000;
+
+    if verbose {
+        Ok(format!(
+            "{} {:>3}.{:06}{} core {} {}: {}\n",
+            open, secs, micros, close, frame.core, module_path, message
+        ))
+    } else {
+        Ok(format!(
+            "{} {:>3}.{:06}{} {}\n",
+            open, secs, micros, close, message
+        ))
+    }
+}
+
+fn run(elf_path: &str, verbose: bool) -> Result<(), String> {
+    let elf_data = fs::read(elf_path).map_err(|e| format!("{}: {}", elf_path, e))?;
+    let format_strings = elf::section_by_name(&elf_data, FORMAT_STRINGS_SECTION)?;
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut input = stdin.lock().bytes().peekable();
+
+    while let Some(byte) = input.next() {
+        let byte = byte.map_err(|e| e.to_string())?;
+
+        if byte != FRAME_START {
+            output.write_all(&[byte]).map_err(|e| e.to_string())?;
+            if byte == b'\n' {
+                output.flush().map_err(|e| e.to_string())?;
+            }
+            continue;
+        }
+
+        let text = next_frame(&mut input)
+            .and_then(|frame| render(&frame, format_strings, verbose))
+            .unwrap_or_else(|e| format!("<binary log decode error: {}>\n", e));
+
+        output
+            .write_all(text.as_bytes())
+            .map_err(|e| e.to_string())?;
+        output.flush().map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let (verbose, elf_path) = match args.as_slice() {
+        [elf_path] => (false, elf_path),
+        [flag, elf_path] if flag == "--verbose" => (true, elf_path),
+        _ => {
+            eprintln!("Usage: binary_log_decoder [--verbose] <kernel ELF>");
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(elf_path, verbose) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(bytes: &[u8]) -> Peekable<impl Iterator<Item = io::Result<u8>> + '_> {
+        bytes.iter().map(|b| Ok(*b)).peekable()
+    }
+
+    /// Escaped start and escape bytes are restored.
+    #[test]
+    fn unescapes_payload() {
+        // Level, core, timestamp 0x7f, id 0xff as LEB128 and one unsigned argument 0xfe.
+        let mut bytes = input(&[
+            1,
+            0,
+            0x7f,
+            ESCAPE,
+            FRAME_START ^ ESCAPE_XOR,
+            0x01,
+            1,
+            0,
+            ESCAPE,
+            ESCAPE ^ ESCAPE_XOR,
+            0x01,
+        ]);
+        let frame = next_frame(&mut bytes).unwrap();
+
+        assert_eq!(frame.timestamp_us, 0x7f);
+        assert_eq!(frame.id, 0xff);
+        assert!(matches!(frame.args.as_slice(), [Value::Unsigned(0xfe)]));
+        assert!(bytes.next().is_none());
+    }
+
+    /// A start byte within a frame aborts it and is left for the next frame.
+    #[test]
+    fn resynchronizes_on_frame_start() {
+        let mut bytes = input(&[1, 0, FRAME_START, 1]);
+
+        assert!(next_frame(&mut bytes).is_err());
+        assert!(matches!(bytes.next(), Some(Ok(FRAME_START))));
+    }
+}
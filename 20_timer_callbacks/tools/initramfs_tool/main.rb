@@ -0,0 +1,56 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+# Pack the contents of a directory into a cpio archive in the "newc" format. The kernel embeds the
+# archive as its initial RAM file system.
+#
+# Usage: main.rb <archive> <directory>
+
+MAGIC = '070701'
+TRAILER_NAME = 'TRAILER!!!'
+
+MODE_DIRECTORY = 0o040755
+MODE_FILE = 0o100644
+
+# Headers and file data start at multiples of four bytes.
+def pad(data)
+    data + ("\0".b * (-data.bytesize % 4))
+end
+
+def entry(ino, name, mode, data)
+    name = "#{name}\0".b
+
+    # Inode, mode, uid, gid, number of links, mtime, file size, device major and minor, rdev major
+    # and minor, name size and checksum. The mtime is zero so that archives are reproducible.
+    fields = [ino, mode, 0, 0, 1, 0, data.bytesize, 0, 0, 0, 0, name.bytesize, 0]
+    header = MAGIC.b + fields.map { |f| format('%08X', f) }.join.b
+
+    pad(header + name) + pad(data)
+end
+
+archive, directory = ARGV
+raise 'Usage: main.rb <archive> <directory>' if archive.nil? || directory.nil?
+
+# Sorting puts every directory before its contents.
+names = Dir.glob('**/*', File::FNM_DOTMATCH, base: directory).reject do |name|
+    ['.', '..'].include?(File.basename(name))
+end.sort
+
+File.open(archive, 'wb') do |file|
+    names.each_with_index do |name, i|
+        path = File.join(directory, name)
+
+        if File.directory?(path)
+            file.write(entry(i + 1, name, MODE_DIRECTORY, ''.b))
+        elsif File.file?(path)
+            file.write(entry(i + 1, name, MODE_FILE, File.binread(path)))
+        else
+            raise "Unsupported file type: #{path}"
+        end
+    end
+
+    file.write(entry(0, TRAILER_NAME, 0, ''.b))
+end
@@ -0,0 +1,52 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2023 Andre Richter <andre.o.richter@gmail.com>
+
+# Create an SD card image with an MBR partition table and a single FAT32 partition, and copy the
+# contents of a directory into it.
+#
+# Usage: main.rb <image> <directory>
+
+# QEMU requires the size of SD card images to be a power of two.
+IMAGE_SIZE = 64 * 1024 * 1024
+SECTOR_SIZE = 512
+
+# The partition starts at 1 MiB, like on images created by the usual tools.
+PARTITION_START_SECTOR = 2048
+PARTITION_NUM_SECTORS = (IMAGE_SIZE / SECTOR_SIZE) - PARTITION_START_SECTOR
+PARTITION_TYPE_FAT32_LBA = 0x0C
+
+MBR_ENTRIES_OFFSET = 446
+MBR_SIGNATURE_OFFSET = 510
+
+def run(*cmd)
+    system(*cmd, exception: true)
+end
+
+def write_mbr(image)
+    # Status, CHS start, type, CHS end, LBA start, number of sectors. CHS values are unused.
+    entry = [0x00, 0xFE, 0xFF, 0xFF, PARTITION_TYPE_FAT32_LBA, 0xFE, 0xFF, 0xFF,
+             PARTITION_START_SECTOR, PARTITION_NUM_SECTORS].pack('C8V2')
+
+    File.open(image, 'wb') do |file|
+        file.truncate(IMAGE_SIZE)
+        file.seek(MBR_ENTRIES_OFFSET)
+        file.write(entry)
+        file.seek(MBR_SIGNATURE_OFFSET)
+        file.write([0x55, 0xAA].pack('C2'))
+    end
+end
+
+image, directory = ARGV
+raise 'Usage: main.rb <image> <directory>' if image.nil? || directory.nil?
+
+write_mbr(image)
+
+# One sector per cluster, so that small files span several clusters. The size is given in KiB.
+run('mkfs.fat', '-F', '32', '-s', '1', '-n', 'MINGO', '--offset', PARTITION_START_SECTOR.to_s,
+    image, (PARTITION_NUM_SECTORS * SECTOR_SIZE / 1024).to_s, out: File::NULL)
+
+files = Dir.children(directory).map { |f| File.join(directory, f) }
+run('mcopy', '-s', '-i', "#{image}@@#{PARTITION_START_SECTOR * SECTOR_SIZE}", *files, '::')
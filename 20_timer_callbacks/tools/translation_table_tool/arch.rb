@@ -182,6 +182,7 @@ end
 class TranslationTable
     module MAIR
         NORMAL = 1
+        NORMAL_NON_CACHEABLE = 2
     end
 
     def initialize
@@ -277,6 +278,9 @@ class TranslationTable
         when :CacheableDRAM
             desc.sh = Stage1PageDescriptor::SH::INNER_SHAREABLE
             desc.attr_indx = MAIR::NORMAL
+        when :NonCacheableDRAM
+            desc.sh = Stage1PageDescriptor::SH::INNER_SHAREABLE
+            desc.attr_indx = MAIR::NORMAL_NON_CACHEABLE
         else
             raise 'Invalid input'
         end
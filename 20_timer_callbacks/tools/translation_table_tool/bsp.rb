@@ -34,16 +34,10 @@ class RaspberryPi
         KERNEL_ELF.virt_addr_to_file_offset(@virt_addr_of_phys_kernel_tables_base_addr)
     end
 
+    # The address space covers the devices of all supported boards, so it is the same for both.
     def phys_addr_space_end_page
-        x = MEMORY_SRC.grep(/pub const END/)
-        x = case BSP_TYPE
-            when :rpi3
-                x[0]
-            when :rpi4
-                x[1]
-            else
-                raise
-            end
+        x = MEMORY_SRC.grep(/pub const END/).find { |line| line.match?(/0x\h/) }
+        raise 'End of the physical address space not found' if x.nil?
 
         # Extract the hex literal with underscores like 0x0123_abcd.
         x = x.scan(/0x[\h_]*/)[0]
@@ -69,6 +69,8 @@ class AttributeFields
         x = case @mem_attributes
             when :CacheableDRAM
                 'C'
+            when :NonCacheableDRAM
+                'NC'
             else
                 '?'
             end
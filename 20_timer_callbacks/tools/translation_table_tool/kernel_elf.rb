@@ -69,6 +69,15 @@ class KernelELF
         end
     end
 
+    # Memory shared with devices is mapped non-cacheable.
+    def segment_get_mem_attributes(section_names)
+        if section_names.split.include?('.dma')
+            :NonCacheableDRAM
+        else
+            :CacheableDRAM
+        end
+    end
+
     def update_max_section_name_length(descriptors)
         MappingDescriptor.update_max_section_name_length(descriptors.map { |i| i.name.size }.max)
     end
@@ -85,7 +94,8 @@ class KernelELF
 
             virt_region = MemoryRegion.new(virt_start_addr, size, BSP.kernel_granule::SIZE)
             phys_region = MemoryRegion.new(phys_start_addr, size, BSP.kernel_granule::SIZE)
-            attributes = AttributeFields.new(:CacheableDRAM, acc_perms, execute_never)
+            mem_attributes = segment_get_mem_attributes(section_names)
+            attributes = AttributeFields.new(mem_attributes, acc_perms, execute_never)
 
             MappingDescriptor.new(section_names, virt_region, phys_region, attributes)
         end